@@ -1,7 +1,11 @@
+use base64::Engine;
+use std::collections::HashMap;
 use std::sync::Arc;
+use tauri::{Emitter, Manager};
+use tokio::sync::broadcast::error::RecvError;
 
 mod ssh;
-use ssh::{SshClient, SshConfig, SshSessionInfo, CommandResult, TerminalSession, TerminalData};
+use ssh::{SshClient, SshConfig, EnvProfile, SshSessionInfo, CommandResult, CommandDiffResult, CommandMacro, EffectiveConfig, HostKeyInfo, PinnedHostKey, ProcessInfo, TerminalSession, TerminalData, PasteOptions, ImportSummary, ExecStreamInfo, ExecStreamData, SyncOptions, SyncSummary, SessionTelemetry, ServerExtensions, CommandOptions, RemotePathInfo, LocalKeyInfo, KeyInfo, AgentIdentity, FileOutputOptions, FileOutputResult, TimedCommandResult, KeyType, RemoteCommandInfo, ConnectionDiagnostics, ConnectionStatus, TerminalForwarding, TransferInfo, TransferAggregate, TransferCheck, ForwardInfo, ForwardSpec, BytesCommandResult, RunningExecInfo, BandwidthTestResult, RemoteByteRange, RemoteCopyResult, RemoteFileEntry, TarDownloadResult, SessionSnapshot, ShellKind, WaitCondition};
 
 /// アプリケーション状態
 pub struct AppState {
@@ -35,42 +39,1021 @@ async fn ssh_create_connection(
         .map_err(|e| e.to_string())
 }
 
+/// ローカルの秘密鍵ファイルを列挙
+#[tauri::command]
+async fn ssh_list_local_keys(
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<LocalKeyInfo>, String> {
+    state
+        .ssh_client
+        .list_local_keys()
+        .map_err(|e| e.to_string())
+}
+
+/// SSHエージェントの鍵を列挙
+#[tauri::command]
+async fn ssh_list_agent_identities(
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<AgentIdentity>, String> {
+    state
+        .ssh_client
+        .list_agent_identities()
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// 鍵ペアを生成し、公開鍵の文字列を返す
+#[tauri::command]
+async fn ssh_generate_keypair(
+    state: tauri::State<'_, AppState>,
+    path: String,
+    key_type: KeyType,
+    passphrase: Option<String>,
+    force: Option<bool>,
+) -> Result<String, String> {
+    state
+        .ssh_client
+        .generate_keypair(path, key_type, passphrase, force.unwrap_or(false))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// 秘密鍵（パスまたはPEMの内容）を復号できるか確かめ、鍵の情報を返す
+#[tauri::command]
+async fn ssh_validate_key(
+    state: tauri::State<'_, AppState>,
+    path_or_pem: String,
+    passphrase: Option<String>,
+) -> Result<KeyInfo, String> {
+    state
+        .ssh_client
+        .validate_key(path_or_pem, passphrase)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// 接続を段階ごとに試して問題のある段階を特定
+#[tauri::command]
+async fn ssh_diagnose_connection(
+    state: tauri::State<'_, AppState>,
+    config: SshConfig,
+) -> Result<ConnectionDiagnostics, String> {
+    Ok(state.ssh_client.diagnose_connection(&config).await)
+}
+
+/// 接続を事前に確立しておく（接続先と認証・ホスト鍵などの設定が同じ `ssh_connect` で引き継ぐ）
+#[tauri::command]
+async fn ssh_prewarm(
+    state: tauri::State<'_, AppState>,
+    config: SshConfig,
+    count: usize,
+    idle_ttl_secs: Option<u64>,
+) -> Result<usize, String> {
+    state
+        .ssh_client
+        .prewarm(config, count, idle_ttl_secs)
+        .await
+        .map_err(|e| e.to_string())
+}
+
 /// SSH接続を確立
 #[tauri::command]
-async fn ssh_connect(
+async fn ssh_connect(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+) -> Result<(), String> {
+    state
+        .ssh_client
+        .connect(&session_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// 進行中の接続を中断
+#[tauri::command]
+async fn ssh_cancel_connect(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+) -> Result<(), String> {
+    state
+        .ssh_client
+        .cancel_connect(&session_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// セッションのタイムアウトを実行中に変更（nullで設定値に戻す）
+#[tauri::command]
+async fn ssh_set_session_timeout(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    timeout_ms: Option<u64>,
+) -> Result<(), String> {
+    state
+        .ssh_client
+        .set_session_timeout(&session_id, timeout_ms.map(std::time::Duration::from_millis))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// サーバーのホスト鍵を公開鍵の1行形式で取得
+///
+/// `session_id` を指定した場合はその接続で提示された鍵、省略した場合は `host`・`port`（既定22）に
+/// 接続して取得する。
+#[tauri::command]
+async fn ssh_get_host_key(
+    state: tauri::State<'_, AppState>,
+    session_id: Option<String>,
+    host: Option<String>,
+    port: Option<u16>,
+) -> Result<HostKeyInfo, String> {
+    let result = match (session_id, host) {
+        (Some(session_id), _) => state.ssh_client.get_host_key(&session_id).await,
+        (None, Some(host)) => state.ssh_client.scan_host_key(&host, port.unwrap_or(22)).await,
+        (None, None) => return Err("either session_id or host is required".to_string()),
+    };
+    result.map_err(|e| e.to_string())
+}
+
+/// `trust_on_first_use` で固定したホスト鍵の一覧を取得
+#[tauri::command]
+async fn ssh_list_pinned_host_keys(state: tauri::State<'_, AppState>) -> Result<Vec<PinnedHostKey>, String> {
+    Ok(state.ssh_client.list_pinned_host_keys().await)
+}
+
+/// ホスト鍵の固定を解除（固定されていなければ false）
+#[tauri::command]
+async fn ssh_remove_pinned_host_key(
+    state: tauri::State<'_, AppState>,
+    host: String,
+    port: Option<u16>,
+) -> Result<bool, String> {
+    state
+        .ssh_client
+        .remove_pinned_host_key(&host, port.unwrap_or(22))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// タイムアウトやキープアライブなど、実際に使われる設定値を取得
+#[tauri::command]
+async fn ssh_get_effective_config(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+) -> Result<EffectiveConfig, String> {
+    state
+        .ssh_client
+        .get_effective_config(&session_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// 同時接続数の上限を設定（nullで無制限）
+#[tauri::command]
+async fn ssh_set_max_connected_sessions(
+    state: tauri::State<'_, AppState>,
+    limit: Option<usize>,
+) -> Result<(), String> {
+    state.ssh_client.set_max_connected_sessions(limit).await;
+    Ok(())
+}
+
+/// 再接続の待機を打ち切ってすぐに再試行
+#[tauri::command]
+async fn ssh_reconnect_now(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+) -> Result<(), String> {
+    state
+        .ssh_client
+        .reconnect_now(&session_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// セッションが接続済みになるまで待機
+#[tauri::command]
+async fn ssh_wait_until_connected(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    timeout_secs: u64,
+) -> Result<(), String> {
+    state
+        .ssh_client
+        .wait_until_connected(&session_id, std::time::Duration::from_secs(timeout_secs))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// パスワード変更要求に新しいパスワードで応答
+#[tauri::command]
+async fn ssh_submit_new_password(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    new_password: String,
+) -> Result<(), String> {
+    state
+        .ssh_client
+        .submit_new_password(&session_id, new_password)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// keyboard-interactive のプロンプトに応答（ssh://auth-prompt-required で通知されたもの）
+#[tauri::command]
+async fn ssh_respond_auth_prompt(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    responses: Vec<String>,
+) -> Result<(), String> {
+    state
+        .ssh_client
+        .respond_auth_prompt(&session_id, responses)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// SSH接続を切断
+#[tauri::command]
+async fn ssh_disconnect(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+) -> Result<(), String> {
+    state
+        .ssh_client
+        .disconnect(&session_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// コマンドを実行
+#[tauri::command]
+async fn ssh_execute_command(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    command: String,
+    options: Option<CommandOptions>,
+) -> Result<CommandResult, String> {
+    state
+        .ssh_client
+        .execute_command(&session_id, &command, &options.unwrap_or_default())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// コマンドを実行し、出力をバイト列のまま（base64で）返す
+#[tauri::command]
+async fn ssh_execute_command_bytes(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    command: String,
+    options: Option<CommandOptions>,
+) -> Result<BytesCommandResult, String> {
+    state
+        .ssh_client
+        .execute_command_bytes(&session_id, &command, &options.unwrap_or_default())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// コマンドを実行し、前回の出力から変わったかを差分とともに返す（終了コードの変化も変更とみなす）
+#[tauri::command]
+async fn ssh_execute_command_diff(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    command: String,
+    previous_output: String,
+    previous_exit_code: Option<u32>,
+    options: Option<CommandOptions>,
+) -> Result<CommandDiffResult, String> {
+    state
+        .ssh_client
+        .execute_command_diff(
+            &session_id,
+            &command,
+            &previous_output,
+            previous_exit_code,
+            &options.unwrap_or_default(),
+        )
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// セッションに登録されたコマンドマクロ一覧を取得
+#[tauri::command]
+async fn macro_list(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+) -> Result<Vec<CommandMacro>, String> {
+    state
+        .ssh_client
+        .list_macros(&session_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// コマンドマクロを登録する（`{name}` は実行時の引数で置き換える）
+#[tauri::command]
+async fn macro_add(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    name: String,
+    command: String,
+) -> Result<CommandMacro, String> {
+    state
+        .ssh_client
+        .add_macro(&session_id, &name, &command)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// コマンドマクロを削除する
+#[tauri::command]
+async fn macro_remove(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    macro_id: String,
+) -> Result<(), String> {
+    state
+        .ssh_client
+        .remove_macro(&session_id, &macro_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// コマンドマクロを実行する
+#[tauri::command]
+async fn macro_run(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    macro_id: String,
+    args: Option<HashMap<String, String>>,
+    options: Option<CommandOptions>,
+) -> Result<CommandResult, String> {
+    state
+        .ssh_client
+        .run_macro(
+            &session_id,
+            &macro_id,
+            &args.unwrap_or_default(),
+            &options.unwrap_or_default(),
+        )
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// コマンドを実行し、所要時間とともに結果を返す
+#[tauri::command]
+async fn ssh_execute_command_timed(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    command: String,
+    options: Option<CommandOptions>,
+) -> Result<TimedCommandResult, String> {
+    state
+        .ssh_client
+        .execute_command_timed(&session_id, &command, &options.unwrap_or_default())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// コマンドを実行し、出力をローカルファイルへ書き出す
+#[tauri::command]
+async fn ssh_execute_command_to_file(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    command: String,
+    local_path: String,
+    options: Option<FileOutputOptions>,
+) -> Result<FileOutputResult, String> {
+    state
+        .ssh_client
+        .execute_command_to_file(&session_id, &command, &local_path, &options.unwrap_or_default())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// リモートにコマンドが存在するかを調べる
+#[tauri::command]
+async fn ssh_remote_command_exists(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    name: String,
+) -> Result<RemoteCommandInfo, String> {
+    state
+        .ssh_client
+        .remote_command_exists(&session_id, &name)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// リモートのログインシェルの環境変数を取得
+#[tauri::command]
+async fn ssh_get_remote_env(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+) -> Result<HashMap<String, String>, String> {
+    state
+        .ssh_client
+        .get_remote_env(&session_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// リモートに一時ファイル・ディレクトリを作成（セッションの切断時に削除される）
+#[tauri::command]
+async fn remote_mktemp(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    template: Option<String>,
+    directory: Option<bool>,
+) -> Result<String, String> {
+    state
+        .ssh_client
+        .remote_mktemp(&session_id, template.as_deref(), directory.unwrap_or(false))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// remote_mktemp で作成した一時ファイル・ディレクトリを削除
+#[tauri::command]
+async fn remote_cleanup_temps(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+) -> Result<usize, String> {
+    state
+        .ssh_client
+        .remote_cleanup_temps(&session_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// リモートのプロセス一覧を取得
+#[tauri::command]
+async fn remote_process_list(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+) -> Result<Vec<ProcessInfo>, String> {
+    state
+        .ssh_client
+        .remote_process_list(&session_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// リモートのプロセスにシグナルを送る（省略時は TERM）
+#[tauri::command]
+async fn remote_kill(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    pid: u32,
+    signal: Option<String>,
+) -> Result<(), String> {
+    state
+        .ssh_client
+        .remote_kill(&session_id, pid, signal.as_deref())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// 強制コマンドが有効かを判定
+#[tauri::command]
+async fn ssh_detect_forced_command(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+) -> Result<bool, String> {
+    state
+        .ssh_client
+        .detect_forced_command(&session_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// リモートの既定のシェルの種類を判定
+#[tauri::command]
+async fn ssh_detect_shell(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+) -> Result<ShellKind, String> {
+    state
+        .ssh_client
+        .detect_shell_kind(&session_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// コマンドをストリーミング実行
+#[tauri::command]
+async fn ssh_execute_command_streaming(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    command: String,
+    confirmed: Option<bool>,
+) -> Result<String, String> {
+    state
+        .ssh_client
+        .execute_command_streaming(&session_id, command, confirmed.unwrap_or(false))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// systemdユニットのログを取得し、`ssh://journal-data` イベントで通知する（`lines` 省略時は100行）
+#[tauri::command]
+async fn journal_tail(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    unit: String,
+    lines: Option<u32>,
+    follow: Option<bool>,
+) -> Result<String, String> {
+    state
+        .ssh_client
+        .journal_tail(&session_id, &unit, lines.unwrap_or(100), follow.unwrap_or(false))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// ストリーミング実行の出力と終了（`Completed`）を受信
+#[tauri::command]
+async fn exec_stream_receive(
+    state: tauri::State<'_, AppState>,
+    exec_id: String,
+) -> Result<Option<ExecStreamData>, String> {
+    state
+        .ssh_client
+        .receive_exec_stream(&exec_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// ストリーミング実行中のコマンドの標準入力へ書き込む
+#[tauri::command]
+async fn exec_stdin_write(
+    state: tauri::State<'_, AppState>,
+    exec_id: String,
+    data: String,
+) -> Result<(), String> {
+    state
+        .ssh_client
+        .write_exec_stdin(&exec_id, data.into_bytes())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// ストリーミング実行中のコマンドの標準入力を閉じる（EOFを送る）
+#[tauri::command]
+async fn exec_stdin_close(
+    state: tauri::State<'_, AppState>,
+    exec_id: String,
+) -> Result<(), String> {
+    state
+        .ssh_client
+        .close_exec_stdin(&exec_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// ストリーミング実行の一覧を取得
+#[tauri::command]
+async fn exec_stream_list(
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<ExecStreamInfo>, String> {
+    Ok(state.ssh_client.list_exec_streams().await)
+}
+
+/// セッションで実行中のコマンド一覧を取得（`exec_stream_cancel` で中断できる）
+#[tauri::command]
+async fn exec_list_running(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+) -> Result<Vec<RunningExecInfo>, String> {
+    Ok(state.ssh_client.list_running_execs(&session_id).await)
+}
+
+/// セッションで実行中の操作をすべて中断（セッションは接続したまま）し、中断した数を返す
+#[tauri::command]
+async fn ssh_abort_all(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+) -> Result<usize, String> {
+    state
+        .ssh_client
+        .abort_all(&session_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// ストリーミング実行をキャンセル
+#[tauri::command]
+async fn exec_stream_cancel(
+    state: tauri::State<'_, AppState>,
+    exec_id: String,
+) -> Result<(), String> {
+    state
+        .ssh_client
+        .cancel_exec_stream(&exec_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// ポート転送を開始（切断後に自動再接続した場合は再確立される）
+#[tauri::command]
+async fn ssh_forward_start(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    spec: ForwardSpec,
+) -> Result<ForwardInfo, String> {
+    state
+        .ssh_client
+        .start_forward(&session_id, spec)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// ポート転送を停止
+#[tauri::command]
+async fn ssh_forward_stop(
+    state: tauri::State<'_, AppState>,
+    forward_id: String,
+) -> Result<(), String> {
+    state
+        .ssh_client
+        .stop_forward(&forward_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// セッションのポート転送一覧を取得
+#[tauri::command]
+async fn ssh_forward_list(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+) -> Result<Vec<ForwardInfo>, String> {
+    Ok(state.ssh_client.list_forwards(&session_id).await)
+}
+
+/// すべてのセッションのポート転送一覧を取得
+#[tauri::command]
+async fn forwards_list_all(state: tauri::State<'_, AppState>) -> Result<Vec<ForwardInfo>, String> {
+    Ok(state.ssh_client.list_all_forwards().await)
+}
+
+/// すべてのセッションのポート転送を停止し、停止した数を返す
+#[tauri::command]
+async fn forwards_cancel_all(state: tauri::State<'_, AppState>) -> Result<usize, String> {
+    Ok(state.ssh_client.stop_all_forwards().await)
+}
+
+/// サブシステム（NETCONF など）のチャネルを開く
+#[tauri::command]
+async fn ssh_open_subsystem(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    name: String,
+) -> Result<String, String> {
+    state
+        .ssh_client
+        .open_subsystem(&session_id, &name)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// サブシステムのチャネルへ書き込む（データはbase64）
+#[tauri::command]
+async fn subsystem_write(
+    state: tauri::State<'_, AppState>,
+    channel_id: String,
+    data: String,
+) -> Result<(), String> {
+    let data = base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .map_err(|e| e.to_string())?;
+    state
+        .ssh_client
+        .subsystem_write(&channel_id, data)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// サブシステムのチャネルから受信（データはbase64、終端でnull）
+#[tauri::command]
+async fn subsystem_read(
+    state: tauri::State<'_, AppState>,
+    channel_id: String,
+) -> Result<Option<String>, String> {
+    let data = state
+        .ssh_client
+        .subsystem_read(&channel_id)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(data.map(|bytes| base64::engine::general_purpose::STANDARD.encode(bytes)))
+}
+
+/// サブシステムのチャネルを閉じる
+#[tauri::command]
+async fn subsystem_close(
+    state: tauri::State<'_, AppState>,
+    channel_id: String,
+) -> Result<(), String> {
+    state
+        .ssh_client
+        .subsystem_close(&channel_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// ローカルとリモートのディレクトリを同期
+#[tauri::command]
+async fn sftp_sync(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    local_dir: String,
+    remote_dir: String,
+    options: SyncOptions,
+) -> Result<SyncSummary, String> {
+    state
+        .ssh_client
+        .sftp_sync(&session_id, &local_dir, &remote_dir, options)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// SFTPでの転送速度を測定（実行中は transfer_list に表示され、sftp_sync_cancel で中断できる）
+#[tauri::command]
+async fn ssh_bandwidth_test(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    bytes: u64,
+) -> Result<BandwidthTestResult, String> {
+    state
+        .ssh_client
+        .bandwidth_test(&session_id, bytes)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// ディレクトリ同期をキャンセル
+#[tauri::command]
+async fn sftp_sync_cancel(
+    state: tauri::State<'_, AppState>,
+    sync_id: String,
+) -> Result<(), String> {
+    state
+        .ssh_client
+        .cancel_sftp_sync(&sync_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// リモートファイルをストリームとして読み込む（データは sftp://stream-data で通知）
+#[tauri::command]
+async fn sftp_stream_read(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    path: String,
+    chunk_size: Option<usize>,
+) -> Result<String, String> {
+    state
+        .ssh_client
+        .sftp_stream_read(&session_id, &path, chunk_size)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// リモートのファイルをサーバー上でコピー（copy-data 拡張がなければ cp を実行）
+#[tauri::command]
+async fn sftp_copy_remote(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    src: String,
+    dst: String,
+) -> Result<RemoteCopyResult, String> {
+    state
+        .ssh_client
+        .sftp_copy_remote(&session_id, &src, &dst)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// 複数のリモートパスを1つの tar アーカイブとしてダウンロード（tar がなければSFTPで1ファイルずつ取得）
+#[tauri::command]
+async fn sftp_download_as_tar(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    remote_paths: Vec<String>,
+    local_tar_path: String,
+) -> Result<TarDownloadResult, String> {
+    state
+        .ssh_client
+        .sftp_download_as_tar(&session_id, &remote_paths, &local_tar_path)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// SFTPサブシステムが使えるかを確認（ファイルブラウザーの有効・無効の判定用）
+#[tauri::command]
+async fn sftp_available(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+) -> Result<bool, String> {
+    state
+        .ssh_client
+        .sftp_available(&session_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// ローカルファイルをリモートへ転送する必要があるかを判定する（理由とともに返す）
+#[tauri::command]
+async fn sftp_needs_transfer(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    local_path: String,
+    remote_path: String,
+) -> Result<TransferCheck, String> {
+    state
+        .ssh_client
+        .sftp_needs_transfer(&session_id, &local_path, &remote_path)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// リモートファイルの指定範囲を読み込む（長さはファイル終端と8MBまでに切り詰める）
+#[tauri::command]
+async fn sftp_read_range(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    path: String,
+    start: u64,
+    len: u64,
+) -> Result<RemoteByteRange, String> {
+    state
+        .ssh_client
+        .sftp_read_range(&session_id, &path, start, len)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// ディレクトリの一覧を順次読み込む（項目は sftp://dir-entries で通知）
+#[tauri::command]
+async fn sftp_list_dir_stream(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    path: String,
+) -> Result<String, String> {
+    state
+        .ssh_client
+        .sftp_list_dir_stream(&session_id, &path)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// ディレクトリ一覧の読み込みをキャンセル
+#[tauri::command]
+async fn sftp_list_dir_cancel(
+    state: tauri::State<'_, AppState>,
+    listing_id: String,
+) -> Result<(), String> {
+    state
+        .ssh_client
+        .cancel_sftp_list_dir(&listing_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// ストリーム読み込みをキャンセル
+#[tauri::command]
+async fn sftp_stream_cancel(
     state: tauri::State<'_, AppState>,
-    session_id: String,
+    stream_id: String,
 ) -> Result<(), String> {
     state
         .ssh_client
-        .connect(&session_id)
+        .cancel_sftp_stream(&stream_id)
         .await
         .map_err(|e| e.to_string())
 }
 
-/// SSH接続を切断
+/// 実行中と完了直後のSFTP転送一覧を取得
 #[tauri::command]
-async fn ssh_disconnect(
+async fn transfer_list(
     state: tauri::State<'_, AppState>,
-    session_id: String,
+) -> Result<Vec<TransferInfo>, String> {
+    Ok(state.ssh_client.list_transfers())
+}
+
+/// 実行中のSFTP転送の速度の上限を変更する（`max_bytes_per_sec` 省略時は制限しない）
+#[tauri::command]
+async fn transfer_set_rate_limit(
+    state: tauri::State<'_, AppState>,
+    transfer_id: String,
+    max_bytes_per_sec: Option<u64>,
 ) -> Result<(), String> {
     state
         .ssh_client
-        .disconnect(&session_id)
+        .set_transfer_rate_limit(&transfer_id, max_bytes_per_sec)
+        .map_err(|e| e.to_string())
+}
+
+/// 終了したSFTP転送を一覧から取り除き、取り除いた数を返す
+#[tauri::command]
+async fn transfer_clear_completed(
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, String> {
+    Ok(state.ssh_client.clear_completed_transfers())
+}
+
+/// 実行中のSFTP転送全体の進捗（合計速度と残りバイト数）を取得
+#[tauri::command]
+async fn transfer_aggregate_progress(
+    state: tauri::State<'_, AppState>,
+) -> Result<TransferAggregate, String> {
+    Ok(state.ssh_client.transfer_aggregate_progress())
+}
+
+/// 公開鍵をリモートの authorized_keys に追加（追加した場合は true）
+#[tauri::command]
+async fn ssh_copy_id(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    public_key: String,
+) -> Result<bool, String> {
+    state
+        .ssh_client
+        .copy_id(&session_id, &public_key)
         .await
         .map_err(|e| e.to_string())
 }
 
-/// コマンドを実行
+/// リモートパスの存在と種類を調べる
 #[tauri::command]
-async fn ssh_execute_command(
+async fn ssh_remote_path_info(
     state: tauri::State<'_, AppState>,
     session_id: String,
-    command: String,
-) -> Result<CommandResult, String> {
+    path: String,
+) -> Result<RemotePathInfo, String> {
+    state
+        .ssh_client
+        .remote_path_info(&session_id, &path)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// グロブに一致するリモートのパスを列挙する
+#[tauri::command]
+async fn sftp_glob(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    pattern: String,
+    max_depth: Option<usize>,
+) -> Result<Vec<RemoteFileEntry>, String> {
+    state
+        .ssh_client
+        .sftp_glob(&session_id, &pattern, max_depth)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// リモートのパスが条件を満たすまで待つ（満たした時点の情報を返す）
+#[tauri::command]
+async fn sftp_wait_for(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    path: String,
+    condition: WaitCondition,
+    timeout_ms: u64,
+    poll_interval_ms: Option<u64>,
+) -> Result<Option<RemoteFileEntry>, String> {
+    state
+        .ssh_client
+        .sftp_wait_for(&session_id, &path, condition, timeout_ms, poll_interval_ms)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// 複数のリモートパスの情報をまとめて取得する（パスごとの失敗は要素のエラーになる）
+#[tauri::command]
+async fn sftp_stat_many(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    paths: Vec<String>,
+) -> Result<Vec<Result<RemoteFileEntry, String>>, String> {
+    state
+        .ssh_client
+        .sftp_stat_many(&session_id, paths)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// セッションの接続情報・通信量・転送・ターミナルなどをまとめて取得
+#[tauri::command]
+async fn ssh_session_snapshot(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+) -> Result<SessionSnapshot, String> {
     state
         .ssh_client
-        .execute_command(&session_id, &command)
+        .get_session_snapshot(&session_id)
         .await
         .map_err(|e| e.to_string())
 }
@@ -88,6 +1071,45 @@ async fn ssh_get_session_info(
         .map_err(|e| e.to_string())
 }
 
+/// セッションの通信テレメトリを取得
+#[tauri::command]
+async fn ssh_get_telemetry(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+) -> Result<SessionTelemetry, String> {
+    state
+        .ssh_client
+        .get_telemetry(&session_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// 鍵再交換を行い、要求した時刻を返す
+#[tauri::command]
+async fn ssh_rekey(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+) -> Result<chrono::DateTime<chrono::Utc>, String> {
+    state
+        .ssh_client
+        .rekey(&session_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// server-sig-algs から判断した RSA 署名アルゴリズムを取得
+#[tauri::command]
+async fn ssh_get_server_extensions(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+) -> Result<ServerExtensions, String> {
+    state
+        .ssh_client
+        .get_server_extensions(&session_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
 /// 全セッション一覧を取得
 #[tauri::command]
 async fn ssh_list_sessions(
@@ -96,6 +1118,19 @@ async fn ssh_list_sessions(
     Ok(state.ssh_client.list_sessions().await)
 }
 
+/// 状態とホスト名で絞り込んだセッション一覧を取得
+#[tauri::command]
+async fn ssh_list_sessions_filtered(
+    state: tauri::State<'_, AppState>,
+    status: Option<ConnectionStatus>,
+    host_contains: Option<String>,
+) -> Result<Vec<SshSessionInfo>, String> {
+    Ok(state
+        .ssh_client
+        .list_sessions_filtered(status, host_contains)
+        .await)
+}
+
 /// セッションを削除
 #[tauri::command]
 async fn ssh_remove_session(
@@ -109,15 +1144,99 @@ async fn ssh_remove_session(
         .map_err(|e| e.to_string())
 }
 
-/// ターミナルセッションを作成
+/// 実行中の操作が終わってからセッションを閉じる（完了時に `ssh://session-drained` を通知）
+///
+/// `timeout_secs`（省略時600秒）を過ぎても終わらない操作は強制的に閉じ、通知に含める。
+#[tauri::command]
+async fn ssh_close_when_idle(
+    state: tauri::State<'_, AppState>,
+    session_id: String,
+    timeout_secs: Option<u64>,
+) -> Result<(), String> {
+    state
+        .ssh_client
+        .close_when_idle(&session_id, timeout_secs.map(std::time::Duration::from_secs))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// セッション設定をファイルへエクスポート
+#[tauri::command]
+async fn ssh_export_sessions(
+    state: tauri::State<'_, AppState>,
+    path: String,
+) -> Result<usize, String> {
+    state
+        .ssh_client
+        .export_sessions(&path)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// セッション設定をファイルからインポート
+#[tauri::command]
+async fn ssh_import_sessions(
+    state: tauri::State<'_, AppState>,
+    path: String,
+) -> Result<ImportSummary, String> {
+    state
+        .ssh_client
+        .import_sessions(&path)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// ターミナルセッションを作成（`terminal_modes` は RFC 4254 のオペコードと値の組）
 #[tauri::command]
 async fn terminal_create_session(
     state: tauri::State<'_, AppState>,
     ssh_session_id: String,
+    terminal_modes: Option<Vec<(u8, u32)>>,
+    forwarding: Option<TerminalForwarding>,
+    nohup_on_detach: Option<bool>,
+    env_profile: Option<String>,
 ) -> Result<String, String> {
     state
         .ssh_client
-        .create_terminal_session(ssh_session_id)
+        .create_terminal_session(
+            ssh_session_id,
+            terminal_modes,
+            forwarding.unwrap_or_default(),
+            nohup_on_detach.unwrap_or(false),
+            env_profile.as_deref(),
+        )
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// 環境変数プロファイル一覧を取得
+#[tauri::command]
+async fn env_profile_list(state: tauri::State<'_, AppState>) -> Result<Vec<EnvProfile>, String> {
+    Ok(state.ssh_client.list_env_profiles().await)
+}
+
+/// 環境変数プロファイルを保存（同名のものは置き換える）
+#[tauri::command]
+async fn env_profile_save(
+    state: tauri::State<'_, AppState>,
+    profile: EnvProfile,
+) -> Result<(), String> {
+    state
+        .ssh_client
+        .save_env_profile(profile)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// 環境変数プロファイルを削除（存在しなければ false）
+#[tauri::command]
+async fn env_profile_remove(
+    state: tauri::State<'_, AppState>,
+    name: String,
+) -> Result<bool, String> {
+    state
+        .ssh_client
+        .remove_env_profile(&name)
         .await
         .map_err(|e| e.to_string())
 }
@@ -136,6 +1255,50 @@ async fn terminal_send_input(
         .map_err(|e| e.to_string())
 }
 
+/// ターミナルセッションに改行付きで1行送信
+#[tauri::command]
+async fn terminal_send_line(
+    state: tauri::State<'_, AppState>,
+    terminal_id: String,
+    text: String,
+) -> Result<(), String> {
+    state
+        .ssh_client
+        .send_terminal_line(&terminal_id, text)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// ターミナルセッションにテキストを分割して貼り付け
+#[tauri::command]
+async fn terminal_paste(
+    state: tauri::State<'_, AppState>,
+    terminal_id: String,
+    text: String,
+    options: Option<PasteOptions>,
+) -> Result<(), String> {
+    state
+        .ssh_client
+        .paste_terminal_input(&terminal_id, text, options.unwrap_or_default())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// クリップボードの内容をOSC 52でターミナルに送る（`selection` 省略時はクリップボード `c`）
+#[tauri::command]
+async fn terminal_send_clipboard(
+    state: tauri::State<'_, AppState>,
+    terminal_id: String,
+    text: String,
+    selection: Option<String>,
+) -> Result<(), String> {
+    state
+        .ssh_client
+        .send_terminal_clipboard(&terminal_id, selection.as_deref().unwrap_or("c"), &text)
+        .await
+        .map_err(|e| e.to_string())
+}
+
 /// ターミナルセッションからの出力を受信
 #[tauri::command]
 async fn terminal_receive_output(
@@ -162,6 +1325,101 @@ async fn terminal_close_session(
         .map_err(|e| e.to_string())
 }
 
+/// シェルに exit を送ってログアウトさせてから終了（タイムアウト時は強制的に閉じ、false を返す）
+#[tauri::command]
+async fn terminal_logout(
+    state: tauri::State<'_, AppState>,
+    terminal_id: String,
+    timeout_ms: Option<u64>,
+) -> Result<bool, String> {
+    state
+        .ssh_client
+        .logout_terminal(&terminal_id, timeout_ms.map(std::time::Duration::from_millis))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// 終了したターミナルを同じ設定で開き直す（作業ディレクトリが分かっていればそこへ移動する）
+#[tauri::command]
+async fn terminal_reconnect(
+    state: tauri::State<'_, AppState>,
+    terminal_id: String,
+) -> Result<String, String> {
+    state
+        .ssh_client
+        .reconnect_terminal(&terminal_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// ターミナルのスクロールバック全体を取得
+#[tauri::command]
+async fn terminal_get_scrollback(
+    state: tauri::State<'_, AppState>,
+    terminal_id: String,
+) -> Result<String, String> {
+    state
+        .ssh_client
+        .get_terminal_scrollback(&terminal_id, None)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// ターミナルのスクロールバックの末尾 n 行を取得
+#[tauri::command]
+async fn terminal_get_scrollback_lines(
+    state: tauri::State<'_, AppState>,
+    terminal_id: String,
+    n: usize,
+) -> Result<String, String> {
+    state
+        .ssh_client
+        .get_terminal_scrollback(&terminal_id, Some(n))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// ターミナルの出力をローカルファイルに書き出し始める
+#[tauri::command]
+async fn terminal_start_log(
+    state: tauri::State<'_, AppState>,
+    terminal_id: String,
+    local_path: String,
+    timestamps: Option<bool>,
+) -> Result<(), String> {
+    state
+        .ssh_client
+        .start_terminal_log(&terminal_id, &local_path, timestamps.unwrap_or(false))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// ターミナルのログの書き出しを止める
+#[tauri::command]
+async fn terminal_stop_log(
+    state: tauri::State<'_, AppState>,
+    terminal_id: String,
+) -> Result<bool, String> {
+    state
+        .ssh_client
+        .stop_terminal_log(&terminal_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// SSHセッションに紐づく全ターミナルを終了
+#[tauri::command]
+async fn terminal_close_all_for_session(
+    state: tauri::State<'_, AppState>,
+    ssh_session_id: String,
+) -> Result<Vec<String>, String> {
+    state
+        .ssh_client
+        .close_all_terminals_for_session(&ssh_session_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
 /// ターミナルセッション情報を取得
 #[tauri::command]
 async fn terminal_get_session(
@@ -198,28 +1456,172 @@ async fn terminal_resize(
         .map_err(|e| e.to_string())
 }
 
+/// ターミナルに環境変数を設定（シェル起動後は拒否されることが多く、その場合も次のターミナルに適用される）
+#[tauri::command]
+async fn terminal_set_env(
+    state: tauri::State<'_, AppState>,
+    terminal_id: String,
+    name: String,
+    value: String,
+) -> Result<bool, String> {
+    state
+        .ssh_client
+        .set_terminal_env(&terminal_id, name, value)
+        .await
+        .map_err(|e| e.to_string())
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .manage(AppState::default())
+        .setup(|app| {
+            // バックエンドイベントをフロントエンドへ転送
+            let handle = app.handle().clone();
+            let mut events = app.state::<AppState>().ssh_client.subscribe_events();
+            tauri::async_runtime::spawn(async move {
+                loop {
+                    match events.recv().await {
+                        Ok(event) => {
+                            let _ = handle.emit(event.name(), &event);
+                        }
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => break,
+                    }
+                }
+            });
+
+            // 環境変数プロファイルと固定したホスト鍵をアプリの設定ディレクトリに保存する
+            if let Ok(dir) = app.path().app_config_dir() {
+                let ssh_client = app.state::<AppState>().ssh_client.clone();
+                tauri::async_runtime::spawn(async move {
+                    let _ = ssh_client.open_env_profiles(&dir.join(ssh::ENV_PROFILES_FILE)).await;
+                    let _ = ssh_client.open_host_trust(&dir.join(ssh::HOST_TRUST_FILE)).await;
+                });
+            }
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             greet,
             ssh_create_connection,
+            ssh_list_local_keys,
+            ssh_list_agent_identities,
+            ssh_generate_keypair,
+            ssh_validate_key,
+            ssh_diagnose_connection,
+            ssh_prewarm,
             ssh_connect,
+            ssh_cancel_connect,
+            ssh_wait_until_connected,
+            ssh_reconnect_now,
+            ssh_set_max_connected_sessions,
+            ssh_set_session_timeout,
+            ssh_get_effective_config,
+            ssh_get_host_key,
+            ssh_list_pinned_host_keys,
+            ssh_remove_pinned_host_key,
+            ssh_submit_new_password,
+            ssh_respond_auth_prompt,
             ssh_disconnect,
             ssh_execute_command,
+            ssh_execute_command_timed,
+            ssh_execute_command_diff,
+            macro_list,
+            macro_add,
+            macro_remove,
+            macro_run,
+            ssh_execute_command_bytes,
+            ssh_execute_command_to_file,
+            ssh_remote_command_exists,
+            ssh_get_remote_env,
+            ssh_detect_forced_command,
+            ssh_detect_shell,
+            remote_mktemp,
+            remote_cleanup_temps,
+            remote_process_list,
+            remote_kill,
+            ssh_execute_command_streaming,
+            journal_tail,
+            exec_stream_receive,
+            exec_stdin_write,
+            exec_stdin_close,
+            exec_stream_list,
+            exec_stream_cancel,
+            exec_list_running,
+            ssh_abort_all,
+            ssh_forward_start,
+            ssh_forward_stop,
+            ssh_forward_list,
+            forwards_list_all,
+            forwards_cancel_all,
+            ssh_open_subsystem,
+            subsystem_write,
+            subsystem_read,
+            subsystem_close,
+            sftp_sync,
+            sftp_sync_cancel,
+            ssh_bandwidth_test,
+            sftp_stream_read,
+            sftp_read_range,
+            sftp_available,
+            sftp_copy_remote,
+            sftp_download_as_tar,
+            sftp_needs_transfer,
+            sftp_stream_cancel,
+            sftp_list_dir_stream,
+            sftp_list_dir_cancel,
+            transfer_list,
+            transfer_aggregate_progress,
+            transfer_clear_completed,
+            transfer_set_rate_limit,
+            ssh_copy_id,
+            ssh_remote_path_info,
+            sftp_glob,
+            sftp_stat_many,
+            sftp_wait_for,
             ssh_get_session_info,
+            ssh_session_snapshot,
+            ssh_get_telemetry,
+            ssh_rekey,
+            ssh_get_server_extensions,
             ssh_list_sessions,
+            ssh_list_sessions_filtered,
             ssh_remove_session,
+            ssh_close_when_idle,
+            ssh_export_sessions,
+            ssh_import_sessions,
             terminal_create_session,
             terminal_send_input,
+            terminal_send_line,
+            terminal_paste,
+            terminal_send_clipboard,
             terminal_receive_output,
             terminal_close_session,
+            env_profile_list,
+            env_profile_save,
+            env_profile_remove,
+            terminal_logout,
+            terminal_reconnect,
+            terminal_get_scrollback,
+            terminal_get_scrollback_lines,
+            terminal_start_log,
+            terminal_stop_log,
+            terminal_close_all_for_session,
             terminal_get_session,
             terminal_list_sessions,
-            terminal_resize
+            terminal_resize,
+            terminal_set_env
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|app, event| {
+            // 終了時にポート転送を止め、ローカルの待ち受けポートを解放する
+            if let tauri::RunEvent::Exit = event {
+                let client = app.state::<AppState>().ssh_client.clone();
+                tauri::async_runtime::block_on(async move {
+                    client.stop_all_forwards().await;
+                });
+            }
+        });
 }
@@ -0,0 +1,158 @@
+use crate::ssh::session::shell_quote;
+use crate::ssh::SshError;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// tar のブロック長
+const BLOCK_SIZE: usize = 512;
+
+/// ustar ヘッダーの名前欄に収まる長さ（超える場合は GNU の長い名前の拡張を使う）
+const MAX_HEADER_NAME: usize = 100;
+
+/// 各パスを親ディレクトリからの名前で格納し、アーカイブを標準出力に書き出す `tar` コマンド
+///
+/// 選択したファイルがアーカイブの最上位に並ぶよう、パスごとに `-C` で親ディレクトリへ移動する。
+/// `-C` は直前の移動先からの相対になるため、相対パスはSFTPと同じくホームディレクトリを起点にする。
+pub fn tar_command(paths: &[String]) -> String {
+    let mut command = String::from("tar -cf -");
+    for path in paths {
+        let (parent, name) = split_archive_path(path);
+        let parent = if parent.starts_with('/') {
+            shell_quote(parent)
+        } else {
+            format!("\"$HOME\"/{}", shell_quote(parent))
+        };
+        command.push_str(&format!(" -C {} {}", parent, shell_quote(name)));
+    }
+    command
+}
+
+/// パスを親ディレクトリとアーカイブ内の名前に分ける
+pub fn split_archive_path(path: &str) -> (&str, &str) {
+    let trimmed = path.trim_end_matches('/');
+    match trimmed.rsplit_once('/') {
+        Some(("", name)) => ("/", name),
+        Some((parent, name)) => (parent, name),
+        None => (".", trimmed),
+    }
+}
+
+/// ustar 形式のアーカイブを書き出す（SFTPで1ファイルずつ取得する場合に使う）
+pub struct TarWriter<W> {
+    writer: W,
+}
+
+impl<W: AsyncWrite + Unpin> TarWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// ディレクトリのエントリを追加する
+    pub async fn append_dir(&mut self, name: &str, mode: u32, mtime: u64) -> Result<(), SshError> {
+        let name = format!("{}/", name.trim_end_matches('/'));
+        self.write_header(&name, mode, mtime, 0, b'5').await
+    }
+
+    /// ファイルを追加し、ファイルから読み込んだバイト数を返す
+    ///
+    /// ヘッダーに記録した `size` と実際の長さが異なる場合（読み込み中に変更された場合）は、
+    /// アーカイブが壊れないよう `size` に切り詰めるか0で埋める。
+    pub async fn append_file<R, F>(
+        &mut self,
+        name: &str,
+        mode: u32,
+        mtime: u64,
+        size: u64,
+        reader: &mut R,
+        mut on_write: F,
+    ) -> Result<u64, SshError>
+    where
+        R: AsyncRead + Unpin,
+        F: FnMut(u64),
+    {
+        self.write_header(name, mode, mtime, size, b'0').await?;
+
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut written = 0u64;
+        while written < size {
+            let limit = buf.len().min((size - written) as usize);
+            let n = reader.read(&mut buf[..limit]).await?;
+            if n == 0 {
+                break;
+            }
+            self.writer.write_all(&buf[..n]).await?;
+            written += n as u64;
+            on_write(n as u64);
+        }
+        let read = written;
+
+        let zeros = [0u8; BLOCK_SIZE];
+        while written < size {
+            let n = zeros.len().min((size - written) as usize);
+            self.writer.write_all(&zeros[..n]).await?;
+            written += n as u64;
+        }
+        self.pad(size).await?;
+        Ok(read)
+    }
+
+    /// 終端の2ブロックを書き出して出力先を返す
+    pub async fn finish(mut self) -> Result<W, SshError> {
+        self.writer.write_all(&[0u8; BLOCK_SIZE * 2]).await?;
+        self.writer.flush().await?;
+        Ok(self.writer)
+    }
+
+    async fn write_header(&mut self, name: &str, mode: u32, mtime: u64, size: u64, kind: u8) -> Result<(), SshError> {
+        if name.len() > MAX_HEADER_NAME {
+            // GNU tar の長い名前の拡張（次のエントリの名前を内容として格納する）
+            let long_name = format!("{}\0", name);
+            let header = header_block("././@LongLink", 0o644, 0, long_name.len() as u64, b'L');
+            self.writer.write_all(&header).await?;
+            self.writer.write_all(long_name.as_bytes()).await?;
+            self.pad(long_name.len() as u64).await?;
+        }
+        let header = header_block(name, mode, mtime, size, kind);
+        self.writer.write_all(&header).await?;
+        Ok(())
+    }
+
+    /// 内容の末尾をブロック境界まで0で埋める
+    async fn pad(&mut self, size: u64) -> Result<(), SshError> {
+        let rest = (size % BLOCK_SIZE as u64) as usize;
+        if rest != 0 {
+            self.writer.write_all(&[0u8; BLOCK_SIZE][..BLOCK_SIZE - rest]).await?;
+        }
+        Ok(())
+    }
+}
+
+/// ustar のヘッダーブロックを作る（名前は100バイトを超える部分を切り捨てる）
+fn header_block(name: &str, mode: u32, mtime: u64, size: u64, kind: u8) -> [u8; BLOCK_SIZE] {
+    let mut header = [0u8; BLOCK_SIZE];
+    let name = name.as_bytes();
+    let name_len = name.len().min(MAX_HEADER_NAME);
+    header[..name_len].copy_from_slice(&name[..name_len]);
+    write_octal(&mut header[100..108], u64::from(mode & 0o7777));
+    write_octal(&mut header[108..116], 0);
+    write_octal(&mut header[116..124], 0);
+    write_octal(&mut header[124..136], size);
+    write_octal(&mut header[136..148], mtime);
+    header[156] = kind;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+
+    // チェックサムは欄を空白として計算する
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+    write_octal(&mut header[148..155], u64::from(checksum));
+    header
+}
+
+/// 欄の長さから終端の NUL を除いた桁数で、0埋めの8進数を書く
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    let text = format!("{:0width$o}", value, width = digits);
+    let text = &text.as_bytes()[text.len().saturating_sub(digits)..];
+    field[..digits].copy_from_slice(text);
+    field[digits] = 0;
+}
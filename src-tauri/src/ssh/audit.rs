@@ -0,0 +1,24 @@
+use crate::ssh::SshError;
+
+/// 実行したコマンドとターミナルへの入力の記録先
+///
+/// 操作のたびに呼び出し元のタスクで同期的に呼ばれるため、時間のかかる書き込みは実装側で
+/// バックグラウンドに回すこと。内容は加工せずに渡すので、コマンド引数に含まれる秘密情報を
+/// 除く必要があれば実装側で行う。
+pub trait CommandAuditor: Send + Sync {
+    /// コマンドを実行した（終了コードが分からない場合は `None`、実行前に拒否・失敗した場合はエラー）
+    fn command_executed(&self, session_id: &str, command: &str, result: Result<Option<u32>, &SshError>);
+
+    /// ターミナルに入力を送った（貼り付けやクリップボードの応答も含む）
+    fn terminal_input(&self, terminal_id: &str, ssh_session_id: &str, data: &[u8]);
+}
+
+/// 何も記録しない標準の実装
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopAuditor;
+
+impl CommandAuditor for NoopAuditor {
+    fn command_executed(&self, _session_id: &str, _command: &str, _result: Result<Option<u32>, &SshError>) {}
+
+    fn terminal_input(&self, _terminal_id: &str, _ssh_session_id: &str, _data: &[u8]) {}
+}
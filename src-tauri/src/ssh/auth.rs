@@ -0,0 +1,319 @@
+use crate::ssh::{EventBus, SshClientHandler, SshError, SshEvent};
+use russh::client::{AuthResult, Handle, KeyboardInteractiveAuthResponse};
+use russh::MethodKind;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{oneshot, Mutex};
+
+/// ユーザー入力を待つ認証フローのデフォルトタイムアウト
+pub const DEFAULT_AUTH_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// keyboard-interactive のやり取りの最大回数
+const MAX_INTERACTIVE_ROUNDS: usize = 10;
+
+/// 認証中にフロントエンドからの応答を待ち合わせる
+#[derive(Clone, Default)]
+pub struct AuthPromptBroker {
+    pending: Arc<Mutex<HashMap<String, oneshot::Sender<Vec<String>>>>>,
+}
+
+impl AuthPromptBroker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 応答待ちを登録し、応答を受け取るレシーバーを返す
+    async fn register(&self, session_id: &str) -> oneshot::Receiver<Vec<String>> {
+        let (sender, receiver) = oneshot::channel();
+        self.pending.lock().await.insert(session_id.to_string(), sender);
+        receiver
+    }
+
+    /// 応答待ちを取り消す
+    async fn unregister(&self, session_id: &str) {
+        self.pending.lock().await.remove(session_id);
+    }
+
+    /// 待機中の認証フローに応答を渡す
+    pub async fn respond(&self, session_id: &str, responses: Vec<String>) -> Result<(), SshError> {
+        let sender = self
+            .pending
+            .lock()
+            .await
+            .remove(session_id)
+            .ok_or_else(|| {
+                SshError::AuthenticationFailed(format!(
+                    "no authentication prompt is pending for session {}",
+                    session_id
+                ))
+            })?;
+
+        sender.send(responses).map_err(|_| {
+            SshError::AuthenticationFailed("authentication flow is no longer waiting".to_string())
+        })
+    }
+}
+
+/// パスワード認証（期限切れによるパスワード変更要求にも対応）
+///
+/// サーバーがパスワード変更を要求する場合、多くの実装（PAM経由のOpenSSHなど）は
+/// keyboard-interactive で現在のパスワードと新しいパスワードを尋ねてくる。
+/// 通常のパスワード認証が拒否され、サーバーが keyboard-interactive を提示している場合のみこのフローを試す。
+/// パスワードを尋ねるプロンプトにはパスワード、新しいパスワードを尋ねるプロンプトには
+/// イベントを発行してフロントエンドから受け取ったパスワードを答える。
+/// それ以外のプロンプトが来た場合や、変更を求められないままパスワードを再度尋ねられた場合は
+/// 元のパスワード認証の失敗を返す。
+pub async fn authenticate_password(
+    connection: &mut Handle<SshClientHandler>,
+    session_id: &str,
+    username: &str,
+    password: &str,
+    events: &EventBus,
+    prompts: &AuthPromptBroker,
+    auth_timeout: Duration,
+) -> Result<AuthResult, SshError> {
+    let result = auth_request(auth_timeout, connection.authenticate_password(username, password)).await?;
+    match &result {
+        AuthResult::Success => return Ok(result),
+        AuthResult::Failure { remaining_methods, .. }
+            if !remaining_methods.contains(&MethodKind::KeyboardInteractive) =>
+        {
+            return Ok(result)
+        }
+        AuthResult::Failure { .. } => {}
+    }
+
+    let mut response = auth_request(
+        auth_timeout,
+        connection.authenticate_keyboard_interactive_start(username, None::<String>),
+    )
+    .await?;
+
+    let mut password_sent = false;
+    let mut password_change = false;
+    let mut new_password: Option<String> = None;
+    for _ in 0..MAX_INTERACTIVE_ROUNDS {
+        let (instructions, prompt_texts) = match response {
+            KeyboardInteractiveAuthResponse::Success => return Ok(AuthResult::Success),
+            KeyboardInteractiveAuthResponse::Failure { .. } => return Ok(result),
+            KeyboardInteractiveAuthResponse::InfoRequest {
+                instructions,
+                prompts: info_prompts,
+                ..
+            } => (
+                instructions,
+                info_prompts
+                    .into_iter()
+                    .map(|p| p.prompt)
+                    .collect::<Vec<String>>(),
+            ),
+        };
+
+        // 想定外のプロンプトにはパスワードを答えない
+        if !prompt_texts.iter().all(|p| is_password_prompt(p)) {
+            return Ok(result);
+        }
+
+        let change_requested = prompt_texts
+            .iter()
+            .any(|p| is_new_password_prompt(p) || is_current_password_prompt(p));
+        if change_requested {
+            password_change = true;
+        } else if password_sent && !password_change {
+            // 変更を求められないまま再度尋ねられた＝パスワードが違う
+            return Ok(result);
+        }
+
+        if new_password.is_none() && prompt_texts.iter().any(|p| is_new_password_prompt(p)) {
+            events.emit(SshEvent::PasswordChangeRequired {
+                session_id: session_id.to_string(),
+                instructions,
+                prompts: prompt_texts.clone(),
+            });
+            new_password = Some(wait_for_new_password(session_id, prompts, auth_timeout).await?);
+        }
+
+        let answers = prompt_texts
+            .iter()
+            .map(|p| match &new_password {
+                Some(new_password) if is_new_password_prompt(p) => new_password.clone(),
+                _ => password.to_string(),
+            })
+            .collect();
+        password_sent |= !prompt_texts.is_empty();
+
+        response = auth_request(
+            auth_timeout,
+            connection.authenticate_keyboard_interactive_respond(answers),
+        )
+        .await?;
+    }
+
+    Err(SshError::AuthenticationFailed(
+        "too many keyboard-interactive rounds".to_string(),
+    ))
+}
+
+/// パスワードとワンタイムパスワードによる keyboard-interactive 認証
+///
+/// パスワードを尋ねるプロンプトにはパスワード、その後のコード・トークン等を尋ねるプロンプトには
+/// OTPを答える（1回のやり取りで両方を尋ねる場合も、2回に分ける場合もある）。
+/// この構造に当てはまらないプロンプトは `AuthPromptRequired` イベントを発行し、フロントエンドからの応答を待つ。
+#[allow(clippy::too_many_arguments)]
+pub async fn authenticate_password_with_otp(
+    connection: &mut Handle<SshClientHandler>,
+    session_id: &str,
+    username: &str,
+    password: &str,
+    otp: &str,
+    events: &EventBus,
+    prompts: &AuthPromptBroker,
+    auth_timeout: Duration,
+) -> Result<AuthResult, SshError> {
+    let mut response = auth_request(
+        auth_timeout,
+        connection.authenticate_keyboard_interactive_start(username, None::<String>),
+    )
+    .await?;
+
+    let mut password_sent = false;
+    let mut otp_sent = false;
+    for _ in 0..MAX_INTERACTIVE_ROUNDS {
+        let (instructions, prompt_texts) = match response {
+            KeyboardInteractiveAuthResponse::Success => return Ok(AuthResult::Success),
+            KeyboardInteractiveAuthResponse::Failure { .. } => {
+                return Err(SshError::AuthenticationFailed(
+                    "keyboard-interactive authentication failed".to_string(),
+                ))
+            }
+            KeyboardInteractiveAuthResponse::InfoRequest {
+                instructions,
+                prompts: info_prompts,
+                ..
+            } => (
+                instructions,
+                info_prompts
+                    .into_iter()
+                    .map(|p| p.prompt)
+                    .collect::<Vec<String>>(),
+            ),
+        };
+
+        // このやり取りのプロンプトがすべて想定どおりの場合のみ自動で答える
+        let mut round_password_sent = password_sent;
+        let mut round_otp_sent = otp_sent;
+        let mut answers = Vec::with_capacity(prompt_texts.len());
+        for prompt in &prompt_texts {
+            if !round_password_sent && is_password_prompt(prompt) {
+                answers.push(password.to_string());
+                round_password_sent = true;
+            } else if round_password_sent && !round_otp_sent && is_otp_prompt(prompt) {
+                answers.push(otp.to_string());
+                round_otp_sent = true;
+            } else {
+                break;
+            }
+        }
+
+        let answers = if answers.len() == prompt_texts.len() {
+            password_sent = round_password_sent;
+            otp_sent = round_otp_sent;
+            answers
+        } else {
+            events.emit(SshEvent::AuthPromptRequired {
+                session_id: session_id.to_string(),
+                instructions,
+                prompts: prompt_texts,
+            });
+            wait_for_responses(session_id, prompts, auth_timeout)
+                .await?
+                .ok_or_else(|| SshError::AuthenticationFailed("authentication prompt was cancelled".to_string()))?
+        };
+
+        response = auth_request(
+            auth_timeout,
+            connection.authenticate_keyboard_interactive_respond(answers),
+        )
+        .await?;
+    }
+
+    Err(SshError::AuthenticationFailed(
+        "too many keyboard-interactive rounds".to_string(),
+    ))
+}
+
+/// サーバーへの認証要求1回の応答を待つ
+///
+/// TCP接続を受け付けたまま応答しないサーバーで接続処理が止まらないよう、`auth_timeout` で打ち切る。
+pub async fn auth_request<T, E, F>(auth_timeout: Duration, request: F) -> Result<T, SshError>
+where
+    E: std::fmt::Display,
+    F: std::future::Future<Output = Result<T, E>>,
+{
+    match tokio::time::timeout(auth_timeout, request).await {
+        Ok(result) => result.map_err(|e| SshError::AuthenticationFailed(e.to_string())),
+        Err(_) => Err(SshError::AuthenticationFailed("authentication timed out".to_string())),
+    }
+}
+
+/// 新しいパスワードの入力を待つ
+async fn wait_for_new_password(
+    session_id: &str,
+    prompts: &AuthPromptBroker,
+    auth_timeout: Duration,
+) -> Result<String, SshError> {
+    match wait_for_responses(session_id, prompts, auth_timeout).await {
+        Ok(Some(mut responses)) if !responses.is_empty() => Ok(responses.swap_remove(0)),
+        Ok(_) => Err(SshError::AuthenticationFailed(
+            "password change was cancelled".to_string(),
+        )),
+        Err(_) => Err(SshError::AuthenticationFailed(
+            "password change timed out".to_string(),
+        )),
+    }
+}
+
+/// フロントエンドからのプロンプトへの応答を待つ（応答待ちが破棄された場合は `None`）
+async fn wait_for_responses(
+    session_id: &str,
+    prompts: &AuthPromptBroker,
+    auth_timeout: Duration,
+) -> Result<Option<Vec<String>>, SshError> {
+    let receiver = prompts.register(session_id).await;
+
+    match tokio::time::timeout(auth_timeout, receiver).await {
+        Ok(responses) => Ok(responses.ok()),
+        Err(_) => {
+            prompts.unregister(session_id).await;
+            Err(SshError::AuthenticationFailed(
+                "authentication prompt timed out".to_string(),
+            ))
+        }
+    }
+}
+
+/// パスワードを尋ねるプロンプトかどうか
+fn is_password_prompt(prompt: &str) -> bool {
+    prompt.to_lowercase().contains("password") && !is_otp_prompt(prompt)
+}
+
+/// ワンタイムパスワード（認証コード・トークン等）を尋ねるプロンプトかどうか
+fn is_otp_prompt(prompt: &str) -> bool {
+    let prompt = prompt.to_lowercase();
+    ["code", "otp", "token", "one-time", "passcode", "verification", "authenticator"]
+        .iter()
+        .any(|keyword| prompt.contains(keyword))
+}
+
+/// 新しいパスワードを尋ねるプロンプトかどうか
+fn is_new_password_prompt(prompt: &str) -> bool {
+    let prompt = prompt.to_lowercase();
+    prompt.contains("password") && (prompt.contains("new") || prompt.contains("retype"))
+}
+
+/// パスワード変更の前に現在のパスワードを尋ねるプロンプトかどうか
+fn is_current_password_prompt(prompt: &str) -> bool {
+    let prompt = prompt.to_lowercase();
+    prompt.contains("password") && (prompt.contains("current") || prompt.contains("old"))
+}
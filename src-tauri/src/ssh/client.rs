@@ -1,18 +1,96 @@
-use crate::ssh::{SshSessionManager, SshConfig, SshSessionInfo, CommandResult, SshError, TerminalManager, TerminalSession, TerminalData};
+use crate::ssh::{CommandAuditor, EnvProfile, EnvProfileStore, SshSessionManager, SshConfig, SshSessionInfo, CommandResult, CommandDiffResult, CommandMacro, EffectiveConfig, HostKeyInfo, PinnedHostKey, ProcessInfo, SshError, TerminalManager, TerminalSession, TerminalSettings, TerminalData, PasteOptions, ImportSummary, ExecStreamManager, ExecStreamInfo, ExecStreamData, EventBus, SshEvent, SftpManager, SyncOptions, SyncSummary, SessionTelemetry, ServerExtensions, CommandOptions, RemotePathInfo, LocalKeyInfo, KeyInfo, AgentIdentity, DEFAULT_READ_BUFFER_SIZE, ExecOrigin, FileOutputOptions, FileOutputResult, SubsystemManager, TimedCommandResult, KeyType, RemoteCommandInfo, ConnectionDiagnostics, ConnectionStatus, DEFAULT_LINE_TERMINATOR, DEFAULT_LOGOUT_TIMEOUT, TerminalForwarding, TransferAggregate, TransferCheck, TransferCheckReason, TransferInfo, TransferState, ForwardInfo, ForwardSpec, BytesCommandResult, RunningExecInfo, BandwidthTestResult, RemoteByteRange, RemoteCopyMethod, RemoteCopyResult, RemoteFileEntry, TarDownloadResult, SessionSnapshot, ShellKind, StdStream, WaitCondition};
+use std::collections::HashMap;
+use tokio::sync::broadcast;
 use std::sync::Arc;
 
+/// `close_when_idle` で実行中の操作の完了を確認する間隔
+const DRAIN_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// `close_when_idle` で実行中の操作の完了を待つ既定の時間（過ぎたら強制的に閉じる）
+pub const DEFAULT_DRAIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(600);
+
 /// SSHクライアントファサード
 pub struct SshClient {
     session_manager: Arc<SshSessionManager>,
     terminal_manager: Arc<TerminalManager>,
+    exec_manager: Arc<ExecStreamManager>,
+    sftp_manager: Arc<SftpManager>,
+    subsystem_manager: Arc<SubsystemManager>,
+    env_profiles: Arc<EnvProfileStore>,
+    events: EventBus,
+    /// 自動再接続の後にターミナルを開き直すタスクを起動した
+    terminal_reopener_started: std::sync::atomic::AtomicBool,
+}
+
+/// マネージャーを作る前に設定を指定して `SshClient` を作成する
+#[derive(Default)]
+pub struct SshClientBuilder {
+    auditor: Option<Arc<dyn CommandAuditor>>,
+    russh_config: Option<russh::client::Config>,
+}
+
+impl SshClientBuilder {
+    /// 実行したコマンドとターミナルへの入力の記録先を指定する
+    pub fn with_auditor(mut self, auditor: Arc<dyn CommandAuditor>) -> Self {
+        self.auditor = Some(auditor);
+        self
+    }
+
+    /// russhの設定を直接指定する
+    ///
+    /// 優先アルゴリズムや制限値などをそのまま使い、`SshConfig` から導出する設定
+    /// （タイムアウト・バッファサイズ）は無視される。
+    pub fn with_russh_config(mut self, config: russh::client::Config) -> Self {
+        self.russh_config = Some(config);
+        self
+    }
+
+    pub fn build(self) -> SshClient {
+        let events = EventBus::new();
+        let mut session_manager = SshSessionManager::new(events.clone());
+        let mut terminal_manager = TerminalManager::new(events.clone());
+        if let Some(auditor) = self.auditor {
+            session_manager = session_manager.with_auditor(auditor.clone());
+            terminal_manager = terminal_manager.with_auditor(auditor);
+        }
+        if let Some(config) = self.russh_config {
+            session_manager = session_manager.with_russh_config(config);
+        }
+        SshClient {
+            session_manager: Arc::new(session_manager),
+            terminal_manager: Arc::new(terminal_manager),
+            exec_manager: Arc::new(ExecStreamManager::new()),
+            sftp_manager: Arc::new(SftpManager::new(events.clone())),
+            subsystem_manager: Arc::new(SubsystemManager::new()),
+            env_profiles: Arc::new(EnvProfileStore::new()),
+            events,
+            terminal_reopener_started: std::sync::atomic::AtomicBool::new(false),
+        }
+    }
 }
 
 impl SshClient {
     pub fn new() -> Self {
-        Self {
-            session_manager: Arc::new(SshSessionManager::new()),
-            terminal_manager: Arc::new(TerminalManager::new()),
-        }
+        Self::builder().build()
+    }
+
+    /// 設定を指定して作成する
+    pub fn builder() -> SshClientBuilder {
+        SshClientBuilder::default()
+    }
+
+    /// セッションが使うrusshの設定を差し替える（次回の接続から適用）
+    pub async fn set_russh_config(
+        &self,
+        session_id: &str,
+        config: Option<russh::client::Config>,
+    ) -> Result<(), SshError> {
+        self.session_manager.set_russh_config(session_id, config).await
+    }
+
+    /// バックエンドイベントを購読
+    pub fn subscribe_events(&self) -> broadcast::Receiver<SshEvent> {
+        self.events.subscribe()
     }
 
     /// セッションマネージャーの参照を取得
@@ -20,6 +98,45 @@ impl SshClient {
         self.session_manager.clone()
     }
 
+    /// ローカルの秘密鍵ファイルを列挙
+    pub fn list_local_keys(&self) -> Result<Vec<LocalKeyInfo>, SshError> {
+        crate::ssh::keys::list_local_keys()
+    }
+
+    /// SSHエージェントの鍵を列挙
+    pub async fn list_agent_identities(&self) -> Result<Vec<AgentIdentity>, SshError> {
+        crate::ssh::keys::list_agent_identities().await
+    }
+
+    /// 鍵ペアを生成し、公開鍵の文字列を返す
+    pub async fn generate_keypair(
+        &self,
+        path: String,
+        key_type: KeyType,
+        passphrase: Option<String>,
+        force: bool,
+    ) -> Result<String, SshError> {
+        // RSA鍵の生成は時間がかかるため非同期ランタイムをブロックしない
+        tokio::task::spawn_blocking(move || {
+            crate::ssh::keys::generate_keypair(&path, &key_type, passphrase.as_deref(), force)
+        })
+        .await
+        .map_err(|e| SshError::ConfigError(e.to_string()))?
+    }
+
+    /// 秘密鍵（パスまたはPEMの内容）を復号できるか確かめ、鍵の情報を返す
+    pub async fn validate_key(&self, path_or_pem: String, passphrase: Option<String>) -> Result<KeyInfo, SshError> {
+        // 暗号化された鍵の復号（bcrypt_pbkdf）は時間がかかるため非同期ランタイムをブロックしない
+        tokio::task::spawn_blocking(move || crate::ssh::keys::validate_key(&path_or_pem, passphrase.as_deref()))
+            .await
+            .map_err(|e| SshError::ConfigError(e.to_string()))?
+    }
+
+    /// 接続を段階ごとに試して問題のある段階を特定（セッションは作成しない）
+    pub async fn diagnose_connection(&self, config: &SshConfig) -> ConnectionDiagnostics {
+        crate::ssh::diagnostics::diagnose_connection(config).await
+    }
+
     /// 新しいSSH接続を作成
     pub async fn create_connection(&self, config: SshConfig) -> Result<String, SshError> {
         self.session_manager.create_session(config).await
@@ -30,8 +147,88 @@ impl SshClient {
         self.session_manager.connect(session_id).await
     }
 
+    /// 接続を事前に確立しておき、確立できた数を返す（`idle_ttl_secs` の間使われなければ切断）
+    pub async fn prewarm(
+        &self,
+        config: SshConfig,
+        count: usize,
+        idle_ttl_secs: Option<u64>,
+    ) -> Result<usize, SshError> {
+        self.session_manager
+            .prewarm(config, count, idle_ttl_secs.map(std::time::Duration::from_secs))
+            .await
+    }
+
+    /// 進行中の接続を中断
+    pub async fn cancel_connect(&self, session_id: &str) -> Result<(), SshError> {
+        self.session_manager.cancel_connect(session_id).await
+    }
+
+    /// セッションのタイムアウトを実行中に変更
+    pub async fn set_session_timeout(&self, session_id: &str, timeout: Option<std::time::Duration>) -> Result<(), SshError> {
+        self.session_manager.set_session_timeout(session_id, timeout).await
+    }
+
+    /// セッションの直近の接続でサーバーが提示したホスト鍵を取得
+    pub async fn get_host_key(&self, session_id: &str) -> Result<HostKeyInfo, SshError> {
+        self.session_manager.get_host_key(session_id).await
+    }
+
+    /// セッションを作らずにホストへ接続し、ホスト鍵を取得する
+    pub async fn scan_host_key(&self, host: &str, port: u16) -> Result<HostKeyInfo, SshError> {
+        crate::ssh::keys::scan_host_key(host, port).await
+    }
+
+    /// 固定したホスト鍵の保存ファイルを読み込み、以降の変更の保存先にする
+    pub async fn open_host_trust(&self, path: &std::path::Path) -> Result<(), SshError> {
+        self.session_manager.host_trust().open(path).await
+    }
+
+    /// `trust_on_first_use` で固定したホスト鍵の一覧を取得
+    pub async fn list_pinned_host_keys(&self) -> Vec<PinnedHostKey> {
+        self.session_manager.host_trust().list().await
+    }
+
+    /// ホスト鍵の固定を解除（固定されていなければ false）
+    pub async fn remove_pinned_host_key(&self, host: &str, port: u16) -> Result<bool, SshError> {
+        self.session_manager.host_trust().remove(host, port).await
+    }
+
+    /// 既定値と上書きを反映した、実際に使われる設定値を取得
+    pub async fn get_effective_config(&self, session_id: &str) -> Result<EffectiveConfig, SshError> {
+        self.session_manager.get_effective_config(session_id).await
+    }
+
+    /// 同時接続数の上限を設定
+    pub async fn set_max_connected_sessions(&self, limit: Option<usize>) {
+        self.session_manager.set_max_connected_sessions(limit).await
+    }
+
+    /// 再接続の待機を打ち切ってすぐに再試行
+    pub async fn reconnect_now(&self, session_id: &str) -> Result<(), SshError> {
+        self.session_manager.reconnect_now(session_id).await
+    }
+
+    /// セッションが接続済みになるまで待機
+    pub async fn wait_until_connected(&self, session_id: &str, timeout: std::time::Duration) -> Result<(), SshError> {
+        self.session_manager.wait_until_connected(session_id, timeout).await
+    }
+
+    /// パスワード変更要求に新しいパスワードで応答
+    pub async fn submit_new_password(&self, session_id: &str, new_password: String) -> Result<(), SshError> {
+        self.session_manager
+            .respond_auth_prompt(session_id, vec![new_password])
+            .await
+    }
+
+    /// keyboard-interactive のプロンプトに応答（`prompts` の順に答える）
+    pub async fn respond_auth_prompt(&self, session_id: &str, responses: Vec<String>) -> Result<(), SshError> {
+        self.session_manager.respond_auth_prompt(session_id, responses).await
+    }
+
     /// SSH接続を切断
     pub async fn disconnect(&self, session_id: &str) -> Result<(), SshError> {
+        self.sftp_manager.forget_session(session_id).await;
         self.session_manager.disconnect(session_id).await
     }
 
@@ -40,8 +237,617 @@ impl SshClient {
         &self,
         session_id: &str,
         command: &str,
+        options: &CommandOptions,
+    ) -> Result<CommandResult, SshError> {
+        let options = self.resolve_env_profile(options).await?;
+        self.session_manager.execute_command(session_id, command, &options).await
+    }
+
+    /// コマンドを実行し、受信した出力を `on_chunk` にその都度渡す（ライブラリとして使う場合向け）
+    pub async fn execute_command_with_chunks<F>(
+        &self,
+        session_id: &str,
+        command: &str,
+        options: &CommandOptions,
+        on_chunk: F,
+    ) -> Result<CommandResult, SshError>
+    where
+        F: FnMut(StdStream, &[u8]) + Send,
+    {
+        let options = self.resolve_env_profile(options).await?;
+        self.session_manager
+            .execute_command_with_chunks(session_id, command, &options, on_chunk)
+            .await
+    }
+
+    /// コマンドを実行し、出力をバイト列のまま（base64で）返す
+    pub async fn execute_command_bytes(
+        &self,
+        session_id: &str,
+        command: &str,
+        options: &CommandOptions,
+    ) -> Result<BytesCommandResult, SshError> {
+        let options = self.resolve_env_profile(options).await?;
+        self.session_manager
+            .execute_command_bytes(session_id, command, &options)
+            .await
+    }
+
+    /// コマンドを実行し、前回の出力から変わったかを差分とともに返す
+    pub async fn execute_command_diff(
+        &self,
+        session_id: &str,
+        command: &str,
+        previous_output: &str,
+        previous_exit_code: Option<u32>,
+        options: &CommandOptions,
+    ) -> Result<CommandDiffResult, SshError> {
+        let options = self.resolve_env_profile(options).await?;
+        self.session_manager
+            .execute_command_diff(session_id, command, previous_output, previous_exit_code, &options)
+            .await
+    }
+
+    /// セッションに登録されたコマンドマクロ一覧を取得
+    pub async fn list_macros(&self, session_id: &str) -> Result<Vec<CommandMacro>, SshError> {
+        self.session_manager.list_macros(session_id).await
+    }
+
+    /// コマンドマクロを登録する
+    pub async fn add_macro(&self, session_id: &str, name: &str, command: &str) -> Result<CommandMacro, SshError> {
+        self.session_manager.add_macro(session_id, name, command).await
+    }
+
+    /// コマンドマクロを削除する
+    pub async fn remove_macro(&self, session_id: &str, macro_id: &str) -> Result<(), SshError> {
+        self.session_manager.remove_macro(session_id, macro_id).await
+    }
+
+    /// 引数を埋め込んだコマンドマクロを実行する
+    pub async fn run_macro(
+        &self,
+        session_id: &str,
+        macro_id: &str,
+        args: &HashMap<String, String>,
+        options: &CommandOptions,
     ) -> Result<CommandResult, SshError> {
-        self.session_manager.execute_command(session_id, command).await
+        let options = self.resolve_env_profile(options).await?;
+        self.session_manager.run_macro(session_id, macro_id, args, &options).await
+    }
+
+    /// コマンドを実行し、所要時間とともに結果を返す
+    pub async fn execute_command_timed(
+        &self,
+        session_id: &str,
+        command: &str,
+        options: &CommandOptions,
+    ) -> Result<TimedCommandResult, SshError> {
+        let options = self.resolve_env_profile(options).await?;
+        self.session_manager
+            .execute_command_timed(session_id, command, &options)
+            .await
+    }
+
+    /// `env_profile` で指定されたプロファイルの環境変数を `env` に展開する（`env` の値が優先）
+    async fn resolve_env_profile(&self, options: &CommandOptions) -> Result<CommandOptions, SshError> {
+        let mut options = options.clone();
+        if let Some(name) = options.env_profile.take() {
+            let mut env = self.env_profiles.get(&name).await?;
+            env.append(&mut options.env);
+            options.env = env;
+        }
+        Ok(options)
+    }
+
+    /// 環境変数プロファイルの保存ファイルを読み込み、以降の変更の保存先にする
+    pub async fn open_env_profiles(&self, path: &std::path::Path) -> Result<(), SshError> {
+        self.env_profiles.open(path).await
+    }
+
+    /// 環境変数プロファイル一覧を取得
+    pub async fn list_env_profiles(&self) -> Vec<EnvProfile> {
+        self.env_profiles.list().await
+    }
+
+    /// 環境変数プロファイルを保存（同名のものは置き換える）
+    pub async fn save_env_profile(&self, profile: EnvProfile) -> Result<(), SshError> {
+        self.env_profiles.save(profile).await
+    }
+
+    /// 環境変数プロファイルを削除（存在しなければ false）
+    pub async fn remove_env_profile(&self, name: &str) -> Result<bool, SshError> {
+        self.env_profiles.remove(name).await
+    }
+
+    /// コマンドを実行し、出力をローカルファイルへ書き出す
+    pub async fn execute_command_to_file(
+        &self,
+        session_id: &str,
+        command: &str,
+        local_path: &str,
+        options: &FileOutputOptions,
+    ) -> Result<FileOutputResult, SshError> {
+        self.session_manager
+            .execute_command_to_file(session_id, command, local_path, options)
+            .await
+    }
+
+    /// リモートにコマンドが存在するかを調べる
+    pub async fn remote_command_exists(&self, session_id: &str, name: &str) -> Result<RemoteCommandInfo, SshError> {
+        self.session_manager.remote_command_exists(session_id, name).await
+    }
+
+    /// リモートのログインシェルの環境変数を取得
+    pub async fn get_remote_env(&self, session_id: &str) -> Result<HashMap<String, String>, SshError> {
+        self.session_manager.get_remote_env(session_id).await
+    }
+
+    /// 強制コマンドが有効かを判定
+    pub async fn detect_forced_command(&self, session_id: &str) -> Result<bool, SshError> {
+        self.session_manager.detect_forced_command(session_id).await
+    }
+
+    /// リモートに一時ファイル・ディレクトリを作成し、そのパスを返す（切断時に削除される）
+    pub async fn remote_mktemp(&self, session_id: &str, template: Option<&str>, directory: bool) -> Result<String, SshError> {
+        self.session_manager.remote_mktemp(session_id, template, directory).await
+    }
+
+    /// `remote_mktemp` で作成した一時ファイル・ディレクトリを削除し、削除した数を返す
+    pub async fn remote_cleanup_temps(&self, session_id: &str) -> Result<usize, SshError> {
+        self.session_manager.remote_cleanup_temps(session_id).await
+    }
+
+    /// リモートのプロセス一覧を取得
+    pub async fn remote_process_list(&self, session_id: &str) -> Result<Vec<ProcessInfo>, SshError> {
+        self.session_manager.remote_process_list(session_id).await
+    }
+
+    /// リモートのプロセスにシグナルを送る（省略時は `TERM`）
+    pub async fn remote_kill(&self, session_id: &str, pid: u32, signal: Option<&str>) -> Result<(), SshError> {
+        self.session_manager.remote_kill(session_id, pid, signal).await
+    }
+
+    /// リモートの既定のシェルの種類を判定
+    pub async fn detect_shell_kind(&self, session_id: &str) -> Result<ShellKind, SshError> {
+        self.session_manager.detect_shell_kind(session_id).await
+    }
+
+    /// コマンドをストリーミング実行し、ストリームIDを返す
+    ///
+    /// `confirmed` はセーフモードで危険と判定されるコマンドも実行する場合に指定する。
+    pub async fn execute_command_streaming(
+        &self,
+        session_id: &str,
+        command: String,
+        confirmed: bool,
+    ) -> Result<String, SshError> {
+        self.start_exec_stream(session_id, command, ExecOrigin::User { confirmed }).await
+    }
+
+    async fn start_exec_stream(&self, session_id: &str, command: String, origin: ExecOrigin) -> Result<String, SshError> {
+        let result = async {
+            let connection = self.session_manager.get_connection(session_id).await?;
+            let ticket = self.session_manager.begin_exec(session_id, &command, origin, true).await?;
+            self.exec_manager
+                .start(session_id.to_string(), connection, command.clone(), ticket)
+                .await
+        }
+        .await;
+        // ストリーミング実行は開始した時点で記録する（終了コードは記録しない）
+        self.session_manager
+            .audit_command(session_id, &command, result.as_ref().map(|_| None));
+        result
+    }
+
+    /// ユニットのjournalを取得し、エントリを `JournalData` イベントで順次通知する
+    ///
+    /// `follow` を指定すると新しいエントリを待ち続ける。返したIDは `cancel_exec_stream` で止められる。
+    pub async fn journal_tail(
+        &self,
+        session_id: &str,
+        unit: &str,
+        lines: u32,
+        follow: bool,
+    ) -> Result<String, SshError> {
+        if !self.session_manager.remote_command_exists(session_id, "journalctl").await?.exists {
+            return Err(SshError::CommandFailed(
+                "journalctl is not available on the remote host".to_string(),
+            ));
+        }
+
+        let journal_id = self
+            .start_exec_stream(session_id, crate::ssh::journal::journal_command(unit, lines, follow), ExecOrigin::Internal)
+            .await?;
+        tokio::spawn(crate::ssh::journal::relay_journal(
+            self.exec_manager.clone(),
+            journal_id.clone(),
+            session_id.to_string(),
+            self.events.clone(),
+        ));
+        Ok(journal_id)
+    }
+
+    /// ストリーミング実行の出力と終了（`Completed`）を受信
+    pub async fn receive_exec_stream(&self, exec_id: &str) -> Result<Option<ExecStreamData>, SshError> {
+        self.exec_manager.receive(exec_id).await
+    }
+
+    /// ストリーミング実行中のコマンドの標準入力へ書き込む
+    pub async fn write_exec_stdin(&self, exec_id: &str, data: Vec<u8>) -> Result<(), SshError> {
+        self.exec_manager.write_stdin(exec_id, data).await
+    }
+
+    /// ストリーミング実行中のコマンドの標準入力を閉じる
+    pub async fn close_exec_stdin(&self, exec_id: &str) -> Result<(), SshError> {
+        self.exec_manager.close_stdin(exec_id).await
+    }
+
+    /// ストリーミング実行の一覧を取得
+    pub async fn list_exec_streams(&self) -> Vec<ExecStreamInfo> {
+        self.exec_manager.list().await
+    }
+
+    /// ストリーミング実行または完了待ちの実行をキャンセル
+    pub async fn cancel_exec_stream(&self, exec_id: &str) -> Result<(), SshError> {
+        match self.exec_manager.cancel(exec_id).await {
+            Err(SshError::SessionNotFound(_)) => self.session_manager.cancel_command(exec_id).await,
+            result => result,
+        }
+    }
+
+    /// セッションで実行中のコマンド（完了待ちの実行とストリーミング実行）を取得
+    pub async fn list_running_execs(&self, session_id: &str) -> Vec<RunningExecInfo> {
+        self.session_manager.list_running_commands(session_id).await
+    }
+
+    /// セッションで実行中のコマンド・ストリーミング実行・転送をすべて中断する
+    ///
+    /// セッション自体は切断しないため、中断後もそのまま使える。中断した操作の数を返す。
+    pub async fn abort_all(&self, session_id: &str) -> Result<usize, SshError> {
+        self.session_manager.get_session_info(session_id).await?;
+
+        // ストリーミング実行も実行中のコマンドとして登録されているため、数えるのは登録側だけにする
+        // （ストリーム側はキャンセル済みのストリームを一覧から外すために呼ぶ）
+        let commands = self.session_manager.cancel_commands_for_session(session_id).await;
+        self.exec_manager.cancel_all_for_session(session_id).await;
+        let transfers = self.sftp_manager.cancel_all_for_session(session_id).await;
+        Ok(commands + transfers)
+    }
+
+    /// ポート転送を開始
+    pub async fn start_forward(&self, session_id: &str, spec: ForwardSpec) -> Result<ForwardInfo, SshError> {
+        self.session_manager.start_forward(session_id, spec).await
+    }
+
+    /// ポート転送を停止
+    pub async fn stop_forward(&self, forward_id: &str) -> Result<(), SshError> {
+        self.session_manager.forwards().stop(forward_id).await
+    }
+
+    /// セッションのポート転送一覧を取得
+    pub async fn list_forwards(&self, session_id: &str) -> Vec<ForwardInfo> {
+        self.session_manager.forwards().list(session_id).await
+    }
+
+    /// すべてのセッションのポート転送一覧を取得
+    pub async fn list_all_forwards(&self) -> Vec<ForwardInfo> {
+        self.session_manager.forwards().list_all().await
+    }
+
+    /// すべてのセッションのポート転送を停止し、停止した数を返す（ローカルの待ち受けも閉じる）
+    pub async fn stop_all_forwards(&self) -> usize {
+        self.session_manager.forwards().stop_all().await
+    }
+
+    /// サブシステム（NETCONF など）のチャネルを開く
+    pub async fn open_subsystem(&self, session_id: &str, name: &str) -> Result<String, SshError> {
+        let connection = self.session_manager.get_connection(session_id).await?;
+        self.subsystem_manager.open(&connection, name).await
+    }
+
+    /// サブシステムのチャネルへ書き込む
+    pub async fn subsystem_write(&self, channel_id: &str, data: Vec<u8>) -> Result<(), SshError> {
+        self.subsystem_manager.write(channel_id, data).await
+    }
+
+    /// サブシステムのチャネルから受信
+    pub async fn subsystem_read(&self, channel_id: &str) -> Result<Option<Vec<u8>>, SshError> {
+        self.subsystem_manager.read(channel_id).await
+    }
+
+    /// サブシステムのチャネルを閉じる
+    pub async fn subsystem_close(&self, channel_id: &str) -> Result<(), SshError> {
+        self.subsystem_manager.close(channel_id).await
+    }
+
+    /// ローカルとリモートのディレクトリを同期
+    pub async fn sftp_sync(
+        &self,
+        session_id: &str,
+        local_dir: &str,
+        remote_dir: &str,
+        options: SyncOptions,
+    ) -> Result<SyncSummary, SshError> {
+        let session_info = self.session_manager.get_session_info(session_id).await?;
+        let connection = self.session_manager.get_connection(session_id).await?;
+        self.apply_transfer_retention(session_id, &session_info);
+        let chunk_size = options
+            .chunk_size
+            .or(session_info.config.read_buffer_size)
+            .unwrap_or(DEFAULT_READ_BUFFER_SIZE);
+        self.sftp_manager
+            .sync(session_id, &connection, local_dir, remote_dir, options, chunk_size)
+            .await
+    }
+
+    /// SFTPでの転送速度を測定（`bytes` は最大1GBに丸める）
+    pub async fn bandwidth_test(&self, session_id: &str, bytes: u64) -> Result<BandwidthTestResult, SshError> {
+        let session_info = self.session_manager.get_session_info(session_id).await?;
+        let connection = self.session_manager.get_connection(session_id).await?;
+        self.apply_transfer_retention(session_id, &session_info);
+        let chunk_size = session_info
+            .config
+            .read_buffer_size
+            .unwrap_or(DEFAULT_READ_BUFFER_SIZE);
+        self.sftp_manager
+            .bandwidth_test(session_id, &connection, bytes, chunk_size)
+            .await
+    }
+
+    /// ディレクトリ同期をキャンセル
+    pub async fn cancel_sftp_sync(&self, sync_id: &str) -> Result<(), SshError> {
+        self.sftp_manager.cancel_sync(sync_id).await
+    }
+
+    /// リモートファイルをストリームとして読み込む
+    ///
+    /// `chunk_size` を省略した場合はセッションの読み込みバッファサイズで読み込む。
+    pub async fn sftp_stream_read(
+        &self,
+        session_id: &str,
+        path: &str,
+        chunk_size: Option<usize>,
+    ) -> Result<String, SshError> {
+        let session_info = self.session_manager.get_session_info(session_id).await?;
+        let connection = self.session_manager.get_connection(session_id).await?;
+        self.apply_transfer_retention(session_id, &session_info);
+        let chunk_size = chunk_size
+            .or(session_info.config.read_buffer_size)
+            .unwrap_or(DEFAULT_READ_BUFFER_SIZE);
+        self.sftp_manager
+            .stream_read(session_id, &connection, path, chunk_size)
+            .await
+    }
+
+    /// ストリーム読み込みをキャンセル
+    pub async fn cancel_sftp_stream(&self, stream_id: &str) -> Result<(), SshError> {
+        self.sftp_manager.cancel_stream(stream_id).await
+    }
+
+    /// ディレクトリの一覧を順次読み込み、一覧IDを返す（項目は `SftpDirEntries` イベントで通知）
+    pub async fn sftp_list_dir_stream(&self, session_id: &str, path: &str) -> Result<String, SshError> {
+        let connection = self.session_manager.get_connection(session_id).await?;
+        self.sftp_manager.list_dir_stream(session_id, &connection, path).await
+    }
+
+    /// ディレクトリ一覧の読み込みをキャンセル
+    pub async fn cancel_sftp_list_dir(&self, listing_id: &str) -> Result<(), SshError> {
+        self.sftp_manager.cancel_stream(listing_id).await
+    }
+
+    /// 実行中と完了直後のSFTP転送一覧を取得
+    pub fn list_transfers(&self) -> Vec<TransferInfo> {
+        self.sftp_manager.transfers().list()
+    }
+
+    /// 実行中のSFTP転送の速度の上限を変更する（`None` で制限しない）
+    pub fn set_transfer_rate_limit(&self, transfer_id: &str, max_bytes_per_sec: Option<u64>) -> Result<(), SshError> {
+        self.sftp_manager.set_rate_limit(transfer_id, max_bytes_per_sec)
+    }
+
+    /// 終了したSFTP転送を一覧から取り除く
+    pub fn clear_completed_transfers(&self) -> usize {
+        self.sftp_manager.transfers().clear_completed()
+    }
+
+    /// セッション設定の転送記録の保持期間を適用
+    fn apply_transfer_retention(&self, session_id: &str, session_info: &SshSessionInfo) {
+        let retention = session_info
+            .config
+            .transfer_retention_secs
+            .map(std::time::Duration::from_secs);
+        self.sftp_manager.transfers().set_retention(session_id, retention);
+    }
+
+    /// 実行中のSFTP転送全体の進捗を取得
+    pub fn transfer_aggregate_progress(&self) -> TransferAggregate {
+        self.sftp_manager.transfers().aggregate()
+    }
+
+    /// 公開鍵をリモートの authorized_keys に追加（追加した場合は true）
+    pub async fn copy_id(&self, session_id: &str, public_key: &str) -> Result<bool, SshError> {
+        let connection = self.session_manager.get_connection(session_id).await?;
+        self.sftp_manager.copy_id(session_id, &connection, public_key).await
+    }
+
+    /// SFTPサブシステムが使えるかを確認する（確認結果はセッションごとに保持）
+    pub async fn sftp_available(&self, session_id: &str) -> Result<bool, SshError> {
+        let connection = self.session_manager.get_connection(session_id).await?;
+        Ok(self.sftp_manager.available(session_id, &connection).await)
+    }
+
+    /// リモートのファイルをローカルを経由せずにコピーする
+    ///
+    /// サーバーが `copy-data@openssh.com` 拡張を提供していればSFTPでコピーし（進捗は転送一覧に表示）、
+    /// 提供していなければ `cp` を実行する。
+    pub async fn sftp_copy_remote(&self, session_id: &str, src: &str, dst: &str) -> Result<RemoteCopyResult, SshError> {
+        let connection = self.session_manager.get_connection(session_id).await?;
+        if let Some(bytes) = self.sftp_manager.copy_remote(session_id, &connection, src, dst).await? {
+            return Ok(RemoteCopyResult {
+                method: RemoteCopyMethod::CopyData,
+                bytes: Some(bytes),
+            });
+        }
+
+        let command = format!(
+            "cp -- {} {}",
+            crate::ssh::session::shell_quote(src),
+            crate::ssh::session::shell_quote(dst)
+        );
+        let (result, _) = self
+            .session_manager
+            .execute_internal(session_id, &command, &CommandOptions::default())
+            .await?;
+        if result.exit_code != Some(0) {
+            return Err(SshError::CommandFailed(format!(
+                "cp exited with {:?}: {}",
+                result.exit_code,
+                result.stderr.trim()
+            )));
+        }
+        Ok(RemoteCopyResult {
+            method: RemoteCopyMethod::Exec,
+            bytes: None,
+        })
+    }
+
+    /// 複数のリモートパスを1つの tar アーカイブとしてダウンロードする
+    ///
+    /// リモートに `tar` があれば1本のストリームで受信し、なければSFTPで1ファイルずつ取得する。
+    pub async fn sftp_download_as_tar(
+        &self,
+        session_id: &str,
+        remote_paths: &[String],
+        local_tar_path: &str,
+    ) -> Result<TarDownloadResult, SshError> {
+        let connection = self.session_manager.get_connection(session_id).await?;
+        let use_tar = self
+            .session_manager
+            .remote_command_exists(session_id, "tar")
+            .await
+            .is_ok_and(|info| info.exists);
+        self.sftp_manager
+            .download_as_tar(session_id, &connection, remote_paths, local_tar_path, use_tar)
+            .await
+    }
+
+    /// リモートパスの存在と種類を調べる
+    pub async fn remote_path_info(&self, session_id: &str, path: &str) -> Result<RemotePathInfo, SshError> {
+        let connection = self.session_manager.get_connection(session_id).await?;
+        self.sftp_manager.path_info(session_id, &connection, path).await
+    }
+
+    /// グロブに一致するリモートのパスを列挙する
+    pub async fn sftp_glob(
+        &self,
+        session_id: &str,
+        pattern: &str,
+        max_depth: Option<usize>,
+    ) -> Result<Vec<RemoteFileEntry>, SshError> {
+        let connection = self.session_manager.get_connection(session_id).await?;
+        self.sftp_manager.glob(session_id, &connection, pattern, max_depth).await
+    }
+
+    /// リモートのパスが条件を満たすまで待つ（`poll_interval_ms` 省略時は1秒ごとに確認）
+    pub async fn sftp_wait_for(
+        &self,
+        session_id: &str,
+        path: &str,
+        condition: WaitCondition,
+        timeout_ms: u64,
+        poll_interval_ms: Option<u64>,
+    ) -> Result<Option<RemoteFileEntry>, SshError> {
+        let connection = self.session_manager.get_connection(session_id).await?;
+        self.sftp_manager
+            .wait_for(
+                session_id,
+                &connection,
+                path,
+                condition,
+                std::time::Duration::from_millis(timeout_ms),
+                poll_interval_ms.map(std::time::Duration::from_millis),
+            )
+            .await
+    }
+
+    /// ローカルファイルをリモートへ転送する必要があるかを判定する
+    ///
+    /// サイズが一致する場合は SHA-256 を比較する（リモートは `sha256sum` で計算）。
+    /// `sha256sum` がない場合は更新時刻（秒単位）で比較する。
+    pub async fn sftp_needs_transfer(
+        &self,
+        session_id: &str,
+        local_path: &str,
+        remote_path: &str,
+    ) -> Result<TransferCheck, SshError> {
+        let check = |needs_transfer, reason| Ok(TransferCheck { needs_transfer, reason });
+
+        let local = tokio::fs::metadata(local_path).await?;
+        let connection = self.session_manager.get_connection(session_id).await?;
+        let Some(remote) = self.sftp_manager.stat(session_id, &connection, remote_path).await? else {
+            return check(true, TransferCheckReason::RemoteMissing);
+        };
+        if remote.size != Some(local.len()) {
+            return check(true, TransferCheckReason::SizeDiffers);
+        }
+
+        if self.session_manager.remote_command_exists(session_id, "sha256sum").await?.exists {
+            let command = format!("sha256sum -- {}", crate::ssh::session::shell_quote(remote_path));
+            let (result, _) = self
+                .session_manager
+                .execute_internal(session_id, &command, &CommandOptions::default())
+                .await?;
+            // ファイル名に改行などを含む場合、行頭に `\` が付く
+            let remote_hash = result
+                .stdout
+                .split_whitespace()
+                .next()
+                .map(|hash| hash.trim_start_matches('\\').to_lowercase())
+                .filter(|hash| result.exit_code == Some(0) && hash.len() == 64);
+            if let Some(remote_hash) = remote_hash {
+                let local_hash = crate::ssh::sftp::sha256_local_file(local_path).await?;
+                return if local_hash == remote_hash {
+                    check(false, TransferCheckReason::HashMatches)
+                } else {
+                    check(true, TransferCheckReason::HashDiffers)
+                };
+            }
+        }
+
+        let local_mtime = local
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|elapsed| elapsed.as_secs());
+        if local_mtime.is_some() && local_mtime == remote.mtime {
+            check(false, TransferCheckReason::MtimeMatches)
+        } else {
+            check(true, TransferCheckReason::MtimeDiffers)
+        }
+    }
+
+    /// リモートファイルの指定範囲を読み込む（メディアのプレビューなどで部分的に読む場合）
+    pub async fn sftp_read_range(
+        &self,
+        session_id: &str,
+        path: &str,
+        start: u64,
+        len: u64,
+    ) -> Result<RemoteByteRange, SshError> {
+        let connection = self.session_manager.get_connection(session_id).await?;
+        self.sftp_manager
+            .read_range(session_id, &connection, path, start, len)
+            .await
+    }
+
+    /// 複数のリモートパスの情報をまとめて取得する（結果は `paths` と同じ順）
+    pub async fn sftp_stat_many(
+        &self,
+        session_id: &str,
+        paths: Vec<String>,
+    ) -> Result<Vec<Result<RemoteFileEntry, String>>, SshError> {
+        let connection = self.session_manager.get_connection(session_id).await?;
+        self.sftp_manager.stat_many(session_id, &connection, paths).await
     }
 
     /// セッション情報を取得
@@ -49,23 +855,153 @@ impl SshClient {
         self.session_manager.get_session_info(session_id).await
     }
 
+    /// 鍵再交換を行い、要求した時刻を返す
+    pub async fn rekey(&self, session_id: &str) -> Result<chrono::DateTime<chrono::Utc>, SshError> {
+        self.session_manager.rekey(session_id).await
+    }
+
+    /// セッションの通信テレメトリを取得
+    pub async fn get_telemetry(&self, session_id: &str) -> Result<SessionTelemetry, SshError> {
+        self.session_manager.get_telemetry(session_id).await
+    }
+
+    /// セッションの接続情報・通信量・転送・ターミナルなどをまとめて取得
+    pub async fn get_session_snapshot(&self, session_id: &str) -> Result<SessionSnapshot, SshError> {
+        let mut snapshot = self.session_manager.get_session_snapshot(session_id).await?;
+        snapshot.terminals = self
+            .terminal_manager
+            .list_terminal_sessions()
+            .await
+            .into_iter()
+            .filter(|terminal| terminal.ssh_session_id == session_id)
+            .collect();
+        snapshot.exec_streams = self
+            .exec_manager
+            .list()
+            .await
+            .into_iter()
+            .filter(|stream| stream.session_id == session_id && !stream.finished)
+            .collect();
+        snapshot.open_channels += snapshot.terminals.iter().filter(|terminal| terminal.is_active).count()
+            + snapshot.exec_streams.len();
+        Ok(snapshot)
+    }
+
+    /// server-sig-algs から判断した RSA 署名アルゴリズムを取得
+    pub async fn get_server_extensions(&self, session_id: &str) -> Result<ServerExtensions, SshError> {
+        self.session_manager.get_server_extensions(session_id).await
+    }
+
     /// 全セッション一覧を取得
     pub async fn list_sessions(&self) -> Vec<SshSessionInfo> {
         self.session_manager.list_sessions().await
     }
 
+    /// 状態とホスト名で絞り込んだセッション一覧を取得
+    pub async fn list_sessions_filtered(
+        &self,
+        status: Option<ConnectionStatus>,
+        host_contains: Option<String>,
+    ) -> Vec<SshSessionInfo> {
+        self.session_manager
+            .list_sessions_filtered(status, host_contains)
+            .await
+    }
+
     /// セッションを削除
     pub async fn remove_session(&self, session_id: &str) -> Result<(), SshError> {
+        self.sftp_manager.forget_session(session_id).await;
+        self.terminal_manager.clear_env_for_session(session_id).await;
         self.session_manager.remove_session(session_id).await
     }
 
+    /// 実行中のコマンド・転送・ターミナルがすべて終わってからセッションを切断・削除する
+    ///
+    /// 呼び出した時点から新しい操作は受け付けない。`timeout`（省略時10分）を過ぎても終わらない操作は
+    /// 中断・強制的に閉じる。削除したら、強制的に閉じた操作とともに `SessionDrained` イベントを送る。
+    pub async fn close_when_idle(&self, session_id: &str, timeout: Option<std::time::Duration>) -> Result<(), SshError> {
+        self.session_manager.begin_drain(session_id).await?;
+        tokio::spawn(drain_session(
+            self.session_manager.clone(),
+            self.terminal_manager.clone(),
+            self.exec_manager.clone(),
+            self.sftp_manager.clone(),
+            self.events.clone(),
+            session_id.to_string(),
+            tokio::time::Instant::now() + timeout.unwrap_or(DEFAULT_DRAIN_TIMEOUT),
+        ));
+        Ok(())
+    }
+
+    /// セッション設定をファイルへエクスポート
+    pub async fn export_sessions(&self, path: &str) -> Result<usize, SshError> {
+        self.session_manager.export_sessions(path).await
+    }
+
+    /// セッション設定をファイルからインポート
+    pub async fn import_sessions(&self, path: &str) -> Result<ImportSummary, SshError> {
+        self.session_manager.import_sessions(path).await
+    }
+
     /// ターミナルセッションを作成
-    pub async fn create_terminal_session(&self, ssh_session_id: String) -> Result<String, SshError> {
-        // SSH接続が存在することを確認
-        let _session_info = self.session_manager.get_session_info(&ssh_session_id).await?;
-        
-        // 疑似ターミナルセッションを作成（実際のSSH接続は使用しない）
-        self.terminal_manager.create_terminal_session(ssh_session_id).await
+    pub async fn create_terminal_session(
+        &self,
+        ssh_session_id: String,
+        terminal_modes: Option<Vec<(u8, u32)>>,
+        forwarding: TerminalForwarding,
+        nohup_on_detach: bool,
+        env_profile: Option<&str>,
+    ) -> Result<String, SshError> {
+        let env = match env_profile {
+            Some(name) => self.env_profiles.get(name).await?,
+            None => Default::default(),
+        };
+        self.open_terminal(ssh_session_id, terminal_modes, forwarding, nohup_on_detach, env)
+            .await
+    }
+
+    /// 終了したターミナルと同じ設定でSSHセッション上にシェルを開き直し、新しいターミナルIDを返す
+    ///
+    /// 作成時に指定したターミナルモード・転送・環境変数をそのまま使う。
+    /// 元のターミナルが OSC 7 で作業ディレクトリを通知していた場合は、そこへ `cd` して
+    /// `TerminalCwdRestored` を通知する。元のターミナルはスクロールバックを読めるよう残す。
+    /// 自動再接続に成功したセッションでは、接続が切れて終了したターミナルを同じ方法で自動的に開き直す。
+    pub async fn reconnect_terminal(&self, terminal_id: &str) -> Result<String, SshError> {
+        let new_id = reopen_terminal(&self.session_manager, &self.terminal_manager, terminal_id).await?;
+        restore_terminal_cwd(&self.session_manager, &self.terminal_manager, &self.events, terminal_id, &new_id).await?;
+        Ok(new_id)
+    }
+
+    /// セッションの設定からターミナルの動作を決めてシェルを開く
+    async fn open_terminal(
+        &self,
+        ssh_session_id: String,
+        terminal_modes: Option<Vec<(u8, u32)>>,
+        forwarding: TerminalForwarding,
+        nohup_on_detach: bool,
+        env: std::collections::BTreeMap<String, String>,
+    ) -> Result<String, SshError> {
+        if !self
+            .terminal_reopener_started
+            .swap(true, std::sync::atomic::Ordering::SeqCst)
+        {
+            tokio::spawn(reopen_terminals_on_reconnect(
+                self.events.subscribe(),
+                Arc::downgrade(&self.session_manager),
+                Arc::downgrade(&self.terminal_manager),
+                self.events.clone(),
+            ));
+        }
+        open_terminal(
+            &self.session_manager,
+            &self.terminal_manager,
+            ssh_session_id,
+            terminal_modes,
+            forwarding,
+            nohup_on_detach,
+            env,
+        )
+        .await
     }
 
     /// ターミナルセッションに入力を送信
@@ -73,6 +1009,33 @@ impl SshClient {
         self.terminal_manager.send_input(terminal_id, input).await
     }
 
+    /// ターミナルセッションにセッション設定の改行を付けて1行送信
+    pub async fn send_terminal_line(&self, terminal_id: &str, text: String) -> Result<(), SshError> {
+        let terminal = self.terminal_manager.get_terminal_session(terminal_id).await?;
+        let session_info = self.session_manager.get_session_info(&terminal.ssh_session_id).await?;
+        let terminator = session_info
+            .config
+            .line_terminator
+            .unwrap_or_else(|| DEFAULT_LINE_TERMINATOR.to_string());
+
+        self.terminal_manager.send_line(terminal_id, text, &terminator).await
+    }
+
+    /// ターミナルセッションにテキストを分割して貼り付け
+    pub async fn paste_terminal_input(
+        &self,
+        terminal_id: &str,
+        text: String,
+        options: PasteOptions,
+    ) -> Result<(), SshError> {
+        self.terminal_manager.paste(terminal_id, text, options).await
+    }
+
+    /// クリップボードの内容をOSC 52でターミナルに送る
+    pub async fn send_terminal_clipboard(&self, terminal_id: &str, selection: &str, text: &str) -> Result<(), SshError> {
+        self.terminal_manager.send_clipboard(terminal_id, selection, text).await
+    }
+
     /// ターミナルセッションからの出力を受信
     pub async fn receive_terminal_output(&self, terminal_id: &str) -> Result<Option<TerminalData>, SshError> {
         self.terminal_manager.receive_output(terminal_id).await
@@ -83,6 +1046,35 @@ impl SshClient {
         self.terminal_manager.close_terminal_session(terminal_id).await
     }
 
+    /// シェルをログアウトさせてからターミナルセッションを終了（正常にログアウトできた場合は true）
+    pub async fn logout_terminal(&self, terminal_id: &str, timeout: Option<std::time::Duration>) -> Result<bool, SshError> {
+        self.terminal_manager
+            .logout_terminal(terminal_id, timeout.unwrap_or(DEFAULT_LOGOUT_TIMEOUT))
+            .await
+    }
+
+    /// ターミナルのスクロールバックを取得（`lines` 指定時は末尾の行数分）
+    pub async fn get_terminal_scrollback(&self, terminal_id: &str, lines: Option<usize>) -> Result<String, SshError> {
+        self.terminal_manager.get_scrollback(terminal_id, lines).await
+    }
+
+    /// ターミナルの出力をローカルファイルに書き出し始める（`timestamps` 指定時は行ごとに時刻を付ける）
+    pub async fn start_terminal_log(&self, terminal_id: &str, local_path: &str, timestamps: bool) -> Result<(), SshError> {
+        self.terminal_manager
+            .start_log(terminal_id, std::path::Path::new(local_path), timestamps)
+            .await
+    }
+
+    /// ターミナルのログの書き出しを止める（書き出していなければ false）
+    pub async fn stop_terminal_log(&self, terminal_id: &str) -> Result<bool, SshError> {
+        self.terminal_manager.stop_log(terminal_id).await
+    }
+
+    /// SSHセッションに紐づく全ターミナルを終了
+    pub async fn close_all_terminals_for_session(&self, ssh_session_id: &str) -> Result<Vec<String>, SshError> {
+        self.terminal_manager.close_all_for_session(ssh_session_id).await
+    }
+
     /// ターミナルセッション情報を取得
     pub async fn get_terminal_session(&self, terminal_id: &str) -> Result<TerminalSession, SshError> {
         self.terminal_manager.get_terminal_session(terminal_id).await
@@ -97,6 +1089,206 @@ impl SshClient {
     pub async fn resize_terminal(&self, terminal_id: &str, width: u32, height: u32) -> Result<(), SshError> {
         self.terminal_manager.resize_terminal(terminal_id, width, height).await
     }
+
+    /// ターミナルに環境変数を設定（サーバーが受け付けたかを返す）
+    pub async fn set_terminal_env(&self, terminal_id: &str, name: String, value: String) -> Result<bool, SshError> {
+        self.terminal_manager.set_env(terminal_id, name, value).await
+    }
+}
+
+/// セッションの設定からターミナルの動作を決めてシェルを開く
+async fn open_terminal(
+    session_manager: &SshSessionManager,
+    terminal_manager: &TerminalManager,
+    ssh_session_id: String,
+    terminal_modes: Option<Vec<(u8, u32)>>,
+    forwarding: TerminalForwarding,
+    nohup_on_detach: bool,
+    env: std::collections::BTreeMap<String, String>,
+) -> Result<String, SshError> {
+    let session_info = session_manager.get_session_info(&ssh_session_id).await?;
+    let connection = session_manager.get_connection(&ssh_session_id).await?;
+    let x11_slot = session_manager.x11_slot(&ssh_session_id).await?;
+    let settings = TerminalSettings {
+        idle_close: session_info
+            .config
+            .terminal_idle_close_secs
+            .map(std::time::Duration::from_secs),
+        output_filter: session_info.config.terminal_filter.clone(),
+        allow_clipboard_write: session_info.config.allow_clipboard_write,
+        nohup_on_detach,
+        env,
+        scrollback_bytes: session_info.config.max_scrollback_bytes,
+        scrollback_lines: session_info.config.max_scrollback_lines,
+    };
+
+    terminal_manager
+        .create_terminal_session(
+            ssh_session_id,
+            &connection,
+            settings,
+            terminal_modes,
+            forwarding,
+            &x11_slot,
+        )
+        .await
+}
+
+/// 終了したターミナルを作成時と同じ設定で開き直す
+async fn reopen_terminal(
+    session_manager: &SshSessionManager,
+    terminal_manager: &TerminalManager,
+    terminal_id: &str,
+) -> Result<String, SshError> {
+    let previous = terminal_manager.get_terminal_session(terminal_id).await?;
+    if previous.is_active {
+        return Err(SshError::ConfigError(format!("terminal is still active: {}", terminal_id)));
+    }
+
+    let (terminal_modes, forwarding) = terminal_manager.creation_options(terminal_id).await?;
+    open_terminal(
+        session_manager,
+        terminal_manager,
+        previous.ssh_session_id,
+        terminal_modes,
+        forwarding,
+        previous.nohup_on_detach,
+        previous.env.into_iter().collect(),
+    )
+    .await
+}
+
+/// 開き直したターミナルで、元のターミナルが通知していた作業ディレクトリへ `cd` する
+async fn restore_terminal_cwd(
+    session_manager: &SshSessionManager,
+    terminal_manager: &TerminalManager,
+    events: &EventBus,
+    previous_terminal_id: &str,
+    terminal_id: &str,
+) -> Result<(), SshError> {
+    let previous = terminal_manager.get_terminal_session(previous_terminal_id).await?;
+    let Some(cwd) = previous.cwd else {
+        return Ok(());
+    };
+
+    let session_info = session_manager.get_session_info(&previous.ssh_session_id).await?;
+    let terminator = session_info
+        .config
+        .line_terminator
+        .unwrap_or_else(|| DEFAULT_LINE_TERMINATOR.to_string());
+    terminal_manager
+        .send_line(terminal_id, format!("cd -- {}", crate::ssh::session::shell_quote(&cwd)), &terminator)
+        .await?;
+    events.emit(SshEvent::TerminalCwdRestored {
+        terminal_id: terminal_id.to_string(),
+        previous_terminal_id: previous_terminal_id.to_string(),
+        ssh_session_id: previous.ssh_session_id,
+        cwd,
+    });
+    Ok(())
+}
+
+/// 自動再接続に成功したセッションで、接続が切れて終了したターミナルを開き直す
+///
+/// マネージャーが破棄されたら終了する。
+async fn reopen_terminals_on_reconnect(
+    mut receiver: broadcast::Receiver<SshEvent>,
+    session_manager: std::sync::Weak<SshSessionManager>,
+    terminal_manager: std::sync::Weak<TerminalManager>,
+    events: EventBus,
+) {
+    loop {
+        let ssh_session_id = match receiver.recv().await {
+            Ok(SshEvent::Reconnected { session_id, .. }) => session_id,
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        let (Some(session_manager), Some(terminal_manager)) = (session_manager.upgrade(), terminal_manager.upgrade()) else {
+            return;
+        };
+
+        for previous_terminal_id in terminal_manager.take_lost_terminals(&ssh_session_id).await {
+            match reopen_terminal(&session_manager, &terminal_manager, &previous_terminal_id).await {
+                Ok(terminal_id) => {
+                    events.emit(SshEvent::TerminalReopened {
+                        terminal_id: terminal_id.clone(),
+                        previous_terminal_id: previous_terminal_id.clone(),
+                        ssh_session_id: ssh_session_id.clone(),
+                    });
+                    // 開き直した直後にシェルが終了した場合は移動できないが、開き直し自体は済んでいる
+                    let _ = restore_terminal_cwd(&session_manager, &terminal_manager, &events, &previous_terminal_id, &terminal_id).await;
+                }
+                Err(e) => events.emit(SshEvent::TerminalReopenFailed {
+                    previous_terminal_id,
+                    ssh_session_id: ssh_session_id.clone(),
+                    error: e.to_string(),
+                }),
+            }
+        }
+    }
+}
+
+/// 実行中の操作がなくなるまで（最長 `deadline` まで）待ってからセッションを削除する
+///
+/// 期限を過ぎても残っている操作は中断し、ターミナルは閉じる。
+async fn drain_session(
+    session_manager: Arc<SshSessionManager>,
+    terminal_manager: Arc<TerminalManager>,
+    exec_manager: Arc<ExecStreamManager>,
+    sftp_manager: Arc<SftpManager>,
+    events: EventBus,
+    session_id: String,
+    deadline: tokio::time::Instant,
+) {
+    let (commands, terminals, transfers) = loop {
+        // ストリーミング実行も実行中のコマンドとして登録されている
+        let commands: Vec<String> = session_manager
+            .list_running_commands(&session_id)
+            .await
+            .into_iter()
+            .map(|command| command.exec_id)
+            .collect();
+        let terminals: Vec<String> = terminal_manager
+            .list_terminal_sessions()
+            .await
+            .into_iter()
+            .filter(|terminal| terminal.ssh_session_id == session_id && terminal.is_active)
+            .map(|terminal| terminal.id)
+            .collect();
+        let transfers: Vec<String> = sftp_manager
+            .transfers()
+            .list()
+            .into_iter()
+            .filter(|transfer| transfer.session_id == session_id && matches!(transfer.state, TransferState::Running))
+            .map(|transfer| transfer.id)
+            .collect();
+        if commands.is_empty() && terminals.is_empty() && transfers.is_empty() {
+            break Default::default();
+        }
+        if tokio::time::Instant::now() >= deadline {
+            break (commands, terminals, transfers);
+        }
+        tokio::time::sleep_until(deadline.min(tokio::time::Instant::now() + DRAIN_POLL_INTERVAL)).await;
+    };
+
+    if !commands.is_empty() {
+        session_manager.cancel_commands_for_session(&session_id).await;
+        exec_manager.cancel_all_for_session(&session_id).await;
+    }
+    if !transfers.is_empty() {
+        sftp_manager.cancel_all_for_session(&session_id).await;
+    }
+    let _ = terminal_manager.close_all_for_session(&session_id).await;
+
+    sftp_manager.forget_session(&session_id).await;
+    terminal_manager.clear_env_for_session(&session_id).await;
+    let _ = session_manager.remove_session(&session_id).await;
+    events.emit(SshEvent::SessionDrained {
+        session_id,
+        forced_commands: commands,
+        forced_terminals: terminals,
+        forced_transfers: transfers,
+    });
 }
 
 impl Default for SshClient {
@@ -105,3 +1297,62 @@ impl Default for SshClient {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh::test_server::{exec_stderr, exec_stdout, TestServer, TEST_PASSWORD};
+    use crate::ssh::AuthMethod;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn terminal_and_commands_share_a_connection_without_cross_talk() {
+        let server = TestServer::start().await;
+        let client = SshClient::new();
+        let session_id = client
+            .create_connection(server.config(AuthMethod::Password(TEST_PASSWORD.to_string())))
+            .await
+            .unwrap();
+        client.connect(&session_id).await.unwrap();
+        let terminal_id = client
+            .create_terminal_session(session_id.clone(), None, TerminalForwarding::default(), false, None)
+            .await
+            .unwrap();
+
+        let lines: Vec<String> = (0..50).map(|i| format!("typed line {}\n", i)).collect();
+        let typing = async {
+            for line in &lines {
+                client.send_terminal_input(&terminal_id, line.clone()).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        };
+        let options = CommandOptions::default();
+        let commands = async {
+            tokio::join!(
+                client.execute_command(&session_id, "first", &options),
+                client.execute_command(&session_id, "second", &options),
+                client.execute_command(&session_id, "third", &options),
+            )
+        };
+        let ((), (first, second, third)) = tokio::join!(typing, commands);
+
+        for (command, result) in [("first", first), ("second", second), ("third", third)] {
+            let result = result.unwrap();
+            assert_eq!(result.exit_code, Some(0));
+            assert_eq!(result.stdout, exec_stdout(command));
+            assert_eq!(result.stderr, exec_stderr(command));
+        }
+
+        let expected: String = lines.concat();
+        let mut echoed = String::new();
+        while echoed.len() < expected.len() {
+            let data = tokio::time::timeout(Duration::from_secs(5), client.receive_terminal_output(&terminal_id))
+                .await
+                .expect("terminal output timed out")
+                .unwrap()
+                .expect("terminal closed");
+            echoed.push_str(&data.data);
+        }
+        assert_eq!(echoed, expected);
+    }
+}
@@ -0,0 +1,18 @@
+use chrono::{DateTime, Utc};
+
+/// 現在時刻の取得元
+///
+/// 計測や記録に使う時刻を差し替えられるようにするためのもの。
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// システム時刻を返す標準の実装
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
@@ -0,0 +1,152 @@
+use crate::ssh::handshake::HandshakeCapture;
+use crate::ssh::key_provider::FileKeyProvider;
+use crate::ssh::proxy::connect_via_proxy;
+use crate::ssh::resolver::resolve_host;
+use crate::ssh::session::{authenticate, connect_over_stream};
+use crate::ssh::telemetry::{CountingStream, TrafficCounters};
+use crate::ssh::{
+    AuthPromptBroker, ConnectionDiagnostics, DiagnosticStage, EventBus, SshClientHandler,
+    SshConfig, SshError, StageResult,
+};
+use russh::client::AuthResult;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+
+/// 各段階の既定のタイムアウト
+const DEFAULT_STAGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 診断用の認証で使うセッションID（パスワード変更要求などのイベントの識別用）
+const DIAGNOSTICS_SESSION_ID: &str = "diagnostics";
+
+/// 名前解決・TCP接続・SSHハンドシェイク・認証を順に試し、どの段階で失敗したかを調べる
+///
+/// セッションは登録せず、最後に必ず切断する。各段階には `timeout`（未指定時は10秒）を適用する。
+pub async fn diagnose_connection(config: &SshConfig) -> ConnectionDiagnostics {
+    let stage_timeout = config
+        .timeout
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_STAGE_TIMEOUT);
+    let mut diagnostics = ConnectionDiagnostics::default();
+
+    // 名前解決（プロキシ経由の場合はプロキシ側で行われる）
+    let addrs = match &config.proxy {
+        Some(_) => Vec::new(),
+        None => {
+            let result = run_stage(&mut diagnostics, DiagnosticStage::Dns, stage_timeout, async {
+                let addrs = resolve_host(&config.host, config.port, config.resolver.as_ref()).await?;
+                let detail = addrs.iter().map(|a| a.ip().to_string()).collect::<Vec<_>>().join(", ");
+                Ok((addrs, Some(detail)))
+            })
+            .await;
+            match result {
+                Some(addrs) => addrs,
+                None => return diagnostics,
+            }
+        }
+    };
+
+    // TCP接続
+    let stream = run_stage(&mut diagnostics, DiagnosticStage::TcpConnect, stage_timeout, async {
+        let stream = match &config.proxy {
+            Some(proxy) => connect_via_proxy(proxy, &config.host, config.port).await?,
+            None => TcpStream::connect(&addrs[..])
+                .await
+                .map_err(|e| SshError::ConnectionFailed(e.to_string()))?,
+        };
+        let detail = stream.peer_addr().ok().map(|addr| addr.to_string());
+        Ok((stream, detail))
+    })
+    .await;
+    let Some(stream) = stream else {
+        return diagnostics;
+    };
+
+    // SSHハンドシェイク（識別文字列の交換と鍵交換）
+    let handshake = Arc::new(HandshakeCapture::new());
+    let connection = run_stage(&mut diagnostics, DiagnosticStage::Handshake, stage_timeout, async {
+        let stream = CountingStream::new(stream, TrafficCounters::new())
+            .with_handshake_capture(handshake.clone());
+        let handler = SshClientHandler::new(TrafficCounters::new());
+        let connection = connect_over_stream(russh::client::Config::default(), stream, handler).await?;
+        Ok((connection, handshake.server_version()))
+    })
+    .await;
+    diagnostics.server_version = handshake.server_version();
+    let Some(mut connection) = connection else {
+        return diagnostics;
+    };
+
+    // 認証
+    let events = EventBus::new();
+    let prompts = AuthPromptBroker::new();
+    run_stage(&mut diagnostics, DiagnosticStage::Authentication, stage_timeout, async {
+        match authenticate(
+            &mut connection,
+            config,
+            DIAGNOSTICS_SESSION_ID,
+            &events,
+            &prompts,
+            &FileKeyProvider,
+        )
+        .await?
+        {
+            AuthResult::Success => Ok(((), None)),
+            _ => Err(SshError::AuthenticationFailed("Authentication failed".to_string())),
+        }
+    })
+    .await;
+
+    let _ = connection
+        .disconnect(russh::Disconnect::ByApplication, "diagnostics complete", "en")
+        .await;
+
+    diagnostics
+}
+
+/// 1段階を実行して結果を記録する（失敗した場合は `None`）
+async fn run_stage<T, F>(
+    diagnostics: &mut ConnectionDiagnostics,
+    stage: DiagnosticStage,
+    timeout: Duration,
+    future: F,
+) -> Option<T>
+where
+    F: Future<Output = Result<(T, Option<String>), SshError>>,
+{
+    let started = Instant::now();
+    let result = match tokio::time::timeout(timeout, future).await {
+        Ok(result) => result,
+        Err(_) => Err(SshError::Timeout(format!(
+            "{:?} did not complete within {}s",
+            stage,
+            timeout.as_secs()
+        ))),
+    };
+    let duration_ms = started.elapsed().as_millis() as u64;
+
+    match result {
+        Ok((value, detail)) => {
+            diagnostics.stages.push(StageResult {
+                stage,
+                success: true,
+                duration_ms,
+                detail,
+                error: None,
+            });
+            Some(value)
+        }
+        Err(e) => {
+            diagnostics.stages.push(StageResult {
+                stage: stage.clone(),
+                success: false,
+                duration_ms,
+                detail: None,
+                error: Some(e.to_string()),
+            });
+            diagnostics.failed_stage = Some(stage);
+            None
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use crate::ssh::{EnvProfile, SshError};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tokio::sync::RwLock;
+
+/// 環境変数プロファイルの保存ファイル名
+pub const ENV_PROFILES_FILE: &str = "env_profiles.json";
+
+/// 名前付きの環境変数プロファイルを保持する
+///
+/// `open` で保存先を指定した場合は、変更のたびにJSONで書き出す。
+#[derive(Debug, Default)]
+pub struct EnvProfileStore {
+    profiles: RwLock<BTreeMap<String, BTreeMap<String, String>>>,
+    path: RwLock<Option<PathBuf>>,
+}
+
+impl EnvProfileStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 保存ファイルを読み込み、以降の変更の保存先にする（ファイルがなければ空のまま）
+    ///
+    /// 解釈できないファイルは上書きしないよう、保存先にしない。
+    pub async fn open(&self, path: &Path) -> Result<(), SshError> {
+        match tokio::fs::read_to_string(path).await {
+            Ok(json) => {
+                let profiles: Vec<EnvProfile> = serde_json::from_str(&json)
+                    .map_err(|e| SshError::ConfigError(format!("invalid environment profile file: {}", e)))?;
+                *self.profiles.write().await = profiles
+                    .into_iter()
+                    .map(|profile| (profile.name, profile.vars))
+                    .collect();
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        *self.path.write().await = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    /// プロファイル一覧（名前順）
+    pub async fn list(&self) -> Vec<EnvProfile> {
+        self.profiles
+            .read()
+            .await
+            .iter()
+            .map(|(name, vars)| EnvProfile {
+                name: name.clone(),
+                vars: vars.clone(),
+            })
+            .collect()
+    }
+
+    /// プロファイルの環境変数を取得
+    pub async fn get(&self, name: &str) -> Result<BTreeMap<String, String>, SshError> {
+        self.profiles
+            .read()
+            .await
+            .get(name)
+            .cloned()
+            .ok_or_else(|| SshError::ConfigError(format!("environment profile not found: {}", name)))
+    }
+
+    /// プロファイルを追加する（同名のものは置き換える）
+    pub async fn save(&self, profile: EnvProfile) -> Result<(), SshError> {
+        if profile.name.trim().is_empty() {
+            return Err(SshError::ConfigError("environment profile name is empty".to_string()));
+        }
+        if let Some(name) = profile.vars.keys().find(|name| !is_valid_env_name(name)) {
+            return Err(SshError::ConfigError(format!("invalid environment variable name: {:?}", name)));
+        }
+
+        self.profiles.write().await.insert(profile.name, profile.vars);
+        self.persist().await
+    }
+
+    /// プロファイルを削除する（存在しなければ false）
+    pub async fn remove(&self, name: &str) -> Result<bool, SshError> {
+        if self.profiles.write().await.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.persist().await?;
+        Ok(true)
+    }
+
+    /// 保存先が指定されていれば全プロファイルを書き出す
+    async fn persist(&self) -> Result<(), SshError> {
+        let Some(path) = self.path.read().await.clone() else {
+            return Ok(());
+        };
+        let json = serde_json::to_string_pretty(&self.list().await)
+            .map_err(|e| SshError::ConfigError(e.to_string()))?;
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        tokio::fs::write(&path, json).await?;
+        Ok(())
+    }
+}
+
+/// シェルの変数名として使える名前か（`export` で渡すため英数字と `_` のみ）
+pub fn is_valid_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
@@ -0,0 +1,259 @@
+use crate::ssh::{ConnectionDetails, JournalEntry, RemoteFileEntry, TerminalExitReason};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+/// イベントチャネルのバッファサイズ
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// バックエンドからフロントエンドへ通知するイベント
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum SshEvent {
+    /// 接続が確立された
+    Connected {
+        session_id: String,
+        #[serde(flatten)]
+        details: ConnectionDetails,
+    },
+    /// サーバーがパスワードの変更を要求している
+    PasswordChangeRequired {
+        session_id: String,
+        instructions: String,
+        prompts: Vec<String>,
+    },
+    /// keyboard-interactive のプロンプトへの応答が必要（`ssh_respond_auth_prompt` で応答する）
+    AuthPromptRequired {
+        session_id: String,
+        instructions: String,
+        prompts: Vec<String>,
+    },
+    /// 初めて接続したホストの鍵を固定した（`trust_on_first_use`）
+    HostKeyPinned {
+        session_id: String,
+        host: String,
+        port: u16,
+        key_type: String,
+        fingerprint: String,
+    },
+    /// ディレクトリ同期の進捗
+    SyncProgress {
+        sync_id: String,
+        session_id: String,
+        path: String,
+        file_transferred: u64,
+        file_total: u64,
+        files_completed: usize,
+        files_total: usize,
+        bytes_transferred: u64,
+        bytes_total: u64,
+        /// 同期開始からの経過時間
+        elapsed_ms: u64,
+        /// 平均転送速度から見積もった残り時間（まだ転送していない場合は `None`）
+        eta_ms: Option<u64>,
+    },
+    /// リモートファイルのストリーム読み込みで受信したデータ（`data` はbase64）
+    ///
+    /// 最後に `eof` を立てたイベントを送る。エラーやキャンセルで終了した場合もその旨を付ける。
+    SftpStreamData {
+        stream_id: String,
+        data: String,
+        offset: u64,
+        eof: bool,
+        cancelled: bool,
+        error: Option<String>,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    },
+    /// `sftp_download_as_tar` の受信済みバイト数（最後に `done` を立てたイベントを送る）
+    TarDownloadProgress {
+        transfer_id: String,
+        session_id: String,
+        bytes_received: u64,
+        done: bool,
+    },
+    /// `sftp_list_dir_stream` で読み込んだディレクトリの項目
+    ///
+    /// 最後に `done` を立てたイベント（`entries` は空）を送る。`total` はそれまでに通知した項目数。
+    SftpDirEntries {
+        listing_id: String,
+        session_id: String,
+        entries: Vec<RemoteFileEntry>,
+        done: bool,
+        cancelled: bool,
+        total: u64,
+        error: Option<String>,
+    },
+    /// `journal_tail` で受信したログエントリ
+    ///
+    /// 最後に `eof` を立てたイベント（`entry` は空）を送る。journalctl が失敗した場合は `error` を付ける。
+    JournalData {
+        journal_id: String,
+        session_id: String,
+        entry: Option<JournalEntry>,
+        eof: bool,
+        exit_code: Option<u32>,
+        error: Option<String>,
+    },
+    /// ターミナルが終了した
+    TerminalExit {
+        terminal_id: String,
+        ssh_session_id: String,
+        reason: TerminalExitReason,
+    },
+    /// 自動再接続の後、接続が切れて終了したターミナルを同じ設定で開き直した
+    TerminalReopened {
+        terminal_id: String,
+        previous_terminal_id: String,
+        ssh_session_id: String,
+    },
+    /// 自動再接続の後、ターミナルを開き直せなかった
+    TerminalReopenFailed {
+        previous_terminal_id: String,
+        ssh_session_id: String,
+        error: String,
+    },
+    /// 再接続したターミナルで、元のターミナルの作業ディレクトリに移動した
+    TerminalCwdRestored {
+        terminal_id: String,
+        previous_terminal_id: String,
+        ssh_session_id: String,
+        cwd: String,
+    },
+    /// ターミナルのログファイルへの書き込みに失敗し、ログを止めた
+    TerminalLogFailed {
+        terminal_id: String,
+        ssh_session_id: String,
+        path: String,
+        error: String,
+    },
+    /// リモートのプログラムがクリップボードへの書き込みを要求した（OSC 52）
+    TerminalClipboard {
+        terminal_id: String,
+        ssh_session_id: String,
+        selection: String,
+        text: String,
+    },
+    /// authorized_keys の強制コマンドが有効であることを検出した
+    ForcedCommandDetected {
+        session_id: String,
+    },
+    /// 接続が予期せず終了した（サーバーからの切断・通信エラーなど）
+    ConnectionLost {
+        session_id: String,
+        reason: String,
+    },
+    /// 接続数の上限を超えるため、最も使われていないセッションを切断した
+    SessionEvicted {
+        session_id: String,
+    },
+    /// 切断を検知し、待機後に再接続を試みる
+    Reconnecting {
+        session_id: String,
+        attempt: u32,
+        delay_ms: u64,
+    },
+    /// 再接続に成功した
+    Reconnected {
+        session_id: String,
+        attempt: u32,
+    },
+    /// 再接続の試行回数を使い切った
+    ReconnectFailed {
+        session_id: String,
+        attempts: u32,
+        error: String,
+    },
+    /// 再接続後にポート転送を再確立した
+    ForwardRestored {
+        session_id: String,
+        forward_id: String,
+    },
+    /// 再接続後のポート転送の再確立に失敗した
+    ForwardRestoreFailed {
+        session_id: String,
+        forward_id: String,
+        error: String,
+    },
+    /// 公開鍵が受け入れられず、パスワード認証に切り替えた
+    AuthFallback {
+        session_id: String,
+        from: String,
+        to: String,
+    },
+    /// `ssh_close_when_idle` で実行中の操作がすべて終わり（または期限を過ぎ）、セッションを削除した
+    ///
+    /// `forced_*` は期限を過ぎても終わらず、中断・強制的に閉じた操作のID。
+    SessionDrained {
+        session_id: String,
+        forced_commands: Vec<String>,
+        forced_terminals: Vec<String>,
+        forced_transfers: Vec<String>,
+    },
+    /// 終了した転送を一覧から取り除いた
+    TransferRemoved {
+        transfer_id: String,
+        session_id: String,
+    },
+}
+
+impl SshEvent {
+    /// Tauriイベント名
+    pub fn name(&self) -> &'static str {
+        match self {
+            SshEvent::Connected { .. } => "ssh://connected",
+            SshEvent::PasswordChangeRequired { .. } => "ssh://password-change-required",
+            SshEvent::AuthPromptRequired { .. } => "ssh://auth-prompt-required",
+            SshEvent::HostKeyPinned { .. } => "ssh://host-key-pinned",
+            SshEvent::SyncProgress { .. } => "sftp://sync-progress",
+            SshEvent::SftpStreamData { .. } => "sftp://stream-data",
+            SshEvent::SftpDirEntries { .. } => "sftp://dir-entries",
+            SshEvent::TarDownloadProgress { .. } => "sftp://tar-progress",
+            SshEvent::JournalData { .. } => "ssh://journal-data",
+            SshEvent::TerminalExit { .. } => "terminal://exit",
+            SshEvent::TerminalReopened { .. } => "terminal://reopened",
+            SshEvent::TerminalReopenFailed { .. } => "terminal://reopen-failed",
+            SshEvent::TerminalCwdRestored { .. } => "terminal://cwd-restored",
+            SshEvent::TerminalLogFailed { .. } => "terminal://log-failed",
+            SshEvent::TerminalClipboard { .. } => "terminal://clipboard",
+            SshEvent::ForcedCommandDetected { .. } => "ssh://forced-command-detected",
+            SshEvent::ConnectionLost { .. } => "ssh://connection-lost",
+            SshEvent::SessionEvicted { .. } => "ssh://session-evicted",
+            SshEvent::Reconnecting { .. } => "ssh://reconnecting",
+            SshEvent::Reconnected { .. } => "ssh://reconnected",
+            SshEvent::ReconnectFailed { .. } => "ssh://reconnect-failed",
+            SshEvent::ForwardRestored { .. } => "ssh://forward-restored",
+            SshEvent::ForwardRestoreFailed { .. } => "ssh://forward-restore-failed",
+            SshEvent::AuthFallback { .. } => "ssh://auth-fallback",
+            SshEvent::SessionDrained { .. } => "ssh://session-drained",
+            SshEvent::TransferRemoved { .. } => "sftp://transfer-removed",
+        }
+    }
+}
+
+/// イベントの配信先（購読者がいなければ破棄される）
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<SshEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        Self { sender }
+    }
+
+    /// イベントを発行
+    pub fn emit(&self, event: SshEvent) {
+        let _ = self.sender.send(event);
+    }
+
+    /// イベントを購読
+    pub fn subscribe(&self) -> broadcast::Receiver<SshEvent> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
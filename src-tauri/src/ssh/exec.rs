@@ -0,0 +1,321 @@
+use crate::ssh::output::Utf8Decoder;
+use crate::ssh::{ExecCompletion, ExecStreamData, ExecStreamInfo, ExecTicket, SshConnection, SshError, StdStream};
+use russh::{ChannelMsg, Sig};
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex, RwLock};
+use tokio_util::sync::CancellationToken;
+
+/// 受信側が読み取っていない出力チャンクを溜めておく上限
+///
+/// 溜まっている間はチャネルからの読み取りを止めるため、SSHのウィンドウが閉じて
+/// リモートのコマンドの出力が止まる。
+const OUTPUT_BUFFER_CHUNKS: usize = 256;
+
+/// チャネルへ送っていない標準入力の書き込みを溜めておく上限（超えると書き込みが待たされる）
+const INPUT_BUFFER_CHUNKS: usize = 64;
+
+/// ストリーミング実行中のコマンドを管理する
+pub struct ExecStreamManager {
+    streams: Arc<RwLock<HashMap<String, ExecStream>>>,
+}
+
+/// 個別のストリーミング実行
+struct ExecStream {
+    info: Arc<Mutex<ExecStreamInfo>>,
+    input_sender: mpsc::Sender<ExecInput>,
+    output_receiver: Arc<Mutex<mpsc::Receiver<ExecStreamData>>>,
+    cancel: CancellationToken,
+}
+
+/// 標準入力への指示
+enum ExecInput {
+    Data(Vec<u8>),
+    Eof,
+}
+
+impl ExecStreamManager {
+    pub fn new() -> Self {
+        Self {
+            streams: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// コマンドを実行し、出力をストリームとして受け取れるようにする
+    ///
+    /// `ticket` は実行中のコマンドとしての登録で、ストリームIDにはその実行IDを使う。
+    /// 登録は実行の終了まで保持し、そのトークンで中断されたら実行を止める。
+    pub async fn start(
+        &self,
+        session_id: String,
+        connection: Arc<SshConnection>,
+        command: String,
+        ticket: ExecTicket,
+    ) -> Result<String, SshError> {
+        let mut channel = connection
+            .channel_open_session()
+            .await
+            .map_err(|e| SshError::CommandFailed(e.to_string()))?;
+
+        channel
+            .exec(true, command.as_str())
+            .await
+            .map_err(|e| SshError::CommandFailed(e.to_string()))?;
+
+        let exec_id = ticket.exec_id().to_string();
+        let (input_sender, mut input_receiver) = mpsc::channel::<ExecInput>(INPUT_BUFFER_CHUNKS);
+        let (output_sender, output_receiver) = mpsc::channel::<ExecStreamData>(OUTPUT_BUFFER_CHUNKS);
+        let cancel = ticket.cancel_token().clone();
+        let info = Arc::new(Mutex::new(ExecStreamInfo {
+            id: exec_id.clone(),
+            session_id,
+            command,
+            started_at: chrono::Utc::now(),
+            finished: false,
+            exit_code: None,
+            bytes_produced: 0,
+            exit_signal: None,
+        }));
+
+        let mut streams = self.streams.write().await;
+        streams.insert(
+            exec_id.clone(),
+            ExecStream {
+                info: info.clone(),
+                input_sender,
+                output_receiver: Arc::new(Mutex::new(output_receiver)),
+                cancel: cancel.clone(),
+            },
+        );
+        drop(streams);
+
+        // チャネルの読み取りタスク（送信側がドロップされると受信側は終端を検知する）
+        let stream_id = exec_id.clone();
+        tokio::spawn(async move {
+            let mut cancelled = false;
+            let mut stdin_open = true;
+            let mut completion = None;
+            // チャンクの境界で分かれたマルチバイト文字は次のチャンクと合わせて文字列にする
+            let mut stdout_decoder = Utf8Decoder::new();
+            let mut stderr_decoder = Utf8Decoder::new();
+            loop {
+                let msg = tokio::select! {
+                    _ = cancel.cancelled() => {
+                        cancelled = true;
+                        break;
+                    }
+                    input = input_receiver.recv(), if stdin_open => {
+                        match input {
+                            Some(ExecInput::Data(bytes)) => {
+                                let _ = channel.data(&bytes[..]).await;
+                            }
+                            Some(ExecInput::Eof) => {
+                                let _ = channel.eof().await;
+                                stdin_open = false;
+                            }
+                            None => stdin_open = false,
+                        }
+                        continue;
+                    }
+                    msg = channel.wait() => msg,
+                };
+
+                // 到着順に送るため、各ストリーム内の順序は保たれる
+                let (stream, data) = match msg {
+                    Some(ChannelMsg::Data { data }) => (StdStream::Stdout, data),
+                    Some(ChannelMsg::ExtendedData { data, ext: 1 }) => (StdStream::Stderr, data),
+                    Some(ChannelMsg::ExitStatus { exit_status }) => {
+                        info.lock().await.exit_code = Some(exit_status);
+                        completion = Some(ExecCompletion::Exited { exit_code: exit_status });
+                        continue;
+                    }
+                    Some(ChannelMsg::ExitSignal {
+                        signal_name,
+                        core_dumped,
+                        error_message,
+                        ..
+                    }) => {
+                        let signal = signal_name_of(&signal_name);
+                        info.lock().await.exit_signal = Some(signal.clone());
+                        completion = Some(ExecCompletion::Signaled {
+                            signal,
+                            core_dumped,
+                            error_message,
+                        });
+                        continue;
+                    }
+                    Some(ChannelMsg::Close) | None => break,
+                    Some(_) => continue,
+                };
+                info.lock().await.bytes_produced += data.len() as u64;
+                ticket.produced().fetch_add(data.len() as u64, Ordering::Relaxed);
+
+                let text = match stream {
+                    StdStream::Stdout => stdout_decoder.decode(&data),
+                    StdStream::Stderr => stderr_decoder.decode(&data),
+                };
+                if text.is_empty() {
+                    continue;
+                }
+                let chunk = ExecStreamData::Output {
+                    stream_id: stream_id.clone(),
+                    stream,
+                    data: text,
+                    timestamp: chrono::Utc::now(),
+                };
+                // 受信側が追いつくまで次の読み取りを待つ（待っている間も中断できる）
+                tokio::select! {
+                    _ = cancel.cancelled() => {
+                        cancelled = true;
+                        break;
+                    }
+                    sent = output_sender.send(chunk) => {
+                        if sent.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+
+            if cancelled {
+                let _ = channel.close().await;
+            } else {
+                // 末尾で持ち越したバイト列を送ってから終了を知らせる
+                for (stream, decoder) in [
+                    (StdStream::Stdout, &mut stdout_decoder),
+                    (StdStream::Stderr, &mut stderr_decoder),
+                ] {
+                    let data = decoder.finish();
+                    if !data.is_empty() {
+                        let _ = output_sender
+                            .send(ExecStreamData::Output {
+                                stream_id: stream_id.clone(),
+                                stream,
+                                data,
+                                timestamp: chrono::Utc::now(),
+                            })
+                            .await;
+                    }
+                }
+                if let Some(completion) = completion {
+                    let _ = output_sender
+                        .send(ExecStreamData::Completed {
+                            stream_id: stream_id.clone(),
+                            completion,
+                            timestamp: chrono::Utc::now(),
+                        })
+                        .await;
+                }
+            }
+            info.lock().await.finished = true;
+            drop(ticket);
+        });
+
+        Ok(exec_id)
+    }
+
+    /// ストリームから次の出力を受信（終端に達したらNoneを返し、登録を解除する）
+    pub async fn receive(&self, exec_id: &str) -> Result<Option<ExecStreamData>, SshError> {
+        let receiver = {
+            let streams = self.streams.read().await;
+            streams
+                .get(exec_id)
+                .ok_or_else(|| SshError::SessionNotFound(exec_id.to_string()))?
+                .output_receiver
+                .clone()
+        };
+
+        let data = receiver.lock().await.recv().await;
+        if data.is_none() {
+            self.streams.write().await.remove(exec_id);
+        }
+
+        Ok(data)
+    }
+
+    /// 実行中のコマンドの標準入力へ書き込む
+    pub async fn write_stdin(&self, exec_id: &str, data: Vec<u8>) -> Result<(), SshError> {
+        self.send_input(exec_id, ExecInput::Data(data)).await
+    }
+
+    /// 実行中のコマンドの標準入力を閉じる（EOFを送る）
+    ///
+    /// `wc` や `sort` のように入力の終端まで読むコマンドは、これを送るまで結果を出力しない。
+    pub async fn close_stdin(&self, exec_id: &str) -> Result<(), SshError> {
+        self.send_input(exec_id, ExecInput::Eof).await
+    }
+
+    /// 標準入力への指示を送る（未送信の書き込みが溜まっている間は空くまで待つ）
+    async fn send_input(&self, exec_id: &str, input: ExecInput) -> Result<(), SshError> {
+        let input_sender = self
+            .streams
+            .read()
+            .await
+            .get(exec_id)
+            .ok_or_else(|| SshError::SessionNotFound(exec_id.to_string()))?
+            .input_sender
+            .clone();
+        input_sender
+            .send(input)
+            .await
+            .map_err(|_| SshError::CommandFailed("command has already finished".to_string()))
+    }
+
+    /// 登録中のストリーム一覧を取得
+    pub async fn list(&self) -> Vec<ExecStreamInfo> {
+        let streams = self.streams.read().await;
+        let mut infos = Vec::new();
+
+        for stream in streams.values() {
+            infos.push(stream.info.lock().await.clone());
+        }
+
+        infos
+    }
+
+    /// ストリーミング実行をキャンセルし、登録を解除する
+    pub async fn cancel(&self, exec_id: &str) -> Result<(), SshError> {
+        let mut streams = self.streams.write().await;
+        let stream = streams
+            .remove(exec_id)
+            .ok_or_else(|| SshError::SessionNotFound(exec_id.to_string()))?;
+
+        stream.cancel.cancel();
+
+        Ok(())
+    }
+
+    /// セッションの実行中のストリーミング実行をすべてキャンセルし、キャンセルした数を返す
+    pub async fn cancel_all_for_session(&self, session_id: &str) -> usize {
+        let mut streams = self.streams.write().await;
+        let mut ids = Vec::new();
+        for (id, stream) in streams.iter() {
+            let info = stream.info.lock().await;
+            if info.session_id == session_id && !info.finished {
+                ids.push(id.clone());
+            }
+        }
+
+        for id in &ids {
+            if let Some(stream) = streams.remove(id) {
+                stream.cancel.cancel();
+            }
+        }
+        ids.len()
+    }
+}
+
+impl Default for ExecStreamManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// シグナル名（`SIG` を除いた `KILL` などの形式）
+fn signal_name_of(signal: &Sig) -> String {
+    match signal {
+        Sig::Custom(name) => name.clone(),
+        other => format!("{:?}", other),
+    }
+}
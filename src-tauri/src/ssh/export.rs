@@ -0,0 +1,87 @@
+use crate::ssh::{AuthMethod, SshConfig, SshError};
+use serde::{Deserialize, Serialize};
+
+/// エクスポートファイルのスキーマバージョン
+pub const SESSION_EXPORT_VERSION: u32 = 1;
+
+/// セッション設定のエクスポート形式
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionExport {
+    pub version: u32,
+    pub exported_at: chrono::DateTime<chrono::Utc>,
+    pub sessions: Vec<SshConfig>,
+}
+
+/// インポート結果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportSummary {
+    /// 新しく作成されたセッションID
+    pub imported: Vec<String>,
+    /// 重複のためスキップした件数
+    pub skipped: usize,
+}
+
+impl SessionExport {
+    /// 秘密情報を取り除いたエクスポートを作成
+    pub fn new(configs: impl IntoIterator<Item = SshConfig>) -> Self {
+        Self {
+            version: SESSION_EXPORT_VERSION,
+            exported_at: chrono::Utc::now(),
+            sessions: configs.into_iter().map(redact_secrets).collect(),
+        }
+    }
+
+    /// JSON文字列から読み込み、バージョンを検証する
+    pub fn from_json(json: &str) -> Result<Self, SshError> {
+        let value: serde_json::Value = serde_json::from_str(json)
+            .map_err(|e| SshError::ConfigError(format!("invalid session export file: {}", e)))?;
+
+        let version = value
+            .get("version")
+            .and_then(|v| v.as_u64())
+            .ok_or_else(|| {
+                SshError::ConfigError("session export file has no version field".to_string())
+            })?;
+        if version != SESSION_EXPORT_VERSION as u64 {
+            return Err(SshError::ConfigError(format!(
+                "unsupported session export version {} (expected {})",
+                version, SESSION_EXPORT_VERSION
+            )));
+        }
+
+        serde_json::from_value(value)
+            .map_err(|e| SshError::ConfigError(format!("invalid session export file: {}", e)))
+    }
+}
+
+/// パスワードやパスフレーズを取り除く
+fn redact_secrets(mut config: SshConfig) -> SshConfig {
+    config.auth_method = match config.auth_method {
+        AuthMethod::Password(_) => AuthMethod::Password(String::new()),
+        AuthMethod::PasswordWithOtp { .. } => AuthMethod::PasswordWithOtp {
+            password: String::new(),
+            otp: String::new(),
+        },
+        AuthMethod::PublicKey {
+            private_key_path, ..
+        } => AuthMethod::PublicKey {
+            private_key_path,
+            passphrase: None,
+        },
+        AuthMethod::Agent => AuthMethod::Agent,
+    };
+    if let Some(proxy) = config.proxy.as_mut() {
+        proxy.password = None;
+    }
+    config.fallback_password = None;
+    config
+}
+
+/// 重複判定に使うキー（ホスト・ユーザー・ポート）
+pub fn session_identity(config: &SshConfig) -> (String, String, u16) {
+    (
+        config.host.to_lowercase(),
+        config.username.clone(),
+        config.port,
+    )
+}
@@ -0,0 +1,389 @@
+use crate::ssh::{EventBus, ForwardInfo, ForwardSpec, SshConnection, SshError, SshEvent};
+use russh::client::Msg;
+use russh::Channel;
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::RwLock;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+/// リモート転送の接続先（サーバー側の待ち受けポート → ローカルの接続先）
+///
+/// 再接続してもハンドラーから同じ表を参照できるよう、セッションが保持する。
+pub type RemoteForwardTargets = Arc<std::sync::Mutex<HashMap<u32, (String, u16)>>>;
+
+/// 転送に使う現在の接続（再接続時に差し替える）
+type ConnectionSlot = Arc<std::sync::Mutex<Arc<SshConnection>>>;
+
+/// ポート転送を管理する
+///
+/// 転送の定義は接続とは別に保持し、再接続後に同じ定義で再確立できるようにする。
+#[derive(Clone, Default)]
+pub struct ForwardManager {
+    forwards: Arc<RwLock<HashMap<String, ForwardEntry>>>,
+}
+
+/// 個別のポート転送
+struct ForwardEntry {
+    info: ForwardInfo,
+    connection: ConnectionSlot,
+    remote_targets: RemoteForwardTargets,
+    cancel: CancellationToken,
+}
+
+impl ForwardManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// ポート転送を開始する
+    pub async fn start(
+        &self,
+        session_id: &str,
+        connection: Arc<SshConnection>,
+        remote_targets: RemoteForwardTargets,
+        spec: ForwardSpec,
+    ) -> Result<ForwardInfo, SshError> {
+        let connection: ConnectionSlot = Arc::new(std::sync::Mutex::new(connection));
+        let cancel = CancellationToken::new();
+
+        let bound_port = match &spec {
+            ForwardSpec::Local {
+                bind_address,
+                bind_port,
+                remote_host,
+                remote_port,
+            } => {
+                let listener = TcpListener::bind((bind_address.as_str(), *bind_port)).await?;
+                let bound_port = listener.local_addr()?.port();
+                tokio::spawn(run_local_listener(
+                    listener,
+                    connection.clone(),
+                    Some((remote_host.clone(), *remote_port)),
+                    cancel.clone(),
+                ));
+                bound_port
+            }
+            ForwardSpec::Dynamic {
+                bind_address,
+                bind_port,
+            } => {
+                let listener = TcpListener::bind((bind_address.as_str(), *bind_port)).await?;
+                let bound_port = listener.local_addr()?.port();
+                tokio::spawn(run_local_listener(listener, connection.clone(), None, cancel.clone()));
+                bound_port
+            }
+            ForwardSpec::Remote { .. } => {
+                let current = current_connection(&connection);
+                request_remote_forward(&current, &remote_targets, &spec).await?
+            }
+        };
+
+        let info = ForwardInfo {
+            id: Uuid::new_v4().to_string(),
+            session_id: session_id.to_string(),
+            spec,
+            bound_port,
+            active: true,
+            error: None,
+        };
+        self.forwards.write().await.insert(
+            info.id.clone(),
+            ForwardEntry {
+                info: info.clone(),
+                connection,
+                remote_targets,
+                cancel,
+            },
+        );
+
+        Ok(info)
+    }
+
+    /// ポート転送を停止し、定義を削除する
+    pub async fn stop(&self, forward_id: &str) -> Result<(), SshError> {
+        let entry = self
+            .forwards
+            .write()
+            .await
+            .remove(forward_id)
+            .ok_or_else(|| SshError::SessionNotFound(forward_id.to_string()))?;
+
+        stop_entry(&entry).await;
+        Ok(())
+    }
+
+    /// セッションのポート転送をすべて停止する
+    pub async fn stop_all_for_session(&self, session_id: &str) {
+        let entries: Vec<ForwardEntry> = {
+            let mut forwards = self.forwards.write().await;
+            let ids: Vec<String> = forwards
+                .values()
+                .filter(|entry| entry.info.session_id == session_id)
+                .map(|entry| entry.info.id.clone())
+                .collect();
+            ids.iter().filter_map(|id| forwards.remove(id)).collect()
+        };
+
+        for entry in &entries {
+            stop_entry(entry).await;
+        }
+    }
+
+    /// すべてのセッションのポート転送を停止し、停止した数を返す
+    pub async fn stop_all(&self) -> usize {
+        let entries: Vec<ForwardEntry> = self.forwards.write().await.drain().map(|(_, entry)| entry).collect();
+
+        for entry in &entries {
+            stop_entry(entry).await;
+        }
+        entries.len()
+    }
+
+    /// すべてのセッションのポート転送一覧を取得
+    pub async fn list_all(&self) -> Vec<ForwardInfo> {
+        self.forwards
+            .read()
+            .await
+            .values()
+            .map(|entry| entry.info.clone())
+            .collect()
+    }
+
+    /// セッションのポート転送一覧を取得
+    pub async fn list(&self, session_id: &str) -> Vec<ForwardInfo> {
+        self.forwards
+            .read()
+            .await
+            .values()
+            .filter(|entry| entry.info.session_id == session_id)
+            .map(|entry| entry.info.clone())
+            .collect()
+    }
+
+    /// 再接続後の新しい接続上でセッションのポート転送を再確立する
+    ///
+    /// ローカル側で待ち受ける転送は待ち受けを続けたまま接続だけを差し替え、
+    /// リモート転送はサーバーに改めて待ち受けを要求する。結果は転送ごとにイベントで通知する。
+    pub async fn restore(&self, session_id: &str, connection: Arc<SshConnection>, events: &EventBus) {
+        let mut forwards = self.forwards.write().await;
+        for entry in forwards
+            .values_mut()
+            .filter(|entry| entry.info.session_id == session_id)
+        {
+            if let Ok(mut slot) = entry.connection.lock() {
+                *slot = connection.clone();
+            }
+
+            let result = match &entry.info.spec {
+                ForwardSpec::Remote { .. } => {
+                    request_remote_forward(&connection, &entry.remote_targets, &entry.info.spec)
+                        .await
+                        .map(|port| entry.info.bound_port = port)
+                }
+                _ => Ok(()),
+            };
+
+            match result {
+                Ok(()) => {
+                    entry.info.active = true;
+                    entry.info.error = None;
+                    events.emit(SshEvent::ForwardRestored {
+                        session_id: session_id.to_string(),
+                        forward_id: entry.info.id.clone(),
+                    });
+                }
+                Err(e) => {
+                    entry.info.active = false;
+                    entry.info.error = Some(e.to_string());
+                    events.emit(SshEvent::ForwardRestoreFailed {
+                        session_id: session_id.to_string(),
+                        forward_id: entry.info.id.clone(),
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+fn current_connection(slot: &ConnectionSlot) -> Arc<SshConnection> {
+    match slot.lock() {
+        Ok(connection) => connection.clone(),
+        Err(poisoned) => poisoned.into_inner().clone(),
+    }
+}
+
+/// 待ち受けを止め、リモート転送ならサーバー側の待ち受けも解除する
+async fn stop_entry(entry: &ForwardEntry) {
+    entry.cancel.cancel();
+
+    if let ForwardSpec::Remote { bind_address, .. } = &entry.info.spec {
+        if let Ok(mut targets) = entry.remote_targets.lock() {
+            targets.remove(&(entry.info.bound_port as u32));
+        }
+        let connection = current_connection(&entry.connection);
+        let _ = connection
+            .cancel_tcpip_forward(bind_address.clone(), entry.info.bound_port as u32)
+            .await;
+    }
+}
+
+/// サーバーにリモート転送の待ち受けを要求し、割り当てられたポートを返す
+async fn request_remote_forward(
+    connection: &SshConnection,
+    remote_targets: &RemoteForwardTargets,
+    spec: &ForwardSpec,
+) -> Result<u16, SshError> {
+    let ForwardSpec::Remote {
+        bind_address,
+        bind_port,
+        local_host,
+        local_port,
+    } = spec
+    else {
+        return Err(SshError::ConfigError("not a remote forward".to_string()));
+    };
+
+    let assigned = connection
+        .tcpip_forward(bind_address.clone(), *bind_port as u32)
+        .await
+        .map_err(|e| SshError::ConnectionFailed(format!("remote forward rejected: {}", e)))?;
+    // ポートを指定した場合、サーバーは割り当て結果として0を返すことがある
+    let port = if *bind_port == 0 { assigned } else { *bind_port as u32 };
+
+    if let Ok(mut targets) = remote_targets.lock() {
+        targets.insert(port, (local_host.clone(), *local_port));
+    }
+    Ok(port as u16)
+}
+
+/// ローカルの待ち受けを受け付け、接続ごとに direct-tcpip チャネルへ中継する
+///
+/// `target` が `None` の場合は SOCKS5 のリクエストから接続先を決める。
+async fn run_local_listener(
+    listener: TcpListener,
+    connection: ConnectionSlot,
+    target: Option<(String, u16)>,
+    cancel: CancellationToken,
+) {
+    loop {
+        let accepted = tokio::select! {
+            _ = cancel.cancelled() => break,
+            accepted = listener.accept() => accepted,
+        };
+        let Ok((socket, peer)) = accepted else {
+            continue;
+        };
+
+        // 受け付けた時点の接続を使う（再接続後は差し替えられた接続になる）
+        let connection = current_connection(&connection);
+        let target = target.clone();
+        let cancel = cancel.clone();
+        tokio::spawn(async move {
+            let is_socks = target.is_none();
+            let relay = async move {
+                let mut socket = socket;
+                let (host, port) = match target {
+                    Some(target) => target,
+                    None => socks5_handshake(&mut socket).await?,
+                };
+                let channel = connection
+                    .channel_open_direct_tcpip(host, port as u32, peer.ip().to_string(), peer.port() as u32)
+                    .await;
+                if is_socks {
+                    let reply = if channel.is_ok() { SOCKS5_SUCCEEDED } else { SOCKS5_FAILURE };
+                    socket.write_all(&reply).await?;
+                }
+                let channel = channel.map_err(|e| std::io::Error::other(e.to_string()))?;
+                relay_channel(channel, socket).await
+            };
+            tokio::select! {
+                _ = cancel.cancelled() => {}
+                _ = relay => {}
+            }
+        });
+    }
+}
+
+/// サーバーから開かれたリモート転送のチャネルをローカルの接続先へ中継する
+pub(crate) async fn relay_to_local(channel: Channel<Msg>, host: String, port: u16) {
+    match TcpStream::connect((host.as_str(), port)).await {
+        Ok(socket) => {
+            let _ = relay_channel(channel, socket).await;
+        }
+        Err(_) => {
+            let _ = channel.close().await;
+        }
+    }
+}
+
+async fn relay_channel(channel: Channel<Msg>, mut socket: TcpStream) -> std::io::Result<()> {
+    let mut stream = channel.into_stream();
+    tokio::io::copy_bidirectional(&mut stream, &mut socket).await?;
+    Ok(())
+}
+
+/// SOCKS5 の応答（バインドアドレスは使わないため 0.0.0.0:0 を返す）
+const SOCKS5_SUCCEEDED: [u8; 10] = [5, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+const SOCKS5_FAILURE: [u8; 10] = [5, 1, 0, 1, 0, 0, 0, 0, 0, 0];
+const SOCKS5_COMMAND_NOT_SUPPORTED: [u8; 10] = [5, 7, 0, 1, 0, 0, 0, 0, 0, 0];
+const SOCKS5_ADDRESS_NOT_SUPPORTED: [u8; 10] = [5, 8, 0, 1, 0, 0, 0, 0, 0, 0];
+
+/// SOCKS5 のネゴシエーションを行い、CONNECT の接続先を返す（認証なしのみ対応）
+async fn socks5_handshake(socket: &mut TcpStream) -> std::io::Result<(String, u16)> {
+    let mut header = [0u8; 2];
+    socket.read_exact(&mut header).await?;
+    if header[0] != 5 {
+        return Err(invalid_data("unsupported SOCKS version"));
+    }
+    let mut methods = vec![0u8; header[1] as usize];
+    socket.read_exact(&mut methods).await?;
+    if !methods.contains(&0) {
+        socket.write_all(&[5, 0xff]).await?;
+        return Err(invalid_data("no acceptable SOCKS authentication method"));
+    }
+    socket.write_all(&[5, 0]).await?;
+
+    let mut request = [0u8; 4];
+    socket.read_exact(&mut request).await?;
+    if request[1] != 1 {
+        socket.write_all(&SOCKS5_COMMAND_NOT_SUPPORTED).await?;
+        return Err(invalid_data("only SOCKS CONNECT is supported"));
+    }
+
+    let host = match request[3] {
+        1 => {
+            let mut addr = [0u8; 4];
+            socket.read_exact(&mut addr).await?;
+            Ipv4Addr::from(addr).to_string()
+        }
+        3 => {
+            let mut len = [0u8; 1];
+            socket.read_exact(&mut len).await?;
+            let mut name = vec![0u8; len[0] as usize];
+            socket.read_exact(&mut name).await?;
+            String::from_utf8_lossy(&name).to_string()
+        }
+        4 => {
+            let mut addr = [0u8; 16];
+            socket.read_exact(&mut addr).await?;
+            Ipv6Addr::from(addr).to_string()
+        }
+        _ => {
+            socket.write_all(&SOCKS5_ADDRESS_NOT_SUPPORTED).await?;
+            return Err(invalid_data("unsupported SOCKS address type"));
+        }
+    };
+
+    let mut port = [0u8; 2];
+    socket.read_exact(&mut port).await?;
+    Ok((host, u16::from_be_bytes(port)))
+}
+
+fn invalid_data(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
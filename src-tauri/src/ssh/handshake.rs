@@ -0,0 +1,158 @@
+use std::sync::Mutex;
+
+/// 平文のハンドシェイクとして保持する最大バイト数
+const MAX_CAPTURE_BYTES: usize = 64 * 1024;
+
+/// SSH_MSG_KEXINIT のメッセージ番号
+const MSG_KEXINIT: u8 = 20;
+
+/// サーバーから受信した平文部分（識別文字列と KEXINIT）を記録する
+///
+/// russh はネゴシエーション結果を公開していないため、暗号化が始まる前の
+/// 受信データから取得し、クライアント側の優先順位と突き合わせて算出する。
+#[derive(Default)]
+pub struct HandshakeCapture {
+    state: Mutex<CaptureState>,
+}
+
+#[derive(Default)]
+struct CaptureState {
+    buffer: Vec<u8>,
+    server_version: Option<String>,
+    server_kexinit: Option<KexInit>,
+    done: bool,
+}
+
+/// サーバーが提示したアルゴリズム一覧
+#[derive(Debug, Clone, Default)]
+pub struct KexInit {
+    pub kex: Vec<String>,
+    pub host_key: Vec<String>,
+    pub cipher_client_to_server: Vec<String>,
+    pub cipher_server_to_client: Vec<String>,
+    pub mac_client_to_server: Vec<String>,
+    pub mac_server_to_client: Vec<String>,
+    pub compression_client_to_server: Vec<String>,
+    pub compression_server_to_client: Vec<String>,
+}
+
+impl HandshakeCapture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 記録が完了しているか
+    pub fn is_complete(&self) -> bool {
+        self.state.lock().map(|s| s.done).unwrap_or(true)
+    }
+
+    /// 受信データを追加
+    pub fn feed(&self, bytes: &[u8]) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        if state.done {
+            return;
+        }
+
+        state.buffer.extend_from_slice(bytes);
+        state.parse();
+
+        if state.buffer.len() > MAX_CAPTURE_BYTES {
+            state.done = true;
+            state.buffer = Vec::new();
+        }
+    }
+
+    /// サーバーの識別文字列（例: `SSH-2.0-OpenSSH_9.6`）
+    pub fn server_version(&self) -> Option<String> {
+        self.state.lock().ok()?.server_version.clone()
+    }
+
+    /// サーバーの KEXINIT
+    pub fn server_kexinit(&self) -> Option<KexInit> {
+        self.state.lock().ok()?.server_kexinit.clone()
+    }
+}
+
+impl CaptureState {
+    fn parse(&mut self) {
+        // 識別文字列の前にはバナー行が来ることがある（RFC 4253 4.2）
+        while self.server_version.is_none() {
+            let Some(end) = self.buffer.iter().position(|b| *b == b'\n') else {
+                return;
+            };
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line).trim_end().to_string();
+            if line.starts_with("SSH-") {
+                self.server_version = Some(line);
+            }
+        }
+
+        // 識別文字列の後の最初のバイナリパケットが KEXINIT
+        if self.buffer.len() < 5 {
+            return;
+        }
+        let packet_length = u32::from_be_bytes([
+            self.buffer[0],
+            self.buffer[1],
+            self.buffer[2],
+            self.buffer[3],
+        ]) as usize;
+        if self.buffer.len() < 4 + packet_length {
+            return;
+        }
+
+        let padding_length = self.buffer[4] as usize;
+        let payload_end = (4 + packet_length).saturating_sub(padding_length);
+        if payload_end > 5 && self.buffer[5] == MSG_KEXINIT {
+            self.server_kexinit = parse_kexinit(&self.buffer[5..payload_end]);
+        }
+
+        self.done = true;
+        self.buffer = Vec::new();
+    }
+}
+
+/// KEXINIT ペイロードを解析する
+fn parse_kexinit(payload: &[u8]) -> Option<KexInit> {
+    // メッセージ番号(1) + cookie(16)
+    let mut rest = payload.get(17..)?;
+    let mut lists = Vec::with_capacity(8);
+
+    for _ in 0..8 {
+        let len = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+        let names = rest.get(4..4 + len)?;
+        lists.push(
+            String::from_utf8_lossy(names)
+                .split(',')
+                .filter(|n| !n.is_empty())
+                .map(str::to_string)
+                .collect::<Vec<_>>(),
+        );
+        rest = &rest[4 + len..];
+    }
+
+    let mut lists = lists.into_iter();
+    Some(KexInit {
+        kex: lists.next()?,
+        host_key: lists.next()?,
+        cipher_client_to_server: lists.next()?,
+        cipher_server_to_client: lists.next()?,
+        mac_client_to_server: lists.next()?,
+        mac_server_to_client: lists.next()?,
+        compression_client_to_server: lists.next()?,
+        compression_server_to_client: lists.next()?,
+    })
+}
+
+/// クライアントの優先順で、サーバーも対応している最初のアルゴリズムを選ぶ（RFC 4253 7.1）
+pub fn negotiate<'a, I>(client: I, server: &[String]) -> Option<String>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    client
+        .into_iter()
+        .find(|name| server.iter().any(|s| s == name))
+        .map(str::to_string)
+}
@@ -0,0 +1,91 @@
+use crate::ssh::{PinnedHostKey, SshError};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tokio::sync::RwLock;
+
+/// 固定したホスト鍵の保存ファイル名
+pub const HOST_TRUST_FILE: &str = "host_keys.json";
+
+/// アプリが固定したホスト鍵（`trust_on_first_use` で初回接続時に記録する）
+///
+/// `open` で保存先を指定した場合は、変更のたびにJSONで書き出す。
+#[derive(Debug, Default)]
+pub struct HostTrustStore {
+    /// `host:port` ごとの鍵
+    keys: RwLock<BTreeMap<(String, u16), PinnedHostKey>>,
+    path: RwLock<Option<PathBuf>>,
+}
+
+impl HostTrustStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 保存ファイルを読み込み、以降の変更の保存先にする（ファイルがなければ空のまま）
+    ///
+    /// 解釈できないファイルは上書きしないよう、保存先にしない。
+    pub async fn open(&self, path: &Path) -> Result<(), SshError> {
+        match tokio::fs::read_to_string(path).await {
+            Ok(json) => {
+                let keys: Vec<PinnedHostKey> = serde_json::from_str(&json)
+                    .map_err(|e| SshError::ConfigError(format!("invalid host key file: {}", e)))?;
+                *self.keys.write().await = keys
+                    .into_iter()
+                    .map(|key| ((key.host.clone(), key.port), key))
+                    .collect();
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        *self.path.write().await = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    /// 固定した鍵の一覧（ホスト名・ポート順）
+    pub async fn list(&self) -> Vec<PinnedHostKey> {
+        self.keys.read().await.values().cloned().collect()
+    }
+
+    /// ホストに固定した鍵
+    pub async fn get(&self, host: &str, port: u16) -> Option<PinnedHostKey> {
+        self.keys.read().await.get(&(host.to_string(), port)).cloned()
+    }
+
+    /// 鍵を固定する（同じホストの鍵は置き換える）
+    pub async fn pin(&self, key: PinnedHostKey) -> Result<(), SshError> {
+        self.keys
+            .write()
+            .await
+            .insert((key.host.clone(), key.port), key);
+        self.persist().await
+    }
+
+    /// 固定を解除する（固定されていなければ false）
+    pub async fn remove(&self, host: &str, port: u16) -> Result<bool, SshError> {
+        if self
+            .keys
+            .write()
+            .await
+            .remove(&(host.to_string(), port))
+            .is_none()
+        {
+            return Ok(false);
+        }
+        self.persist().await?;
+        Ok(true)
+    }
+
+    /// 保存先が指定されていれば全件を書き出す
+    async fn persist(&self) -> Result<(), SshError> {
+        let Some(path) = self.path.read().await.clone() else {
+            return Ok(());
+        };
+        let json = serde_json::to_string_pretty(&self.list().await)
+            .map_err(|e| SshError::ConfigError(e.to_string()))?;
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        tokio::fs::write(&path, json).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+use crate::ssh::session::shell_quote;
+use crate::ssh::{EventBus, ExecCompletion, ExecStreamData, ExecStreamManager, JournalEntry, SshEvent, StdStream};
+use std::sync::Arc;
+
+/// エラーとして通知する標準エラー出力の最大長
+const MAX_JOURNAL_STDERR: usize = 4096;
+
+/// `journalctl` でユニットのログをJSON形式で取得するコマンド
+pub fn journal_command(unit: &str, lines: u32, follow: bool) -> String {
+    let mut command = format!(
+        "journalctl --no-pager -o json -n {} -u {}",
+        lines,
+        shell_quote(unit)
+    );
+    if follow {
+        command.push_str(" -f");
+    }
+    command
+}
+
+/// `journalctl -o json` の1行を解釈する（解釈できない行は `None`）
+pub fn parse_journal_line(line: &str) -> Option<JournalEntry> {
+    let value: serde_json::Value = serde_json::from_str(line).ok()?;
+    let fields = value.as_object()?;
+    let text = |name: &str| fields.get(name).and_then(journal_field_text);
+
+    let timestamp = text("__REALTIME_TIMESTAMP")
+        .and_then(|micros| micros.parse::<i64>().ok())
+        .and_then(chrono::DateTime::from_timestamp_micros);
+    Some(JournalEntry {
+        timestamp,
+        priority: text("PRIORITY").and_then(|priority| priority.parse().ok()),
+        message: text("MESSAGE").unwrap_or_default(),
+        unit: text("_SYSTEMD_UNIT").or_else(|| text("UNIT")),
+        identifier: text("SYSLOG_IDENTIFIER"),
+        pid: text("_PID").and_then(|pid| pid.parse().ok()),
+    })
+}
+
+/// フィールドの値を文字列にする
+///
+/// 表示できないバイトを含む値は、journalctl がバイト値の配列として出力する。
+fn journal_field_text(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(text) => Some(text.clone()),
+        serde_json::Value::Array(bytes) => {
+            let bytes: Vec<u8> = bytes
+                .iter()
+                .filter_map(|byte| byte.as_u64().and_then(|byte| u8::try_from(byte).ok()))
+                .collect();
+            Some(String::from_utf8_lossy(&bytes).into_owned())
+        }
+        _ => None,
+    }
+}
+
+/// ストリーミング実行した `journalctl` の出力を行ごとに解釈し、`JournalData` イベントで通知する
+///
+/// 終了時は `eof` を立てたイベントを送る。正常終了しなかった場合は標準エラー出力を `error` に付ける。
+pub async fn relay_journal(
+    exec_manager: Arc<ExecStreamManager>,
+    journal_id: String,
+    session_id: String,
+    events: EventBus,
+) {
+    let emit = |entry: Option<JournalEntry>, eof: bool, exit_code: Option<u32>, error: Option<String>| {
+        events.emit(SshEvent::JournalData {
+            journal_id: journal_id.clone(),
+            session_id: session_id.clone(),
+            entry,
+            eof,
+            exit_code,
+            error,
+        });
+    };
+
+    let mut pending = String::new();
+    let mut stderr = String::new();
+    let mut completion = None;
+    while let Ok(Some(item)) = exec_manager.receive(&journal_id).await {
+        let (stream, data) = match item {
+            ExecStreamData::Output { stream, data, .. } => (stream, data),
+            ExecStreamData::Completed { completion: done, .. } => {
+                completion = Some(done);
+                continue;
+            }
+        };
+        match stream {
+            StdStream::Stdout => pending.push_str(&data),
+            StdStream::Stderr => {
+                if stderr.len() < MAX_JOURNAL_STDERR {
+                    stderr.push_str(&data);
+                }
+                continue;
+            }
+        }
+
+        // 最後の改行までを処理し、途中の行は次のチャンクと連結する
+        let Some(end) = pending.rfind('\n') else {
+            continue;
+        };
+        let rest = pending.split_off(end + 1);
+        for line in pending.lines() {
+            if let Some(entry) = parse_journal_line(line) {
+                emit(Some(entry), false, None, None);
+            }
+        }
+        pending = rest;
+    }
+    if let Some(entry) = parse_journal_line(pending.trim_end()) {
+        emit(Some(entry), false, None, None);
+    }
+
+    let (exit_code, error) = match completion {
+        Some(ExecCompletion::Exited { exit_code: 0 }) => (Some(0), None),
+        Some(ExecCompletion::Exited { exit_code }) => (Some(exit_code), Some(stderr.trim().to_string())),
+        Some(ExecCompletion::Signaled { signal, .. }) => (None, Some(format!("terminated by signal {}", signal))),
+        // キャンセルまたは接続の切断
+        None => (None, None),
+    };
+    emit(None, true, exit_code, error);
+}
@@ -0,0 +1,141 @@
+use crate::ssh::SshError;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// 秘密鍵の読み込み元
+///
+/// 設定の `private_key_path` から鍵の内容を取得する処理を差し替えられるようにするためのもの。
+pub trait KeyProvider: Send + Sync {
+    /// `path` の秘密鍵の内容（PEM/OpenSSH形式の文字列）を返す
+    fn read_key(&self, path: &str, allow_insecure_permissions: bool) -> Result<String, SshError>;
+}
+
+/// ファイルから鍵を読み込む標準の実装
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileKeyProvider;
+
+impl KeyProvider for FileKeyProvider {
+    fn read_key(&self, path: &str, allow_insecure_permissions: bool) -> Result<String, SshError> {
+        if !allow_insecure_permissions {
+            check_key_permissions(path)?;
+        }
+
+        std::fs::read_to_string(path).map_err(|e| SshError::AuthenticationFailed(e.to_string()))
+    }
+}
+
+/// パスごとに登録した鍵をメモリから返す実装（ファイルに書き出さずに鍵を渡す場合に使う）
+#[derive(Debug, Default)]
+pub struct MemoryKeyProvider {
+    keys: RwLock<HashMap<String, String>>,
+}
+
+impl MemoryKeyProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `path` として参照される鍵を登録する
+    pub fn insert(&self, path: &str, contents: &str) {
+        if let Ok(mut keys) = self.keys.write() {
+            keys.insert(path.to_string(), contents.to_string());
+        }
+    }
+
+    /// 登録した鍵を削除する
+    pub fn remove(&self, path: &str) {
+        if let Ok(mut keys) = self.keys.write() {
+            keys.remove(path);
+        }
+    }
+}
+
+impl KeyProvider for MemoryKeyProvider {
+    fn read_key(&self, path: &str, _allow_insecure_permissions: bool) -> Result<String, SshError> {
+        self.keys
+            .read()
+            .ok()
+            .and_then(|keys| keys.get(path).cloned())
+            .ok_or_else(|| SshError::AuthenticationFailed(format!("key not found: {}", path)))
+    }
+}
+
+/// 秘密鍵ファイルがグループ・その他から読めないことを確認する（OpenSSHと同じ基準）
+#[cfg(unix)]
+fn check_key_permissions(path: &str) -> Result<(), SshError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = std::fs::metadata(path)
+        .map_err(|e| SshError::AuthenticationFailed(e.to_string()))?
+        .permissions()
+        .mode()
+        & 0o777;
+
+    if mode & 0o077 != 0 {
+        return Err(SshError::AuthenticationFailed(format!(
+            "private key has insecure permissions {:04o} (expected 0600)",
+            mode
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_key_permissions(_path: &str) -> Result<(), SshError> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh::test_server::{TestServer, TEST_CLIENT_KEY};
+    use crate::ssh::{AuthMethod, EventBus, SshSessionManager};
+    use std::sync::Arc;
+
+    const KEY_PATH: &str = "memory://client";
+
+    fn public_key_auth(path: &str) -> AuthMethod {
+        AuthMethod::PublicKey {
+            private_key_path: path.to_string(),
+            passphrase: None,
+        }
+    }
+
+    #[test]
+    fn memory_provider_returns_registered_keys_only() {
+        let provider = MemoryKeyProvider::new();
+        provider.insert(KEY_PATH, TEST_CLIENT_KEY);
+        assert_eq!(provider.read_key(KEY_PATH, false).unwrap(), TEST_CLIENT_KEY);
+
+        provider.remove(KEY_PATH);
+        assert!(matches!(
+            provider.read_key(KEY_PATH, false),
+            Err(SshError::AuthenticationFailed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn session_authenticates_with_key_from_memory_provider() {
+        let server = TestServer::start().await;
+        let provider = Arc::new(MemoryKeyProvider::new());
+        provider.insert(KEY_PATH, TEST_CLIENT_KEY);
+        let manager = SshSessionManager::new(EventBus::new()).with_key_provider(provider);
+
+        let session_id = manager.create_session(server.config(public_key_auth(KEY_PATH))).await.unwrap();
+        manager.connect(&session_id).await.unwrap();
+        assert!(manager.get_connection(&session_id).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn session_does_not_fall_back_to_filesystem_for_unknown_keys() {
+        let server = TestServer::start().await;
+        let manager = SshSessionManager::new(EventBus::new()).with_key_provider(Arc::new(MemoryKeyProvider::new()));
+
+        let session_id = manager.create_session(server.config(public_key_auth(KEY_PATH))).await.unwrap();
+        assert!(matches!(
+            manager.connect(&session_id).await,
+            Err(SshError::AuthenticationFailed(_))
+        ));
+    }
+}
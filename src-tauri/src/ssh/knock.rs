@@ -0,0 +1,41 @@
+use crate::ssh::{KnockProtocol, PortKnockConfig, SshError};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::{TcpStream, UdpSocket};
+
+/// ノックの既定の間隔
+const DEFAULT_KNOCK_DELAY: Duration = Duration::from_millis(200);
+
+/// TCPのノックで接続要求を送ってから諦めるまでの時間（SYNが送られれば十分）
+const TCP_KNOCK_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// 設定された順にポートをノックする
+///
+/// ノック先のポートは閉じているのが普通なので、TCPの接続拒否やタイムアウトは無視する。
+pub async fn knock(ip: IpAddr, config: &PortKnockConfig) -> Result<(), SshError> {
+    let delay = config.delay_ms.map(Duration::from_millis).unwrap_or(DEFAULT_KNOCK_DELAY);
+
+    for (i, (port, protocol)) in config.sequence.iter().enumerate() {
+        if i > 0 && !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        let target = SocketAddr::new(ip, *port);
+        match protocol {
+            KnockProtocol::Tcp => {
+                let _ = tokio::time::timeout(TCP_KNOCK_TIMEOUT, TcpStream::connect(target)).await;
+            }
+            KnockProtocol::Udp => {
+                let bind: SocketAddr = if ip.is_ipv4() {
+                    (Ipv4Addr::UNSPECIFIED, 0).into()
+                } else {
+                    (Ipv6Addr::UNSPECIFIED, 0).into()
+                };
+                let socket = UdpSocket::bind(bind).await?;
+                socket.send_to(&[], target).await?;
+            }
+        }
+    }
+    // 最後のノックから接続までも同じ間隔を空け、ファイアウォールの反映を待つ
+    tokio::time::sleep(delay).await;
+    Ok(())
+}
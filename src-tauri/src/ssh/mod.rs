@@ -1,10 +1,53 @@
+pub mod archive;
+pub mod audit;
+pub mod auth;
 pub mod client;
+pub mod clock;
+pub mod diagnostics;
+pub mod env_profile;
+pub mod events;
+pub mod exec;
+pub mod export;
+pub mod forward;
+pub mod handshake;
+pub mod host_trust;
+pub mod journal;
+pub mod key_provider;
+pub mod keys;
+pub mod knock;
+pub mod output;
+pub mod proxy;
+pub mod resolver;
+pub mod scrollback;
 pub mod session;
+pub mod sftp;
+pub mod subsystem;
+pub mod telemetry;
+#[cfg(test)]
+mod test_server;
+pub mod transfer;
 pub mod types;
 pub mod terminal;
+pub mod terminal_log;
+pub mod x11;
 
+pub use audit::{CommandAuditor, NoopAuditor};
+pub use auth::AuthPromptBroker;
 pub use client::*;
+pub use clock::{Clock, SystemClock};
+pub use env_profile::{EnvProfileStore, ENV_PROFILES_FILE};
+pub use events::*;
+pub use exec::*;
+pub use export::*;
+pub use forward::ForwardManager;
+pub use host_trust::{HostTrustStore, HOST_TRUST_FILE};
+pub use key_provider::{FileKeyProvider, KeyProvider, MemoryKeyProvider};
+pub use scrollback::{Scrollback, DEFAULT_SCROLLBACK_BYTES, DEFAULT_SCROLLBACK_LINES};
 pub use session::*;
+pub use sftp::SftpManager;
+pub use subsystem::SubsystemManager;
+pub use transfer::TransferManager;
 pub use types::*;
 pub use terminal::*;
+pub use terminal_log::TerminalLog;
 
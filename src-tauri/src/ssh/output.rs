@@ -0,0 +1,628 @@
+use crate::ssh::{ProcessInfo, TerminalOutputFilter};
+use base64::Engine;
+use std::collections::{HashMap, VecDeque};
+
+/// コマンド出力の蓄積バッファ
+///
+/// 行数の上限が指定された場合は末尾の行だけをリングバッファに保持し、
+/// それ以前の行は破棄してメモリ使用量を抑える。
+pub struct OutputBuffer {
+    tail_lines: Option<usize>,
+    max_bytes: Option<usize>,
+    received: usize,
+    truncated: bool,
+    data: Vec<u8>,
+    lines: VecDeque<Vec<u8>>,
+}
+
+impl OutputBuffer {
+    pub fn new(tail_lines: Option<usize>) -> Self {
+        Self {
+            tail_lines,
+            max_bytes: None,
+            received: 0,
+            truncated: false,
+            data: Vec::new(),
+            lines: VecDeque::new(),
+        }
+    }
+
+    /// 受け取るバイト数の上限を設定する（超えた分は破棄する）
+    pub fn with_max_bytes(mut self, max_bytes: Option<usize>) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// 上限を超えて破棄した出力があるか
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// 受信したデータを追加
+    pub fn extend(&mut self, bytes: &[u8]) {
+        let bytes = match self.max_bytes {
+            Some(max) => {
+                let remaining = max.saturating_sub(self.received);
+                if bytes.len() > remaining {
+                    self.truncated = true;
+                }
+                &bytes[..bytes.len().min(remaining)]
+            }
+            None => bytes,
+        };
+        self.received += bytes.len();
+
+        let Some(limit) = self.tail_lines else {
+            self.data.extend_from_slice(bytes);
+            return;
+        };
+
+        // data は改行で終わっていない最終行を保持する
+        for chunk in bytes.split_inclusive(|b| *b == b'\n') {
+            self.data.extend_from_slice(chunk);
+            if chunk.ends_with(b"\n") {
+                self.lines.push_back(std::mem::take(&mut self.data));
+                while self.lines.len() > limit {
+                    self.lines.pop_front();
+                }
+            }
+        }
+    }
+
+    /// 保持している出力を取り出す
+    pub fn into_bytes(mut self) -> Vec<u8> {
+        let Some(limit) = self.tail_lines else {
+            return self.data;
+        };
+
+        if !self.data.is_empty() {
+            self.lines.push_back(std::mem::take(&mut self.data));
+        }
+        while self.lines.len() > limit {
+            self.lines.pop_front();
+        }
+
+        self.lines.into_iter().flatten().collect()
+    }
+}
+
+/// 読み込み単位をまたいで分割されたUTF-8の文字を復元するデコーダー
+///
+/// 末尾の不完全なマルチバイト文字は次の読み込みまで持ち越し、置換文字に化けないようにする。
+#[derive(Default)]
+pub struct Utf8Decoder {
+    pending: Vec<u8>,
+}
+
+impl Utf8Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 受信したバイト列を文字列にする
+    pub fn decode(&mut self, bytes: &[u8]) -> String {
+        self.pending.extend_from_slice(bytes);
+        let complete = self.pending.len() - incomplete_tail_len(&self.pending);
+        let rest = self.pending.split_off(complete);
+        let text = String::from_utf8_lossy(&self.pending).to_string();
+        self.pending = rest;
+        text
+    }
+
+    /// 持ち越している不完全なバイト列を取り出す（終端で呼ぶ）
+    pub fn finish(&mut self) -> String {
+        let text = String::from_utf8_lossy(&self.pending).to_string();
+        self.pending.clear();
+        text
+    }
+}
+
+/// 解釈しきれない長さのシーケンスはそのまま通す（CSI / OSC の上限）
+const MAX_CSI_LENGTH: usize = 64;
+const MAX_OSC_LENGTH: usize = 4096;
+
+/// クリップボードへの書き込み（OSC 52）として受け付ける長さの上限
+const MAX_CLIPBOARD_OSC_LENGTH: usize = 1024 * 1024;
+
+/// クリップボード操作のOSCの接頭辞
+const CLIPBOARD_OSC_PREFIX: &[u8] = b"\x1b]52;";
+
+const BEL: u8 = 0x07;
+const ESC: u8 = 0x1b;
+
+/// 制御シーケンスの解析状態
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EscapeState {
+    Ground,
+    Escape,
+    Csi,
+    Osc,
+}
+
+/// リモートのプログラムが要求したクリップボードへの書き込み
+#[derive(Debug, Clone)]
+pub struct ClipboardWrite {
+    /// 対象の選択領域（`c` はクリップボード、`p` はプライマリ選択など）
+    pub selection: String,
+    pub text: String,
+}
+
+/// ターミナル出力から指定した種類の制御シーケンスを取り除くフィルター
+///
+/// 読み込み単位をまたいだシーケンスは完結するまで保持し、途中で分割して出力しない。
+pub struct EscapeFilter {
+    filter: TerminalOutputFilter,
+    state: EscapeState,
+    sequence: Vec<u8>,
+    /// OSC 52 を取り出すか
+    capture_clipboard: bool,
+    clipboard: Vec<ClipboardWrite>,
+}
+
+impl EscapeFilter {
+    pub fn new(filter: TerminalOutputFilter) -> Self {
+        Self {
+            filter,
+            state: EscapeState::Ground,
+            sequence: Vec::new(),
+            capture_clipboard: false,
+            clipboard: Vec::new(),
+        }
+    }
+
+    /// OSC 52 を出力から取り除き、クリップボードへの書き込みとして取り出す
+    ///
+    /// クリップボードの読み取り要求（`?`）は応答せずに破棄する。
+    pub fn with_clipboard_capture(mut self) -> Self {
+        self.capture_clipboard = true;
+        self
+    }
+
+    /// これまでに受け取ったクリップボードへの書き込みを取り出す
+    pub fn take_clipboard(&mut self) -> Vec<ClipboardWrite> {
+        std::mem::take(&mut self.clipboard)
+    }
+
+    /// 受信したバイト列をフィルターにかける
+    pub fn filter(&mut self, bytes: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(bytes.len());
+        for &byte in bytes {
+            match self.state {
+                EscapeState::Ground => match byte {
+                    BEL if self.filter.drop_bell => {}
+                    ESC => {
+                        self.sequence.push(byte);
+                        self.state = EscapeState::Escape;
+                    }
+                    _ => output.push(byte),
+                },
+                EscapeState::Escape => {
+                    self.sequence.push(byte);
+                    match byte {
+                        b'[' => self.state = EscapeState::Csi,
+                        b']' => self.state = EscapeState::Osc,
+                        b'7' | b'8' => self.complete(&mut output, self.filter.drop_cursor_save_restore),
+                        _ => self.complete(&mut output, false),
+                    }
+                }
+                EscapeState::Csi => {
+                    self.sequence.push(byte);
+                    if (0x40..=0x7e).contains(&byte) {
+                        // パラメーター付きの `CSI ... u` は別の意味を持つため対象外
+                        let drop = self.filter.drop_cursor_save_restore
+                            && (self.sequence == b"\x1b[s" || self.sequence == b"\x1b[u");
+                        self.complete(&mut output, drop);
+                    } else if self.sequence.len() > MAX_CSI_LENGTH {
+                        self.complete(&mut output, false);
+                    }
+                }
+                EscapeState::Osc => {
+                    let terminated = byte == BEL || (byte == b'\\' && self.sequence.last() == Some(&ESC));
+                    self.sequence.push(byte);
+                    let clipboard = self.capture_clipboard && self.sequence.starts_with(CLIPBOARD_OSC_PREFIX);
+                    let max_length = if clipboard { MAX_CLIPBOARD_OSC_LENGTH } else { MAX_OSC_LENGTH };
+                    if terminated && clipboard {
+                        self.clipboard.extend(parse_clipboard_osc(&self.sequence));
+                        self.complete(&mut output, true);
+                    } else if terminated {
+                        self.complete(&mut output, self.filter.drop_osc);
+                    } else if self.sequence.len() > max_length {
+                        self.complete(&mut output, false);
+                    }
+                }
+            }
+        }
+        output
+    }
+
+    /// 完結していないシーケンスを取り出す（終端で呼ぶ）
+    pub fn finish(&mut self) -> Vec<u8> {
+        self.state = EscapeState::Ground;
+        std::mem::take(&mut self.sequence)
+    }
+
+    /// 完結したシーケンスを出力するか破棄して、通常の状態に戻る
+    fn complete(&mut self, output: &mut Vec<u8>, drop: bool) {
+        if !drop {
+            output.extend_from_slice(&self.sequence);
+        }
+        self.sequence.clear();
+        self.state = EscapeState::Ground;
+    }
+}
+
+/// `ESC ] 52 ; 選択領域 ; base64 終端` からクリップボードへの書き込みを取り出す
+///
+/// 読み取り要求や解釈できない内容は `None`。
+fn parse_clipboard_osc(sequence: &[u8]) -> Option<ClipboardWrite> {
+    let body = sequence.strip_prefix(CLIPBOARD_OSC_PREFIX)?;
+    let body = body
+        .strip_suffix(&[BEL])
+        .or_else(|| body.strip_suffix(&[ESC, b'\\']))?;
+    let body = std::str::from_utf8(body).ok()?;
+    let (selection, data) = body.split_once(';')?;
+    if data == "?" {
+        return None;
+    }
+
+    let decoded = base64::engine::general_purpose::STANDARD.decode(data).ok()?;
+    Some(ClipboardWrite {
+        // 選択領域の指定がなければクリップボードとして扱う
+        selection: if selection.is_empty() { "c".to_string() } else { selection.to_string() },
+        text: String::from_utf8_lossy(&decoded).into_owned(),
+    })
+}
+
+/// 作業ディレクトリの通知（OSC 7）の接頭辞
+const CWD_OSC_PREFIX: &[u8] = b"\x1b]7;";
+
+/// ターミナル出力の OSC 7（`ESC ] 7 ; file://host/path 終端`）から作業ディレクトリを追跡する
+///
+/// 出力はそのまま変更しない。読み込み単位をまたいだシーケンスも扱う。
+#[derive(Default)]
+pub struct CwdTracker {
+    sequence: Vec<u8>,
+    in_sequence: bool,
+}
+
+impl CwdTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 受信したバイト列を調べ、作業ディレクトリの通知があれば最後のものを返す
+    pub fn feed(&mut self, bytes: &[u8]) -> Option<String> {
+        let mut cwd = None;
+        for &byte in bytes {
+            if !self.in_sequence {
+                if byte == ESC {
+                    self.in_sequence = true;
+                    self.sequence.clear();
+                    self.sequence.push(byte);
+                }
+                continue;
+            }
+
+            let terminated = byte == BEL || (byte == b'\\' && self.sequence.last() == Some(&ESC));
+            self.sequence.push(byte);
+            let prefix_len = self.sequence.len().min(CWD_OSC_PREFIX.len());
+            if self.sequence[..prefix_len] != CWD_OSC_PREFIX[..prefix_len] {
+                // 別のシーケンス（新たなESCで始まる場合はそこから調べ直す）
+                self.in_sequence = byte == ESC;
+                self.sequence.clear();
+                if self.in_sequence {
+                    self.sequence.push(byte);
+                }
+            } else if terminated {
+                cwd = parse_cwd_osc(&self.sequence).or(cwd);
+                self.in_sequence = false;
+            } else if self.sequence.len() > MAX_OSC_LENGTH {
+                self.in_sequence = false;
+            }
+        }
+        cwd
+    }
+}
+
+/// `ESC ] 7 ; file://host/path 終端` からパスを取り出す（パーセントエンコードを復元する）
+fn parse_cwd_osc(sequence: &[u8]) -> Option<String> {
+    let body = sequence.strip_prefix(CWD_OSC_PREFIX)?;
+    let body = body
+        .strip_suffix(&[BEL])
+        .or_else(|| body.strip_suffix(&[ESC, b'\\']))?;
+    let rest = body.strip_prefix(b"file://")?;
+    let path = &rest[rest.iter().position(|&b| b == b'/')?..];
+
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut i = 0;
+    while i < path.len() {
+        let hex = path
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (path[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// 末尾にある不完全なUTF-8文字のバイト数
+fn incomplete_tail_len(bytes: &[u8]) -> usize {
+    for len in 1..=bytes.len().min(3) {
+        let byte = bytes[bytes.len() - len];
+        if byte & 0xC0 == 0x80 {
+            // 継続バイト
+            continue;
+        }
+        let needed = match byte {
+            0xF0.. => 4,
+            0xE0.. => 3,
+            0xC0.. => 2,
+            _ => 1,
+        };
+        return if needed > len { len } else { 0 };
+    }
+    0
+}
+
+/// プロンプトとみなす行末の文字
+const PROMPT_SUFFIXES: [&str; 4] = ["$", "#", ">", "%"];
+
+/// PTY出力からエコーされたコマンド行と末尾のプロンプトを取り除く
+///
+/// 先頭行が送信したコマンドで終わる場合（プロンプト付きのエコーを含む）はその行を削除し、
+/// 改行で終わらない最終行がプロンプト記号で終わる場合はその行を削除する。
+/// PTYが付加する CR は取り除き、改行は LF に揃える。
+pub fn strip_pty_echo(output: &str, command: &str) -> String {
+    let normalized = output.replace("\r\n", "\n").replace('\r', "");
+    let command = command.trim();
+    let mut body = normalized.as_str();
+
+    if !command.is_empty() {
+        let (first, rest) = body.split_once('\n').unwrap_or((body, ""));
+        if first.trim_end().ends_with(command) {
+            body = rest;
+        }
+    }
+
+    if !body.ends_with('\n') {
+        let (head, last) = match body.rfind('\n') {
+            Some(pos) => (&body[..=pos], &body[pos + 1..]),
+            None => ("", body),
+        };
+        let last = last.trim_end();
+        if PROMPT_SUFFIXES.iter().any(|suffix| last.ends_with(suffix)) {
+            body = head;
+        }
+    }
+
+    body.to_string()
+}
+
+/// `env`（または `env -0`）の出力を変数名と値の組に変換する
+///
+/// NUL区切りの出力はそのまま分割する。改行区切りの場合、変数名として妥当でない行は
+/// 直前の値の続き（値に含まれる改行）とみなすため、解析は完全ではない。
+pub fn parse_env_output(output: &str) -> HashMap<String, String> {
+    let mut env = HashMap::new();
+
+    if output.contains('\0') {
+        for entry in output.split('\0') {
+            if let Some((name, value)) = entry.split_once('=') {
+                if is_env_name(name) {
+                    env.insert(name.to_string(), value.to_string());
+                }
+            }
+        }
+        return env;
+    }
+
+    let mut current: Option<(String, String)> = None;
+    for line in output.lines() {
+        match line.split_once('=') {
+            Some((name, value)) if is_env_name(name) => {
+                if let Some((name, value)) = current.take() {
+                    env.insert(name, value);
+                }
+                current = Some((name.to_string(), value.to_string()));
+            }
+            _ => {
+                if let Some((_, value)) = current.as_mut() {
+                    value.push('\n');
+                    value.push_str(line);
+                }
+            }
+        }
+    }
+    if let Some((name, value)) = current {
+        env.insert(name, value);
+    }
+    env
+}
+
+/// 環境変数名として妥当か（英字か `_` で始まり、英数字と `_` のみ）
+fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// `ps aux` のコマンドより前の列数（USER PID %CPU %MEM VSZ RSS TTY STAT START TIME）
+const PS_AUX_FIELDS: usize = 10;
+
+/// `ps aux` の出力を解釈する（ヘッダー行と解釈できない行は飛ばす）
+///
+/// 最後の COMMAND 列は空白を含むため、前の列を切り出した残りをそのまま使う。
+pub fn parse_ps_output(output: &str) -> Vec<ProcessInfo> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (fields, command) = split_leading_fields(line, PS_AUX_FIELDS)?;
+            Some(ProcessInfo {
+                pid: fields[1].parse().ok()?,
+                user: fields[0].to_string(),
+                cpu: fields[2].parse().ok()?,
+                mem: fields[3].parse().ok()?,
+                command: command.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// 空白区切りの先頭 `count` 列と、残りの部分（前後の空白を除く）に分ける
+fn split_leading_fields(line: &str, count: usize) -> Option<(Vec<&str>, &str)> {
+    let mut fields = Vec::with_capacity(count);
+    let mut rest = line.trim_start();
+    while fields.len() < count {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        if end == 0 {
+            return None;
+        }
+        fields.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+    Some((fields, rest.trim_end()))
+}
+
+/// unified diff の変更箇所の前後に含める行数
+const DIFF_CONTEXT_LINES: usize = 3;
+
+/// 最長共通部分列を求める表の大きさの上限（超える場合は全行の置き換えとして扱う）
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// 差分の1行分の操作
+#[derive(Clone, Copy)]
+enum DiffOp {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// 行単位の unified diff を作る（同じ内容なら空文字列）
+pub fn unified_diff(old: &str, new: &str) -> String {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let ops = diff_lines(&old_lines, &new_lines);
+    if ops.iter().all(|op| matches!(op, DiffOp::Equal)) {
+        return String::new();
+    }
+
+    // 各操作の前までに消費した行数
+    let mut positions = Vec::with_capacity(ops.len() + 1);
+    let (mut old_pos, mut new_pos) = (0, 0);
+    for op in &ops {
+        positions.push((old_pos, new_pos));
+        match op {
+            DiffOp::Equal => {
+                old_pos += 1;
+                new_pos += 1;
+            }
+            DiffOp::Delete => old_pos += 1,
+            DiffOp::Insert => new_pos += 1,
+        }
+    }
+    positions.push((old_pos, new_pos));
+
+    let mut diff = String::from("--- previous\n+++ current\n");
+    let mut next = 0;
+    while let Some(first) = (next..ops.len()).find(|&k| !matches!(ops[k], DiffOp::Equal)) {
+        // 変更同士の間が短ければ同じハンクにまとめる
+        let start = first.saturating_sub(DIFF_CONTEXT_LINES);
+        let mut last = first;
+        for (k, op) in ops.iter().enumerate().skip(first + 1) {
+            if !matches!(op, DiffOp::Equal) {
+                last = k;
+            } else if k - last > DIFF_CONTEXT_LINES * 2 {
+                break;
+            }
+        }
+        let end = (last + DIFF_CONTEXT_LINES + 1).min(ops.len());
+
+        let (old_start, new_start) = positions[start];
+        let (old_end, new_end) = positions[end];
+        let range = |start: usize, count: usize| {
+            // 行数が0の場合は直前の行番号を示す
+            let start = if count == 0 { start } else { start + 1 };
+            format!("{},{}", start, count)
+        };
+        diff.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(old_start, old_end - old_start),
+            range(new_start, new_end - new_start)
+        ));
+        for (op, &(old_index, new_index)) in ops[start..end].iter().zip(&positions[start..end]) {
+            let (prefix, line) = match op {
+                DiffOp::Equal => (' ', old_lines[old_index]),
+                DiffOp::Delete => ('-', old_lines[old_index]),
+                DiffOp::Insert => ('+', new_lines[new_index]),
+            };
+            diff.push(prefix);
+            diff.push_str(line);
+            diff.push('\n');
+        }
+        next = end;
+    }
+    diff
+}
+
+/// 最長共通部分列から行ごとの操作列を求める（前後の共通部分は表の計算から除く）
+fn diff_lines(old: &[&str], new: &[&str]) -> Vec<DiffOp> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    let mut ops = vec![DiffOp::Equal; prefix];
+    let (n, m) = (old_mid.len(), new_mid.len());
+    if (n + 1).saturating_mul(m + 1) > MAX_DIFF_CELLS {
+        ops.extend(std::iter::repeat_n(DiffOp::Delete, n));
+        ops.extend(std::iter::repeat_n(DiffOp::Insert, m));
+    } else {
+        // lcs[i][j] は old_mid[i..] と new_mid[j..] の最長共通部分列の長さ
+        let width = m + 1;
+        let mut lcs = vec![0u32; (n + 1) * width];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[i * width + j] = if old_mid[i] == new_mid[j] {
+                    lcs[(i + 1) * width + j + 1] + 1
+                } else {
+                    lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        while i < n && j < m {
+            if old_mid[i] == new_mid[j] {
+                ops.push(DiffOp::Equal);
+                i += 1;
+                j += 1;
+            } else if lcs[(i + 1) * width + j] >= lcs[i * width + j + 1] {
+                ops.push(DiffOp::Delete);
+                i += 1;
+            } else {
+                ops.push(DiffOp::Insert);
+                j += 1;
+            }
+        }
+        ops.extend(std::iter::repeat_n(DiffOp::Delete, n - i));
+        ops.extend(std::iter::repeat_n(DiffOp::Insert, m - j));
+    }
+    ops.extend(std::iter::repeat_n(DiffOp::Equal, suffix));
+    ops
+}
@@ -0,0 +1,218 @@
+use crate::ssh::{ProxyConfig, ProxyKind, SshError};
+use base64::Engine;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// HTTPレスポンスヘッダーの最大サイズ
+const MAX_HTTP_RESPONSE_HEADER: usize = 8192;
+
+/// プロキシ経由で接続先へのTCPストリームを確立する
+pub async fn connect_via_proxy(
+    proxy: &ProxyConfig,
+    target_host: &str,
+    target_port: u16,
+) -> Result<TcpStream, SshError> {
+    let mut stream = TcpStream::connect((proxy.host.as_str(), proxy.port))
+        .await
+        .map_err(|e| {
+            SshError::ProxyFailed(format!(
+                "failed to connect to proxy {}:{}: {}",
+                proxy.host, proxy.port, e
+            ))
+        })?;
+
+    match proxy.kind {
+        ProxyKind::Socks5 => socks5_handshake(&mut stream, proxy, target_host, target_port).await?,
+        ProxyKind::Http => http_connect(&mut stream, proxy, target_host, target_port).await?,
+    }
+
+    Ok(stream)
+}
+
+/// SOCKS5 (RFC 1928 / RFC 1929) のハンドシェイク
+async fn socks5_handshake(
+    stream: &mut TcpStream,
+    proxy: &ProxyConfig,
+    target_host: &str,
+    target_port: u16,
+) -> Result<(), SshError> {
+    let credentials = proxy.username.as_deref().map(|user| {
+        (user, proxy.password.as_deref().unwrap_or(""))
+    });
+
+    // 認証方式のネゴシエーション
+    let greeting: &[u8] = if credentials.is_some() {
+        &[0x05, 0x02, 0x00, 0x02]
+    } else {
+        &[0x05, 0x01, 0x00]
+    };
+    stream.write_all(greeting).await?;
+
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != 0x05 {
+        return Err(SshError::ProxyFailed("invalid SOCKS5 server response".to_string()));
+    }
+
+    match reply[1] {
+        0x00 => {}
+        0x02 => {
+            let (user, pass) = credentials.ok_or_else(|| {
+                SshError::ProxyFailed("SOCKS5 proxy requires authentication".to_string())
+            })?;
+            if user.len() > 255 || pass.len() > 255 {
+                return Err(SshError::ProxyFailed(
+                    "SOCKS5 username or password is too long".to_string(),
+                ));
+            }
+
+            let mut request = vec![0x01, user.len() as u8];
+            request.extend_from_slice(user.as_bytes());
+            request.push(pass.len() as u8);
+            request.extend_from_slice(pass.as_bytes());
+            stream.write_all(&request).await?;
+
+            let mut auth_reply = [0u8; 2];
+            stream.read_exact(&mut auth_reply).await?;
+            if auth_reply[1] != 0x00 {
+                return Err(SshError::ProxyFailed(
+                    "SOCKS5 proxy rejected the credentials".to_string(),
+                ));
+            }
+        }
+        0xFF => {
+            return Err(SshError::ProxyFailed(
+                "SOCKS5 proxy accepted none of the offered authentication methods".to_string(),
+            ));
+        }
+        method => {
+            return Err(SshError::ProxyFailed(format!(
+                "SOCKS5 proxy selected unsupported authentication method {:#04x}",
+                method
+            )));
+        }
+    }
+
+    // CONNECT リクエスト
+    let mut request = vec![0x05, 0x01, 0x00];
+    match target_host.parse::<std::net::IpAddr>() {
+        Ok(std::net::IpAddr::V4(ip)) => {
+            request.push(0x01);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(std::net::IpAddr::V6(ip)) => {
+            request.push(0x04);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            if target_host.len() > 255 {
+                return Err(SshError::ProxyFailed("target hostname is too long".to_string()));
+            }
+            request.push(0x03);
+            request.push(target_host.len() as u8);
+            request.extend_from_slice(target_host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&target_port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header).await?;
+    if header[1] != 0x00 {
+        return Err(SshError::ProxyFailed(format!(
+            "SOCKS5 proxy rejected the connection: {}",
+            socks5_reply_message(header[1])
+        )));
+    }
+
+    // バインドアドレスを読み捨てる
+    let addr_len = match header[3] {
+        0x01 => 4,
+        0x04 => 16,
+        0x03 => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len).await?;
+            len[0] as usize
+        }
+        atyp => {
+            return Err(SshError::ProxyFailed(format!(
+                "SOCKS5 proxy returned unknown address type {:#04x}",
+                atyp
+            )));
+        }
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut bound).await?;
+
+    Ok(())
+}
+
+/// SOCKS5 のリプライコードを説明文に変換
+fn socks5_reply_message(code: u8) -> &'static str {
+    match code {
+        0x01 => "general SOCKS server failure",
+        0x02 => "connection not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+/// HTTP CONNECT によるトンネルの確立
+async fn http_connect(
+    stream: &mut TcpStream,
+    proxy: &ProxyConfig,
+    target_host: &str,
+    target_port: u16,
+) -> Result<(), SshError> {
+    let authority = if target_host.contains(':') {
+        format!("[{}]:{}", target_host, target_port)
+    } else {
+        format!("{}:{}", target_host, target_port)
+    };
+
+    let mut request = format!("CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n");
+    if let Some(user) = &proxy.username {
+        let credentials = format!("{}:{}", user, proxy.password.as_deref().unwrap_or(""));
+        let encoded = base64::engine::general_purpose::STANDARD.encode(credentials);
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", encoded));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // ヘッダーの終端まで1バイトずつ読み込む（トンネル開始後のデータを消費しないため）
+    let mut response = Vec::new();
+    let mut byte = [0u8; 1];
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= MAX_HTTP_RESPONSE_HEADER {
+            return Err(SshError::ProxyFailed("HTTP proxy response header too large".to_string()));
+        }
+        let n = stream.read(&mut byte).await?;
+        if n == 0 {
+            return Err(SshError::ProxyFailed(
+                "HTTP proxy closed the connection during CONNECT".to_string(),
+            ));
+        }
+        response.push(byte[0]);
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    let status_code = status_line.split_whitespace().nth(1).unwrap_or_default();
+
+    match status_code {
+        "200" => Ok(()),
+        "407" => Err(SshError::ProxyFailed(format!(
+            "HTTP proxy requires authentication: {}",
+            status_line
+        ))),
+        _ => Err(SshError::ProxyFailed(format!(
+            "HTTP proxy rejected CONNECT: {}",
+            status_line
+        ))),
+    }
+}
@@ -0,0 +1,203 @@
+use crate::ssh::{ResolverConfig, SshError};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use uuid::Uuid;
+
+/// 名前解決を待つ時間（DNSが応答しない環境で接続が止まらないようにする）
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// 指定DNSサーバーへの問い合わせの再送間隔と回数
+const QUERY_RETRY_INTERVAL: Duration = Duration::from_secs(2);
+const QUERY_ATTEMPTS: usize = 3;
+
+/// DNSサーバーの既定のポート
+const DNS_PORT: u16 = 53;
+
+/// DNSレコードの種類
+const RECORD_A: u16 = 1;
+const RECORD_AAAA: u16 = 28;
+
+/// ホスト名を解決し、接続候補のアドレス一覧を返す
+///
+/// `resolver` が指定された場合はシステムのリゾルバーを使わず、そのDNSサーバーに直接問い合わせる。
+pub async fn resolve_host(
+    host: &str,
+    port: u16,
+    resolver: Option<&ResolverConfig>,
+) -> Result<Vec<SocketAddr>, SshError> {
+    let lookup = async {
+        match resolver {
+            Some(resolver) => lookup_with_nameserver(host, port, resolver).await,
+            None => tokio::net::lookup_host((host, port))
+                .await
+                .map(|addrs| addrs.collect())
+                .map_err(|e| SshError::DnsResolutionFailed(format!("{}: {}", host, e))),
+        }
+    };
+    let addrs: Vec<SocketAddr> = tokio::time::timeout(RESOLVE_TIMEOUT, lookup)
+        .await
+        .map_err(|_| SshError::DnsResolutionFailed("resolution timed out".to_string()))??;
+
+    if addrs.is_empty() {
+        return Err(SshError::DnsResolutionFailed(format!(
+            "{}: no addresses found",
+            host
+        )));
+    }
+
+    Ok(addrs)
+}
+
+/// 指定したDNSサーバーにA/AAAAレコードを問い合わせる（UDPのみ）
+async fn lookup_with_nameserver(
+    host: &str,
+    port: u16,
+    resolver: &ResolverConfig,
+) -> Result<Vec<SocketAddr>, SshError> {
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(vec![SocketAddr::new(ip, port)]);
+    }
+    let nameserver = parse_nameserver(&resolver.nameserver)?;
+
+    let (v4, v6) = tokio::join!(
+        query(nameserver, host, RECORD_A),
+        query(nameserver, host, RECORD_AAAA)
+    );
+    // 片方のみ失敗した場合は得られたアドレスで接続を試みる
+    let mut addrs = Vec::new();
+    let mut error = None;
+    for result in [v4, v6] {
+        match result {
+            Ok(ips) => addrs.extend(ips.into_iter().map(|ip| SocketAddr::new(ip, port))),
+            Err(e) => error = Some(e),
+        }
+    }
+    match error {
+        Some(e) if addrs.is_empty() => Err(e),
+        _ => Ok(addrs),
+    }
+}
+
+/// `host` または `host:port` 形式のDNSサーバーのアドレスを解釈する
+fn parse_nameserver(nameserver: &str) -> Result<SocketAddr, SshError> {
+    if let Ok(addr) = nameserver.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    nameserver
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .map(|ip| SocketAddr::new(ip, DNS_PORT))
+        .map_err(|_| SshError::ConfigError(format!("invalid nameserver address: {}", nameserver)))
+}
+
+/// 1種類のレコードを問い合わせる
+async fn query(nameserver: SocketAddr, host: &str, record_type: u16) -> Result<Vec<IpAddr>, SshError> {
+    let dns_error = |message: String| SshError::DnsResolutionFailed(format!("{}: {}", host, message));
+
+    let bind: SocketAddr = if nameserver.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(nameserver).await?;
+
+    let random = Uuid::new_v4().into_bytes();
+    let id = u16::from_be_bytes([random[0], random[1]]);
+    let request = build_query(id, host, record_type)
+        .ok_or_else(|| dns_error("invalid hostname".to_string()))?;
+
+    let mut buf = [0u8; 1500];
+    for _ in 0..QUERY_ATTEMPTS {
+        socket.send(&request).await?;
+        let Ok(received) = tokio::time::timeout(QUERY_RETRY_INTERVAL, socket.recv(&mut buf)).await else {
+            continue;
+        };
+        let len = received?;
+        // 別の問い合わせへの応答は無視して待ち直す
+        match parse_response(id, &buf[..len]) {
+            Some(result) => return result.map_err(dns_error),
+            None => continue,
+        }
+    }
+    Err(dns_error(format!("no response from nameserver {}", nameserver)))
+}
+
+/// 再帰問い合わせの要求パケットを作る
+fn build_query(id: u16, host: &str, record_type: u16) -> Option<Vec<u8>> {
+    let mut packet = Vec::with_capacity(host.len() + 18);
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&[0x01, 0x00]); // RD
+    packet.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]); // QDCOUNT=1
+
+    for label in host.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return None;
+        }
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend_from_slice(&record_type.to_be_bytes());
+    packet.extend_from_slice(&1u16.to_be_bytes()); // IN
+    Some(packet)
+}
+
+/// 応答パケットから回答セクションのアドレスを取り出す
+///
+/// IDが一致しない・解釈できないパケットは `None`。
+fn parse_response(id: u16, packet: &[u8]) -> Option<Result<Vec<IpAddr>, String>> {
+    let read_u16 = |pos: usize| -> Option<u16> {
+        Some(u16::from_be_bytes([*packet.get(pos)?, *packet.get(pos + 1)?]))
+    };
+
+    if read_u16(0)? != id || packet.get(2)? & 0x80 == 0 {
+        return None;
+    }
+    if packet[2] & 0x02 != 0 {
+        return Some(Err("response truncated".to_string()));
+    }
+    match packet[3] & 0x0f {
+        0 => {}
+        3 => return Some(Ok(Vec::new())),
+        rcode => return Some(Err(format!("nameserver returned error code {}", rcode))),
+    }
+
+    let questions = read_u16(4)?;
+    let answers = read_u16(6)?;
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_name(packet, pos)? + 4;
+    }
+
+    let mut ips = Vec::new();
+    for _ in 0..answers {
+        pos = skip_name(packet, pos)?;
+        let record_type = read_u16(pos)?;
+        let rdlength = read_u16(pos + 8)? as usize;
+        let rdata = packet.get(pos + 10..pos + 10 + rdlength)?;
+        match (record_type, rdata.len()) {
+            (RECORD_A, 4) => ips.push(IpAddr::from(<[u8; 4]>::try_from(rdata).ok()?)),
+            (RECORD_AAAA, 16) => ips.push(IpAddr::from(<[u8; 16]>::try_from(rdata).ok()?)),
+            _ => {}
+        }
+        pos += 10 + rdlength;
+    }
+    Some(Ok(ips))
+}
+
+/// ドメイン名（圧縮ポインタを含む）を読み飛ばし、次の位置を返す
+fn skip_name(packet: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *packet.get(pos)?;
+        if len == 0 {
+            return Some(pos + 1);
+        }
+        if len & 0xc0 == 0xc0 {
+            return Some(pos + 2);
+        }
+        pos += 1 + len as usize;
+    }
+}
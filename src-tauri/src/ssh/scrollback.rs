@@ -0,0 +1,89 @@
+use std::collections::VecDeque;
+
+/// スクロールバックの既定の上限（バイト）
+pub const DEFAULT_SCROLLBACK_BYTES: usize = 1024 * 1024;
+
+/// スクロールバックの既定の上限（行）
+pub const DEFAULT_SCROLLBACK_LINES: usize = 10_000;
+
+/// ターミナル出力の直近部分を保持するリングバッファ
+///
+/// 行単位で保持し、バイト数・行数のどちらかの上限を超えたら古い行から捨てる。
+#[derive(Debug)]
+pub struct Scrollback {
+    /// 改行で終わった行（改行を含む）
+    lines: VecDeque<String>,
+    /// 改行がまだ届いていない末尾の行
+    partial: String,
+    bytes: usize,
+    max_bytes: usize,
+    max_lines: usize,
+}
+
+impl Scrollback {
+    pub fn new(max_bytes: usize, max_lines: usize) -> Self {
+        Self {
+            lines: VecDeque::new(),
+            partial: String::new(),
+            bytes: 0,
+            max_bytes: max_bytes.max(1),
+            max_lines: max_lines.max(1),
+        }
+    }
+
+    /// 出力を追加する
+    pub fn push(&mut self, text: &str) {
+        for segment in text.split_inclusive('\n') {
+            self.partial.push_str(segment);
+            self.bytes += segment.len();
+            if segment.ends_with('\n') {
+                self.lines.push_back(std::mem::take(&mut self.partial));
+            }
+        }
+        self.trim();
+    }
+
+    /// 保持している行数（末尾の改行のない行を含む）
+    pub fn line_count(&self) -> usize {
+        self.lines.len() + usize::from(!self.partial.is_empty())
+    }
+
+    /// 保持している出力全体
+    pub fn contents(&self) -> String {
+        self.last_lines(self.line_count())
+    }
+
+    /// 末尾の `n` 行（末尾の改行のない行も1行に数える）
+    pub fn last_lines(&self, n: usize) -> String {
+        let complete = n.saturating_sub(usize::from(!self.partial.is_empty()));
+        let skip = self.lines.len().saturating_sub(complete);
+        let mut text = String::new();
+        for line in self.lines.iter().skip(skip) {
+            text.push_str(line);
+        }
+        if n > 0 {
+            text.push_str(&self.partial);
+        }
+        text
+    }
+
+    /// 上限を超えた分を古い行から捨てる
+    fn trim(&mut self) {
+        while self.line_count() > self.max_lines || self.bytes > self.max_bytes {
+            let Some(line) = self.lines.pop_front() else {
+                break;
+            };
+            self.bytes -= line.len();
+        }
+
+        // 改行のない1行だけで上限を超える場合は、その先頭を捨てる
+        if self.bytes > self.max_bytes {
+            let mut cut = self.partial.len() - self.max_bytes;
+            while !self.partial.is_char_boundary(cut) {
+                cut += 1;
+            }
+            self.partial.drain(..cut);
+            self.bytes = self.partial.len();
+        }
+    }
+}
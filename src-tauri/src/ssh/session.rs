@@ -1,13 +1,253 @@
-use crate::ssh::{AuthMethod, CommandResult, SshConfig, SshError, SshSessionInfo, ConnectionStatus};
+use crate::ssh::proxy::connect_via_proxy;
+use crate::ssh::resolver::resolve_host;
+use crate::ssh::telemetry::{CountingStream, TrafficCounters};
+use crate::ssh::auth::{auth_request, authenticate_password, authenticate_password_with_otp, DEFAULT_AUTH_TIMEOUT};
+use crate::ssh::env_profile::is_valid_env_name;
+use crate::ssh::handshake::{negotiate, HandshakeCapture};
+use crate::ssh::output::{parse_env_output, parse_ps_output, strip_pty_echo, unified_diff, OutputBuffer};
+use crate::ssh::clock::{Clock, SystemClock};
+use crate::ssh::audit::{CommandAuditor, NoopAuditor};
+use crate::ssh::key_provider::{FileKeyProvider, KeyProvider};
+use crate::ssh::keys::check_known_hosts;
+use crate::ssh::knock::knock;
+use crate::ssh::forward::{relay_to_local, ForwardManager, RemoteForwardTargets};
+use crate::ssh::x11::{relay_x11, X11Slot};
+use crate::ssh::{session_identity, AlgorithmAllowlist, HostKeyCheck, HostKeyStatus, HostTrustStore, PinnedHostKey, ProcessInfo, AuthMethod, ConnectTimings, StdStream, AuthPromptBroker, ConnectionDetails, EffectiveConfig, EventBus, HostKeyInfo, SshEvent, CommandMacro, CommandOptions, CommandResult, CommandDiffResult, BytesCommandResult, RemoteCommandInfo, RunningExecInfo, SafeModeConfig, TimedCommandResult, FileOutputOptions, FileOutputResult, ImportSummary, SessionExport, SessionSnapshot, ServerExtensions, SessionTelemetry, ShellKind, SshConfig, SshError, SshSessionInfo, ConnectionStatus, ForwardInfo, ForwardSpec};
 use russh::client::{self, Handle, AuthResult};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::{Mutex, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::{Mutex, Notify, OwnedSemaphorePermit, RwLock, Semaphore};
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
+/// 読み込みバッファサイズの既定値
+pub const DEFAULT_READ_BUFFER_SIZE: usize = 32 * 1024;
+
+/// サーバーに通知する最大パケットサイズの上限（OpenSSHが受け付ける上限）
+const MAX_PACKET_SIZE: usize = 256 * 1024;
+
+/// `login_shell` で使用する既定のシェル
+const DEFAULT_LOGIN_SHELL: &str = "bash";
+
+/// 強制コマンド判定の応答待ち時間
+const FORCED_COMMAND_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `remote_mktemp` でテンプレート省略時に使うテンプレート
+const DEFAULT_TEMP_TEMPLATE: &str = "/tmp/pardoroid.XXXXXXXX";
+
+/// 切断時の一時ファイルの削除を待つ時間
+const TEMP_CLEANUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// シェルの種類の判定を待つ時間
+const SHELL_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// シェルの種類を判定するコマンド（各シェルで展開される変数だけが値になる）
+///
+/// 順に cmd の `%COMSPEC%`、PowerShell・fish・zsh・bash のバージョン変数。
+const SHELL_PROBE_COMMAND: &str =
+    "echo \"pardoroid-shell|%COMSPEC%|$PSVersionTable|$FISH_VERSION|$ZSH_VERSION|$BASH_VERSION\"";
+
+/// `return_on_first_output` で出力がない場合に待つ時間
+const FIRST_OUTPUT_GRACE: Duration = Duration::from_secs(2);
+
+/// 事前接続を使わずに破棄するまでの既定の時間
+const DEFAULT_WARM_IDLE_TTL: Duration = Duration::from_secs(300);
+
+/// 1回の事前接続で確立できる接続数の上限
+const MAX_WARM_CONNECTIONS: usize = 8;
+
+/// SHA-1署名のRSAホスト鍵アルゴリズム名
+const LEGACY_HOST_KEY_ALGORITHM: &str = "ssh-rsa";
+
+/// セッションごとの同時コマンド実行数の既定値
+///
+/// OpenSSHの `MaxSessions`（既定10）からターミナルとSFTPの分を残した値。
+const DEFAULT_MAX_CONCURRENT_COMMANDS: usize = 8;
+
+/// セーフモードで既定で拒否するパターン
+///
+/// 比較時はコマンドの末尾に空白を補うため、`"rm -rf / "` は `rm -rf /` そのものに一致し、
+/// `rm -rf /tmp/work` には一致しない。
+const DEFAULT_DANGEROUS_PATTERNS: &[&str] = &[
+    "rm -rf / ",
+    "rm -rf /* ",
+    "rm -fr / ",
+    "rm -fr /* ",
+    "mkfs",
+    "dd of=/dev/",
+    "> /dev/sd",
+    ":(){ :|:& };:",
+];
+
+/// 実行中のコマンドの状況
+struct RunningCommand {
+    info: RunningExecInfo,
+    produced: Arc<AtomicU64>,
+    cancel: CancellationToken,
+}
+
+/// 実行中のコマンドの登録先（実行IDごと）
+type RunningRegistry = Arc<std::sync::Mutex<HashMap<String, RunningCommand>>>;
+
+/// 実行するコマンドの由来（セーフモードの確認に使う）
+#[derive(Debug, Clone, Copy)]
+pub enum ExecOrigin {
+    /// ユーザーが指定したコマンド（`confirmed` なら危険なコマンドも実行する）
+    User { confirmed: bool },
+    /// このアプリが組み立てたコマンド（セーフモードの対象外）
+    Internal,
+}
+
+/// 実行中のコマンドとしての登録
+///
+/// 破棄すると一覧から外れ、同時実行数の枠も空く。ストリーミング実行では実行タスクに渡し、終了まで保持する。
+pub struct ExecTicket {
+    exec_id: String,
+    produced: Arc<AtomicU64>,
+    cancel: CancellationToken,
+    running: RunningRegistry,
+    /// セッションの同時実行数の枠（`max_concurrent_commands`）
+    _slot: Option<OwnedSemaphorePermit>,
+}
+
+impl ExecTicket {
+    pub fn exec_id(&self) -> &str {
+        &self.exec_id
+    }
+
+    /// 受信した出力のバイト数を加算するカウンタ
+    pub fn produced(&self) -> &Arc<AtomicU64> {
+        &self.produced
+    }
+
+    /// `cancel_command`・`abort_all` で中断されたことを知らせるトークン
+    pub fn cancel_token(&self) -> &CancellationToken {
+        &self.cancel
+    }
+}
+
+impl Drop for ExecTicket {
+    fn drop(&mut self) {
+        if let Ok(mut running) = self.running.lock() {
+            running.remove(&self.exec_id);
+        }
+    }
+}
+
 /// SSH セッションマネージャー
 pub struct SshSessionManager {
     sessions: Arc<RwLock<HashMap<String, Arc<Mutex<SshSession>>>>>,
+    /// 実行中のコマンド（ストリーミング実行・内部の判定用コマンドを含む）
+    running: RunningRegistry,
+    /// 同時に接続しておくセッション数の上限（超える場合は最も使われていないものを切断）
+    max_connected: RwLock<Option<usize>>,
+    /// 接続処理中のセッションの中断用トークン（接続中はセッションのロックが取れないため別に持つ）
+    connect_cancels: RwLock<HashMap<String, CancellationToken>>,
+    forwards: ForwardManager,
+    clock: Arc<dyn Clock>,
+    key_provider: Arc<dyn KeyProvider>,
+    /// 実行したコマンドの記録先
+    auditor: Arc<dyn CommandAuditor>,
+    /// 新しいセッションに使うrusshの設定（`SshConfig` から導出する設定の代わりに使う）
+    russh_config: Option<Arc<russh::client::Config>>,
+    /// 事前に確立しておいた接続（`connect` で条件の合うものを引き継ぐ）
+    warm_pool: WarmPool,
+    events: EventBus,
+    auth_prompts: AuthPromptBroker,
+    /// `trust_on_first_use` で固定したホスト鍵
+    host_trust: Arc<HostTrustStore>,
+}
+
+/// 事前接続の置き場
+type WarmPool = Arc<Mutex<Vec<WarmConnection>>>;
+
+/// 事前接続の照合に使う接続先（ホスト・ポート・ユーザー名と、安全性に関わる設定のハッシュ）
+type WarmTarget = (String, u16, String, [u8; 32]);
+
+/// 事前に確立した接続
+struct WarmConnection {
+    /// 接続先と接続の安全性に関わる設定（`warm_target`）
+    target: WarmTarget,
+    /// 接続済みで、どのセッションにも登録していないセッション
+    session: SshSession,
+    expires_at: tokio::time::Instant,
+}
+
+/// 確立済みの接続
+///
+/// russh の `Handle` はリモート転送の要求に `&mut` を要するため、共有したまま
+/// 排他的にも使えるようロックで包む。チャネルを開くなどの操作は読み取りロックで並行して行う。
+pub struct SshConnection {
+    handle: RwLock<Handle<SshClientHandler>>,
+}
+
+impl SshConnection {
+    pub fn new(handle: Handle<SshClientHandler>) -> Self {
+        Self {
+            handle: RwLock::new(handle),
+        }
+    }
+
+    /// 接続が閉じているか（リモート転送の要求中で判定できない場合は `false`）
+    pub fn is_closed(&self) -> bool {
+        self.handle.try_read().is_ok_and(|handle| handle.is_closed())
+    }
+
+    pub async fn channel_open_session(&self) -> Result<russh::Channel<client::Msg>, russh::Error> {
+        self.handle.read().await.channel_open_session().await
+    }
+
+    pub async fn channel_open_direct_tcpip(
+        &self,
+        host_to_connect: impl Into<String>,
+        port_to_connect: u32,
+        originator_address: impl Into<String>,
+        originator_port: u32,
+    ) -> Result<russh::Channel<client::Msg>, russh::Error> {
+        self.handle
+            .read()
+            .await
+            .channel_open_direct_tcpip(host_to_connect, port_to_connect, originator_address, originator_port)
+            .await
+    }
+
+    /// サーバーにリモート転送の待ち受けを要求する（`port` が0なら割り当てられたポートを返す）
+    pub async fn tcpip_forward(&self, address: impl Into<String>, port: u32) -> Result<u32, russh::Error> {
+        self.handle.write().await.tcpip_forward(address, port).await
+    }
+
+    pub async fn cancel_tcpip_forward(&self, address: impl Into<String>, port: u32) -> Result<(), russh::Error> {
+        self.handle.read().await.cancel_tcpip_forward(address, port).await
+    }
+
+    pub async fn best_supported_rsa_hash(&self) -> Result<Option<Option<russh::keys::HashAlg>>, russh::Error> {
+        self.handle.read().await.best_supported_rsa_hash().await
+    }
+
+    pub async fn send_keepalive(&self, want_reply: bool) -> Result<(), russh::Error> {
+        self.handle.read().await.send_keepalive(want_reply).await
+    }
+
+    pub async fn rekey_soon(&self) -> Result<(), russh::Error> {
+        self.handle.read().await.rekey_soon().await
+    }
+
+    pub async fn disconnect(
+        &self,
+        reason: russh::Disconnect,
+        description: &str,
+        language_tag: &str,
+    ) -> Result<(), russh::Error> {
+        self.handle
+            .read()
+            .await
+            .disconnect(reason, description, language_tag)
+            .await
+    }
 }
 
 /// 個別のSSHセッション
@@ -15,44 +255,306 @@ pub struct SshSession {
     id: String,
     config: SshConfig,
     status: ConnectionStatus,
-    connection: Option<Handle<SshClientHandler>>,
+    status_changed: Arc<Notify>,
+    connection: Option<Arc<SshConnection>>,
     connected_at: Option<chrono::DateTime<chrono::Utc>>,
+    last_activity: Option<chrono::DateTime<chrono::Utc>>,
+    timeout_override: Option<Duration>,
+    last_error: Option<String>,
+    remote_disconnect: Arc<std::sync::Mutex<Option<String>>>,
+    connection_closed: Arc<Notify>,
+    remote_forwards: RemoteForwardTargets,
+    x11: X11Slot,
+    /// 切断の監視（自動再接続または失敗への遷移）を止める
+    reconnect_cancel: Option<CancellationToken>,
+    reconnect_now: Arc<Notify>,
+    reconnecting: bool,
+    traffic: Arc<TrafficCounters>,
+    rekey_limits: russh::Limits,
+    last_rekey_at: Option<chrono::DateTime<chrono::Utc>>,
+    server_extensions: Option<ServerExtensions>,
+    details: Option<ConnectionDetails>,
+    /// 直近の接続で受信したサーバーの識別文字列（切断後も保持する）
+    server_version: Option<String>,
+    /// 直近の接続でサーバーが提示したホスト鍵（切断後も保持する）
+    host_key: Option<russh::keys::PublicKey>,
+    /// 直近の接続の各段階の所要時間（切断後も保持する）
+    connect_timings: Option<ConnectTimings>,
+    forced_command: Option<bool>,
+    /// `detect_shell_kind` の結果
+    shell_kind: Option<ShellKind>,
+    /// `remote_mktemp` で作成した一時ファイル・ディレクトリ（切断時に削除する）
+    temp_paths: Vec<String>,
+    /// `remote_command_exists` の結果（PATHはほぼ変わらないため接続中はキャッシュする）
+    command_cache: HashMap<String, RemoteCommandInfo>,
+    events: EventBus,
+    auth_prompts: AuthPromptBroker,
+    key_provider: Arc<dyn KeyProvider>,
+    host_trust: Arc<HostTrustStore>,
+    /// 接続の各段階の所要時間の計測に使う
+    clock: Arc<dyn Clock>,
+    /// 指定されていれば `SshConfig` から導出する設定の代わりに使う
+    russh_config: Option<Arc<russh::client::Config>>,
+    /// コマンド実行チャネルの空き（`max_concurrent_commands` 個）
+    command_slots: Arc<Semaphore>,
+    /// 実行中の操作の完了を待って閉じる（新しい操作は受け付けない）
+    draining: bool,
 }
 
 /// SSH クライアントハンドラー
 #[derive(Clone)]
-pub struct SshClientHandler;
+pub struct SshClientHandler {
+    traffic: Arc<TrafficCounters>,
+    server_key: Arc<std::sync::Mutex<Option<russh::keys::PublicKey>>>,
+    remote_disconnect: Arc<std::sync::Mutex<Option<String>>>,
+    closed: Arc<Notify>,
+    remote_forwards: RemoteForwardTargets,
+    x11: X11Slot,
+}
+
+impl SshClientHandler {
+    pub fn new(traffic: Arc<TrafficCounters>) -> Self {
+        Self {
+            traffic,
+            server_key: Arc::new(std::sync::Mutex::new(None)),
+            remote_disconnect: Arc::new(std::sync::Mutex::new(None)),
+            closed: Arc::new(Notify::new()),
+            remote_forwards: RemoteForwardTargets::default(),
+            x11: X11Slot::default(),
+        }
+    }
+
+    /// リモート転送の接続先の表を指定する（再接続をまたいで同じ表を使うため）
+    pub fn with_remote_forwards(mut self, remote_forwards: RemoteForwardTargets) -> Self {
+        self.remote_forwards = remote_forwards;
+        self
+    }
+
+    /// X11転送の中継先を指定する
+    pub fn with_x11(mut self, x11: X11Slot) -> Self {
+        self.x11 = x11;
+        self
+    }
+
+    /// サーバーが提示したホスト鍵の格納先
+    pub fn server_key_slot(&self) -> Arc<std::sync::Mutex<Option<russh::keys::PublicKey>>> {
+        self.server_key.clone()
+    }
+
+    /// サーバーから受け取った切断理由の格納先
+    pub fn remote_disconnect_slot(&self) -> Arc<std::sync::Mutex<Option<String>>> {
+        self.remote_disconnect.clone()
+    }
+
+    /// 接続が終了したときに通知される
+    pub fn closed_signal(&self) -> Arc<Notify> {
+        self.closed.clone()
+    }
+}
 
 impl client::Handler for SshClientHandler {
     type Error = SshError;
 
+    async fn data(
+        &mut self,
+        _channel: russh::ChannelId,
+        data: &[u8],
+        _session: &mut client::Session,
+    ) -> Result<(), Self::Error> {
+        self.traffic.add_payload_received(data.len());
+        Ok(())
+    }
+
+    async fn extended_data(
+        &mut self,
+        _channel: russh::ChannelId,
+        _ext: u32,
+        data: &[u8],
+        _session: &mut client::Session,
+    ) -> Result<(), Self::Error> {
+        self.traffic.add_payload_received(data.len());
+        Ok(())
+    }
+
     async fn check_server_key(
         &mut self,
-        _server_public_key: &russh::keys::PublicKey,
+        server_public_key: &russh::keys::PublicKey,
     ) -> Result<bool, Self::Error> {
+        if let Ok(mut slot) = self.server_key.lock() {
+            *slot = Some(server_public_key.clone());
+        }
+
         // TODO: サーバーキーの検証を実装
         // 現在は全て受け入れる（セキュリティ上推奨されない）
         Ok(true)
     }
+
+    async fn server_channel_open_forwarded_tcpip(
+        &mut self,
+        channel: russh::Channel<client::Msg>,
+        _connected_address: &str,
+        connected_port: u32,
+        _originator_address: &str,
+        _originator_port: u32,
+        _session: &mut client::Session,
+    ) -> Result<(), Self::Error> {
+        let target = self
+            .remote_forwards
+            .lock()
+            .ok()
+            .and_then(|targets| targets.get(&connected_port).cloned());
+        match target {
+            Some((host, port)) => {
+                tokio::spawn(relay_to_local(channel, host, port));
+            }
+            None => {
+                let _ = channel.close().await;
+            }
+        }
+        Ok(())
+    }
+
+    async fn server_channel_open_agent_forward(
+        &mut self,
+        channel: russh::Channel<client::Msg>,
+        _session: &mut client::Session,
+    ) -> Result<(), Self::Error> {
+        tokio::spawn(forward_to_local_agent(channel));
+        Ok(())
+    }
+
+    async fn server_channel_open_x11(
+        &mut self,
+        channel: russh::Channel<client::Msg>,
+        _originator_address: &str,
+        _originator_port: u32,
+        _session: &mut client::Session,
+    ) -> Result<(), Self::Error> {
+        tokio::spawn(relay_x11(channel, self.x11.clone()));
+        Ok(())
+    }
+
+    async fn disconnected(
+        &mut self,
+        reason: client::DisconnectReason<Self::Error>,
+    ) -> Result<(), Self::Error> {
+        match reason {
+            client::DisconnectReason::ReceivedDisconnect(info) => {
+                if let Ok(mut slot) = self.remote_disconnect.lock() {
+                    *slot = Some(format!(
+                        "server disconnected ({:?}): {}",
+                        info.reason_code, info.message
+                    ));
+                }
+                self.closed.notify_one();
+                Ok(())
+            }
+            client::DisconnectReason::Error(e) => {
+                // セッションのタスクが通信エラーで終了した
+                if let Ok(mut slot) = self.remote_disconnect.lock() {
+                    *slot = Some(format!("connection lost: {}", e));
+                }
+                self.closed.notify_one();
+                Err(e)
+            }
+        }
+    }
 }
 
 impl Default for SshSessionManager {
     fn default() -> Self {
-        Self::new()
+        Self::new(EventBus::new())
     }
 }
 
 impl SshSessionManager {
-    pub fn new() -> Self {
+    pub fn new(events: EventBus) -> Self {
+        Self::with_clock(events, Arc::new(SystemClock))
+    }
+
+    /// 時刻の取得元を指定して作成
+    pub fn with_clock(events: EventBus, clock: Arc<dyn Clock>) -> Self {
         Self {
             sessions: Arc::new(RwLock::new(HashMap::new())),
+            running: RunningRegistry::default(),
+            max_connected: RwLock::new(None),
+            connect_cancels: RwLock::new(HashMap::new()),
+            forwards: ForwardManager::new(),
+            clock,
+            key_provider: Arc::new(FileKeyProvider),
+            auditor: Arc::new(NoopAuditor),
+            russh_config: None,
+            warm_pool: WarmPool::default(),
+            events,
+            auth_prompts: AuthPromptBroker::new(),
+            host_trust: Arc::new(HostTrustStore::new()),
         }
     }
 
+    /// 秘密鍵の読み込み元を指定する（以降に作成するセッションに適用）
+    pub fn with_key_provider(mut self, key_provider: Arc<dyn KeyProvider>) -> Self {
+        self.key_provider = key_provider;
+        self
+    }
+
+    /// 実行したコマンドの記録先を指定する
+    pub fn with_auditor(mut self, auditor: Arc<dyn CommandAuditor>) -> Self {
+        self.auditor = auditor;
+        self
+    }
+
+    /// russhの設定を直接指定する（以降に作成するセッションに適用）
+    ///
+    /// タイムアウトやバッファサイズなど `SshConfig` から導出する設定はすべて無視される。
+    pub fn with_russh_config(mut self, config: russh::client::Config) -> Self {
+        self.russh_config = Some(Arc::new(config));
+        self
+    }
+
+    /// セッションが使うrusshの設定を差し替える（`None` で `SshConfig` からの導出に戻す）
+    ///
+    /// 次回の接続から適用される。
+    pub async fn set_russh_config(
+        &self,
+        session_id: &str,
+        config: Option<russh::client::Config>,
+    ) -> Result<(), SshError> {
+        self.get_session(session_id).await?.lock().await.russh_config = config.map(Arc::new);
+        Ok(())
+    }
+
+    /// 固定したホスト鍵の保存先
+    pub fn host_trust(&self) -> &HostTrustStore {
+        &self.host_trust
+    }
+
+    /// ポート転送のマネージャー（再接続時の再確立のためセッションと共に管理する）
+    pub fn forwards(&self) -> &ForwardManager {
+        &self.forwards
+    }
+
+    /// セッションを取得（セッション一覧のロックはすぐに解放する）
+    async fn get_session(&self, session_id: &str) -> Result<Arc<Mutex<SshSession>>, SshError> {
+        let sessions = self.sessions.read().await;
+        sessions
+            .get(session_id)
+            .cloned()
+            .ok_or_else(|| SshError::SessionNotFound(session_id.to_string()))
+    }
+
     /// 新しいSSHセッションを作成
     pub async fn create_session(&self, config: SshConfig) -> Result<String, SshError> {
         let session_id = Uuid::new_v4().to_string();
-        let session = SshSession::new(session_id.clone(), config);
+        let mut session = SshSession::new(
+            session_id.clone(),
+            config,
+            self.events.clone(),
+            self.auth_prompts.clone(),
+            self.key_provider.clone(),
+            self.host_trust.clone(),
+            self.clock.clone(),
+        );
+        session.russh_config = self.russh_config.clone();
         
         let mut sessions = self.sessions.write().await;
         sessions.insert(session_id.clone(), Arc::new(Mutex::new(session)));
@@ -62,265 +564,2425 @@ impl SshSessionManager {
 
     /// セッションに接続
     pub async fn connect(&self, session_id: &str) -> Result<(), SshError> {
-        let sessions = self.sessions.read().await;
-        let session_arc = sessions
-            .get(session_id)
-            .ok_or_else(|| SshError::SessionNotFound(session_id.to_string()))?
-            .clone();
+        let session_arc = self.get_session(session_id).await?;
+        self.evict_for_new_connection(session_id).await;
+
+        let cancel = CancellationToken::new();
+        self.connect_cancels
+            .write()
+            .await
+            .insert(session_id.to_string(), cancel.clone());
 
         let mut session = session_arc.lock().await;
-        session.connect().await
-    }
+        let result = match self.take_warm(&session.config).await {
+            Some(warm) => {
+                session.adopt(warm);
+                Ok(())
+            }
+            None => session.connect(&cancel).await,
+        };
+        self.connect_cancels.write().await.remove(session_id);
+        result?;
 
-    /// セッションを切断
-    pub async fn disconnect(&self, session_id: &str) -> Result<(), SshError> {
-        let sessions = self.sessions.read().await;
-        let session_arc = sessions
-            .get(session_id)
-            .ok_or_else(|| SshError::SessionNotFound(session_id.to_string()))?
-            .clone();
+        // 切断の監視を開始（自動再接続が無効なら、切断を検知した時点で失敗に遷移させる）
+        if let Some(previous) = session.reconnect_cancel.take() {
+            previous.cancel();
+        }
+        let cancel = CancellationToken::new();
+        session.reconnect_cancel = Some(cancel.clone());
+        if session.config.reconnect.is_some() {
+            tokio::spawn(monitor_reconnect(session_arc.clone(), cancel, self.forwards.clone()));
+        } else if let Some(connection) = &session.connection {
+            tokio::spawn(watch_connection(
+                session_arc.clone(),
+                Arc::downgrade(connection),
+                session.connection_closed.clone(),
+                cancel,
+            ));
+        }
 
-        let mut session = session_arc.lock().await;
-        session.disconnect().await
+        // シェルの判定は失敗しても接続を続ける
+        let detect_shell = session.config.detect_shell;
+        drop(session);
+        if detect_shell {
+            let _ = self.detect_shell_kind(session_id).await;
+        }
+
+        Ok(())
     }
 
-    /// コマンドを実行
-    pub async fn execute_command(
+    /// 接続先への接続を事前に `count` 本確立しておき、確立できた数を返す
+    ///
+    /// ホスト・ポート・ユーザー名に加え、認証方法・ホスト鍵の検証・アルゴリズム・プロキシ・名前解決などの
+    /// 設定も一致するセッションの `connect` は、ハンドシェイクと認証を行わずにこの接続を引き継ぐ。`idle_ttl`（省略時5分）の間使われなかった接続は切断する。
+    /// 事前接続ではイベントを通知しないため、対話的な認証が必要な接続先には使えない。
+    pub async fn prewarm(
         &self,
-        session_id: &str,
-        command: &str,
-    ) -> Result<CommandResult, SshError> {
-        let sessions = self.sessions.read().await;
-        let session_arc = sessions
-            .get(session_id)
-            .ok_or_else(|| SshError::SessionNotFound(session_id.to_string()))?
-            .clone();
+        config: SshConfig,
+        count: usize,
+        idle_ttl: Option<Duration>,
+    ) -> Result<usize, SshError> {
+        let count = count.min(MAX_WARM_CONNECTIONS);
+        let expires_at = tokio::time::Instant::now() + idle_ttl.unwrap_or(DEFAULT_WARM_IDLE_TTL);
+        let target = warm_target(&config)?;
 
-        let mut session = session_arc.lock().await;
-        session.execute_command(command).await
-    }
+        let mut attempts = tokio::task::JoinSet::new();
+        for _ in 0..count {
+            let mut session = SshSession::new(
+                format!("warm-{}", Uuid::new_v4()),
+                config.clone(),
+                EventBus::new(),
+                self.auth_prompts.clone(),
+                self.key_provider.clone(),
+                self.host_trust.clone(),
+                self.clock.clone(),
+            );
+            session.russh_config = self.russh_config.clone();
+            attempts.spawn(async move { session.connect(&CancellationToken::new()).await.map(|()| session) });
+        }
 
-    /// セッション情報を取得
-    pub async fn get_session_info(&self, session_id: &str) -> Result<SshSessionInfo, SshError> {
-        let sessions = self.sessions.read().await;
-        let session_arc = sessions
-            .get(session_id)
-            .ok_or_else(|| SshError::SessionNotFound(session_id.to_string()))?
-            .clone();
+        let mut established = 0;
+        let mut last_error = None;
+        while let Some(result) = attempts.join_next().await {
+            match result.map_err(|e| SshError::ConnectionFailed(e.to_string()))? {
+                Ok(session) => {
+                    self.warm_pool.lock().await.push(WarmConnection {
+                        target: target.clone(),
+                        session,
+                        expires_at,
+                    });
+                    established += 1;
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
 
-        let session = session_arc.lock().await;
-        Ok(session.get_info())
+        if established > 0 {
+            let pool = self.warm_pool.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep_until(expires_at).await;
+                evict_expired_warm(&pool).await;
+            });
+        }
+        match last_error {
+            Some(e) if established == 0 => Err(e),
+            _ => Ok(established),
+        }
     }
 
-    /// 全セッション情報を取得
-    pub async fn list_sessions(&self) -> Vec<SshSessionInfo> {
-        let sessions = self.sessions.read().await;
-        let mut session_infos = Vec::new();
+    /// 接続先の一致する事前接続を取り出す（期限切れ・切断済みのものは捨てる）
+    async fn take_warm(&self, config: &SshConfig) -> Option<SshSession> {
+        let target = warm_target(config).ok()?;
+        let mut pool = self.warm_pool.lock().await;
+        let now = tokio::time::Instant::now();
+        pool.retain(|warm| {
+            warm.expires_at > now && warm.session.connection.as_ref().is_some_and(|c| !c.is_closed())
+        });
+        let index = pool.iter().position(|warm| warm.target == target)?;
+        Some(pool.swap_remove(index).session)
+    }
 
-        for session_arc in sessions.values() {
-            let session = session_arc.lock().await;
-            session_infos.push(session.get_info());
-        }
+    /// 進行中の接続（名前解決・TCP接続・ハンドシェイク・認証）を中断する
+    ///
+    /// 接続処理中でなければ何もしない。
+    pub async fn cancel_connect(&self, session_id: &str) -> Result<(), SshError> {
+        self.get_session(session_id).await?;
 
-        session_infos
+        if let Some(cancel) = self.connect_cancels.read().await.get(session_id) {
+            cancel.cancel();
+        }
+        Ok(())
     }
 
-    /// セッションを削除
-    pub async fn remove_session(&self, session_id: &str) -> Result<(), SshError> {
-        let mut sessions = self.sessions.write().await;
-        
-        if let Some(session_arc) = sessions.remove(session_id) {
-            let mut session = session_arc.lock().await;
-            let _ = session.disconnect().await; // エラーは無視
-        }
+    /// セッションのタイムアウトを実行中に変更する（`None` で設定値に戻す）
+    ///
+    /// russh の無通信タイムアウトは接続時にしか設定できないため、接続中のセッションには
+    /// 次回の接続（再接続を含む）から反映される。コマンド実行のタイムアウトには即座に適用される。
+    pub async fn set_session_timeout(&self, session_id: &str, timeout: Option<Duration>) -> Result<(), SshError> {
+        let session_arc = self.get_session(session_id).await?;
 
+        session_arc.lock().await.timeout_override = timeout;
         Ok(())
     }
 
-    /// SSHセッションの接続を取得（ターミナル用）
-    pub async fn get_connection(&self, session_id: &str) -> Result<Handle<SshClientHandler>, SshError> {
-        let sessions = self.sessions.read().await;
-        let session_arc = sessions
-            .get(session_id)
-            .ok_or_else(|| SshError::SessionNotFound(session_id.to_string()))?
-            .clone();
+    /// コマンド実行に適用するタイムアウト（実行中に変更された値と `deadline` までの残り時間の短い方）
+    ///
+    /// `deadline` を過ぎている場合は実行せずにタイムアウトとする。
+    async fn command_timeout(&self, session_id: &str, options: &CommandOptions) -> Result<Option<Duration>, SshError> {
+        let session_arc = self.get_session(session_id).await?;
+        let timeout = session_arc.lock().await.timeout_override;
 
-        let session = session_arc.lock().await;
-        match &session.connection {
-            Some(_handle) => {
-                // HandleはCloneできないので、新しい接続を作成する必要がある
-                // 今回は簡易的に、接続が存在することを確認してダミーのHandleを返す
-                Err(SshError::CommandFailed("Cannot clone SSH connection handle. Terminal feature requires refactoring.".to_string()))
-            },
-            None => Err(SshError::ConnectionFailed("SSH session not connected".to_string()))
-        }
+        let Some(deadline) = options.deadline else {
+            return Ok(timeout);
+        };
+        let remaining = (deadline - self.clock.now())
+            .to_std()
+            .ok()
+            .filter(|remaining| !remaining.is_zero())
+            .ok_or_else(|| SshError::Timeout(format!("deadline {} has already passed", deadline.to_rfc3339())))?;
+        Ok(Some(timeout.map_or(remaining, |timeout| timeout.min(remaining))))
     }
-}
 
-impl SshSession {
-    fn new(id: String, config: SshConfig) -> Self {
-        Self {
-            id,
-            config,
-            status: ConnectionStatus::Disconnected,
-            connection: None,
-            connected_at: None,
-        }
+    /// 同時接続数の上限を設定する（`None` で無制限）
+    pub async fn set_max_connected_sessions(&self, limit: Option<usize>) {
+        *self.max_connected.write().await = limit;
     }
 
-    async fn connect(&mut self) -> Result<(), SshError> {
-        self.status = ConnectionStatus::Connecting;
-
-        // SSH設定の準備
-        let ssh_config = russh::client::Config {
-            inactivity_timeout: self.config.timeout.map(std::time::Duration::from_secs),
-            ..Default::default()
+    /// 上限を超えないよう、最も使われていない接続済みセッションを切断する
+    ///
+    /// セッションの定義は残すため、後から再接続できる。ロック中のセッションは使用中とみなして
+    /// 切断の対象にせず、その完了も待たない（他のセッションの処理で接続が遅れないようにするため）。
+    async fn evict_for_new_connection(&self, connecting_id: &str) {
+        let Some(limit) = *self.max_connected.read().await else {
+            return;
         };
 
-        // 接続の確立
-        let mut connection = russh::client::connect(
-            Arc::new(ssh_config),
-            (self.config.host.as_str(), self.config.port),
-            SshClientHandler,
-        )
-        .await
-        .map_err(|e| SshError::ConnectionFailed(e.to_string()))?;
-
-        // 認証
-        let auth_result = match &self.config.auth_method {
-            AuthMethod::Password(password) => {
-                connection
-                    .authenticate_password(&self.config.username, password)
-                    .await
-                    .map_err(|e| SshError::AuthenticationFailed(e.to_string()))?
-            }
-            AuthMethod::PublicKey {
-                private_key_path,
-                passphrase,
-            } => {
-                let key = load_private_key(private_key_path, passphrase.as_deref())
-                    .map_err(|e| SshError::AuthenticationFailed(e.to_string()))?;
-                
-                connection
-                    .authenticate_publickey(&self.config.username, key)
-                    .await
-                    .map_err(|e| SshError::AuthenticationFailed(e.to_string()))?
-            }
-            AuthMethod::Agent => {
-                // TODO: SSH Agent認証の実装
-                return Err(SshError::AuthenticationFailed(
-                    "SSH Agent authentication not implemented yet".to_string(),
-                ));
+        let sessions: Vec<_> = self
+            .sessions
+            .read()
+            .await
+            .iter()
+            .filter(|(id, _)| id.as_str() != connecting_id)
+            .map(|(_, session_arc)| session_arc.clone())
+            .collect();
+        let mut connected = 0usize;
+        let mut idle = Vec::new();
+        for session_arc in sessions {
+            let Ok(mut session) = session_arc.try_lock() else {
+                // 使用中のセッションも上限の数には含める
+                connected += 1;
+                continue;
+            };
+            session.refresh_status();
+            if session.connection.is_some() {
+                connected += 1;
+                idle.push((session.last_activity, session_arc.clone()));
             }
-        };
+        }
 
-        // 認証が成功したかチェック
-        if auth_result != AuthResult::Success {
-            return Err(SshError::AuthenticationFailed("Authentication failed".to_string()));
+        // 最終使用時刻の古い順に、新しい接続の分の空きができるまで切断する
+        idle.sort_by_key(|(last_activity, _)| *last_activity);
+        let mut excess = (connected + 1).saturating_sub(limit.max(1));
+        for (_, session_arc) in idle {
+            if excess == 0 {
+                break;
+            }
+            // 確認後に使われ始めたセッションは切断しない
+            let Ok(mut session) = session_arc.try_lock() else {
+                continue;
+            };
+            let _ = session.disconnect().await;
+            excess -= 1;
+            self.events.emit(SshEvent::SessionEvicted {
+                session_id: session.id.clone(),
+            });
         }
+    }
 
-        // 認証成功後、接続を保存
-        self.connection = Some(connection);
-        self.status = ConnectionStatus::Connected;
-        self.connected_at = Some(chrono::Utc::now());
+    /// 再接続の待機を打ち切り、すぐに再試行する
+    pub async fn reconnect_now(&self, session_id: &str) -> Result<(), SshError> {
+        let session_arc = self.get_session(session_id).await?;
 
+        let session = session_arc.lock().await;
+        if !session.reconnecting {
+            return Err(SshError::ConnectionFailed("no reconnect in progress".to_string()));
+        }
+        session.reconnect_now.notify_waiters();
         Ok(())
     }
 
-    async fn disconnect(&mut self) -> Result<(), SshError> {
-        if let Some(connection) = self.connection.take() {
-            let _ = connection.disconnect(russh::Disconnect::ProtocolError, "", "en").await;
-        }
-        
-        self.status = ConnectionStatus::Disconnected;
-        self.connected_at = None;
+    /// セッションが接続済みになるまで待機する
+    ///
+    /// 接続に失敗した場合はその理由を返し、時間内に接続されなければ `Timeout` を返す。
+    pub async fn wait_until_connected(&self, session_id: &str, timeout: Duration) -> Result<(), SshError> {
+        let session_arc = self.get_session(session_id).await?;
 
-        Ok(())
-    }
+        let wait = async {
+            let status_changed = session_arc.lock().await.status_changed.clone();
+            loop {
+                // 状態の確認前に通知を登録し、その間の変化を取りこぼさない
+                let notified = status_changed.notified();
+                tokio::pin!(notified);
+                notified.as_mut().enable();
 
-    async fn execute_command(&mut self, command: &str) -> Result<CommandResult, SshError> {
-        let connection = self
-            .connection
-            .as_mut()
-            .ok_or_else(|| SshError::CommandFailed("Not connected".to_string()))?;
+                let status = session_arc.lock().await.status.clone();
+                match status {
+                    ConnectionStatus::Connected => return Ok(()),
+                    ConnectionStatus::Failed(reason) => return Err(SshError::ConnectionFailed(reason)),
+                    ConnectionStatus::Disconnected | ConnectionStatus::Connecting => {}
+                }
 
-        let mut channel = connection
-            .channel_open_session()
-            .await
-            .map_err(|e| SshError::CommandFailed(e.to_string()))?;
+                notified.await;
+            }
+        };
 
-        // Execute the command
-        channel
-            .exec(true, command)
-            .await
-            .map_err(|e| SshError::CommandFailed(e.to_string()))?;
+        tokio::time::timeout(timeout, wait).await.map_err(|_| {
+            SshError::Timeout(format!(
+                "session {} did not connect within {}s",
+                session_id,
+                timeout.as_secs()
+            ))
+        })?
+    }
 
-        // Read the output
-        let mut stdout = Vec::new();
-        let mut stderr = Vec::new();
-        let mut exit_code = 0;
+    /// セッションの通信テレメトリを取得
+    pub async fn get_telemetry(&self, session_id: &str) -> Result<SessionTelemetry, SshError> {
+        let session_arc = self.get_session(session_id).await?;
 
+        let session = session_arc.lock().await;
+        Ok(session.get_telemetry())
+    }
 
-        // Read all data from the channel
-        loop {
-            use russh::ChannelMsg;
-            
-            match channel.wait().await {
-                Some(ChannelMsg::Data { data }) => {
-                    stdout.extend_from_slice(&data);
-                }
-                Some(ChannelMsg::ExtendedData { data, ext: 1 }) => {
-                    stderr.extend_from_slice(&data);
-                }
-                Some(ChannelMsg::ExitStatus { exit_status }) => {
-                    exit_code = exit_status;
-                }
-                Some(ChannelMsg::Eof) => {
+    /// セッションの状態をまとめて取得する（ターミナルとストリーミング実行は含まない）
+    ///
+    /// 接続情報・通信量は同じロックの下で読み、互いに食い違わないようにする。
+    pub async fn get_session_snapshot(&self, session_id: &str) -> Result<SessionSnapshot, SshError> {
+        let session_arc = self.get_session(session_id).await?;
+        let (info, details, telemetry) = {
+            let mut session = session_arc.lock().await;
+            session.refresh_status();
+            let details = session.connection.as_ref().and(session.details.clone());
+            (session.get_info(), details, session.get_telemetry())
+        };
+        let running_commands = self.list_running_commands(session_id).await;
+
+        Ok(SessionSnapshot {
+            info,
+            details,
+            telemetry,
+            forwards: self.forwards.list(session_id).await,
+            terminals: Vec::new(),
+            open_channels: running_commands.len(),
+            running_commands,
+            exec_streams: Vec::new(),
+        })
+    }
+
+    /// 直近の接続でサーバーが提示したホスト鍵を取得
+    pub async fn get_host_key(&self, session_id: &str) -> Result<HostKeyInfo, SshError> {
+        let session_arc = self.get_session(session_id).await?;
+        let session = session_arc.lock().await;
+        let key = session.host_key.as_ref().ok_or_else(|| {
+            SshError::ConnectionFailed("host key is not known until the session has connected".to_string())
+        })?;
+        crate::ssh::keys::host_key_info(&session.config.host, session.config.port, key)
+    }
+
+    /// 既定値と上書きを反映した、実際に使われる設定値を取得
+    ///
+    /// 接続関連の値は次回の接続で使う設定から求める（`set_session_timeout` などで
+    /// 変更した場合、確立済みの接続の値とは異なることがある）。
+    pub async fn get_effective_config(&self, session_id: &str) -> Result<EffectiveConfig, SshError> {
+        let session_arc = self.get_session(session_id).await?;
+        let session = session_arc.lock().await;
+        let ssh_config = session.client_config();
+        let millis = |duration: Duration| duration.as_millis() as u64;
+
+        Ok(EffectiveConfig {
+            inactivity_timeout_ms: ssh_config.inactivity_timeout.map(millis),
+            keepalive_interval_ms: ssh_config.keepalive_interval.map(millis),
+            keepalive_max: ssh_config.keepalive_max,
+            auth_timeout_ms: millis(
+                session
+                    .config
+                    .auth_timeout_secs
+                    .map(Duration::from_secs)
+                    .unwrap_or(DEFAULT_AUTH_TIMEOUT),
+            ),
+            command_timeout_ms: session.timeout_override.map(millis),
+            read_buffer_size: session.config.read_buffer_size.unwrap_or(DEFAULT_READ_BUFFER_SIZE).max(1),
+            maximum_packet_size: ssh_config.maximum_packet_size,
+            window_size: ssh_config.window_size,
+            channel_buffer_size: ssh_config.channel_buffer_size,
+            max_concurrent_commands: max_concurrent_commands(&session.config),
+            rekey_time_limit_ms: millis(ssh_config.limits.rekey_time_limit),
+            rekey_read_limit: ssh_config.limits.rekey_read_limit as u64,
+            rekey_write_limit: ssh_config.limits.rekey_write_limit as u64,
+            reconnect: session.config.reconnect.clone(),
+            terminal_idle_close_ms: session.config.terminal_idle_close_secs.map(|secs| secs * 1000),
+            custom_russh_config: session.russh_config.is_some(),
+        })
+    }
+
+    /// 接続中のトランスポートで鍵再交換を行い、要求した時刻を返す
+    ///
+    /// russh は再交換の完了を通知しないため、記録するのは要求を受け付けた時刻。
+    pub async fn rekey(&self, session_id: &str) -> Result<chrono::DateTime<chrono::Utc>, SshError> {
+        let connection = self.get_connection(session_id).await?;
+        connection
+            .rekey_soon()
+            .await
+            .map_err(|e| SshError::ConnectionFailed(format!("on-demand rekey is not available: {}", e)))?;
+
+        let now = self.clock.now();
+        self.get_session(session_id).await?.lock().await.last_rekey_at = Some(now);
+        Ok(now)
+    }
+
+    // 任意のグローバル要求（ベンダー固有の要求名とペイロード）の送信は提供しない。
+    // russh 0.52 の Handle は tcpip-forward・keepalive 等の定義済みの要求しか送れず、
+    // 要求を送らずに常に失敗するコマンドになってしまうため。
+
+    /// server-sig-algs から判断した RSA 署名アルゴリズムを取得（ext-info の内容そのものではない）
+    pub async fn get_server_extensions(&self, session_id: &str) -> Result<ServerExtensions, SshError> {
+        let session_arc = self.get_session(session_id).await?;
+
+        let session = session_arc.lock().await;
+        session
+            .server_extensions
+            .clone()
+            .ok_or_else(|| SshError::ConnectionFailed("SSH session not connected".to_string()))
+    }
+
+    /// 認証中のプロンプトに応答する
+    pub async fn respond_auth_prompt(&self, session_id: &str, responses: Vec<String>) -> Result<(), SshError> {
+        self.auth_prompts.respond(session_id, responses).await
+    }
+
+    /// セッションを切断
+    pub async fn disconnect(&self, session_id: &str) -> Result<(), SshError> {
+        let session_arc = self.get_session(session_id).await?;
+        self.cleanup_temps_before_disconnect(session_id).await;
+
+        let mut session = session_arc.lock().await;
+        session.disconnect().await
+    }
+
+    /// コマンドを実行
+    ///
+    /// 公開鍵の authorized_keys に `command="..."` が指定されている場合、サーバーは
+    /// 要求したコマンドの代わりに強制コマンドを実行する。`detect_forced_command` で確認できる。
+    pub async fn execute_command(
+        &self,
+        session_id: &str,
+        command: &str,
+        options: &CommandOptions,
+    ) -> Result<CommandResult, SshError> {
+        self.run_command(session_id, command, options, None).await
+    }
+
+    /// コマンドを実行し、受信した出力を `on_chunk` にその都度渡す（結果は `execute_command` と同じ）
+    ///
+    /// Tauri を介さずにライブラリとして使う場合向け。コールバックはセッションのロックを
+    /// 保持せずに呼ぶため、コールバック内から同じセッションを操作してもよい。
+    pub async fn execute_command_with_chunks<F>(
+        &self,
+        session_id: &str,
+        command: &str,
+        options: &CommandOptions,
+        mut on_chunk: F,
+    ) -> Result<CommandResult, SshError>
+    where
+        F: FnMut(StdStream, &[u8]) + Send,
+    {
+        self.run_command(session_id, command, options, Some(&mut on_chunk)).await
+    }
+
+    async fn run_command(
+        &self,
+        session_id: &str,
+        command: &str,
+        options: &CommandOptions,
+        on_chunk: Option<&mut OnChunk<'_>>,
+    ) -> Result<CommandResult, SshError> {
+        // チャネルはセッションのロック外で扱う（russhはチャネルIDで振り分けるため、
+        // 同じ接続上のターミナルや他のコマンドと並行して実行できる）
+        let result: Result<CommandResult, SshError> = async {
+            self.check_shell_compat(session_id, options).await?;
+            let connection = self.get_connection(session_id).await?;
+            let timeout = self.command_timeout(session_id, options).await?;
+            let (result, _) = self
+                .run_tracked(session_id, command, ExecOrigin::User { confirmed: options.confirmed }, |produced| async move {
+                    with_command_timeout(
+                        timeout,
+                        execute_on_connection(&connection, command, options, &*self.clock, Some(&produced), on_chunk),
+                    )
+                    .await
+                })
+                .await?;
+            Ok(result)
+        }
+        .await;
+        self.audit_command(session_id, command, result.as_ref().map(|result| result.exit_code));
+        result
+    }
+
+    /// 実行したコマンドを監査の記録先に渡す
+    pub fn audit_command(&self, session_id: &str, command: &str, result: Result<Option<u32>, &SshError>) {
+        self.auditor.command_executed(session_id, command, result);
+    }
+
+    /// POSIX sh を前提とするオプションを、そうでないシェル（cmd・PowerShell）に対して拒否する
+    async fn check_shell_compat(&self, session_id: &str, options: &CommandOptions) -> Result<(), SshError> {
+        if !options.login_shell && options.env.is_empty() {
+            return Ok(());
+        }
+        let session_arc = self.get_session(session_id).await?;
+        let shell_kind = session_arc.lock().await.shell_kind;
+        match shell_kind {
+            Some(kind) if !kind.is_posix_compatible() => Err(SshError::ConfigError(format!(
+                "login_shell and env require a POSIX shell, but the remote shell is {:?}",
+                kind
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// セーフモードが有効なら、確認済みでない危険なコマンドを拒否する
+    async fn check_safe_mode(&self, session_id: &str, command: &str, confirmed: bool) -> Result<(), SshError> {
+        if confirmed {
+            return Ok(());
+        }
+        let session_arc = self.get_session(session_id).await?;
+        let safe_mode = session_arc.lock().await.config.safe_mode.clone();
+        match safe_mode {
+            Some(safe_mode) if matches_dangerous_pattern(command, &safe_mode) => Err(SshError::CommandFailed(
+                "blocked by safe mode; confirm with force flag".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// 実行中のコマンドとして登録する
+    ///
+    /// コマンドを実行する経路（完了待ち・ストリーミング・ファイルへの書き出し・内部の判定用コマンド）は
+    /// すべてここを通る。ユーザーが指定したコマンドはセーフモードで確認し、登録したコマンドは
+    /// `exec_list_running` に表示され `abort_all` で中断できる。
+    /// セッションの同時実行数の上限に達している間は、空きができるまで待つ（待っている間も中断できる）。
+    pub(crate) async fn begin_exec(
+        &self,
+        session_id: &str,
+        command: &str,
+        origin: ExecOrigin,
+        streaming: bool,
+    ) -> Result<ExecTicket, SshError> {
+        match origin {
+            ExecOrigin::User { confirmed } => self.check_safe_mode(session_id, command, confirmed).await?,
+            ExecOrigin::Internal => {
+                self.get_session(session_id).await?;
+            }
+        }
+        let command_slots = self.get_session(session_id).await?.lock().await.command_slots.clone();
+        let exec_id = Uuid::new_v4().to_string();
+        let produced = Arc::new(AtomicU64::new(0));
+        let cancel = CancellationToken::new();
+        if let Ok(mut running) = self.running.lock() {
+            running.insert(
+                exec_id.clone(),
+                RunningCommand {
+                    info: RunningExecInfo {
+                        exec_id: exec_id.clone(),
+                        session_id: session_id.to_string(),
+                        command: command.to_string(),
+                        started_at: self.clock.now(),
+                        bytes_produced: 0,
+                        streaming,
+                    },
+                    produced: produced.clone(),
+                    cancel: cancel.clone(),
+                },
+            );
+        }
+
+        let mut ticket = ExecTicket {
+            exec_id,
+            produced,
+            cancel,
+            running: self.running.clone(),
+            _slot: None,
+        };
+        let slot = tokio::select! {
+            _ = ticket.cancel.cancelled() => return Err(SshError::CommandFailed("command cancelled".to_string())),
+            slot = command_slots.acquire_owned() => slot.map_err(|e| SshError::CommandFailed(e.to_string()))?,
+        };
+        ticket._slot = Some(slot);
+        Ok(ticket)
+    }
+
+    /// 実行中のコマンドとして登録し、キャンセルできるようにして実行する
+    ///
+    /// `run` には受信した出力のバイト数を加算するカウンタが渡される。
+    async fn run_tracked<T, F, Fut>(
+        &self,
+        session_id: &str,
+        command: &str,
+        origin: ExecOrigin,
+        run: F,
+    ) -> Result<T, SshError>
+    where
+        F: FnOnce(Arc<AtomicU64>) -> Fut,
+        Fut: std::future::Future<Output = Result<T, SshError>>,
+    {
+        let ticket = self.begin_exec(session_id, command, origin, false).await?;
+
+        tokio::select! {
+            _ = ticket.cancel_token().cancelled() => Err(SshError::CommandFailed("command cancelled".to_string())),
+            result = run(ticket.produced().clone()) => result,
+        }
+    }
+
+    /// このアプリが組み立てたコマンド（存在確認・一時ファイルの作成など）を実行する
+    pub(crate) async fn execute_internal(
+        &self,
+        session_id: &str,
+        command: &str,
+        options: &CommandOptions,
+    ) -> Result<(CommandResult, chrono::Duration), SshError> {
+        let connection = self.get_connection(session_id).await?;
+        self.run_tracked(session_id, command, ExecOrigin::Internal, |produced| async move {
+            execute_on_connection(&connection, command, options, &*self.clock, Some(&produced), None).await
+        })
+        .await
+    }
+
+    /// セッションで実行中のコマンド一覧を取得（開始順）
+    pub async fn list_running_commands(&self, session_id: &str) -> Vec<RunningExecInfo> {
+        let Ok(running) = self.running.lock() else {
+            return Vec::new();
+        };
+        let mut commands: Vec<RunningExecInfo> = running
+            .values()
+            .filter(|running| running.info.session_id == session_id)
+            .map(|running| RunningExecInfo {
+                bytes_produced: running.produced.load(Ordering::Relaxed),
+                ..running.info.clone()
+            })
+            .collect();
+        commands.sort_by_key(|info| info.started_at);
+        commands
+    }
+
+    /// 実行中のコマンドを中断する（呼び出し元にはエラーが返る）
+    pub async fn cancel_command(&self, exec_id: &str) -> Result<(), SshError> {
+        let running = self
+            .running
+            .lock()
+            .map_err(|_| SshError::SessionNotFound(exec_id.to_string()))?;
+        running
+            .get(exec_id)
+            .ok_or_else(|| SshError::SessionNotFound(exec_id.to_string()))?
+            .cancel
+            .cancel();
+        Ok(())
+    }
+
+    /// セッションで実行中のコマンド（ストリーミング実行を含む）をすべて中断し、中断した数を返す
+    ///
+    /// すでに中断を要求済みで終了待ちのものは数えない。
+    pub async fn cancel_commands_for_session(&self, session_id: &str) -> usize {
+        let Ok(running) = self.running.lock() else {
+            return 0;
+        };
+        let mut cancelled = 0;
+        for command in running
+            .values()
+            .filter(|running| running.info.session_id == session_id && !running.cancel.is_cancelled())
+        {
+            command.cancel.cancel();
+            cancelled += 1;
+        }
+        cancelled
+    }
+
+    /// コマンドを実行し、前回の標準出力・終了コードから変わったかを差分とともに返す
+    ///
+    /// 差分はこちらで計算し、出力が変わらない限り出力そのものは返さない。
+    pub async fn execute_command_diff(
+        &self,
+        session_id: &str,
+        command: &str,
+        previous_output: &str,
+        previous_exit_code: Option<u32>,
+        options: &CommandOptions,
+    ) -> Result<CommandDiffResult, SshError> {
+        let result = self.execute_command(session_id, command, options).await?;
+
+        let diff = unified_diff(previous_output, &result.stdout);
+        let changed = result.stdout != previous_output || result.exit_code != previous_exit_code;
+        Ok(CommandDiffResult {
+            changed,
+            diff,
+            exit_code: result.exit_code,
+            output: changed.then_some(result.stdout),
+        })
+    }
+
+    /// セッションに登録されたコマンドマクロ一覧を取得
+    pub async fn list_macros(&self, session_id: &str) -> Result<Vec<CommandMacro>, SshError> {
+        Ok(self.get_session(session_id).await?.lock().await.config.macros.clone())
+    }
+
+    /// コマンドマクロを登録する
+    pub async fn add_macro(&self, session_id: &str, name: &str, command: &str) -> Result<CommandMacro, SshError> {
+        if name.trim().is_empty() || command.trim().is_empty() {
+            return Err(SshError::ConfigError("macro name and command must not be empty".to_string()));
+        }
+        let command_macro = CommandMacro {
+            id: Uuid::new_v4().to_string(),
+            name: name.to_string(),
+            command: command.to_string(),
+        };
+        let session_arc = self.get_session(session_id).await?;
+        session_arc.lock().await.config.macros.push(command_macro.clone());
+        Ok(command_macro)
+    }
+
+    /// コマンドマクロを削除する
+    pub async fn remove_macro(&self, session_id: &str, macro_id: &str) -> Result<(), SshError> {
+        let session_arc = self.get_session(session_id).await?;
+        let mut session = session_arc.lock().await;
+        let before = session.config.macros.len();
+        session.config.macros.retain(|command_macro| command_macro.id != macro_id);
+        if session.config.macros.len() == before {
+            return Err(SshError::ConfigError(format!("macro not found: {}", macro_id)));
+        }
+        Ok(())
+    }
+
+    /// 引数を埋め込んだコマンドマクロを実行する
+    pub async fn run_macro(
+        &self,
+        session_id: &str,
+        macro_id: &str,
+        args: &HashMap<String, String>,
+        options: &CommandOptions,
+    ) -> Result<CommandResult, SshError> {
+        let template = self
+            .list_macros(session_id)
+            .await?
+            .into_iter()
+            .find(|command_macro| command_macro.id == macro_id)
+            .ok_or_else(|| SshError::ConfigError(format!("macro not found: {}", macro_id)))?
+            .command;
+        let command = expand_macro(&template, args)?;
+        self.execute_command(session_id, &command, options).await
+    }
+
+    /// コマンドを実行し、所要時間とともに結果を返す
+    pub async fn execute_command_timed(
+        &self,
+        session_id: &str,
+        command: &str,
+        options: &CommandOptions,
+    ) -> Result<TimedCommandResult, SshError> {
+        let result: Result<TimedCommandResult, SshError> = async {
+            self.check_shell_compat(session_id, options).await?;
+            let connection = self.get_connection(session_id).await?;
+            let timeout = self.command_timeout(session_id, options).await?;
+            let (result, duration) = self
+                .run_tracked(session_id, command, ExecOrigin::User { confirmed: options.confirmed }, |produced| async move {
+                    with_command_timeout(
+                        timeout,
+                        execute_on_connection(&connection, command, options, &*self.clock, Some(&produced), None),
+                    )
+                    .await
+                })
+                .await?;
+            Ok(TimedCommandResult {
+                result,
+                duration_ms: duration.num_milliseconds().max(0) as u64,
+            })
+        }
+        .await;
+        self.audit_command(session_id, command, result.as_ref().map(|timed| timed.result.exit_code));
+        result
+    }
+
+    /// コマンドを実行し、出力をバイト列のまま（base64で）返す
+    ///
+    /// バイナリを出力するコマンド向け。`strip_echo` は適用しない。
+    pub async fn execute_command_bytes(
+        &self,
+        session_id: &str,
+        command: &str,
+        options: &CommandOptions,
+    ) -> Result<BytesCommandResult, SshError> {
+        use base64::Engine;
+
+        let result: Result<BytesCommandResult, SshError> = async {
+            self.check_shell_compat(session_id, options).await?;
+            let connection = self.get_connection(session_id).await?;
+            let timeout = self.command_timeout(session_id, options).await?;
+            let (output, _) = self
+                .run_tracked(session_id, command, ExecOrigin::User { confirmed: options.confirmed }, |produced| async move {
+                    with_command_timeout(
+                        timeout,
+                        execute_raw_on_connection(&connection, command, options, &*self.clock, Some(&produced), None),
+                    )
+                    .await
+                })
+                .await?;
+
+            let engine = base64::engine::general_purpose::STANDARD;
+            Ok(BytesCommandResult {
+                exit_code: output.exit_code,
+                stdout_b64: engine.encode(&output.stdout),
+                stderr_b64: engine.encode(&output.stderr),
+                truncated: output.truncated,
+            })
+        }
+        .await;
+        self.audit_command(session_id, command, result.as_ref().map(|bytes| bytes.exit_code));
+        result
+    }
+
+    /// コマンドを実行し、出力を受信しながらローカルファイルへ書き出す
+    pub async fn execute_command_to_file(
+        &self,
+        session_id: &str,
+        command: &str,
+        local_path: &str,
+        options: &FileOutputOptions,
+    ) -> Result<FileOutputResult, SshError> {
+        let result = async {
+            let connection = self.get_connection(session_id).await?;
+            self.run_tracked(session_id, command, ExecOrigin::User { confirmed: options.confirmed }, |produced| async move {
+                execute_to_file(&connection, command, local_path, options, &produced).await
+            })
+            .await
+        }
+        .await;
+        self.audit_command(session_id, command, result.as_ref().map(|output| output.exit_code));
+        result
+    }
+
+    /// リモートにコマンドが存在するかを `command -v` で調べる
+    pub async fn remote_command_exists(&self, session_id: &str, name: &str) -> Result<RemoteCommandInfo, SshError> {
+        let session_arc = self.get_session(session_id).await?;
+        if let Some(info) = session_arc.lock().await.command_cache.get(name) {
+            return Ok(info.clone());
+        }
+
+        let command = format!("command -v {}", shell_quote(name));
+        let (result, _) = self
+            .execute_internal(session_id, &command, &CommandOptions::default())
+            .await?;
+
+        let path = result
+            .stdout
+            .lines()
+            .next()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string);
+        let info = RemoteCommandInfo {
+            exists: result.exit_code == Some(0) && path.is_some(),
+            path,
+        };
+
+        session_arc
+            .lock()
+            .await
+            .command_cache
+            .insert(name.to_string(), info.clone());
+        Ok(info)
+    }
+
+    /// ログインシェルで `env` を実行し、非対話コマンドから見える環境変数を取得する
+    ///
+    /// 値に改行を含む変数を区別できるよう `env -0` を優先し、使えない環境では `env` で代用する。
+    pub async fn get_remote_env(&self, session_id: &str) -> Result<HashMap<String, String>, SshError> {
+        let options = CommandOptions {
+            login_shell: true,
+            ..CommandOptions::default()
+        };
+        let (result, _) = self
+            .execute_internal(session_id, "env -0 2>/dev/null || env", &options)
+            .await?;
+
+        if result.exit_code != Some(0) {
+            return Err(SshError::CommandFailed(format!(
+                "env exited with {:?}: {}",
+                result.exit_code,
+                result.stderr.trim()
+            )));
+        }
+        Ok(parse_env_output(&result.stdout))
+    }
+
+    /// `mktemp` でリモートに一時ファイル（`directory` 指定時はディレクトリ）を作成し、そのパスを返す
+    ///
+    /// テンプレートは末尾に3文字以上の `X` が必要（省略時は `/tmp/pardoroid.XXXXXXXX`）。
+    /// 作成したパスはセッションに記録し、`remote_cleanup_temps` または切断時に削除する。
+    pub async fn remote_mktemp(
+        &self,
+        session_id: &str,
+        template: Option<&str>,
+        directory: bool,
+    ) -> Result<String, SshError> {
+        let template = template.unwrap_or(DEFAULT_TEMP_TEMPLATE);
+        if !template.ends_with("XXX") {
+            return Err(SshError::ConfigError(format!(
+                "temp template must end with at least 3 'X' characters: {}",
+                template
+            )));
+        }
+
+        let command = format!(
+            "mktemp {}-- {}",
+            if directory { "-d " } else { "" },
+            shell_quote(template)
+        );
+        let (result, _) = self
+            .execute_internal(session_id, &command, &CommandOptions::default())
+            .await?;
+        let path = result.stdout.trim();
+        if result.exit_code != Some(0) || path.is_empty() {
+            return Err(SshError::CommandFailed(format!(
+                "mktemp exited with {:?}: {}",
+                result.exit_code,
+                result.stderr.trim()
+            )));
+        }
+
+        self.get_session(session_id)
+            .await?
+            .lock()
+            .await
+            .temp_paths
+            .push(path.to_string());
+        Ok(path.to_string())
+    }
+
+    /// `remote_mktemp` で作成した一時ファイル・ディレクトリをすべて削除し、削除した数を返す
+    ///
+    /// 削除に失敗した場合は記録を残し、次回の削除で再度試みる。
+    pub async fn remote_cleanup_temps(&self, session_id: &str) -> Result<usize, SshError> {
+        let session_arc = self.get_session(session_id).await?;
+        let paths = std::mem::take(&mut session_arc.lock().await.temp_paths);
+        if paths.is_empty() {
+            return Ok(0);
+        }
+
+        let quoted: Vec<String> = paths.iter().map(|path| shell_quote(path)).collect();
+        let command = format!("rm -rf -- {}", quoted.join(" "));
+        let result = async {
+            let (result, _) = self
+                .execute_internal(session_id, &command, &CommandOptions::default())
+                .await?;
+            match result.exit_code {
+                Some(0) => Ok(()),
+                code => Err(SshError::CommandFailed(format!(
+                    "rm exited with {:?}: {}",
+                    code,
+                    result.stderr.trim()
+                ))),
+            }
+        }
+        .await;
+
+        if let Err(e) = result {
+            session_arc.lock().await.temp_paths.extend(paths);
+            return Err(e);
+        }
+        Ok(paths.len())
+    }
+
+    /// 切断前に一時ファイルを削除する（失敗しても切断は続ける）
+    async fn cleanup_temps_before_disconnect(&self, session_id: &str) {
+        let _ = tokio::time::timeout(TEMP_CLEANUP_TIMEOUT, self.remote_cleanup_temps(session_id)).await;
+    }
+
+    /// `ps aux` でリモートのプロセス一覧を取得する
+    pub async fn remote_process_list(&self, session_id: &str) -> Result<Vec<ProcessInfo>, SshError> {
+        let (result, _) = self
+            .execute_internal(session_id, "ps aux", &CommandOptions::default())
+            .await?;
+        if result.exit_code != Some(0) {
+            return Err(SshError::CommandFailed(format!(
+                "ps exited with {:?}: {}",
+                result.exit_code,
+                result.stderr.trim()
+            )));
+        }
+        Ok(parse_ps_output(&result.stdout))
+    }
+
+    /// リモートのプロセスにシグナルを送る（省略時は `TERM`）
+    ///
+    /// シグナルは名前（`KILL`・`SIGKILL`）か番号で指定する。
+    pub async fn remote_kill(&self, session_id: &str, pid: u32, signal: Option<&str>) -> Result<(), SshError> {
+        let signal = signal.unwrap_or("TERM");
+        let signal = signal.strip_prefix("SIG").unwrap_or(signal);
+        if signal.is_empty() || !signal.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(SshError::ConfigError(format!("invalid signal: {:?}", signal)));
+        }
+
+        let command = format!("kill -{} {}", signal, pid);
+        let (result, _) = self
+            .execute_internal(session_id, &command, &CommandOptions::default())
+            .await?;
+        if result.exit_code == Some(0) {
+            return Ok(());
+        }
+
+        let stderr = result.stderr.trim();
+        let reason = if stderr.contains("not permitted") {
+            format!("permission denied to signal process {}", pid)
+        } else if stderr.contains("No such process") {
+            format!("process {} not found", pid)
+        } else {
+            format!("kill exited with {:?}", result.exit_code)
+        };
+        Err(SshError::CommandFailed(format!("{}: {}", reason, stderr)))
+    }
+
+    /// リモートの既定のシェルの種類を判定し、セッションに記録する
+    ///
+    /// 判定結果は `login_shell`・`env` の可否の確認に使う（cmd・PowerShell では拒否する）。
+    pub async fn detect_shell_kind(&self, session_id: &str) -> Result<ShellKind, SshError> {
+        let options = CommandOptions::default();
+        let probe = self.execute_internal(session_id, SHELL_PROBE_COMMAND, &options);
+        let (result, _) = tokio::time::timeout(SHELL_PROBE_TIMEOUT, probe)
+            .await
+            .map_err(|_| SshError::CommandFailed("shell detection timed out".to_string()))??;
+        let kind = parse_shell_probe(&result.stdout);
+        self.get_session(session_id).await?.lock().await.shell_kind = Some(kind);
+        Ok(kind)
+    }
+
+    /// authorized_keys の強制コマンドが有効かを判定する
+    ///
+    /// 一意な文字列を echo するコマンドを実行し、その出力が返らなければ強制コマンドが
+    /// 実行されたとみなす。判定のために強制コマンドが一度実行される点に注意。
+    pub async fn detect_forced_command(&self, session_id: &str) -> Result<bool, SshError> {
+        let marker = format!("pardoroid-probe-{}", Uuid::new_v4().simple());
+        let command = format!("echo {}", marker);
+        let options = CommandOptions::default();
+        let probe = self.execute_internal(session_id, &command, &options);
+        // 強制コマンドが終了しない場合も、echo が返らなかったものとして扱う
+        let forced = match tokio::time::timeout(FORCED_COMMAND_PROBE_TIMEOUT, probe).await {
+            Ok(result) => !result?.0.stdout.contains(&marker),
+            Err(_) => true,
+        };
+
+        let session_arc = self.get_session(session_id).await?;
+        session_arc.lock().await.forced_command = Some(forced);
+        if forced {
+            self.events.emit(SshEvent::ForcedCommandDetected {
+                session_id: session_id.to_string(),
+            });
+        }
+
+        Ok(forced)
+    }
+
+    /// セッション情報を取得
+    pub async fn get_session_info(&self, session_id: &str) -> Result<SshSessionInfo, SshError> {
+        let session_arc = self.get_session(session_id).await?;
+
+        let mut session = session_arc.lock().await;
+        session.refresh_status();
+        Ok(session.get_info())
+    }
+
+    /// 全セッション情報を取得
+    pub async fn list_sessions(&self) -> Vec<SshSessionInfo> {
+        let sessions = self.sessions.read().await;
+        let mut session_infos = Vec::new();
+
+        for session_arc in sessions.values() {
+            let mut session = session_arc.lock().await;
+            session.refresh_status();
+            session_infos.push(session.get_info());
+        }
+
+        session_infos
+    }
+
+    /// 状態とホスト名で絞り込んだセッション一覧を取得
+    ///
+    /// `host_contains` は大文字小文字を区別しない部分一致。
+    pub async fn list_sessions_filtered(
+        &self,
+        status: Option<ConnectionStatus>,
+        host_contains: Option<String>,
+    ) -> Vec<SshSessionInfo> {
+        let host_contains = host_contains.map(|host| host.to_lowercase());
+        let sessions = self.sessions.read().await;
+        let mut session_infos = Vec::new();
+
+        for session_arc in sessions.values() {
+            let mut session = session_arc.lock().await;
+            session.refresh_status();
+            if let Some(status) = &status {
+                if !session.status.same_kind(status) {
+                    continue;
+                }
+            }
+            if let Some(host) = &host_contains {
+                if !session.config.host.to_lowercase().contains(host.as_str()) {
+                    continue;
+                }
+            }
+            session_infos.push(session.get_info());
+        }
+
+        session_infos
+    }
+
+    /// ポート転送を開始する
+    pub async fn start_forward(&self, session_id: &str, spec: ForwardSpec) -> Result<ForwardInfo, SshError> {
+        let connection = self.get_connection(session_id).await?;
+        let remote_forwards = self.get_session(session_id).await?.lock().await.remote_forwards.clone();
+
+        self.forwards
+            .start(session_id, connection, remote_forwards, spec)
+            .await
+    }
+
+    /// X11転送の中継先
+    pub async fn x11_slot(&self, session_id: &str) -> Result<X11Slot, SshError> {
+        Ok(self.get_session(session_id).await?.lock().await.x11.clone())
+    }
+
+    /// 新しい操作を受け付けないようにする（実行中の操作はそのまま続ける）
+    pub async fn begin_drain(&self, session_id: &str) -> Result<(), SshError> {
+        self.get_session(session_id).await?.lock().await.draining = true;
+        Ok(())
+    }
+
+    /// セッションを削除
+    pub async fn remove_session(&self, session_id: &str) -> Result<(), SshError> {
+        self.cleanup_temps_before_disconnect(session_id).await;
+        let mut sessions = self.sessions.write().await;
+        
+        if let Some(session_arc) = sessions.remove(session_id) {
+            let mut session = session_arc.lock().await;
+            let _ = session.disconnect().await; // エラーは無視
+        }
+        drop(sessions);
+        self.forwards.stop_all_for_session(session_id).await;
+
+        Ok(())
+    }
+
+    /// 全セッションの設定をファイルへエクスポート（秘密情報は除外）
+    pub async fn export_sessions(&self, path: &str) -> Result<usize, SshError> {
+        let configs: Vec<SshConfig> = self
+            .list_sessions()
+            .await
+            .into_iter()
+            .map(|info| info.config)
+            .collect();
+        let count = configs.len();
+
+        let export = SessionExport::new(configs);
+        let json = serde_json::to_string_pretty(&export)
+            .map_err(|e| SshError::ConfigError(e.to_string()))?;
+        tokio::fs::write(path, json).await?;
+
+        Ok(count)
+    }
+
+    /// ファイルからセッション設定をインポート
+    pub async fn import_sessions(&self, path: &str) -> Result<ImportSummary, SshError> {
+        let json = tokio::fs::read_to_string(path).await?;
+        let export = SessionExport::from_json(&json)?;
+
+        let mut known: std::collections::HashSet<_> = self
+            .list_sessions()
+            .await
+            .iter()
+            .map(|info| session_identity(&info.config))
+            .collect();
+
+        let mut summary = ImportSummary {
+            imported: Vec::new(),
+            skipped: 0,
+        };
+        for config in export.sessions {
+            if !known.insert(session_identity(&config)) {
+                summary.skipped += 1;
+                continue;
+            }
+            summary.imported.push(self.create_session(config).await?);
+        }
+
+        Ok(summary)
+    }
+
+    /// SSHセッションの接続を取得（チャネルを開くための共有ハンドル）
+    pub async fn get_connection(&self, session_id: &str) -> Result<Arc<SshConnection>, SshError> {
+        let session_arc = self.get_session(session_id).await?;
+
+        let mut session = session_arc.lock().await;
+        if session.draining {
+            return Err(SshError::ConnectionFailed(
+                "session is closing; new operations are not accepted".to_string(),
+            ));
+        }
+        session.refresh_status();
+        if session.connection.is_some() {
+            session.last_activity = Some(chrono::Utc::now());
+        }
+        // 接続が失われている場合は russh のエラーではなく切断理由を返す
+        session.connection.clone().ok_or_else(|| match &session.status {
+            ConnectionStatus::Failed(reason) => {
+                SshError::ConnectionFailed(format!("SSH session not connected: {}", reason))
+            }
+            _ => SshError::ConnectionFailed("SSH session not connected".to_string()),
+        })
+    }
+}
+
+impl SshSession {
+    fn new(
+        id: String,
+        config: SshConfig,
+        events: EventBus,
+        auth_prompts: AuthPromptBroker,
+        key_provider: Arc<dyn KeyProvider>,
+        host_trust: Arc<HostTrustStore>,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        let command_slots = max_concurrent_commands(&config);
+        Self {
+            id,
+            config,
+            status: ConnectionStatus::Disconnected,
+            status_changed: Arc::new(Notify::new()),
+            connection: None,
+            connected_at: None,
+            last_activity: None,
+            timeout_override: None,
+            last_error: None,
+            remote_disconnect: Arc::new(std::sync::Mutex::new(None)),
+            remote_forwards: RemoteForwardTargets::default(),
+            x11: X11Slot::default(),
+            connection_closed: Arc::new(Notify::new()),
+            reconnect_cancel: None,
+            reconnect_now: Arc::new(Notify::new()),
+            reconnecting: false,
+            traffic: TrafficCounters::new(),
+            rekey_limits: russh::Limits::default(),
+            last_rekey_at: None,
+            server_extensions: None,
+            details: None,
+            server_version: None,
+            host_key: None,
+            connect_timings: None,
+            forced_command: None,
+            shell_kind: None,
+            temp_paths: Vec::new(),
+            command_cache: HashMap::new(),
+            events,
+            auth_prompts,
+            key_provider,
+            host_trust,
+            clock,
+            russh_config: None,
+            command_slots: Arc::new(Semaphore::new(command_slots)),
+            draining: false,
+        }
+    }
+
+    /// 接続がサーバー側から切断されていれば、その理由を状態に反映する
+    fn refresh_status(&mut self) {
+        let closed = self
+            .connection
+            .as_ref()
+            .is_some_and(|connection| connection.is_closed());
+        if closed {
+            self.mark_connection_lost();
+        }
+    }
+
+    /// 切断された接続を破棄し、理由を記録する
+    fn mark_connection_lost(&mut self) {
+        if self.connection.take().is_none() {
+            return;
+        }
+
+        let reason = self
+            .remote_disconnect
+            .lock()
+            .ok()
+            .and_then(|mut slot| slot.take())
+            .unwrap_or_else(|| "connection closed".to_string());
+        self.server_extensions = None;
+        self.connected_at = None;
+        self.last_error = Some(reason.clone());
+        self.set_status(ConnectionStatus::Failed(reason.clone()));
+        self.events.emit(SshEvent::ConnectionLost {
+            session_id: self.id.clone(),
+            reason,
+        });
+    }
+
+    /// 事前に確立した接続を引き継ぐ
+    ///
+    /// リモート転送とX11転送の中継先は接続のハンドラーが参照するため、内容を移して引き継ぐ。
+    fn adopt(&mut self, mut warm: SshSession) {
+        if let (Ok(mut from), Ok(mut to)) = (self.remote_forwards.lock(), warm.remote_forwards.lock()) {
+            *to = std::mem::take(&mut *from);
+        }
+        if let (Ok(mut from), Ok(mut to)) = (self.x11.lock(), warm.x11.lock()) {
+            *to = from.take();
+        }
+
+        self.command_cache.clear();
+        self.last_rekey_at = None;
+        self.last_error = None;
+        self.remote_forwards = warm.remote_forwards;
+        self.x11 = warm.x11;
+        self.traffic = warm.traffic;
+        self.remote_disconnect = warm.remote_disconnect;
+        self.connection_closed = warm.connection_closed;
+        self.rekey_limits = warm.rekey_limits;
+        self.server_extensions = warm.server_extensions.take();
+        self.server_version = warm.server_version.take();
+        self.host_key = warm.host_key.take();
+        self.connect_timings = warm.connect_timings.take();
+        self.connection = warm.connection.take();
+        self.set_status(ConnectionStatus::Connected);
+        self.connected_at = Some(chrono::Utc::now());
+        self.last_activity = self.connected_at;
+
+        if let Some(details) = warm.details.take() {
+            self.details = Some(details.clone());
+            self.events.emit(SshEvent::Connected {
+                session_id: self.id.clone(),
+                details,
+            });
+        }
+    }
+
+    /// 接続状態を更新し、待機中の呼び出し元に通知する
+    fn set_status(&mut self, status: ConnectionStatus) {
+        self.status = status;
+        self.status_changed.notify_waiters();
+    }
+
+    /// 接続する（`cancel` が発火した場合は処理を打ち切って失敗とする）
+    async fn connect(&mut self, cancel: &CancellationToken) -> Result<(), SshError> {
+        self.set_status(ConnectionStatus::Connecting);
+        self.command_cache.clear();
+        self.last_rekey_at = None;
+
+        let result = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => Err(SshError::ConnectionFailed("connect cancelled".to_string())),
+            result = self.establish() => result,
+        };
+        match &result {
+            Ok(()) => self.last_error = None,
+            Err(e) => {
+                self.last_error = Some(e.to_string());
+                self.set_status(ConnectionStatus::Failed(e.to_string()));
+            }
+        }
+        result
+    }
+
+    /// 接続に使うrusshの設定（直接指定された設定があればそれを優先する）
+    fn client_config(&self) -> russh::client::Config {
+        let buffer_size = self.config.read_buffer_size.unwrap_or(DEFAULT_READ_BUFFER_SIZE).max(1);
+        let mut ssh_config = match &self.russh_config {
+            Some(config) => copy_client_config(config),
+            None => russh::client::Config {
+                inactivity_timeout: self
+                    .timeout_override
+                    .or_else(|| self.config.timeout.map(Duration::from_secs)),
+                // バッファを大きくした場合はチャネルのデータも大きな単位で受け取る
+                maximum_packet_size: buffer_size.clamp(DEFAULT_READ_BUFFER_SIZE, MAX_PACKET_SIZE) as u32,
+                ..Default::default()
+            },
+        };
+        if self.config.allow_legacy_host_keys {
+            let legacy = russh::keys::Algorithm::Rsa { hash: None };
+            if !ssh_config.preferred.key.contains(&legacy) {
+                let mut key = ssh_config.preferred.key.to_vec();
+                key.push(legacy);
+                ssh_config.preferred.key = key.into();
+            }
+        }
+        // 期待する種類の鍵をサーバーが持っていれば、それが選ばれるよう最優先にする
+        if let Some(expected) = self
+            .config
+            .expected_host_key_type
+            .as_deref()
+            .and_then(|name| name.trim().parse::<russh::keys::Algorithm>().ok())
+        {
+            if ssh_config.preferred.key.contains(&expected) {
+                let mut key: Vec<_> = ssh_config
+                    .preferred
+                    .key
+                    .iter()
+                    .filter(|a| **a != expected)
+                    .cloned()
+                    .collect();
+                key.insert(0, expected);
+                ssh_config.preferred.key = key.into();
+            }
+        }
+        ssh_config
+    }
+
+    /// 固定済みの鍵と照合し、`trust_on_first_use` なら初めてのホストの鍵を固定する
+    ///
+    /// 固定済みの鍵と一致しない場合は常に拒否する。`trust_on_first_use` では
+    /// known_hosts の鍵と一致しない場合も拒否する。
+    async fn verify_host_key(
+        &self,
+        key: &russh::keys::PublicKey,
+        known_hosts: Option<&HostKeyCheck>,
+    ) -> Result<(), SshError> {
+        let (host, port) = (&self.config.host, self.config.port);
+        let info = crate::ssh::keys::host_key_info(host, port, key)?;
+        let changed = |expected: &str| {
+            SshError::ConnectionFailed(format!(
+                "host key for {}:{} has changed (expected {}, got {})",
+                host, port, expected, info.fingerprint
+            ))
+        };
+
+        if let Some(pinned) = self.host_trust.get(host, port).await {
+            if pinned.public_key != info.public_key {
+                return Err(changed(&pinned.fingerprint));
+            }
+            return Ok(());
+        }
+        if !self.config.trust_on_first_use {
+            return Ok(());
+        }
+        match known_hosts.map(|check| check.status) {
+            Some(HostKeyStatus::Trusted) => return Ok(()),
+            Some(HostKeyStatus::Mismatch) => {
+                let expected = known_hosts
+                    .and_then(|check| check.matches.first())
+                    .map(|m| m.fingerprint.clone())
+                    .unwrap_or_default();
+                return Err(changed(&expected));
+            }
+            Some(HostKeyStatus::Unknown) | None => {}
+        }
+
+        self.host_trust
+            .pin(PinnedHostKey {
+                host: host.clone(),
+                port,
+                key_type: info.key_type.clone(),
+                public_key: info.public_key.clone(),
+                fingerprint: info.fingerprint.clone(),
+                pinned_at: chrono::Utc::now(),
+            })
+            .await?;
+        self.events.emit(SshEvent::HostKeyPinned {
+            session_id: self.id.clone(),
+            host: host.clone(),
+            port,
+            key_type: info.key_type,
+            fingerprint: info.fingerprint,
+        });
+        Ok(())
+    }
+
+    async fn establish(&mut self) -> Result<(), SshError> {
+        let started_at = self.clock.now();
+
+        // SSH設定の準備
+        let buffer_size = self.config.read_buffer_size.unwrap_or(DEFAULT_READ_BUFFER_SIZE).max(1);
+        let ssh_config = self.client_config();
+        self.rekey_limits = ssh_config.limits.clone();
+        let preferred = ssh_config.preferred.clone();
+
+        // TCPストリームの確立（プロキシ経由または直接）
+        let stream = match &self.config.proxy {
+            Some(proxy) => connect_via_proxy(proxy, &self.config.host, self.config.port).await?,
+            None => {
+                let addrs = resolve_host(&self.config.host, self.config.port, self.config.resolver.as_ref()).await?;
+                match &self.config.port_knock {
+                    // 最初のアドレスにノックし、接続も最初にそのアドレスへ試みる
+                    Some(port_knock) => {
+                        knock(addrs[0].ip(), port_knock).await?;
+                        TcpStream::connect(&addrs[..]).await.map_err(|e| {
+                            SshError::ConnectionFailed(format!(
+                                "{} (port knock may not have opened the port)",
+                                e
+                            ))
+                        })?
+                    }
+                    None => TcpStream::connect(&addrs[..])
+                        .await
+                        .map_err(|e| SshError::ConnectionFailed(e.to_string()))?,
+                }
+            }
+        };
+        let resolved_address = match self.config.proxy {
+            Some(_) => None,
+            None => stream.peer_addr().ok().map(|addr| addr.to_string()),
+        };
+        let tcp_connected_at = self.clock.now();
+
+        // 接続の確立（通信量を計測するためストリームをラップする）
+        self.traffic = TrafficCounters::new();
+        let handshake = Arc::new(HandshakeCapture::new());
+        let stream = BufReader::with_capacity(buffer_size, stream);
+        let stream = CountingStream::new(stream, self.traffic.clone())
+            .with_handshake_capture(handshake.clone());
+        let handler = SshClientHandler::new(self.traffic.clone())
+            .with_remote_forwards(self.remote_forwards.clone())
+            .with_x11(self.x11.clone());
+        let server_key = handler.server_key_slot();
+        self.remote_disconnect = handler.remote_disconnect_slot();
+        self.connection_closed = handler.closed_signal();
+        let mut connection = connect_over_stream(ssh_config, stream, handler).await?;
+        self.server_version = handshake.server_version();
+
+        // 認証前にネゴシエーション結果を確定し、許可リストに反しないか確認する
+        let server_key = server_key.lock().ok().and_then(|k| k.clone());
+        self.host_key = server_key.clone();
+        let mut details = connection_details(&preferred, &handshake, server_key.as_ref(), resolved_address);
+        details.host_key_check = server_key
+            .as_ref()
+            .map(|key| check_known_hosts(&self.config.host, self.config.port, key));
+        if let Some(expected) = &self.config.expected_host_key_type {
+            if let Err(e) = check_host_key_type(server_key.as_ref(), expected) {
+                let _ = connection
+                    .disconnect(russh::Disconnect::HostKeyNotVerifiable, "unexpected host key type", "en")
+                    .await;
+                return Err(e);
+            }
+        }
+        if let Some(key) = &server_key {
+            if let Err(e) = self.verify_host_key(key, details.host_key_check.as_ref()).await {
+                let _ = connection
+                    .disconnect(russh::Disconnect::HostKeyNotVerifiable, "host key changed", "en")
+                    .await;
+                return Err(e);
+            }
+        }
+        if let Some(allowlist) = &self.config.allowed_algorithms {
+            if let Err(e) = check_allowed_algorithms(&details, allowlist) {
+                let _ = connection
+                    .disconnect(russh::Disconnect::ByApplication, "algorithm not allowed", "en")
+                    .await;
+                return Err(e);
+            }
+        }
+
+        // 認証
+        let auth_started_at = self.clock.now();
+        let auth_result = authenticate(
+            &mut connection,
+            &self.config,
+            &self.id,
+            &self.events,
+            &self.auth_prompts,
+            &*self.key_provider,
+        )
+        .await;
+        let auth_finished_at = self.clock.now();
+
+        // 失敗・タイムアウトした接続は閉じ、認証途中の接続を残さない
+        if !matches!(auth_result, Ok(AuthResult::Success)) {
+            let _ = connection
+                .disconnect(russh::Disconnect::ByApplication, "authentication failed", "en")
+                .await;
+        }
+
+        // 認証が成功したかチェック
+        if auth_result? != AuthResult::Success {
+            return Err(SshError::AuthenticationFailed("Authentication failed".to_string()));
+        }
+
+        // ネゴシエーション済みの拡張情報を記録
+        let connection = SshConnection::new(connection);
+        self.server_extensions = Some(query_server_extensions(&connection).await);
+        self.connect_timings = Some(ConnectTimings {
+            tcp_connect_ms: elapsed_ms(started_at, tcp_connected_at),
+            handshake_ms: elapsed_ms(tcp_connected_at, auth_started_at),
+            auth_ms: elapsed_ms(auth_started_at, auth_finished_at),
+        });
+
+        // 認証成功後、接続を保存
+        self.connection = Some(Arc::new(connection));
+        self.set_status(ConnectionStatus::Connected);
+        self.connected_at = Some(chrono::Utc::now());
+        self.last_activity = self.connected_at;
+
+        // ネゴシエーション結果を通知
+        self.details = Some(details.clone());
+        self.events.emit(SshEvent::Connected {
+            session_id: self.id.clone(),
+            details,
+        });
+
+        Ok(())
+    }
+
+    async fn disconnect(&mut self) -> Result<(), SshError> {
+        // ユーザーによる切断では再接続しない
+        if let Some(cancel) = self.reconnect_cancel.take() {
+            cancel.cancel();
+        }
+        self.reconnecting = false;
+        self.server_extensions = None;
+        self.details = None;
+        self.forced_command = None;
+        self.shell_kind = None;
+        self.command_cache.clear();
+        self.last_error = None;
+        if let Some(connection) = self.connection.take() {
+            let _ = connection
+                .disconnect(russh::Disconnect::ByApplication, "user disconnected", "en")
+                .await;
+        }
+        
+        self.set_status(ConnectionStatus::Disconnected);
+        self.connected_at = None;
+
+        Ok(())
+    }
+
+    fn get_telemetry(&self) -> SessionTelemetry {
+        let bytes_sent = self.traffic.wire_sent();
+        let bytes_received = self.traffic.wire_received();
+        let payload_bytes_received = self.traffic.payload_received();
+        let connected_secs = self
+            .connected_at
+            .map(|t| (chrono::Utc::now() - t).num_seconds().max(0) as u64)
+            .unwrap_or(0);
+
+        let compression_ratio = if bytes_received > 0 && payload_bytes_received > 0 {
+            Some(payload_bytes_received as f64 / bytes_received as f64)
+        } else {
+            None
+        };
+
+        // 送信量・受信量・経過時間のうち最も早く上限に達するものが再交換を起こす
+        let limits = &self.rekey_limits;
+        let by_write = bytes_sent / (limits.rekey_write_limit.max(1) as u64);
+        let by_read = bytes_received / (limits.rekey_read_limit.max(1) as u64);
+        let by_time = connected_secs / limits.rekey_time_limit.as_secs().max(1);
+
+        SessionTelemetry {
+            session_id: self.id.clone(),
+            bytes_sent,
+            bytes_received,
+            payload_bytes_received,
+            compression_ratio,
+            estimated_rekeys: by_write.max(by_read).max(by_time),
+            connected_secs,
+            last_rekey_at: self.last_rekey_at,
+        }
+    }
+
+    fn get_info(&self) -> SshSessionInfo {
+        SshSessionInfo {
+            id: self.id.clone(),
+            config: self.config.clone(),
+            status: self.status.clone(),
+            connected_at: self.connected_at,
+            last_activity: self.last_activity,
+            forced_command: self.forced_command,
+            last_error: self.last_error.clone(),
+            server_version: self.server_version.clone(),
+            shell_kind: self.shell_kind,
+            connect_timings: self.connect_timings.clone(),
+        }
+    }
+}
+
+/// 2つの時刻の間隔（ミリ秒、時刻が戻った場合は0）
+fn elapsed_ms(from: chrono::DateTime<chrono::Utc>, to: chrono::DateTime<chrono::Utc>) -> u64 {
+    (to - from).num_milliseconds().max(0) as u64
+}
+
+/// 自動再接続を使わないセッションの接続終了を監視し、検知した時点で失敗に遷移させる
+///
+/// 監視対象の接続がすでに破棄・置き換えられていれば何もしない。
+async fn watch_connection(
+    session_arc: Arc<Mutex<SshSession>>,
+    connection: std::sync::Weak<SshConnection>,
+    closed: Arc<Notify>,
+    cancel: CancellationToken,
+) {
+    tokio::select! {
+        biased;
+        _ = cancel.cancelled() => return,
+        _ = closed.notified() => {}
+    }
+
+    let mut session = session_arc.lock().await;
+    let current = session
+        .connection
+        .as_ref()
+        .is_some_and(|current| std::ptr::eq(Arc::as_ptr(current), connection.as_ptr()));
+    if current {
+        session.mark_connection_lost();
+    }
+}
+
+/// 事前接続の照合に使う接続先
+///
+/// 認証やホスト鍵の検証の条件が異なるセッションに接続を引き継がないよう、接続時に確認する設定を
+/// まとめてハッシュにする（認証情報をそのまま照合用に持たないため）。
+fn warm_target(config: &SshConfig) -> Result<WarmTarget, SshError> {
+    use sha2::{Digest, Sha256};
+
+    let settings = serde_json::to_vec(&(
+        &config.auth_method,
+        &config.fallback_password,
+        config.allow_insecure_key_permissions,
+        config.allow_legacy_host_keys,
+        config.trust_on_first_use,
+        &config.expected_host_key_type,
+        &config.allowed_algorithms,
+        &config.proxy,
+        &config.resolver,
+        &config.port_knock,
+    ))
+    .map_err(|e| SshError::ConfigError(e.to_string()))?;
+    Ok((
+        config.host.clone(),
+        config.port,
+        config.username.clone(),
+        Sha256::digest(&settings).into(),
+    ))
+}
+
+/// 期限切れの事前接続を切断して捨てる
+async fn evict_expired_warm(pool: &WarmPool) {
+    let now = tokio::time::Instant::now();
+    let expired: Vec<_> = {
+        let mut pool = pool.lock().await;
+        let (expired, kept) = std::mem::take(&mut *pool)
+            .into_iter()
+            .partition(|warm| warm.expires_at <= now);
+        *pool = kept;
+        expired
+    };
+    for mut warm in expired {
+        let _ = warm.session.disconnect().await;
+    }
+}
+
+/// 接続の終了を監視し、ポリシーに従って再接続する
+///
+/// `cancel` はユーザーによる切断やセッション削除で発火し、監視を終了させる。
+/// 再接続に成功したら、切断前のポート転送を新しい接続上で再確立する。
+async fn monitor_reconnect(
+    session_arc: Arc<Mutex<SshSession>>,
+    cancel: CancellationToken,
+    forwards: ForwardManager,
+) {
+    loop {
+        let (closed, reconnect_now, policy, events, session_id) = {
+            let session = session_arc.lock().await;
+            let Some(policy) = session.config.reconnect.clone() else {
+                return;
+            };
+            (
+                session.connection_closed.clone(),
+                session.reconnect_now.clone(),
+                policy,
+                session.events.clone(),
+                session.id.clone(),
+            )
+        };
+
+        tokio::select! {
+            biased;
+            _ = cancel.cancelled() => return,
+            _ = closed.notified() => {}
+        }
+
+        let mut last_error = {
+            let mut session = session_arc.lock().await;
+            session.mark_connection_lost();
+            session.reconnecting = true;
+            session.last_error.clone().unwrap_or_default()
+        };
+
+        let mut delay = Duration::from_millis(policy.initial_delay_ms);
+        let max_delay = Duration::from_millis(policy.max_delay_ms.max(policy.initial_delay_ms));
+        let mut reconnected = None;
+
+        for attempt in 1..=policy.max_attempts {
+            let wait = with_jitter(delay, policy.jitter_ratio);
+            events.emit(SshEvent::Reconnecting {
+                session_id: session_id.clone(),
+                attempt,
+                delay_ms: wait.as_millis() as u64,
+            });
+
+            tokio::select! {
+                biased;
+                _ = cancel.cancelled() => return,
+                _ = reconnect_now.notified() => {}
+                _ = tokio::time::sleep(wait) => {}
+            }
+
+            let mut session = session_arc.lock().await;
+            if cancel.is_cancelled() {
+                return;
+            }
+            match session.connect(&cancel).await {
+                Ok(()) => {
+                    session.reconnecting = false;
+                    events.emit(SshEvent::Reconnected {
+                        session_id: session_id.clone(),
+                        attempt,
+                    });
+                    reconnected = session.connection.clone();
+                    break;
+                }
+                Err(e) => {
+                    last_error = e.to_string();
+                    delay = (delay * 2).min(max_delay);
+                }
+            }
+        }
+
+        if let Some(connection) = reconnected {
+            forwards.restore(&session_id, connection, &events).await;
+        } else {
+            session_arc.lock().await.reconnecting = false;
+            events.emit(SshEvent::ReconnectFailed {
+                session_id,
+                attempts: policy.max_attempts,
+                error: last_error,
+            });
+            return;
+        }
+    }
+}
+
+/// 待機時間を `ratio` の割合だけ前後にランダムにずらす
+fn with_jitter(delay: Duration, ratio: f64) -> Duration {
+    let ratio = if ratio.is_finite() { ratio.clamp(0.0, 1.0) } else { 0.0 };
+    if ratio == 0.0 {
+        return delay;
+    }
+    // UUIDv4 の末尾48ビット（すべてランダム）から -1.0〜1.0 の乱数を作る
+    let bytes = Uuid::new_v4().into_bytes();
+    let bits = bytes[10..].iter().fold(0u64, |acc, &b| (acc << 8) | b as u64);
+    let random = bits as f64 / ((1u64 << 48) - 1) as f64 * 2.0 - 1.0;
+    delay.mul_f64(1.0 + ratio * random)
+}
+
+/// 転送されたエージェントのチャネルをローカルの `SSH_AUTH_SOCK` に中継する
+#[cfg(unix)]
+async fn forward_to_local_agent(channel: russh::Channel<client::Msg>) {
+    let Some(path) = std::env::var_os("SSH_AUTH_SOCK") else {
+        let _ = channel.close().await;
+        return;
+    };
+    let Ok(mut agent) = tokio::net::UnixStream::connect(path).await else {
+        let _ = channel.close().await;
+        return;
+    };
+    let mut stream = channel.into_stream();
+    let _ = tokio::io::copy_bidirectional(&mut stream, &mut agent).await;
+}
+
+/// 転送されたエージェントのチャネルをローカルの `SSH_AUTH_SOCK` に中継する
+#[cfg(not(unix))]
+async fn forward_to_local_agent(channel: russh::Channel<client::Msg>) {
+    let _ = channel.close().await;
+}
+
+/// russhの設定を複製する（`russh::client::Config` は `Clone` を実装していない）
+fn copy_client_config(config: &russh::client::Config) -> russh::client::Config {
+    russh::client::Config {
+        client_id: match &config.client_id {
+            russh::SshId::Standard(id) => russh::SshId::Standard(id.clone()),
+            russh::SshId::Raw(id) => russh::SshId::Raw(id.clone()),
+        },
+        limits: config.limits.clone(),
+        window_size: config.window_size,
+        maximum_packet_size: config.maximum_packet_size,
+        channel_buffer_size: config.channel_buffer_size,
+        preferred: config.preferred.clone(),
+        inactivity_timeout: config.inactivity_timeout,
+        keepalive_interval: config.keepalive_interval,
+        keepalive_max: config.keepalive_max,
+        anonymous: config.anonymous,
+        gex: config.gex.clone(),
+    }
+}
+
+/// 確立済みのストリーム上でSSHハンドシェイクを行う
+pub(crate) async fn connect_over_stream<S>(
+    config: russh::client::Config,
+    stream: S,
+    handler: SshClientHandler,
+) -> Result<Handle<SshClientHandler>, SshError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    russh::client::connect_stream(Arc::new(config), stream, handler)
+        .await
+        .map_err(|e| SshError::ConnectionFailed(e.to_string()))
+}
+
+/// タイムアウトが指定されていればその時間内に完了しなければ `Timeout` とする
+async fn with_command_timeout<T, F>(timeout: Option<Duration>, future: F) -> Result<T, SshError>
+where
+    F: std::future::Future<Output = Result<T, SshError>>,
+{
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future).await.map_err(|_| {
+            SshError::Timeout(format!("command did not finish within {}ms", timeout.as_millis()))
+        })?,
+        None => future.await,
+    }
+}
+
+/// 変換前のコマンド出力
+struct RawCommandOutput {
+    exit_code: Option<u32>,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    truncated: bool,
+    started_at: chrono::DateTime<chrono::Utc>,
+    ended_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// 接続上で新しいチャネルを開いてコマンドを実行する
+///
+/// 結果とともに、exec 要求からチャネルが閉じるまでの経過時間を返す。
+async fn execute_on_connection(
+    connection: &SshConnection,
+    command: &str,
+    options: &CommandOptions,
+    clock: &dyn Clock,
+    produced: Option<&AtomicU64>,
+    on_chunk: Option<&mut OnChunk<'_>>,
+) -> Result<(CommandResult, chrono::Duration), SshError> {
+    let (output, duration) = execute_raw_on_connection(connection, command, options, clock, produced, on_chunk).await?;
+
+    let mut stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    if options.pty && options.strip_echo {
+        stdout = strip_pty_echo(&stdout, command);
+    }
+
+    let result = CommandResult {
+        exit_code: output.exit_code,
+        stdout,
+        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        started_at: output.started_at,
+        ended_at: output.ended_at,
+        is_success: options.is_success(output.exit_code),
+    };
+
+    Ok((result, duration))
+}
+
+/// 受信したコマンド出力を受け取るコールバック
+pub type OnChunk<'a> = dyn FnMut(StdStream, &[u8]) + Send + 'a;
+
+/// コマンドを実行し、出力をバイト列のまま返す
+///
+/// `produced` が指定されていれば、受信した出力のバイト数を加算していく。
+/// `on_chunk` が指定されていれば、受信した出力をその都度渡す。
+async fn execute_raw_on_connection(
+    connection: &SshConnection,
+    command: &str,
+    options: &CommandOptions,
+    clock: &dyn Clock,
+    produced: Option<&AtomicU64>,
+    mut on_chunk: Option<&mut OnChunk<'_>>,
+) -> Result<(RawCommandOutput, chrono::Duration), SshError> {
+    let mut channel = connection
+        .channel_open_session()
+        .await
+        .map_err(|e| SshError::CommandFailed(e.to_string()))?;
+
+    if options.pty {
+        channel
+            .request_pty(true, "xterm", 80, 24, 0, 0, &[])
+            .await
+            .map_err(|e| SshError::CommandFailed(e.to_string()))?;
+    }
+
+    // Execute the command
+    let command = with_env(command, &options.env)?;
+    let exec_command = if options.login_shell {
+        wrap_login_shell(options.shell.as_deref().unwrap_or(DEFAULT_LOGIN_SHELL), &command)
+    } else {
+        command
+    };
+    let started = clock.now();
+    channel
+        .exec(true, exec_command)
+        .await
+        .map_err(|e| SshError::CommandFailed(e.to_string()))?;
+
+    // Read the output
+    let mut stdout = OutputBuffer::new(options.tail_lines).with_max_bytes(options.max_output_bytes);
+    let mut stderr = OutputBuffer::new(options.tail_lines).with_max_bytes(options.max_output_bytes);
+    let mut exit_code = None;
+    let first_output_deadline = options
+        .return_on_first_output
+        .then(|| tokio::time::Instant::now() + FIRST_OUTPUT_GRACE);
+    let mut detached = false;
+
+    // Read all data from the channel until Close so that the exit status is not missed
+    loop {
+        use russh::ChannelMsg;
+
+        let msg = match first_output_deadline {
+            Some(deadline) => match tokio::time::timeout_at(deadline, channel.wait()).await {
+                Ok(msg) => msg,
+                Err(_) => {
+                    detached = true;
                     break;
                 }
-                Some(ChannelMsg::Close) => {
+            },
+            None => channel.wait().await,
+        };
+
+        match msg {
+            Some(ChannelMsg::Data { data }) => {
+                if let Some(produced) = produced {
+                    produced.fetch_add(data.len() as u64, Ordering::Relaxed);
+                }
+                if let Some(on_chunk) = on_chunk.as_mut() {
+                    on_chunk(StdStream::Stdout, &data);
+                }
+                stdout.extend(&data);
+                if first_output_deadline.is_some() {
+                    detached = true;
                     break;
                 }
-                Some(_) => {
-                    // Handle other message types if needed
+            }
+            Some(ChannelMsg::ExtendedData { data, ext: 1 }) => {
+                if let Some(produced) = produced {
+                    produced.fetch_add(data.len() as u64, Ordering::Relaxed);
+                }
+                if let Some(on_chunk) = on_chunk.as_mut() {
+                    on_chunk(StdStream::Stderr, &data);
                 }
-                None => {
+                stderr.extend(&data);
+                if first_output_deadline.is_some() {
+                    detached = true;
                     break;
                 }
             }
+            Some(ChannelMsg::ExitStatus { exit_status }) => {
+                exit_code = Some(exit_status);
+            }
+            Some(ChannelMsg::Close) => {
+                break;
+            }
+            Some(_) => {
+                // Handle other message types if needed
+            }
+            None => {
+                break;
+            }
         }
+    }
+
+    let ended = clock.now();
 
+    if detached {
+        // コマンドは動き続けているため閉じずに、チャネルが閉じられるまで読み捨てる
+        tokio::spawn(async move {
+            while let Some(msg) = channel.wait().await {
+                if matches!(msg, russh::ChannelMsg::Close) {
+                    break;
+                }
+            }
+        });
+    } else {
         // Close the channel
         let _ = channel.close().await;
+    }
 
-        Ok(CommandResult {
-            exit_code,
-            stdout: String::from_utf8_lossy(&stdout).to_string(),
-            stderr: String::from_utf8_lossy(&stderr).to_string(),
-        })
+    let output = RawCommandOutput {
+        exit_code: exit_code.filter(|_| !detached),
+        truncated: stdout.truncated() || stderr.truncated(),
+        stdout: stdout.into_bytes(),
+        stderr: stderr.into_bytes(),
+        started_at: started,
+        ended_at: (!detached).then_some(ended),
+    };
+
+    Ok((output, ended - started))
+}
+
+/// 接続上で新しいチャネルを開いてコマンドを実行し、出力をファイルへ書き出す
+async fn execute_to_file(
+    connection: &SshConnection,
+    command: &str,
+    local_path: &str,
+    options: &FileOutputOptions,
+    produced: &AtomicU64,
+) -> Result<FileOutputResult, SshError> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(options.append)
+        .truncate(!options.append)
+        .open(local_path)
+        .await?;
+
+    let mut channel = connection
+        .channel_open_session()
+        .await
+        .map_err(|e| SshError::CommandFailed(e.to_string()))?;
+    channel
+        .exec(true, command)
+        .await
+        .map_err(|e| SshError::CommandFailed(e.to_string()))?;
+
+    let mut bytes_written = 0u64;
+    let mut exit_code = None;
+
+    loop {
+        use russh::ChannelMsg;
+
+        match channel.wait().await {
+            Some(ChannelMsg::Data { data }) => {
+                file.write_all(&data).await?;
+                bytes_written += data.len() as u64;
+                produced.fetch_add(data.len() as u64, Ordering::Relaxed);
+            }
+            Some(ChannelMsg::ExtendedData { data, ext: 1 }) => {
+                produced.fetch_add(data.len() as u64, Ordering::Relaxed);
+                if options.include_stderr {
+                    file.write_all(&data).await?;
+                    bytes_written += data.len() as u64;
+                }
+            }
+            Some(ChannelMsg::ExitStatus { exit_status }) => {
+                exit_code = Some(exit_status);
+            }
+            Some(ChannelMsg::Close) | None => break,
+            Some(_) => {}
+        }
     }
 
-    fn get_info(&self) -> SshSessionInfo {
-        SshSessionInfo {
-            id: self.id.clone(),
-            config: self.config.clone(),
-            status: self.status.clone(),
-            connected_at: self.connected_at,
+    let _ = channel.close().await;
+    file.flush().await?;
+
+    Ok(FileOutputResult {
+        exit_code,
+        bytes_written,
+    })
+}
+
+/// 判定コマンドの出力からシェルの種類を決める（展開されなかった変数は `%`・`$` で始まったまま残る）
+fn parse_shell_probe(output: &str) -> ShellKind {
+    let Some(line) = output.lines().find(|line| line.contains("pardoroid-shell|")) else {
+        return ShellKind::Unknown;
+    };
+    let fields: Vec<&str> = line.trim().trim_matches('"').split('|').collect();
+    let expanded = |index: usize, unexpanded: char| {
+        fields
+            .get(index)
+            .is_some_and(|value| !value.is_empty() && !value.starts_with(unexpanded))
+    };
+
+    if expanded(1, '%') {
+        ShellKind::Cmd
+    } else if expanded(2, '$') {
+        ShellKind::PowerShell
+    } else if expanded(3, '$') {
+        ShellKind::Fish
+    } else if expanded(4, '$') {
+        ShellKind::Zsh
+    } else if expanded(5, '$') {
+        ShellKind::Bash
+    } else {
+        ShellKind::Sh
+    }
+}
+
+/// 環境変数を export してからコマンドを実行する形に変換する
+fn with_env(command: &str, env: &BTreeMap<String, String>) -> Result<String, SshError> {
+    if env.is_empty() {
+        return Ok(command.to_string());
+    }
+    let mut exports = Vec::with_capacity(env.len());
+    for (name, value) in env {
+        if !is_valid_env_name(name) {
+            return Err(SshError::ConfigError(format!("invalid environment variable name: {:?}", name)));
+        }
+        exports.push(format!("{}={}", name, shell_quote(value)));
+    }
+    Ok(format!("export {}; {}", exports.join(" "), command))
+}
+
+/// コマンドをログインシェル経由で実行する形に変換する
+fn wrap_login_shell(shell: &str, command: &str) -> String {
+    format!("{} -lc {}", shell, shell_quote(command))
+}
+
+/// セッションの同時コマンド実行数の上限
+fn max_concurrent_commands(config: &SshConfig) -> usize {
+    config
+        .max_concurrent_commands
+        .unwrap_or(DEFAULT_MAX_CONCURRENT_COMMANDS)
+        .max(1)
+}
+
+/// マクロの `{name}` を引数で置き換える
+///
+/// 引数はクォートして埋め込むため、値に空白やメタ文字が含まれていても1つの引数として渡る。
+/// `$` の直後の `{` や、名前として使えない文字を含む `{...}` はそのまま残す。
+fn expand_macro(template: &str, args: &HashMap<String, String>) -> Result<String, SshError> {
+    let mut command = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let (before, after) = rest.split_at(start);
+        command.push_str(before);
+        let name = after[1..].find('}').map(|end| &after[1..end + 1]).filter(|name| {
+            !before.ends_with('$')
+                && !name.is_empty()
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        });
+        let Some(name) = name else {
+            command.push('{');
+            rest = &after[1..];
+            continue;
+        };
+        let value = args
+            .get(name)
+            .ok_or_else(|| SshError::ConfigError(format!("missing macro argument: {}", name)))?;
+        command.push_str(&shell_quote(value));
+        rest = &after[name.len() + 2..];
+    }
+    command.push_str(rest);
+    Ok(command)
+}
+
+/// 文字列をシングルクォートで囲み、POSIXシェルの単一引数として扱えるようにする
+pub(crate) fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// 受信したハンドシェイクとクライアントの優先順位からネゴシエーション結果を算出
+fn connection_details(
+    preferred: &russh::Preferred,
+    handshake: &HandshakeCapture,
+    server_key: Option<&russh::keys::PublicKey>,
+    resolved_address: Option<String>,
+) -> ConnectionDetails {
+    let mut details = ConnectionDetails {
+        resolved_address,
+        server_version: handshake.server_version(),
+        host_key_fingerprint: server_key
+            .map(|key| key.fingerprint(russh::keys::HashAlg::Sha256).to_string()),
+        ..Default::default()
+    };
+
+    if let Some(server) = handshake.server_kexinit() {
+        details.kex_algorithm = negotiate(preferred.kex.iter().map(|n| n.as_ref()), &server.kex);
+        details.host_key_algorithm =
+            negotiate(preferred.key.iter().map(|a| a.as_str()), &server.host_key);
+        details.cipher = negotiate(
+            preferred.cipher.iter().map(|n| n.as_ref()),
+            &server.cipher_client_to_server,
+        );
+        details.mac = negotiate(
+            preferred.mac.iter().map(|n| n.as_ref()),
+            &server.mac_client_to_server,
+        );
+        details.compression = negotiate(
+            preferred.compression.iter().map(|n| n.as_ref()),
+            &server.compression_client_to_server,
+        );
+    }
+    if details.host_key_algorithm.as_deref() == Some(LEGACY_HOST_KEY_ALGORITHM) {
+        details
+            .warnings
+            .push("legacy host key algorithm ssh-rsa (SHA-1 signatures) was used".to_string());
+    }
+
+    details
+}
+
+/// 設定された方式で認証する
+pub(crate) async fn authenticate(
+    connection: &mut Handle<SshClientHandler>,
+    config: &SshConfig,
+    session_id: &str,
+    events: &EventBus,
+    prompts: &AuthPromptBroker,
+    key_provider: &dyn KeyProvider,
+) -> Result<AuthResult, SshError> {
+    let auth_timeout = config
+        .auth_timeout_secs
+        .map(std::time::Duration::from_secs)
+        .unwrap_or(DEFAULT_AUTH_TIMEOUT);
+
+    match &config.auth_method {
+        AuthMethod::Password(password) => {
+            authenticate_password(
+                connection,
+                session_id,
+                &config.username,
+                password,
+                events,
+                prompts,
+                auth_timeout,
+            )
+            .await
+        }
+        AuthMethod::PasswordWithOtp { password, otp } => {
+            authenticate_password_with_otp(
+                connection,
+                session_id,
+                &config.username,
+                password,
+                otp,
+                events,
+                prompts,
+                auth_timeout,
+            )
+            .await
+        }
+        AuthMethod::PublicKey {
+            private_key_path,
+            passphrase,
+        } => {
+            let key = load_private_key(
+                key_provider,
+                private_key_path,
+                passphrase.as_deref(),
+                config.allow_insecure_key_permissions,
+            )?;
+
+            let result = auth_request(auth_timeout, connection.authenticate_publickey(&config.username, key)).await?;
+
+            // サーバーが鍵を受け入れなかった場合のみ切り替える（通信エラーでは切り替えない）
+            match (&result, &config.fallback_password) {
+                (AuthResult::Failure { .. }, Some(password)) => {
+                    events.emit(SshEvent::AuthFallback {
+                        session_id: session_id.to_string(),
+                        from: "publickey".to_string(),
+                        to: "password".to_string(),
+                    });
+                    authenticate_password(
+                        connection,
+                        session_id,
+                        &config.username,
+                        password,
+                        events,
+                        prompts,
+                        auth_timeout,
+                    )
+                    .await
+                }
+                _ => Ok(result),
+            }
+        }
+        AuthMethod::Agent => {
+            // TODO: SSH Agent認証の実装
+            Err(SshError::AuthenticationFailed(
+                "SSH Agent authentication not implemented yet".to_string(),
+            ))
+        }
+    }
+}
+
+/// コマンドがセーフモードの拒否パターンに一致するか（連続する空白は1つとみなして比較する）
+fn matches_dangerous_pattern(command: &str, safe_mode: &SafeModeConfig) -> bool {
+    let normalize = |text: &str| text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let command = format!("{} ", normalize(command));
+    match &safe_mode.patterns {
+        Some(patterns) => patterns
+            .iter()
+            .map(|pattern| normalize(pattern))
+            .any(|pattern| !pattern.is_empty() && command.contains(&pattern)),
+        None => DEFAULT_DANGEROUS_PATTERNS
+            .iter()
+            .any(|pattern| command.contains(pattern)),
+    }
+}
+
+/// サーバーが提示したホスト鍵が期待する種類か確認する
+///
+/// RSA鍵は署名アルゴリズム（`rsa-sha2-256` など）で指定しても `ssh-rsa` として比較する。
+fn check_host_key_type(
+    key: Option<&russh::keys::PublicKey>,
+    expected: &str,
+) -> Result<(), SshError> {
+    let key_type = |name: &str| match name.trim() {
+        "rsa-sha2-256" | "rsa-sha2-512" => "ssh-rsa".to_string(),
+        name => name.to_ascii_lowercase(),
+    };
+    let expected = key_type(expected);
+    let offered = key
+        .map(|key| key.algorithm().to_string())
+        .ok_or_else(|| SshError::ConnectionFailed("could not determine the server host key type".to_string()))?;
+    if key_type(&offered) != expected {
+        return Err(SshError::ConnectionFailed(format!(
+            "server offered a {} host key but {} was expected",
+            offered, expected
+        )));
+    }
+    Ok(())
+}
+
+/// ネゴシエーションされたアルゴリズムが許可リストに含まれているか確認する
+fn check_allowed_algorithms(
+    details: &ConnectionDetails,
+    allowlist: &AlgorithmAllowlist,
+) -> Result<(), SshError> {
+    let is_aead = details
+        .cipher
+        .as_deref()
+        .is_some_and(|cipher| cipher.contains("gcm") || cipher.starts_with("chacha20-poly1305"));
+
+    let checks = [
+        ("kex", details.kex_algorithm.as_deref(), allowlist.kex.as_ref()),
+        ("cipher", details.cipher.as_deref(), allowlist.cipher.as_ref()),
+        ("mac", details.mac.as_deref(), allowlist.mac.as_ref().filter(|_| !is_aead)),
+    ];
+
+    for (kind, negotiated, allowed) in checks {
+        let Some(allowed) = allowed else {
+            continue;
+        };
+        // 判定できない場合も許可しない
+        let name = negotiated.ok_or_else(|| {
+            SshError::ConnectionFailed(format!("could not determine negotiated {} algorithm", kind))
+        })?;
+        if !allowed.iter().any(|a| a == name) {
+            return Err(SshError::ConnectionFailed(format!(
+                "negotiated weak algorithm: {}",
+                name
+            )));
         }
     }
+
+    Ok(())
+}
+
+/// server-sig-algs から判断した RSA 署名アルゴリズムを問い合わせる
+async fn query_server_extensions(connection: &SshConnection) -> ServerExtensions {
+    use russh::keys::HashAlg;
+
+    let best_rsa_signature_algorithm = match connection.best_supported_rsa_hash().await {
+        Ok(Some(Some(HashAlg::Sha512))) => Some("rsa-sha2-512"),
+        Ok(Some(Some(HashAlg::Sha256))) => Some("rsa-sha2-256"),
+        Ok(Some(_)) => Some("ssh-rsa"),
+        _ => None,
+    };
+    ServerExtensions {
+        best_rsa_signature_algorithm: best_rsa_signature_algorithm.map(str::to_string),
+    }
 }
 
 /// 秘密鍵を読み込む
-fn load_private_key(path: &str, passphrase: Option<&str>) -> Result<russh::keys::PrivateKeyWithHashAlg, Box<dyn std::error::Error>> {
+fn load_private_key(
+    key_provider: &dyn KeyProvider,
+    path: &str,
+    passphrase: Option<&str>,
+    allow_insecure_permissions: bool,
+) -> Result<russh::keys::PrivateKeyWithHashAlg, SshError> {
     use russh::keys::decode_secret_key;
+
+    let key_data = key_provider.read_key(path, allow_insecure_permissions)?;
     
-    let key_data = std::fs::read_to_string(path)?;
-    
-    let private_key = if let Some(passphrase) = passphrase {
-        decode_secret_key(&key_data, Some(passphrase))?
-    } else {
-        decode_secret_key(&key_data, None)?
-    };
+    let private_key = decode_secret_key(&key_data, passphrase)
+        .map_err(|e| SshError::AuthenticationFailed(e.to_string()))?;
     
     // Wrap PrivateKey in PrivateKeyWithHashAlg
     Ok(russh::keys::PrivateKeyWithHashAlg::new(
@@ -328,4 +2990,3 @@ fn load_private_key(path: &str, passphrase: Option<&str>) -> Result<russh::keys:
         Some(russh::keys::HashAlg::Sha256)
     ))
 }
-
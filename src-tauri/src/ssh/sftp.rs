@@ -0,0 +1,1816 @@
+use crate::ssh::{
+    BandwidthTestResult, EventBus, RemoteByteRange, RemoteFileEntry, RemotePathInfo, SshConnection, SshError, SshEvent, SyncDirection, SyncOptions,
+    SyncSummary, TarDownloadMethod, TarDownloadResult, TransferKind, TransferManager, TransferState, WaitCondition,
+};
+use crate::ssh::archive::{split_archive_path, tar_command, TarWriter};
+use crate::ssh::transfer::RateLimiter;
+use base64::Engine;
+use russh::client::Msg;
+use russh::{Channel, ChannelMsg};
+use russh_sftp::client::{RawSftpSession, SftpSession};
+use russh_sftp::client::error::Error as SftpError;
+use russh_sftp::protocol::{FileAttributes, OpenFlags, Packet, StatusCode, Version};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+/// 転送の読み書き単位の上限（russh-sftpが1回の要求で扱う最大長で、最大パケットサイズにも収まる）
+pub const MAX_SFTP_CHUNK_SIZE: usize = 261_120;
+
+/// 転送速度の測定で転送できる最大バイト数
+pub const MAX_BANDWIDTH_TEST_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// `**` が既定でたどるディレクトリの深さ
+const DEFAULT_GLOB_MAX_DEPTH: usize = 8;
+
+/// `wait_for` の既定の確認間隔
+const DEFAULT_WAIT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// `wait_for` の確認間隔の下限（サーバーへの負荷を抑える）
+const MIN_WAIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// `read_range` で1回に返す最大バイト数
+const MAX_RANGE_LENGTH: u64 = 8 * 1024 * 1024;
+
+/// サーバー上でファイルをコピーするSFTP拡張
+const COPY_DATA_EXTENSION: &str = "copy-data@openssh.com";
+
+/// `copy-data` 拡張で1回の要求にコピーする長さ（この単位で進捗を更新する）
+const COPY_DATA_CHUNK: u64 = 64 * 1024 * 1024;
+
+/// `TarDownloadProgress` を通知する最短の間隔
+const TAR_PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// 警告として返す `tar` の標準エラー出力の最大長
+const MAX_TAR_STDERR: usize = 4096;
+
+/// 一括stat で同時に送る要求数の上限
+const MAX_STAT_CONCURRENCY: usize = 32;
+
+/// 転送の読み書き単位の下限
+const MIN_SFTP_CHUNK_SIZE: usize = 1024;
+
+/// 転送の読み書き単位をSFTPの制限内に収める
+pub fn clamp_chunk_size(chunk_size: usize) -> usize {
+    chunk_size.clamp(MIN_SFTP_CHUNK_SIZE, MAX_SFTP_CHUNK_SIZE)
+}
+
+/// SFTP操作を管理する
+pub struct SftpManager {
+    /// SSHセッションごとに開いたままにしておくSFTPサブシステム
+    sessions: Arc<Mutex<HashMap<String, Arc<SftpSession>>>>,
+    /// SSHセッションごとのSFTPサブシステムの有無（確認済みのもの）
+    availability: Arc<Mutex<HashMap<String, bool>>>,
+    syncs: Arc<Mutex<HashMap<String, CancellationToken>>>,
+    streams: Arc<Mutex<HashMap<String, CancellationToken>>>,
+    transfers: TransferManager,
+    events: EventBus,
+}
+
+/// 同期対象ファイルの状態
+#[derive(Debug, Clone, Copy)]
+struct FileState {
+    size: u64,
+    mtime: Option<u64>,
+}
+
+impl FileState {
+    fn matches(&self, other: &FileState) -> bool {
+        self.size == other.size && self.mtime.is_some() && self.mtime == other.mtime
+    }
+}
+
+/// ディレクトリツリーの走査結果（パスは同期ルートからの相対パス）
+#[derive(Default)]
+struct TreeListing {
+    files: BTreeMap<String, FileState>,
+    dirs: BTreeSet<String>,
+}
+
+impl SftpManager {
+    pub fn new(events: EventBus) -> Self {
+        Self {
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            availability: Arc::new(Mutex::new(HashMap::new())),
+            syncs: Arc::new(Mutex::new(HashMap::new())),
+            streams: Arc::new(Mutex::new(HashMap::new())),
+            transfers: TransferManager::new(events.clone()),
+            events,
+        }
+    }
+
+    /// 同期とストリーム読み込みの進捗を追跡するマネージャー
+    pub fn transfers(&self) -> &TransferManager {
+        &self.transfers
+    }
+
+    /// キャッシュ済みのSFTPセッションを取得（なければ開く）
+    pub async fn session(
+        &self,
+        session_id: &str,
+        connection: &SshConnection,
+    ) -> Result<Arc<SftpSession>, SshError> {
+        // 同時に複数回開かないよう、開き終えるまでロックを保持する
+        let mut sessions = self.sessions.lock().await;
+        if let Some(sftp) = sessions.get(session_id) {
+            return Ok(sftp.clone());
+        }
+
+        let sftp = Arc::new(open_sftp(connection).await?);
+        sessions.insert(session_id.to_string(), sftp.clone());
+        Ok(sftp)
+    }
+
+    /// キャッシュ済みのSFTPセッションを閉じて破棄する
+    pub async fn invalidate(&self, session_id: &str) {
+        let sftp = self.sessions.lock().await.remove(session_id);
+        if let Some(sftp) = sftp {
+            let _ = sftp.close().await;
+        }
+    }
+
+    /// 切断・削除したセッションのSFTPセッションと確認結果を破棄する
+    pub async fn forget_session(&self, session_id: &str) {
+        self.availability.lock().await.remove(session_id);
+        self.invalidate(session_id).await;
+    }
+
+    /// SFTPサブシステムが使えるかを確認する（結果はセッションごとに保持）
+    ///
+    /// 開いたSFTPセッションがあれば使えるものとし、なければ一時的に開いてすぐ閉じる。
+    pub async fn available(&self, session_id: &str, connection: &SshConnection) -> bool {
+        if let Some(&available) = self.availability.lock().await.get(session_id) {
+            return available;
+        }
+        if self.sessions.lock().await.contains_key(session_id) {
+            return true;
+        }
+
+        let available = match open_sftp(connection).await {
+            Ok(sftp) => {
+                let _ = sftp.close().await;
+                true
+            }
+            Err(_) => false,
+        };
+        self.availability
+            .lock()
+            .await
+            .insert(session_id.to_string(), available);
+        available
+    }
+
+    /// チャネルが使えなくなったことを示すエラーならキャッシュを破棄する
+    async fn invalidate_on_channel_error<T>(&self, session_id: &str, result: &Result<T, SshError>) {
+        if let Err(SshError::SftpChannelFailed(_)) = result {
+            self.invalidate(session_id).await;
+        }
+    }
+
+    /// ローカルとリモートのディレクトリを同期する
+    ///
+    /// サイズと更新時刻が一致するファイルはスキップするため、中断後に再実行すると
+    /// 未転送のファイルから再開される。
+    pub async fn sync(
+        &self,
+        session_id: &str,
+        connection: &SshConnection,
+        local_dir: &str,
+        remote_dir: &str,
+        options: SyncOptions,
+        chunk_size: usize,
+    ) -> Result<SyncSummary, SshError> {
+        let sync_id = Uuid::new_v4().to_string();
+        let cancel = CancellationToken::new();
+        self.syncs.lock().await.insert(sync_id.clone(), cancel.clone());
+        let limiter = self
+            .transfers
+            .start(&sync_id, session_id, TransferKind::Sync, remote_dir, None);
+        limiter.set_limit(options.max_bytes_per_sec);
+
+        let result = match self.session(session_id, connection).await {
+            Ok(sftp) => {
+                let mut job = SyncJob {
+                    sync_id: sync_id.clone(),
+                    session_id: session_id.to_string(),
+                    sftp: &sftp,
+                    local_root: PathBuf::from(local_dir),
+                    remote_root: remote_dir.trim_end_matches('/').to_string(),
+                    chunk_size: clamp_chunk_size(chunk_size),
+                    limiter,
+                    cancel: &cancel,
+                    events: &self.events,
+                    transfers: &self.transfers,
+                    summary: SyncSummary {
+                        sync_id: sync_id.clone(),
+                        ..Default::default()
+                    },
+                    started: Instant::now(),
+                };
+                // キャンセルされた場合もそこまでの結果を返す
+                match job.run(&options).await {
+                    Ok(()) => Ok(job.summary),
+                    Err(_) if job.summary.cancelled => Ok(job.summary),
+                    Err(e) => Err(e),
+                }
+            }
+            Err(e) => Err(e),
+        };
+        self.invalidate_on_channel_error(session_id, &result).await;
+
+        let state = match &result {
+            Ok(summary) if summary.cancelled => TransferState::Cancelled,
+            Ok(_) => TransferState::Completed,
+            Err(e) => TransferState::Failed(e.to_string()),
+        };
+        self.transfers.finish(&sync_id, state);
+        self.syncs.lock().await.remove(&sync_id);
+        result
+    }
+
+    /// 実行中の同期・ストリーム読み込みの転送速度の上限を変更する（`None` で制限しない）
+    pub fn set_rate_limit(&self, transfer_id: &str, max_bytes_per_sec: Option<u64>) -> Result<(), SshError> {
+        if self.transfers.set_rate_limit(transfer_id, max_bytes_per_sec) {
+            Ok(())
+        } else {
+            Err(SshError::SessionNotFound(transfer_id.to_string()))
+        }
+    }
+
+    /// リモートパスの存在と種類を調べる
+    pub async fn path_info(
+        &self,
+        session_id: &str,
+        connection: &SshConnection,
+        path: &str,
+    ) -> Result<RemotePathInfo, SshError> {
+        let sftp = self.session(session_id, connection).await?;
+        let result = stat_path(&sftp, path).await;
+        self.invalidate_on_channel_error(session_id, &result).await;
+        result
+    }
+
+    /// シェル形式のグロブ（`*`・`?`・`[...]`・`**`）に一致するリモートのパスを列挙する
+    ///
+    /// サーバー側でシェルを使わず、ディレクトリを列挙してクライアント側で照合する。
+    /// `**` は `max_depth`（省略時 `DEFAULT_GLOB_MAX_DEPTH`）階層までたどる。
+    pub async fn glob(
+        &self,
+        session_id: &str,
+        connection: &SshConnection,
+        pattern: &str,
+        max_depth: Option<usize>,
+    ) -> Result<Vec<RemoteFileEntry>, SshError> {
+        let sftp = self.session(session_id, connection).await?;
+        let max_depth = max_depth.unwrap_or(DEFAULT_GLOB_MAX_DEPTH);
+        let result = expand_glob(&sftp, pattern, max_depth).await;
+        self.invalidate_on_channel_error(session_id, &result).await;
+        result
+    }
+
+    /// リモートのパスが条件を満たすまで定期的に調べて待つ
+    ///
+    /// 条件を満たした時点の情報を返す（`NotExists` の場合は `None`）。
+    /// `timeout` までに満たさなければ `Timeout`。
+    pub async fn wait_for(
+        &self,
+        session_id: &str,
+        connection: &SshConnection,
+        path: &str,
+        condition: WaitCondition,
+        timeout: Duration,
+        poll_interval: Option<Duration>,
+    ) -> Result<Option<RemoteFileEntry>, SshError> {
+        let sftp = self.session(session_id, connection).await?;
+        let interval = poll_interval
+            .unwrap_or(DEFAULT_WAIT_POLL_INTERVAL)
+            .max(MIN_WAIT_POLL_INTERVAL);
+        let name = path.rsplit('/').next().unwrap_or_default().to_string();
+
+        let poll = async {
+            let mut previous_size = None;
+            loop {
+                let entry = match sftp.metadata(path).await {
+                    Ok(attrs) => Some(remote_file_entry(path.to_string(), name.clone(), &attrs)),
+                    Err(SftpError::Status(status)) if status.status_code == StatusCode::NoSuchFile => None,
+                    Err(e) => return Err(sftp_error(e)),
+                };
+                match (condition, entry) {
+                    (WaitCondition::Exists, Some(entry)) => return Ok(Some(entry)),
+                    (WaitCondition::NotExists, None) => return Ok(None),
+                    (WaitCondition::SizeStable, Some(entry)) => {
+                        if previous_size.is_some() && previous_size == entry.size {
+                            return Ok(Some(entry));
+                        }
+                        previous_size = entry.size;
+                    }
+                    (WaitCondition::SizeStable, None) => previous_size = None,
+                    _ => {}
+                }
+                tokio::time::sleep(interval).await;
+            }
+        };
+
+        let result = match tokio::time::timeout(timeout, poll).await {
+            Ok(result) => result,
+            Err(_) => Err(SshError::Timeout(format!(
+                "{} did not satisfy {:?} within {}ms",
+                path,
+                condition,
+                timeout.as_millis()
+            ))),
+        };
+        self.invalidate_on_channel_error(session_id, &result).await;
+        result
+    }
+
+    /// 複数のパスの情報をまとめて取得する（結果は `paths` と同じ順）
+    ///
+    /// 要求は並行して送り、往復の待ち時間を重ねる。パスごとの失敗は結果の要素に入り、
+    /// 呼び出し全体は失敗しない。シンボリックリンクはリンク自体の情報を返す。
+    pub async fn stat_many(
+        &self,
+        session_id: &str,
+        connection: &SshConnection,
+        paths: Vec<String>,
+    ) -> Result<Vec<Result<RemoteFileEntry, String>>, SshError> {
+        let sftp = self.session(session_id, connection).await?;
+        let limit = Arc::new(tokio::sync::Semaphore::new(MAX_STAT_CONCURRENCY));
+        let mut tasks = tokio::task::JoinSet::new();
+        for (index, path) in paths.iter().cloned().enumerate() {
+            let sftp = sftp.clone();
+            let limit = limit.clone();
+            tasks.spawn(async move {
+                let _permit = limit.acquire_owned().await;
+                let result = sftp.symlink_metadata(path.clone()).await.map_err(sftp_error);
+                (index, result.map(|attrs| {
+                    let name = path.rsplit('/').next().unwrap_or_default().to_string();
+                    remote_file_entry(path, name, &attrs)
+                }))
+            });
+        }
+
+        let mut results: Vec<Result<RemoteFileEntry, String>> =
+            vec![Err("stat was not performed".to_string()); paths.len()];
+        let mut channel_failed = false;
+        while let Some(joined) = tasks.join_next().await {
+            let Ok((index, result)) = joined else {
+                continue;
+            };
+            channel_failed |= matches!(result, Err(SshError::SftpChannelFailed(_)));
+            results[index] = result.map_err(|e| e.to_string());
+        }
+        if channel_failed {
+            self.invalidate(session_id).await;
+        }
+        Ok(results)
+    }
+
+    /// リモートのパスの情報を取得する（存在しなければ `None`）
+    pub async fn stat(
+        &self,
+        session_id: &str,
+        connection: &SshConnection,
+        path: &str,
+    ) -> Result<Option<RemoteFileEntry>, SshError> {
+        let sftp = self.session(session_id, connection).await?;
+        let name = path.rsplit('/').next().unwrap_or_default().to_string();
+        let result = match sftp.metadata(path).await {
+            Ok(attrs) => Ok(Some(remote_file_entry(path.to_string(), name, &attrs))),
+            Err(SftpError::Status(status)) if status.status_code == StatusCode::NoSuchFile => Ok(None),
+            Err(e) => Err(sftp_error(e)),
+        };
+        self.invalidate_on_channel_error(session_id, &result).await;
+        result
+    }
+
+    /// リモートファイルの指定範囲を読み込む（HTTPのRange要求に相当）
+    ///
+    /// `len` はファイルの終端と `MAX_RANGE_LENGTH` までに切り詰める。開始位置がファイルの
+    /// 終端を超える場合はエラーにする。
+    pub async fn read_range(
+        &self,
+        session_id: &str,
+        connection: &SshConnection,
+        path: &str,
+        start: u64,
+        len: u64,
+    ) -> Result<RemoteByteRange, SshError> {
+        let sftp = self.session(session_id, connection).await?;
+        let result: Result<RemoteByteRange, SshError> = async {
+            let mut file = sftp.open(path).await.map_err(sftp_error)?;
+            let file_size = file
+                .metadata()
+                .await
+                .map_err(sftp_error)?
+                .size
+                .ok_or_else(|| SshError::TransferFailed(format!("{}: file size is unknown", path)))?;
+            if start > file_size {
+                return Err(SshError::TransferFailed(format!(
+                    "range start {} is beyond the end of {} ({} bytes)",
+                    start, path, file_size
+                )));
+            }
+
+            let len = len.min(file_size - start).min(MAX_RANGE_LENGTH);
+            let mut data = Vec::with_capacity(len as usize);
+            if len > 0 {
+                file.seek(std::io::SeekFrom::Start(start)).await?;
+                (&mut file).take(len).read_to_end(&mut data).await?;
+            }
+            Ok(RemoteByteRange {
+                data_b64: base64::engine::general_purpose::STANDARD.encode(&data),
+                start,
+                length: data.len() as u64,
+                file_size,
+            })
+        }
+        .await;
+        self.invalidate_on_channel_error(session_id, &result).await;
+        result
+    }
+
+    /// リモートファイルを分割して読み込み、`SftpStreamData` イベントで順次通知する
+    ///
+    /// 読み込みはバックグラウンドで行い、すぐにストリームIDを返す。
+    pub async fn stream_read(
+        &self,
+        session_id: &str,
+        connection: &SshConnection,
+        path: &str,
+        chunk_size: usize,
+    ) -> Result<String, SshError> {
+        let sftp = self.session(session_id, connection).await?;
+        let result = sftp.open(path).await.map_err(sftp_error);
+        self.invalidate_on_channel_error(session_id, &result).await;
+        let mut file = result?;
+
+        let stream_id = Uuid::new_v4().to_string();
+        let cancel = CancellationToken::new();
+        self.streams.lock().await.insert(stream_id.clone(), cancel.clone());
+        let size = file.metadata().await.ok().and_then(|metadata| metadata.size);
+        let limiter = self
+            .transfers
+            .start(&stream_id, session_id, TransferKind::StreamRead, path, size);
+
+        let result = stream_id.clone();
+        let streams = self.streams.clone();
+        let transfers = self.transfers.clone();
+        let events = self.events.clone();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; clamp_chunk_size(chunk_size)];
+            let mut offset = 0u64;
+
+            let error = loop {
+                let read = tokio::select! {
+                    _ = cancel.cancelled() => break None,
+                    read = file.read(&mut buf) => read,
+                };
+                match read {
+                    Ok(0) => break None,
+                    Ok(n) => {
+                        tokio::select! {
+                            _ = cancel.cancelled() => break None,
+                            _ = limiter.acquire(n as u64) => {}
+                        }
+                        events.emit(SshEvent::SftpStreamData {
+                            stream_id: stream_id.clone(),
+                            data: base64::engine::general_purpose::STANDARD.encode(&buf[..n]),
+                            offset,
+                            eof: false,
+                            cancelled: false,
+                            error: None,
+                            timestamp: chrono::Utc::now(),
+                        });
+                        offset += n as u64;
+                        transfers.update(&stream_id, offset, None);
+                    }
+                    Err(e) => break Some(e.to_string()),
+                }
+            };
+
+            let _ = file.shutdown().await;
+            let state = match (&error, cancel.is_cancelled()) {
+                (Some(e), _) => TransferState::Failed(e.clone()),
+                (None, true) => TransferState::Cancelled,
+                (None, false) => TransferState::Completed,
+            };
+            transfers.finish(&stream_id, state);
+            events.emit(SshEvent::SftpStreamData {
+                stream_id: stream_id.clone(),
+                data: String::new(),
+                offset,
+                eof: true,
+                cancelled: cancel.is_cancelled(),
+                error,
+                timestamp: chrono::Utc::now(),
+            });
+            streams.lock().await.remove(&stream_id);
+        });
+
+        Ok(result)
+    }
+
+    /// ディレクトリの一覧をサーバーから届いた単位で読み込み、`SftpDirEntries` イベントで順次通知する
+    ///
+    /// 項目数の多いディレクトリ向け。専用のSFTPチャネルで読み込み、すぐに一覧IDを返す。
+    /// 完了・キャンセル・エラーのいずれの場合もディレクトリハンドルとチャネルを閉じる。
+    pub async fn list_dir_stream(
+        &self,
+        session_id: &str,
+        connection: &SshConnection,
+        path: &str,
+    ) -> Result<String, SshError> {
+        let dir = if path.is_empty() { "." } else { path };
+        let (raw, _) = open_raw_sftp(connection).await?;
+        let handle = match raw.opendir(dir).await {
+            Ok(handle) => handle.handle,
+            Err(e) => {
+                let _ = raw.close_session();
+                return Err(sftp_error(e));
+            }
+        };
+
+        let listing_id = Uuid::new_v4().to_string();
+        let cancel = CancellationToken::new();
+        self.streams.lock().await.insert(listing_id.clone(), cancel.clone());
+
+        let result = listing_id.clone();
+        let session_id = session_id.to_string();
+        let path = path.to_string();
+        let streams = self.streams.clone();
+        let events = self.events.clone();
+        tokio::spawn(async move {
+            let mut total = 0u64;
+            let error = loop {
+                let read = tokio::select! {
+                    _ = cancel.cancelled() => break None,
+                    read = raw.readdir(handle.as_str()) => read,
+                };
+                match read {
+                    Ok(name) => {
+                        let entries: Vec<RemoteFileEntry> = name
+                            .files
+                            .into_iter()
+                            .filter(|file| file.filename != "." && file.filename != "..")
+                            .map(|file| remote_file_entry(join_remote(&path, &file.filename), file.filename, &file.attrs))
+                            .collect();
+                        if entries.is_empty() {
+                            continue;
+                        }
+                        total += entries.len() as u64;
+                        events.emit(SshEvent::SftpDirEntries {
+                            listing_id: listing_id.clone(),
+                            session_id: session_id.clone(),
+                            entries,
+                            done: false,
+                            cancelled: false,
+                            total,
+                            error: None,
+                        });
+                    }
+                    Err(SftpError::Status(status)) if status.status_code == StatusCode::Eof => break None,
+                    Err(e) => break Some(sftp_error(e).to_string()),
+                }
+            };
+
+            let _ = raw.close(handle).await;
+            let _ = raw.close_session();
+            events.emit(SshEvent::SftpDirEntries {
+                listing_id: listing_id.clone(),
+                session_id,
+                entries: Vec::new(),
+                done: true,
+                cancelled: cancel.is_cancelled(),
+                total,
+                error,
+            });
+            streams.lock().await.remove(&listing_id);
+        });
+
+        Ok(result)
+    }
+
+    /// 公開鍵をリモートの `~/.ssh/authorized_keys` に追加する（ssh-copy-id 相当）
+    ///
+    /// `.ssh` はモード 700、`authorized_keys` はモード 600 で作成する。
+    /// 同じ鍵が登録済みなら何もせず `false` を返す。
+    pub async fn copy_id(
+        &self,
+        session_id: &str,
+        connection: &SshConnection,
+        public_key: &str,
+    ) -> Result<bool, SshError> {
+        let sftp = self.session(session_id, connection).await?;
+        let result = append_authorized_key(&sftp, public_key).await;
+        self.invalidate_on_channel_error(session_id, &result).await;
+        result
+    }
+
+    /// ストリーム読み込み・ディレクトリ一覧の読み込みをキャンセル
+    pub async fn cancel_stream(&self, stream_id: &str) -> Result<(), SshError> {
+        let streams = self.streams.lock().await;
+        let cancel = streams
+            .get(stream_id)
+            .ok_or_else(|| SshError::SessionNotFound(stream_id.to_string()))?;
+
+        cancel.cancel();
+
+        Ok(())
+    }
+
+    /// セッションの実行中の転送（同期とストリーム読み込み）をすべてキャンセルし、キャンセルした数を返す
+    pub async fn cancel_all_for_session(&self, session_id: &str) -> usize {
+        let running: Vec<String> = self
+            .transfers
+            .list()
+            .into_iter()
+            .filter(|info| info.session_id == session_id && matches!(info.state, TransferState::Running))
+            .map(|info| info.id)
+            .collect();
+
+        let syncs = self.syncs.lock().await;
+        let streams = self.streams.lock().await;
+        let mut cancelled = 0;
+        for id in &running {
+            if let Some(cancel) = syncs.get(id).or_else(|| streams.get(id)) {
+                cancel.cancel();
+                cancelled += 1;
+            }
+        }
+        cancelled
+    }
+
+    /// ゼロ埋めのデータをアップロードしてからダウンロードし、それぞれの転送速度を測る
+    ///
+    /// 一時ファイルはホームディレクトリに作成し、失敗やキャンセルの場合も削除する。
+    /// 測定中は転送一覧に表示され、同期と同じく `cancel_sync` でキャンセルできる。
+    pub async fn bandwidth_test(
+        &self,
+        session_id: &str,
+        connection: &SshConnection,
+        bytes: u64,
+        chunk_size: usize,
+    ) -> Result<BandwidthTestResult, SshError> {
+        let bytes = bytes.clamp(1, MAX_BANDWIDTH_TEST_BYTES);
+        let sftp = self.session(session_id, connection).await?;
+
+        let test_id = Uuid::new_v4().to_string();
+        let path = format!(".pardoroid-bandwidth-{}", Uuid::new_v4().simple());
+        let cancel = CancellationToken::new();
+        self.syncs.lock().await.insert(test_id.clone(), cancel.clone());
+        self.transfers
+            .start(&test_id, session_id, TransferKind::BandwidthTest, &path, Some(bytes * 2));
+
+        let result = run_bandwidth_test(
+            &sftp,
+            &path,
+            bytes,
+            clamp_chunk_size(chunk_size),
+            &cancel,
+            |transferred| self.transfers.update(&test_id, transferred, None),
+        )
+        .await;
+        let _ = sftp.remove_file(path).await;
+        self.invalidate_on_channel_error(session_id, &result).await;
+
+        let state = match &result {
+            Ok(_) => TransferState::Completed,
+            Err(_) if cancel.is_cancelled() => TransferState::Cancelled,
+            Err(e) => TransferState::Failed(e.to_string()),
+        };
+        self.transfers.finish(&test_id, state);
+        self.syncs.lock().await.remove(&test_id);
+        result
+    }
+
+    /// `copy-data@openssh.com` 拡張でファイルをサーバー上でコピーし、コピーしたバイト数を返す
+    ///
+    /// 拡張が提供されていない場合は `None` を返す。コピー先は作成または切り詰める。
+    /// コピー中は転送一覧に表示され、同期と同じく `cancel_sync` でキャンセルできる。
+    pub async fn copy_remote(
+        &self,
+        session_id: &str,
+        connection: &SshConnection,
+        src: &str,
+        dst: &str,
+    ) -> Result<Option<u64>, SshError> {
+        let (raw, version) = open_raw_sftp(connection).await?;
+        if !version.extensions.contains_key(COPY_DATA_EXTENSION) {
+            let _ = raw.close_session();
+            return Ok(None);
+        }
+
+        let copy_id = Uuid::new_v4().to_string();
+        let cancel = CancellationToken::new();
+        self.syncs.lock().await.insert(copy_id.clone(), cancel.clone());
+        self.transfers
+            .start(&copy_id, session_id, TransferKind::RemoteCopy, dst, None);
+
+        let result = copy_data(&raw, src, dst, &cancel, |copied, size| {
+            self.transfers.update(&copy_id, copied, Some(size))
+        })
+        .await;
+        let _ = raw.close_session();
+
+        let state = match &result {
+            Ok(_) => TransferState::Completed,
+            Err(_) if cancel.is_cancelled() => TransferState::Cancelled,
+            Err(e) => TransferState::Failed(e.to_string()),
+        };
+        self.transfers.finish(&copy_id, state);
+        self.syncs.lock().await.remove(&copy_id);
+        result.map(Some)
+    }
+
+    /// 複数のリモートパスを1つの tar アーカイブとしてローカルファイルにダウンロードする
+    ///
+    /// `use_tar` の場合はリモートの `tar` の出力を1本の exec チャネルで受信し、
+    /// そうでなければSFTPで1ファイルずつ取得してローカルでアーカイブにする。
+    /// 各パスはアーカイブの最上位に名前で格納する（ディレクトリは中身ごと）。
+    /// 受信済みバイト数は転送一覧と `TarDownloadProgress` イベントで通知し、
+    /// 同期と同じく `cancel_sync` でキャンセルできる。失敗・キャンセル時は作りかけのファイルを削除する。
+    pub async fn download_as_tar(
+        &self,
+        session_id: &str,
+        connection: &SshConnection,
+        remote_paths: &[String],
+        local_path: &str,
+        use_tar: bool,
+    ) -> Result<TarDownloadResult, SshError> {
+        if remote_paths.is_empty() {
+            return Err(SshError::ConfigError("no remote paths to archive".to_string()));
+        }
+
+        let transfer_id = Uuid::new_v4().to_string();
+        let cancel = CancellationToken::new();
+        self.syncs.lock().await.insert(transfer_id.clone(), cancel.clone());
+        self.transfers
+            .start(&transfer_id, session_id, TransferKind::TarDownload, local_path, None);
+
+        let mut last_emit: Option<Instant> = None;
+        let mut progress = |bytes: u64, done: bool| {
+            self.transfers.update(&transfer_id, bytes, None);
+            if !done && last_emit.is_some_and(|last| last.elapsed() < TAR_PROGRESS_INTERVAL) {
+                return;
+            }
+            last_emit = Some(Instant::now());
+            self.events.emit(SshEvent::TarDownloadProgress {
+                transfer_id: transfer_id.clone(),
+                session_id: session_id.to_string(),
+                bytes_received: bytes,
+                done,
+            });
+        };
+
+        let result = async {
+            let file = tokio::fs::File::create(local_path).await?;
+            if use_tar {
+                let (bytes, warning) =
+                    receive_remote_tar(connection, &tar_command(remote_paths), file, &cancel, &mut progress).await?;
+                Ok((TarDownloadMethod::Tar, bytes, warning))
+            } else {
+                let sftp = self.session(session_id, connection).await?;
+                let (bytes, warning) = archive_over_sftp(&sftp, remote_paths, file, &cancel, &mut progress).await?;
+                Ok((TarDownloadMethod::Sftp, bytes, warning))
+            }
+        }
+        .await;
+        self.invalidate_on_channel_error(session_id, &result).await;
+
+        let state = match &result {
+            Ok(_) => TransferState::Completed,
+            Err(_) if cancel.is_cancelled() => TransferState::Cancelled,
+            Err(e) => TransferState::Failed(e.to_string()),
+        };
+        match &result {
+            Ok((_, bytes, _)) => progress(*bytes, true),
+            Err(_) => {
+                let _ = tokio::fs::remove_file(local_path).await;
+            }
+        }
+        self.transfers.finish(&transfer_id, state);
+        self.syncs.lock().await.remove(&transfer_id);
+        let (method, bytes, warning) = result?;
+        Ok(TarDownloadResult {
+            transfer_id,
+            method,
+            bytes,
+            warning,
+        })
+    }
+
+    /// 実行中の同期をキャンセル
+    pub async fn cancel_sync(&self, sync_id: &str) -> Result<(), SshError> {
+        let syncs = self.syncs.lock().await;
+        let cancel = syncs
+            .get(sync_id)
+            .ok_or_else(|| SshError::SessionNotFound(sync_id.to_string()))?;
+
+        cancel.cancel();
+
+        Ok(())
+    }
+}
+
+/// 1回分の同期処理
+struct SyncJob<'a> {
+    sync_id: String,
+    session_id: String,
+    sftp: &'a SftpSession,
+    local_root: PathBuf,
+    remote_root: String,
+    chunk_size: usize,
+    limiter: Arc<RateLimiter>,
+    cancel: &'a CancellationToken,
+    events: &'a EventBus,
+    transfers: &'a TransferManager,
+    summary: SyncSummary,
+    started: Instant,
+}
+
+impl SyncJob<'_> {
+    async fn run(&mut self, options: &SyncOptions) -> Result<(), SshError> {
+        let local = scan_local(&self.local_root).await?;
+        let remote = if self.sftp.try_exists(self.remote_root.clone()).await.map_err(sftp_error)? {
+            scan_remote(self.sftp, &self.remote_root).await?
+        } else {
+            self.sftp.create_dir(self.remote_root.clone()).await.map_err(sftp_error)?;
+            TreeListing::default()
+        };
+
+        let (source, destination) = match options.direction {
+            SyncDirection::Upload => (&local, &remote),
+            SyncDirection::Download => (&remote, &local),
+        };
+
+        // 転送が必要なファイルを決定
+        let mut pending = Vec::new();
+        for (path, state) in &source.files {
+            match destination.files.get(path) {
+                Some(existing) if state.matches(existing) => self.summary.skipped += 1,
+                Some(_) => pending.push((path.clone(), *state, false)),
+                None => pending.push((path.clone(), *state, true)),
+            }
+        }
+        self.summary.bytes_total = pending.iter().map(|(_, state, _)| state.size).sum();
+        let files_total = pending.len();
+
+        // 転送先にディレクトリを作成
+        for dir in source.dirs.difference(&destination.dirs) {
+            self.check_cancelled()?;
+            match options.direction {
+                SyncDirection::Upload => {
+                    self.sftp
+                        .create_dir(self.remote_path(dir))
+                        .await
+                        .map_err(sftp_error)?;
+                }
+                SyncDirection::Download => {
+                    tokio::fs::create_dir_all(self.local_path(dir)).await?;
+                }
+            }
+        }
+
+        for (index, (path, state, is_new)) in pending.into_iter().enumerate() {
+            self.check_cancelled()?;
+            self.emit_progress(&path, 0, state.size, index, files_total);
+
+            match options.direction {
+                SyncDirection::Upload => self.upload(&path, state, index, files_total).await?,
+                SyncDirection::Download => self.download(&path, state, index, files_total).await?,
+            }
+
+            if is_new {
+                self.summary.added += 1;
+            } else {
+                self.summary.updated += 1;
+            }
+        }
+
+        if options.delete {
+            self.delete_extraneous(source, destination, options.direction).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn upload(&mut self, path: &str, state: FileState, index: usize, total: usize) -> Result<(), SshError> {
+        let remote_path = self.remote_path(path);
+        let mut reader = tokio::fs::File::open(self.local_path(path)).await?;
+        let mut writer = self.sftp.create(remote_path.clone()).await.map_err(sftp_error)?;
+        self.copy(path, &mut reader, &mut writer, state.size, index, total).await?;
+        writer.shutdown().await?;
+
+        // 次回の比較のために更新時刻を揃える
+        if let Some(mtime) = state.mtime {
+            let attrs = FileAttributes {
+                atime: Some(mtime as u32),
+                mtime: Some(mtime as u32),
+                ..Default::default()
+            };
+            self.sftp
+                .set_metadata(remote_path, attrs)
+                .await
+                .map_err(sftp_error)?;
+        }
+
+        Ok(())
+    }
+
+    async fn download(&mut self, path: &str, state: FileState, index: usize, total: usize) -> Result<(), SshError> {
+        let local_path = self.local_path(path);
+        let mut reader = self.sftp.open(self.remote_path(path)).await.map_err(sftp_error)?;
+        let mut writer = tokio::fs::File::create(&local_path).await?;
+        self.copy(path, &mut reader, &mut writer, state.size, index, total).await?;
+        writer.flush().await?;
+        drop(writer);
+
+        if let Some(mtime) = state.mtime {
+            let file = std::fs::File::options().write(true).open(&local_path)?;
+            file.set_modified(UNIX_EPOCH + Duration::from_secs(mtime))?;
+        }
+
+        Ok(())
+    }
+
+    async fn copy<R, W>(
+        &mut self,
+        path: &str,
+        reader: &mut R,
+        writer: &mut W,
+        size: u64,
+        index: usize,
+        total: usize,
+    ) -> Result<(), SshError>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut buf = vec![0u8; self.chunk_size];
+        let mut transferred = 0u64;
+
+        loop {
+            self.check_cancelled()?;
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            tokio::select! {
+                _ = self.cancel.cancelled() => {}
+                _ = self.limiter.acquire(n as u64) => {}
+            }
+            self.check_cancelled()?;
+            writer.write_all(&buf[..n]).await?;
+            transferred += n as u64;
+            self.summary.bytes_transferred += n as u64;
+            self.emit_progress(path, transferred, size, index, total);
+        }
+
+        Ok(())
+    }
+
+    async fn delete_extraneous(
+        &mut self,
+        source: &TreeListing,
+        destination: &TreeListing,
+        direction: SyncDirection,
+    ) -> Result<(), SshError> {
+        for path in destination.files.keys().filter(|p| !source.files.contains_key(*p)) {
+            self.check_cancelled()?;
+            match direction {
+                SyncDirection::Upload => {
+                    self.sftp
+                        .remove_file(self.remote_path(path))
+                        .await
+                        .map_err(sftp_error)?;
+                }
+                SyncDirection::Download => tokio::fs::remove_file(self.local_path(path)).await?,
+            }
+            self.summary.deleted += 1;
+        }
+
+        // 深い階層から順に空になったディレクトリを削除
+        let mut dirs: Vec<&String> = destination.dirs.difference(&source.dirs).collect();
+        dirs.sort_by_key(|d| std::cmp::Reverse(d.matches('/').count()));
+        for dir in dirs {
+            let removed = match direction {
+                SyncDirection::Upload => self.sftp.remove_dir(self.remote_path(dir)).await.is_ok(),
+                SyncDirection::Download => tokio::fs::remove_dir(self.local_path(dir)).await.is_ok(),
+            };
+            if removed {
+                self.summary.deleted += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_cancelled(&mut self) -> Result<(), SshError> {
+        if self.cancel.is_cancelled() {
+            self.summary.cancelled = true;
+            return Err(SshError::TransferFailed("sync cancelled".to_string()));
+        }
+        Ok(())
+    }
+
+    fn emit_progress(&self, path: &str, transferred: u64, size: u64, index: usize, total: usize) {
+        let elapsed_ms = self.started.elapsed().as_millis() as u64;
+        self.transfers.update(
+            &self.sync_id,
+            self.summary.bytes_transferred,
+            Some(self.summary.bytes_total),
+        );
+        self.events.emit(SshEvent::SyncProgress {
+            sync_id: self.sync_id.clone(),
+            session_id: self.session_id.clone(),
+            path: path.to_string(),
+            file_transferred: transferred,
+            file_total: size,
+            files_completed: index,
+            files_total: total,
+            bytes_transferred: self.summary.bytes_transferred,
+            bytes_total: self.summary.bytes_total,
+            elapsed_ms,
+            eta_ms: estimate_remaining_ms(
+                self.summary.bytes_transferred,
+                self.summary.bytes_total,
+                elapsed_ms,
+            ),
+        });
+    }
+
+    fn local_path(&self, relative: &str) -> PathBuf {
+        relative
+            .split('/')
+            .fold(self.local_root.clone(), |path, part| path.join(part))
+    }
+
+    fn remote_path(&self, relative: &str) -> String {
+        format!("{}/{}", self.remote_root, relative)
+    }
+}
+
+/// SFTPサブシステムを開く
+pub async fn open_sftp(connection: &SshConnection) -> Result<SftpSession, SshError> {
+    let channel = open_sftp_channel(connection).await?;
+    SftpSession::new(channel.into_stream())
+        .await
+        .map_err(sftp_error)
+}
+
+/// SFTPサブシステムを低レベルのAPIで開き、サーバーが通知したバージョン情報とともに返す
+///
+/// ハンドルや拡張要求を直接扱う場合に使う。
+async fn open_raw_sftp(connection: &SshConnection) -> Result<(RawSftpSession, Version), SshError> {
+    let channel = open_sftp_channel(connection).await?;
+    let raw = RawSftpSession::new(channel.into_stream());
+    let version = raw.init().await.map_err(sftp_error)?;
+    Ok((raw, version))
+}
+
+/// SFTPサブシステムを要求したチャネルを開く
+async fn open_sftp_channel(connection: &SshConnection) -> Result<Channel<Msg>, SshError> {
+    let channel = connection
+        .channel_open_session()
+        .await
+        .map_err(|e| SshError::TransferFailed(e.to_string()))?;
+    channel
+        .request_subsystem(true, "sftp")
+        .await
+        .map_err(|e| SshError::TransferFailed(e.to_string()))?;
+    Ok(channel)
+}
+
+/// `copy-data` 拡張で `src` の内容を `dst` へ書き込む
+async fn copy_data(
+    raw: &RawSftpSession,
+    src: &str,
+    dst: &str,
+    cancel: &CancellationToken,
+    mut progress: impl FnMut(u64, u64),
+) -> Result<u64, SshError> {
+    let source = raw
+        .open(src, OpenFlags::READ, FileAttributes::empty())
+        .await
+        .map_err(sftp_error)?
+        .handle;
+    let target = match raw
+        .open(dst, OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE, FileAttributes::empty())
+        .await
+    {
+        Ok(handle) => handle.handle,
+        Err(e) => {
+            let _ = raw.close(source).await;
+            return Err(sftp_error(e));
+        }
+    };
+
+    let result = async {
+        let size = raw.fstat(source.as_str()).await.map_err(sftp_error)?.attrs.size;
+        // サイズが分からない場合は終端まで（長さ 0）を1回で要求する
+        let Some(size) = size else {
+            copy_data_request(raw, &source, 0, 0, &target).await?;
+            return Ok(0);
+        };
+
+        let mut offset = 0;
+        while offset < size {
+            if cancel.is_cancelled() {
+                return Err(SshError::TransferFailed("remote copy cancelled".to_string()));
+            }
+            let length = (size - offset).min(COPY_DATA_CHUNK);
+            copy_data_request(raw, &source, offset, length, &target).await?;
+            offset += length;
+            progress(offset, size);
+        }
+        Ok(size)
+    }
+    .await;
+
+    let _ = raw.close(source).await;
+    let _ = raw.close(target).await;
+    result
+}
+
+/// `copy-data` 要求を1回送る（読み込み位置と同じ位置へ書き込む）
+async fn copy_data_request(
+    raw: &RawSftpSession,
+    read_handle: &str,
+    offset: u64,
+    length: u64,
+    write_handle: &str,
+) -> Result<(), SshError> {
+    let mut data = Vec::with_capacity(read_handle.len() + write_handle.len() + 32);
+    data.extend_from_slice(&(read_handle.len() as u32).to_be_bytes());
+    data.extend_from_slice(read_handle.as_bytes());
+    data.extend_from_slice(&offset.to_be_bytes());
+    data.extend_from_slice(&length.to_be_bytes());
+    data.extend_from_slice(&(write_handle.len() as u32).to_be_bytes());
+    data.extend_from_slice(write_handle.as_bytes());
+    data.extend_from_slice(&offset.to_be_bytes());
+
+    match raw.extended(COPY_DATA_EXTENSION, data).await.map_err(sftp_error)? {
+        Packet::Status(status) if status.status_code == StatusCode::Ok => Ok(()),
+        Packet::Status(status) => Err(sftp_error(SftpError::Status(status))),
+        _ => Err(SshError::TransferFailed("unexpected reply to copy-data request".to_string())),
+    }
+}
+
+/// SFTPの相対パスはログインユーザーのホームディレクトリを基準とする
+const REMOTE_SSH_DIR: &str = ".ssh";
+const REMOTE_AUTHORIZED_KEYS: &str = ".ssh/authorized_keys";
+
+/// authorized_keys に公開鍵を追記する（登録済みなら false）
+async fn append_authorized_key(sftp: &SftpSession, public_key: &str) -> Result<bool, SshError> {
+    let public_key = public_key.trim();
+    let key_id = authorized_key_id(public_key)
+        .ok_or_else(|| SshError::ConfigError("invalid public key".to_string()))?;
+
+    if !sftp.try_exists(REMOTE_SSH_DIR).await.map_err(sftp_error)? {
+        sftp.create_dir(REMOTE_SSH_DIR).await.map_err(sftp_error)?;
+        set_permissions(sftp, REMOTE_SSH_DIR, 0o700).await?;
+    }
+
+    let existing = if sftp.try_exists(REMOTE_AUTHORIZED_KEYS).await.map_err(sftp_error)? {
+        Some(sftp.read(REMOTE_AUTHORIZED_KEYS).await.map_err(sftp_error)?)
+    } else {
+        None
+    };
+
+    let mut entry = String::new();
+    if let Some(existing) = &existing {
+        let text = String::from_utf8_lossy(existing);
+        if text.lines().any(|line| authorized_key_id(line) == Some(key_id)) {
+            return Ok(false);
+        }
+        if !existing.is_empty() && !existing.ends_with(b"\n") {
+            entry.push('\n');
+        }
+    }
+    entry.push_str(public_key);
+    entry.push('\n');
+
+    let mut file = sftp
+        .open_with_flags(
+            REMOTE_AUTHORIZED_KEYS,
+            OpenFlags::CREATE | OpenFlags::WRITE | OpenFlags::APPEND,
+        )
+        .await
+        .map_err(sftp_error)?;
+    file.write_all(entry.as_bytes()).await?;
+    file.shutdown().await?;
+
+    if existing.is_none() {
+        set_permissions(sftp, REMOTE_AUTHORIZED_KEYS, 0o600).await?;
+    }
+
+    Ok(true)
+}
+
+/// authorized_keys の行から鍵の種類と本体を取り出す（オプションとコメントは無視する）
+fn authorized_key_id(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let pos = fields
+        .iter()
+        .position(|f| f.starts_with("ssh-") || f.starts_with("ecdsa-") || f.starts_with("sk-"))?;
+    Some((fields[pos], *fields.get(pos + 1)?))
+}
+
+/// リモートパスのパーミッションを設定する
+async fn set_permissions(sftp: &SftpSession, path: &str, mode: u32) -> Result<(), SshError> {
+    let attrs = FileAttributes {
+        permissions: Some(mode),
+        ..Default::default()
+    };
+    sftp.set_metadata(path, attrs).await.map_err(sftp_error)
+}
+
+/// lstat/stat でパスの情報を取得（シンボリックリンクはリンク先の種類も調べる）
+async fn stat_path(sftp: &SftpSession, path: &str) -> Result<RemotePathInfo, SshError> {
+    let link_metadata = match sftp.symlink_metadata(path).await {
+        Ok(metadata) => metadata,
+        Err(SftpError::Status(status)) if status.status_code == StatusCode::NoSuchFile => {
+            return Ok(RemotePathInfo::default());
+        }
+        Err(SftpError::Status(status)) if status.status_code == StatusCode::PermissionDenied => {
+            return Ok(RemotePathInfo {
+                exists: true,
+                permission_denied: true,
+                ..Default::default()
+            });
+        }
+        Err(e) => return Err(sftp_error(e)),
+    };
+
+    let is_symlink = link_metadata.is_symlink();
+    // リンク切れの場合はリンク自体の情報を返す
+    let metadata = if is_symlink {
+        sftp.metadata(path).await.unwrap_or(link_metadata)
+    } else {
+        link_metadata
+    };
+
+    Ok(RemotePathInfo {
+        exists: true,
+        is_dir: metadata.is_dir(),
+        is_file: metadata.is_regular(),
+        is_symlink,
+        size: metadata.size,
+        mode: metadata.permissions,
+        permission_denied: false,
+    })
+}
+
+/// グロブを展開する（一致したパスの順に並べる）
+///
+/// 相対パスはSFTPの作業ディレクトリ（通常はホームディレクトリ）を基準にする。
+/// `**` はシンボリックリンク先のディレクトリをたどらない。
+async fn expand_glob(sftp: &SftpSession, pattern: &str, max_depth: usize) -> Result<Vec<RemoteFileEntry>, SshError> {
+    let components: Vec<&str> = pattern
+        .split('/')
+        .filter(|component| !component.is_empty() && *component != ".")
+        .collect();
+    if components.is_empty() {
+        return Err(SshError::ConfigError("empty glob pattern".to_string()));
+    }
+
+    let root = if pattern.starts_with('/') { "/".to_string() } else { String::new() };
+    let mut matches = BTreeMap::new();
+    // (ディレクトリ, 照合するパターンの位置, `**` でたどった深さ)
+    let mut stack = vec![(root, 0usize, 0usize)];
+
+    while let Some((dir, index, depth)) = stack.pop() {
+        let component = components[index];
+        let last = index + 1 == components.len();
+
+        if component == "**" {
+            // 0階層分の一致
+            if !last {
+                stack.push((dir.clone(), index + 1, depth));
+            }
+            for (name, attrs) in list_dir(sftp, &dir).await? {
+                if name.starts_with('.') {
+                    continue;
+                }
+                let path = join_remote(&dir, &name);
+                if attrs.is_dir() && depth < max_depth {
+                    stack.push((path.clone(), index, depth + 1));
+                }
+                if last {
+                    matches.insert(path.clone(), remote_file_entry(path, name, &attrs));
+                }
+            }
+        } else if has_glob_magic(component) {
+            for (name, attrs) in list_dir(sftp, &dir).await? {
+                if !glob_match(component, &name) {
+                    continue;
+                }
+                let path = join_remote(&dir, &name);
+                if last {
+                    matches.insert(path.clone(), remote_file_entry(path, name, &attrs));
+                } else if attrs.is_dir() {
+                    stack.push((path, index + 1, depth));
+                }
+            }
+        } else {
+            let name = unescape_glob(component);
+            let path = join_remote(&dir, &name);
+            if !last {
+                stack.push((path, index + 1, depth));
+                continue;
+            }
+            match sftp.symlink_metadata(path.clone()).await {
+                Ok(attrs) => {
+                    matches.insert(path.clone(), remote_file_entry(path, name, &attrs));
+                }
+                Err(SftpError::Status(status)) if is_missing_or_denied(status.status_code) => {}
+                Err(e) => return Err(sftp_error(e)),
+            }
+        }
+    }
+
+    Ok(matches.into_values().collect())
+}
+
+/// ディレクトリの内容を列挙する（存在しない・読めないディレクトリは空として扱う）
+async fn list_dir(sftp: &SftpSession, dir: &str) -> Result<Vec<(String, FileAttributes)>, SshError> {
+    let dir = if dir.is_empty() { "." } else { dir };
+    match sftp.read_dir(dir).await {
+        Ok(entries) => Ok(entries
+            .filter(|entry| entry.file_name() != "." && entry.file_name() != "..")
+            .map(|entry| (entry.file_name(), entry.metadata()))
+            .collect()),
+        Err(SftpError::Status(status)) if is_missing_or_denied(status.status_code) => Ok(Vec::new()),
+        Err(e) => Err(sftp_error(e)),
+    }
+}
+
+fn is_missing_or_denied(code: StatusCode) -> bool {
+    matches!(code, StatusCode::NoSuchFile | StatusCode::PermissionDenied)
+}
+
+fn join_remote(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else if dir.ends_with('/') {
+        format!("{}{}", dir, name)
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+fn remote_file_entry(path: String, name: String, attrs: &FileAttributes) -> RemoteFileEntry {
+    RemoteFileEntry {
+        path,
+        name,
+        is_dir: attrs.is_dir(),
+        is_file: attrs.is_regular(),
+        is_symlink: attrs.is_symlink(),
+        size: attrs.size,
+        mode: attrs.permissions,
+        mtime: attrs.mtime.map(u64::from),
+    }
+}
+
+fn has_glob_magic(component: &str) -> bool {
+    component.contains(['*', '?', '['])
+}
+
+/// `\` によるエスケープを取り除く
+fn unescape_glob(component: &str) -> String {
+    let mut result = String::with_capacity(component.len());
+    let mut chars = component.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.extend(chars.next()),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// ファイル名がグロブの1要素に一致するか
+///
+/// シェルと同様に、先頭の `.` はパターンで明示しない限り一致しない。
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    if name.first() == Some(&'.') && pattern.first() != Some(&'.') {
+        return false;
+    }
+
+    let (mut p, mut n) = (0, 0);
+    // 直前の `*` の位置と、そこまでに一致させた文字数
+    let mut backtrack = None;
+    while n < name.len() {
+        if pattern.get(p) == Some(&'*') {
+            backtrack = Some((p, n));
+            p += 1;
+            continue;
+        }
+        if p < pattern.len() {
+            if let Some(next) = match_one(&pattern, p, name[n]) {
+                p = next;
+                n += 1;
+                continue;
+            }
+        }
+        // `*` に1文字多く一致させてやり直す
+        match backtrack {
+            Some((star, matched)) => {
+                backtrack = Some((star, matched + 1));
+                p = star + 1;
+                n = matched + 1;
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// パターンの `p` の位置の1文字分が `c` に一致すれば、次の位置を返す
+fn match_one(pattern: &[char], p: usize, c: char) -> Option<usize> {
+    match pattern[p] {
+        '?' => Some(p + 1),
+        '[' => match match_class(pattern, p, c) {
+            Some((matched, next)) => matched.then_some(next),
+            // 閉じていない `[` は文字として扱う
+            None => (c == '[').then_some(p + 1),
+        },
+        '\\' if p + 1 < pattern.len() => (pattern[p + 1] == c).then_some(p + 2),
+        literal => (literal == c).then_some(p + 1),
+    }
+}
+
+/// `[...]`（`!`/`^` による否定と `a-z` の範囲を含む）を照合し、結果と次の位置を返す
+///
+/// `]` で閉じていなければ `None`。
+fn match_class(pattern: &[char], p: usize, c: char) -> Option<(bool, usize)> {
+    let mut i = p + 1;
+    let negated = matches!(pattern.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let start = *pattern.get(i)?;
+        // 先頭の `]` は文字として扱う
+        if start == ']' && !first {
+            break;
+        }
+        first = false;
+        match (pattern.get(i + 1), pattern.get(i + 2)) {
+            (Some('-'), Some(&end)) if end != ']' => {
+                matched |= start <= c && c <= end;
+                i += 3;
+            }
+            _ => {
+                matched |= start == c;
+                i += 1;
+            }
+        }
+    }
+    Some((matched != negated, i + 1))
+}
+
+/// リモートで `tar` を実行し、標準出力のアーカイブをファイルに書き出して、そのバイト数を返す
+///
+/// 途中で消えたファイルがあるなど `tar` が0以外で終了した場合も、受信したアーカイブは残して
+/// 標準エラー出力を警告として返す。
+async fn receive_remote_tar<F>(
+    connection: &SshConnection,
+    command: &str,
+    mut file: tokio::fs::File,
+    cancel: &CancellationToken,
+    progress: &mut F,
+) -> Result<(u64, Option<String>), SshError>
+where
+    F: FnMut(u64, bool),
+{
+    let mut channel = connection
+        .channel_open_session()
+        .await
+        .map_err(|e| SshError::CommandFailed(e.to_string()))?;
+    channel
+        .exec(true, command)
+        .await
+        .map_err(|e| SshError::CommandFailed(e.to_string()))?;
+
+    let mut bytes = 0u64;
+    let mut stderr = Vec::new();
+    let mut exit_status = None;
+    loop {
+        let msg = tokio::select! {
+            _ = cancel.cancelled() => {
+                let _ = channel.close().await;
+                return Err(SshError::TransferFailed("tar download cancelled".to_string()));
+            }
+            msg = channel.wait() => msg,
+        };
+        match msg {
+            Some(ChannelMsg::Data { data }) => {
+                file.write_all(&data).await?;
+                bytes += data.len() as u64;
+                progress(bytes, false);
+            }
+            Some(ChannelMsg::ExtendedData { data, ext: 1 }) => {
+                if stderr.len() < MAX_TAR_STDERR {
+                    stderr.extend_from_slice(&data);
+                }
+            }
+            Some(ChannelMsg::ExitStatus { exit_status: status }) => exit_status = Some(status),
+            Some(ChannelMsg::Close) | None => break,
+            Some(_) => {}
+        }
+    }
+    file.flush().await?;
+
+    let stderr = String::from_utf8_lossy(&stderr).trim().to_string();
+    match exit_status {
+        Some(0) => Ok((bytes, None)),
+        Some(code) => Ok((bytes, Some(format!("tar exited with {}: {}", code, stderr)))),
+        None => Err(SshError::TransferFailed(format!("tar did not exit normally: {}", stderr))),
+    }
+}
+
+/// SFTPで1ファイルずつ読み込んでローカルで tar アーカイブにし、そのバイト数を返す
+///
+/// 途中で消えた・読み切れなかったファイルは飛ばすか0で埋め、警告にまとめて返す。
+/// 通常のファイルとディレクトリ以外（シンボリックリンクなど）は格納しない。
+async fn archive_over_sftp<F>(
+    sftp: &SftpSession,
+    remote_paths: &[String],
+    file: tokio::fs::File,
+    cancel: &CancellationToken,
+    progress: &mut F,
+) -> Result<(u64, Option<String>), SshError>
+where
+    F: FnMut(u64, bool),
+{
+    let mut tar = TarWriter::new(tokio::io::BufWriter::new(file));
+    let mut received = 0u64;
+    let mut incomplete = Vec::new();
+
+    // (リモートのパス, アーカイブ内の名前, 属性) を深さ優先でたどる
+    let mut pending: Vec<(String, String, FileAttributes)> = Vec::new();
+    for path in remote_paths.iter().rev() {
+        match sftp.metadata(path.clone()).await {
+            Ok(attrs) => pending.push((path.clone(), split_archive_path(path).1.to_string(), attrs)),
+            Err(SftpError::Status(status)) if is_missing_or_denied(status.status_code) => {
+                incomplete.push(path.clone());
+            }
+            Err(e) => return Err(sftp_error(e)),
+        }
+    }
+
+    while let Some((path, name, attrs)) = pending.pop() {
+        if cancel.is_cancelled() {
+            return Err(SshError::TransferFailed("tar download cancelled".to_string()));
+        }
+        let mode = attrs.permissions.unwrap_or(0o644);
+        let mtime = attrs.mtime.map(u64::from).unwrap_or(0);
+
+        let file_type = attrs.file_type();
+        if file_type.is_dir() {
+            tar.append_dir(&name, mode, mtime).await?;
+            let mut entries = list_dir(sftp, &path).await?;
+            entries.sort_by(|a, b| b.0.cmp(&a.0));
+            for (child, child_attrs) in entries {
+                pending.push((join_remote(&path, &child), format!("{}/{}", name, child), child_attrs));
+            }
+        } else if file_type.is_file() {
+            let mut reader = match sftp.open(path.clone()).await {
+                Ok(reader) => reader,
+                Err(SftpError::Status(status)) if is_missing_or_denied(status.status_code) => {
+                    incomplete.push(path);
+                    continue;
+                }
+                Err(e) => return Err(sftp_error(e)),
+            };
+            let size = attrs.size.unwrap_or(0);
+            let append = tar.append_file(&name, mode, mtime, size, &mut reader, |n| {
+                received += n;
+                progress(received, false);
+            });
+            let read = tokio::select! {
+                _ = cancel.cancelled() => {
+                    return Err(SshError::TransferFailed("tar download cancelled".to_string()));
+                }
+                read = append => read?,
+            };
+            if read < size {
+                incomplete.push(path);
+            }
+        }
+    }
+
+    let writer = tar.finish().await?;
+    let bytes = writer.get_ref().metadata().await?.len();
+    let warning = (!incomplete.is_empty()).then(|| {
+        format!(
+            "{} file(s) vanished or could not be read completely: {}",
+            incomplete.len(),
+            incomplete.join(", ")
+        )
+    });
+    Ok((bytes, warning))
+}
+
+/// SFTPエラーを変換（サーバーのステータス応答以外はチャネルの異常として扱う）
+pub fn sftp_error(err: russh_sftp::client::error::Error) -> SshError {
+    match err {
+        SftpError::Status(status) => {
+            SshError::TransferFailed(format!("{:?}: {}", status.status_code, status.error_message))
+        }
+        other => SshError::SftpChannelFailed(other.to_string()),
+    }
+}
+
+/// 一時ファイルへの書き込みと読み込みにかかった時間を測る
+async fn run_bandwidth_test(
+    sftp: &SftpSession,
+    path: &str,
+    bytes: u64,
+    chunk_size: usize,
+    cancel: &CancellationToken,
+    progress: impl Fn(u64),
+) -> Result<BandwidthTestResult, SshError> {
+    let check_cancelled = || {
+        if cancel.is_cancelled() {
+            Err(SshError::TransferFailed("bandwidth test cancelled".to_string()))
+        } else {
+            Ok(())
+        }
+    };
+    let mut buf = vec![0u8; chunk_size];
+
+    let started = Instant::now();
+    let mut writer = sftp.create(path.to_string()).await.map_err(sftp_error)?;
+    let mut written = 0u64;
+    while written < bytes {
+        check_cancelled()?;
+        let n = (bytes - written).min(chunk_size as u64) as usize;
+        writer.write_all(&buf[..n]).await?;
+        written += n as u64;
+        progress(written);
+    }
+    writer.shutdown().await?;
+    let upload = started.elapsed();
+
+    let started = Instant::now();
+    let mut reader = sftp.open(path.to_string()).await.map_err(sftp_error)?;
+    let mut read = 0u64;
+    loop {
+        check_cancelled()?;
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        read += n as u64;
+        progress(bytes + read);
+    }
+    let download = started.elapsed();
+
+    let rate = |bytes: u64, elapsed: Duration| {
+        let secs = elapsed.as_secs_f64();
+        if secs > 0.0 {
+            bytes as f64 / secs
+        } else {
+            0.0
+        }
+    };
+    Ok(BandwidthTestResult {
+        bytes,
+        upload_ms: upload.as_millis() as u64,
+        download_ms: download.as_millis() as u64,
+        upload_bytes_per_sec: rate(bytes, upload),
+        download_bytes_per_sec: rate(read, download),
+    })
+}
+
+/// ローカルファイルのSHA-256（16進文字列）
+pub async fn sha256_local_file(path: &str) -> Result<String, SshError> {
+    use sha2::{Digest, Sha256};
+
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; MAX_SFTP_CHUNK_SIZE];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// ローカルディレクトリを再帰的に走査
+async fn scan_local(root: &Path) -> Result<TreeListing, SshError> {
+    let mut listing = TreeListing::default();
+    let mut stack = vec![String::new()];
+
+    while let Some(relative) = stack.pop() {
+        let dir = if relative.is_empty() {
+            root.to_path_buf()
+        } else {
+            root.join(&relative)
+        };
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let path = join_relative(&relative, &name);
+            let metadata = entry.metadata().await?;
+
+            if metadata.is_dir() {
+                listing.dirs.insert(path.clone());
+                stack.push(path);
+            } else if metadata.is_file() {
+                let mtime = metadata
+                    .modified()
+                    .ok()
+                    .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs());
+                listing.files.insert(
+                    path,
+                    FileState {
+                        size: metadata.len(),
+                        mtime,
+                    },
+                );
+            }
+        }
+    }
+
+    Ok(listing)
+}
+
+/// リモートディレクトリを再帰的に走査
+async fn scan_remote(sftp: &SftpSession, root: &str) -> Result<TreeListing, SshError> {
+    let mut listing = TreeListing::default();
+    let mut stack = vec![String::new()];
+
+    while let Some(relative) = stack.pop() {
+        let dir = if relative.is_empty() {
+            root.to_string()
+        } else {
+            format!("{}/{}", root, relative)
+        };
+
+        for entry in sftp.read_dir(dir).await.map_err(sftp_error)? {
+            let name = entry.file_name();
+            if name == "." || name == ".." {
+                continue;
+            }
+            let path = join_relative(&relative, &name);
+            let file_type = entry.file_type();
+            let metadata = entry.metadata();
+
+            if file_type.is_dir() {
+                listing.dirs.insert(path.clone());
+                stack.push(path);
+            } else if file_type.is_file() {
+                listing.files.insert(
+                    path,
+                    FileState {
+                        size: metadata.size.unwrap_or(0),
+                        mtime: metadata.mtime.map(u64::from),
+                    },
+                );
+            }
+        }
+    }
+
+    Ok(listing)
+}
+
+fn join_relative(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", parent, name)
+    }
+}
+
+/// ここまでの平均転送速度から残り時間を見積もる（速度が0の場合は `None`）
+fn estimate_remaining_ms(transferred: u64, total: u64, elapsed_ms: u64) -> Option<u64> {
+    if transferred == 0 || elapsed_ms == 0 {
+        return None;
+    }
+    let remaining = total.saturating_sub(transferred) as u128;
+    Some((remaining * elapsed_ms as u128 / transferred as u128) as u64)
+}
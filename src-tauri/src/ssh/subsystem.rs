@@ -0,0 +1,140 @@
+use crate::ssh::{SshConnection, SshError};
+use russh::client::Msg;
+use russh::{Channel, ChannelMsg};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex, RwLock};
+use uuid::Uuid;
+
+/// サブシステムチャネル（NETCONF など）を管理する
+pub struct SubsystemManager {
+    channels: Arc<RwLock<HashMap<String, SubsystemChannel>>>,
+}
+
+/// 個別のサブシステムチャネル
+struct SubsystemChannel {
+    input_sender: mpsc::UnboundedSender<SubsystemCommand>,
+    output_receiver: Arc<Mutex<mpsc::UnboundedReceiver<Vec<u8>>>>,
+}
+
+/// サブシステムのI/Oタスクへの指示
+enum SubsystemCommand {
+    Write(Vec<u8>),
+    Close,
+}
+
+impl SubsystemManager {
+    pub fn new() -> Self {
+        Self {
+            channels: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// サブシステムを要求したチャネルを開き、チャネルIDを返す
+    pub async fn open(&self, connection: &SshConnection, name: &str) -> Result<String, SshError> {
+        let channel = connection
+            .channel_open_session()
+            .await
+            .map_err(|e| SshError::CommandFailed(e.to_string()))?;
+        channel
+            .request_subsystem(true, name)
+            .await
+            .map_err(|e| SshError::CommandFailed(e.to_string()))?;
+
+        let channel_id = Uuid::new_v4().to_string();
+        let (input_sender, input_receiver) = mpsc::unbounded_channel();
+        let (output_sender, output_receiver) = mpsc::unbounded_channel();
+
+        self.channels.write().await.insert(
+            channel_id.clone(),
+            SubsystemChannel {
+                input_sender,
+                output_receiver: Arc::new(Mutex::new(output_receiver)),
+            },
+        );
+
+        tokio::spawn(run_subsystem_io(channel, input_receiver, output_sender));
+
+        Ok(channel_id)
+    }
+
+    /// チャネルへバイト列を書き込む
+    pub async fn write(&self, channel_id: &str, data: Vec<u8>) -> Result<(), SshError> {
+        let channels = self.channels.read().await;
+        channels
+            .get(channel_id)
+            .ok_or_else(|| SshError::SessionNotFound(channel_id.to_string()))?
+            .input_sender
+            .send(SubsystemCommand::Write(data))
+            .map_err(|_| SshError::CommandFailed("subsystem channel closed".to_string()))
+    }
+
+    /// チャネルから次のデータを受信（終端に達したらNoneを返し、登録を解除する）
+    pub async fn read(&self, channel_id: &str) -> Result<Option<Vec<u8>>, SshError> {
+        let receiver = {
+            let channels = self.channels.read().await;
+            channels
+                .get(channel_id)
+                .ok_or_else(|| SshError::SessionNotFound(channel_id.to_string()))?
+                .output_receiver
+                .clone()
+        };
+
+        let data = receiver.lock().await.recv().await;
+        if data.is_none() {
+            self.channels.write().await.remove(channel_id);
+        }
+
+        Ok(data)
+    }
+
+    /// チャネルを閉じ、登録を解除する
+    pub async fn close(&self, channel_id: &str) -> Result<(), SshError> {
+        let channel = self
+            .channels
+            .write()
+            .await
+            .remove(channel_id)
+            .ok_or_else(|| SshError::SessionNotFound(channel_id.to_string()))?;
+
+        let _ = channel.input_sender.send(SubsystemCommand::Close);
+
+        Ok(())
+    }
+}
+
+impl Default for SubsystemManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// サブシステムのチャネルを駆動するタスク
+async fn run_subsystem_io(
+    mut channel: Channel<Msg>,
+    mut commands: mpsc::UnboundedReceiver<SubsystemCommand>,
+    output: mpsc::UnboundedSender<Vec<u8>>,
+) {
+    loop {
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(SubsystemCommand::Write(bytes)) => {
+                    if channel.data(&bytes[..]).await.is_err() {
+                        break;
+                    }
+                }
+                Some(SubsystemCommand::Close) | None => {
+                    let _ = channel.close().await;
+                    break;
+                }
+            },
+            msg = channel.wait() => match msg {
+                Some(ChannelMsg::Data { data }) => {
+                    let _ = output.send(data.to_vec());
+                }
+                Some(ChannelMsg::Close) | None => break,
+                Some(_) => {}
+            },
+        }
+    }
+}
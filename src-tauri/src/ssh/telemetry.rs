@@ -0,0 +1,110 @@
+use crate::ssh::handshake::HandshakeCapture;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// 通信量カウンター
+#[derive(Debug, Default)]
+pub struct TrafficCounters {
+    /// トランスポート上で送信したバイト数（暗号化・圧縮後）
+    pub wire_sent: AtomicU64,
+    /// トランスポート上で受信したバイト数（暗号化・圧縮後）
+    pub wire_received: AtomicU64,
+    /// チャネルで受信したペイロードのバイト数（復号・展開後）
+    pub payload_received: AtomicU64,
+}
+
+impl TrafficCounters {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    pub fn add_payload_received(&self, bytes: usize) {
+        self.payload_received.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn wire_sent(&self) -> u64 {
+        self.wire_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn wire_received(&self) -> u64 {
+        self.wire_received.load(Ordering::Relaxed)
+    }
+
+    pub fn payload_received(&self) -> u64 {
+        self.payload_received.load(Ordering::Relaxed)
+    }
+}
+
+/// 読み書きしたバイト数を数えるストリームラッパー
+pub struct CountingStream<S> {
+    inner: S,
+    counters: Arc<TrafficCounters>,
+    handshake: Option<Arc<HandshakeCapture>>,
+}
+
+impl<S> CountingStream<S> {
+    pub fn new(inner: S, counters: Arc<TrafficCounters>) -> Self {
+        Self {
+            inner,
+            counters,
+            handshake: None,
+        }
+    }
+
+    /// 受信した平文ハンドシェイクを記録する
+    pub fn with_handshake_capture(mut self, capture: Arc<HandshakeCapture>) -> Self {
+        self.handshake = Some(capture);
+        self
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CountingStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            let read = buf.filled().len() - before;
+            self.counters
+                .wire_received
+                .fetch_add(read as u64, Ordering::Relaxed);
+            if let Some(capture) = &self.handshake {
+                if !capture.is_complete() {
+                    capture.feed(&buf.filled()[before..]);
+                }
+            }
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CountingStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            self.counters
+                .wire_sent
+                .fetch_add(written as u64, Ordering::Relaxed);
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
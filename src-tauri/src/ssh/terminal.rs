@@ -1,104 +1,398 @@
-use crate::ssh::{SshError, TerminalSession, TerminalData};
-use russh::client::Handle;
-use std::collections::HashMap;
+use crate::ssh::{EventBus, PasteOptions, Scrollback, DEFAULT_SCROLLBACK_BYTES, DEFAULT_SCROLLBACK_LINES, TerminalForwarding, TerminalOutputFilter, SshConnection, SshError, SshEvent, TerminalExitReason, TerminalLog, TerminalSession, TerminalData};
+use crate::ssh::audit::{CommandAuditor, NoopAuditor};
+use crate::ssh::output::{CwdTracker, EscapeFilter, Utf8Decoder};
+use crate::ssh::x11::{X11Display, X11Slot};
+use russh::client::Msg;
+use russh::{Channel, ChannelMsg, Pty};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::Path;
 use std::sync::Arc;
-use tokio::sync::{Mutex, RwLock, mpsc};
+use std::time::Duration;
+use tokio::sync::{Mutex, RwLock, mpsc, oneshot};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
+/// PTYのデフォルトサイズ
+const DEFAULT_TERMINAL_WIDTH: u32 = 80;
+const DEFAULT_TERMINAL_HEIGHT: u32 = 24;
+
+/// リサイズ指示をまとめる待ち時間（この間に次の指示が来なければ送る）
+const RESIZE_DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// リサイズ指示が続いても、最初の指示からこの時間が経ったら送る
+const RESIZE_MAX_DELAY: Duration = Duration::from_millis(200);
+
+/// `logout_terminal` でシェルの終了を待つ既定の時間
+pub const DEFAULT_LOGOUT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 1行送信時の既定の改行（Enterキーと同じCR）
+pub const DEFAULT_LINE_TERMINATOR: &str = "\r";
+
+/// PTY要求で指定する既定のターミナルモード（入出力の通信速度のみ）
+const DEFAULT_TERMINAL_MODES: &[(Pty, u32)] = &[(Pty::TTY_OP_ISPEED, 38400), (Pty::TTY_OP_OSPEED, 38400)];
+
+/// `nohup_on_detach` 指定時にシェルの代わりに実行するコマンド
+///
+/// 無視したSIGHUPは exec 後のシェルとそこから起動したジョブに引き継がれる。
+/// ログインシェルがPOSIX互換であることを前提とする。
+const NOHUP_SHELL_COMMAND: &str = "trap '' HUP; exec \"${SHELL:-/bin/sh}\" -l";
+
 /// PTYターミナルセッションを管理する
 pub struct TerminalManager {
     sessions: Arc<RwLock<HashMap<String, Arc<Mutex<TerminalSessionData>>>>>,
+    /// SSHセッションIDからターミナルIDへの逆引き
+    by_ssh_session: Arc<RwLock<HashMap<String, Vec<String>>>>,
+    /// SSHセッションごとに設定された環境変数（次に作成するターミナルのPTY要求前に送る）
+    env_overrides: Arc<RwLock<HashMap<String, BTreeMap<String, String>>>>,
+    /// ターミナルへの入力の記録先
+    auditor: Arc<dyn CommandAuditor>,
+    events: EventBus,
 }
 
 /// 個別のターミナルセッションデータ
 pub struct TerminalSessionData {
     pub info: TerminalSession,
-    pub connection: Option<Handle<crate::ssh::SshClientHandler>>,
-    #[allow(dead_code)]
-    pub input_sender: Option<mpsc::UnboundedSender<String>>,
+    pub input_sender: Option<mpsc::UnboundedSender<TerminalCommand>>,
     pub output_receiver: Option<Arc<Mutex<mpsc::UnboundedReceiver<TerminalData>>>>,
+    /// 受信した出力の直近部分（ターミナル終了後も削除まで保持する）
+    pub scrollback: Arc<std::sync::Mutex<Scrollback>>,
+    /// 出力を書き出しているログファイル（ターミナル終了時に閉じる）
+    pub log: Arc<std::sync::Mutex<Option<TerminalLog>>>,
+    /// `close_terminal_session` で閉じられた（受信待ちを終わらせる）
+    pub closed: CancellationToken,
+    /// 作成時に指定したターミナルモードと転送（開き直すときにそのまま使う）
+    pub terminal_modes: Option<Vec<(u8, u32)>>,
+    pub forwarding: TerminalForwarding,
+    /// SSH接続が切れたことで終了し、まだ開き直していない
+    pub connection_lost: bool,
+}
+
+/// SSHセッションの設定と作成時の指定から決まるターミナルの動作
+#[derive(Debug, Clone, Default)]
+pub struct TerminalSettings {
+    /// 入出力がこの時間途絶えたら閉じる
+    pub idle_close: Option<Duration>,
+    /// 出力から取り除く制御シーケンス
+    pub output_filter: Option<TerminalOutputFilter>,
+    /// OSC 52 によるクリップボードへの書き込みをイベントで通知する
+    pub allow_clipboard_write: bool,
+    /// 切断後もリモートのジョブが残るよう、SIGHUPを無視した状態でシェルを起動する
+    pub nohup_on_detach: bool,
+    /// 作成時に送る環境変数（環境変数プロファイルを展開したもの。`terminal_set_env` の値より優先）
+    pub env: BTreeMap<String, String>,
+    /// スクロールバックの上限（バイト・行、未指定時は既定値）
+    pub scrollback_bytes: Option<usize>,
+    pub scrollback_lines: Option<usize>,
+}
+
+/// ターミナルのI/Oタスクへの指示
+pub enum TerminalCommand {
+    Input(Vec<u8>),
+    Resize { width: u32, height: u32 },
+    // ブレーク信号（RFC 4335 の break 要求）は送れない。russh 0.52 のチャネルは
+    // 任意のチャネル要求を送るAPIを公開していないため、対応するまでコマンドも提供しない。
+    /// 環境変数を設定する（サーバーが受け付けたかを返す）
+    SetEnv {
+        name: String,
+        value: String,
+        reply: oneshot::Sender<bool>,
+    },
+    /// シェルに `exit` を送る（チャネルが閉じると `done` が破棄される）
+    Logout { done: oneshot::Sender<()> },
+    Close,
 }
 
 impl TerminalManager {
-    pub fn new() -> Self {
+    pub fn new(events: EventBus) -> Self {
         Self {
             sessions: Arc::new(RwLock::new(HashMap::new())),
+            by_ssh_session: Arc::new(RwLock::new(HashMap::new())),
+            env_overrides: Arc::new(RwLock::new(HashMap::new())),
+            auditor: Arc::new(NoopAuditor),
+            events,
         }
     }
 
-    /// 新しいターミナルセッションを作成（簡易版）
+    /// ターミナルへの入力の記録先を指定する
+    pub fn with_auditor(mut self, auditor: Arc<dyn CommandAuditor>) -> Self {
+        self.auditor = auditor;
+        self
+    }
+
+    /// 新しいターミナルセッションを作成（PTYを確保してシェルを起動）
+    ///
+    /// サーバー側で強制コマンドが設定されている場合はシェルの代わりにそれが起動し、
+    /// ターミナルはその入出力をそのまま扱う。
+    ///
+    /// `terminal_modes` は RFC 4254 のオペコードと値の組（例: ECHO を無効にするなら `(53, 0)`）。
+    /// 未指定の場合は既定のモードを使い、未知のオペコードは無視する。
+    ///
+    /// 転送の要求はサーバーに黙って拒否されることがあるため、応答を待って許可されたかを記録する。
+    /// X11転送は `$DISPLAY` が未設定の場合やサーバーに拒否された場合はエラーにする。
     pub async fn create_terminal_session(
         &self,
         ssh_session_id: String,
+        connection: &SshConnection,
+        settings: TerminalSettings,
+        terminal_modes: Option<Vec<(u8, u32)>>,
+        forwarding: TerminalForwarding,
+        x11_slot: &X11Slot,
     ) -> Result<String, SshError> {
+        let modes: Vec<(Pty, u32)> = match &terminal_modes {
+            Some(modes) => modes
+                .iter()
+                .filter_map(|&(opcode, value)| Pty::from_u8(opcode).map(|pty| (pty, value)))
+                .collect(),
+            None => DEFAULT_TERMINAL_MODES.to_vec(),
+        };
+
+        // 同じ接続の他のターミナルと中継先を共有する
+        let x11_display = if forwarding.x11 {
+            let existing = x11_slot.lock().ok().and_then(|slot| slot.clone());
+            let display = match existing {
+                Some(display) => display,
+                None => X11Display::from_env().await?,
+            };
+            if let Ok(mut slot) = x11_slot.lock() {
+                *slot = Some(display.clone());
+            }
+            Some(display)
+        } else {
+            None
+        };
+
+        let mut channel = connection
+            .channel_open_session()
+            .await
+            .map_err(|e| SshError::CommandFailed(e.to_string()))?;
+
+        // 多くのサーバーは env 要求をPTY要求・シェル起動前にしか受け付けない
+        let mut env = self
+            .env_overrides
+            .read()
+            .await
+            .get(&ssh_session_id)
+            .cloned()
+            .unwrap_or_default();
+        env.extend(settings.env.clone());
+        for (name, value) in &env {
+            channel
+                .set_env(false, name.as_str(), value.as_str())
+                .await
+                .map_err(|e| SshError::CommandFailed(e.to_string()))?;
+        }
+
+        let mut agent_forwarded = false;
+        if forwarding.agent {
+            channel
+                .agent_forward(true)
+                .await
+                .map_err(|e| SshError::CommandFailed(e.to_string()))?;
+            agent_forwarded = wait_for_request_reply(&mut channel).await?;
+        }
+        let mut x11_forwarded = false;
+        if let Some(display) = &x11_display {
+            channel
+                .request_x11(true, false, "MIT-MAGIC-COOKIE-1", display.fake_cookie_hex(), 0)
+                .await
+                .map_err(|e| SshError::CommandFailed(e.to_string()))?;
+            if !wait_for_request_reply(&mut channel).await? {
+                return Err(SshError::CommandFailed(
+                    "X11 forwarding was denied by the server".to_string(),
+                ));
+            }
+            x11_forwarded = true;
+        }
+
+        channel
+            .request_pty(
+                true,
+                "xterm-256color",
+                DEFAULT_TERMINAL_WIDTH,
+                DEFAULT_TERMINAL_HEIGHT,
+                0,
+                0,
+                &modes,
+            )
+            .await
+            .map_err(|e| SshError::CommandFailed(e.to_string()))?;
+        // SIGHUPの無視は起動時にしか設定できないため、作成後に切り替えることはできない。
+        // シェル自体はPTYの切断で終了し、切断後の出力は失われる。SIGHUPを自前で扱う
+        // プログラム（エディタ等）は終了するため、再接続が必要なら tmux/screen を使う。
+        let launched = if settings.nohup_on_detach {
+            channel.exec(true, NOHUP_SHELL_COMMAND).await
+        } else {
+            channel.request_shell(true).await
+        };
+        launched.map_err(|e| SshError::CommandFailed(e.to_string()))?;
+
         let terminal_id = Uuid::new_v4().to_string();
-        
+
         // 入力/出力チャネルを設定
-        let (input_sender, _input_receiver) = mpsc::unbounded_channel::<String>();
+        let (input_sender, input_receiver) = mpsc::unbounded_channel::<TerminalCommand>();
         let (output_sender, output_receiver) = mpsc::unbounded_channel::<TerminalData>();
 
+        self.by_ssh_session
+            .write()
+            .await
+            .entry(ssh_session_id.clone())
+            .or_default()
+            .push(terminal_id.clone());
+
         // ターミナルセッション情報を作成
         let session_info = TerminalSession {
             id: terminal_id.clone(),
             ssh_session_id,
             created_at: chrono::Utc::now(),
             is_active: true,
+            agent_forwarded,
+            x11_forwarded,
+            nohup_on_detach: settings.nohup_on_detach,
+            env: env.into_iter().collect(),
+            cwd: None,
+            log_path: None,
         };
 
         // セッションデータを作成
-        let session_data = TerminalSessionData {
+        let session_data = Arc::new(Mutex::new(TerminalSessionData {
             info: session_info,
-            connection: None, // 疑似ターミナルなので接続は不要
             input_sender: Some(input_sender),
             output_receiver: Some(Arc::new(Mutex::new(output_receiver))),
-        };
+            scrollback: Arc::new(std::sync::Mutex::new(Scrollback::new(
+                settings.scrollback_bytes.unwrap_or(DEFAULT_SCROLLBACK_BYTES),
+                settings.scrollback_lines.unwrap_or(DEFAULT_SCROLLBACK_LINES),
+            ))),
+            log: Arc::new(std::sync::Mutex::new(None)),
+            closed: CancellationToken::new(),
+            terminal_modes,
+            forwarding,
+            connection_lost: false,
+        }));
 
         // セッションを保存
         let mut sessions = self.sessions.write().await;
-        sessions.insert(terminal_id.clone(), Arc::new(Mutex::new(session_data)));
+        sessions.insert(terminal_id.clone(), session_data.clone());
+        drop(sessions);
 
-        // 初期データを送信（接続成功のメッセージ）
-        let initial_data = TerminalData {
-            session_id: terminal_id.clone(),
-            data: format!("Terminal session {} created successfully.\r\n$ ", terminal_id),
-            timestamp: chrono::Utc::now(),
-        };
-
-        if let Err(_) = output_sender.send(initial_data) {
-            return Err(SshError::CommandFailed("Failed to send initial terminal data".to_string()));
-        }
+        tokio::spawn(run_terminal_io(
+            terminal_id.clone(),
+            channel,
+            input_receiver,
+            output_sender,
+            session_data,
+            settings,
+            self.events.clone(),
+        ));
 
         Ok(terminal_id)
     }
 
-    /// ターミナルセッションに入力を送信（簡易版）
-    pub async fn send_input(&self, terminal_id: &str, _input: String) -> Result<(), SshError> {
+    /// I/Oタスクに指示を送る
+    async fn send_command(&self, terminal_id: &str, command: TerminalCommand) -> Result<(), SshError> {
         let sessions = self.sessions.read().await;
         let session_arc = sessions
             .get(terminal_id)
             .ok_or_else(|| SshError::SessionNotFound(terminal_id.to_string()))?;
 
-        let _session = session_arc.lock().await;
-        
-        // 簡易的なエコーバック（実際のSSHコマンド実行の代わり）
-        // 現在は何も実行しない（フロントエンドでエコーを処理）
+        let session = session_arc.lock().await;
+        if let TerminalCommand::Input(data) = &command {
+            self.auditor.terminal_input(terminal_id, &session.info.ssh_session_id, data);
+        }
+        session
+            .input_sender
+            .as_ref()
+            .ok_or_else(|| SshError::CommandFailed("terminal input channel closed".to_string()))?
+            .send(command)
+            .map_err(|_| SshError::CommandFailed("terminal input channel closed".to_string()))
+    }
+
+    /// ターミナルセッションに入力を送信
+    pub async fn send_input(&self, terminal_id: &str, input: String) -> Result<(), SshError> {
+        self.send_command(terminal_id, TerminalCommand::Input(input.into_bytes()))
+            .await
+    }
+
+    /// テキストに改行を付けて送信（コマンドを入力してEnterを押したのと同じ扱い）
+    pub async fn send_line(&self, terminal_id: &str, text: String, terminator: &str) -> Result<(), SshError> {
+        let mut line = text.into_bytes();
+        line.extend_from_slice(terminator.as_bytes());
+        self.send_command(terminal_id, TerminalCommand::Input(line)).await
+    }
+
+    /// クリップボードの内容をOSC 52の応答としてターミナルに送る（リモートの読み取り要求への応答）
+    pub async fn send_clipboard(&self, terminal_id: &str, selection: &str, text: &str) -> Result<(), SshError> {
+        use base64::Engine;
+
+        let encoded = base64::engine::general_purpose::STANDARD.encode(text);
+        let sequence = format!("\x1b]52;{};{}\x07", selection, encoded);
+        self.send_command(terminal_id, TerminalCommand::Input(sequence.into_bytes()))
+            .await
+    }
+
+    /// 大きなテキストを分割してターミナルに貼り付ける
+    pub async fn paste(
+        &self,
+        terminal_id: &str,
+        text: String,
+        options: PasteOptions,
+    ) -> Result<(), SshError> {
+        let text = if options.bracketed {
+            format!("\x1b[200~{}\x1b[201~", text)
+        } else {
+            text
+        };
+
+        let chunks = split_utf8_chunks(&text, options.chunk_size.max(1));
+        let delay = std::time::Duration::from_millis(options.delay_ms);
+
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            if i > 0 && !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+            self.send_input(terminal_id, chunk.to_string()).await?;
+        }
 
         Ok(())
     }
 
     /// ターミナルセッションからの出力を受信
+    ///
+    /// 出力が終端に達した（シェルの終了やチャネルの切断）場合は `TerminalClosed` を返すため、
+    /// 呼び出し側はそれ以上ポーリングしなくてよい。受信待ちの間に `close_terminal_session` で
+    /// 閉じられた場合は、すぐに `None` を返す。
     pub async fn receive_output(&self, terminal_id: &str) -> Result<Option<TerminalData>, SshError> {
-        let sessions = self.sessions.read().await;
-        let session_arc = sessions
-            .get(terminal_id)
-            .ok_or_else(|| SshError::SessionNotFound(terminal_id.to_string()))?;
+        let (session_arc, receiver, closed) = {
+            let sessions = self.sessions.read().await;
+            let session_arc = sessions
+                .get(terminal_id)
+                .ok_or_else(|| SshError::SessionNotFound(terminal_id.to_string()))?
+                .clone();
 
-        let session = session_arc.lock().await;
-        if let Some(ref receiver) = session.output_receiver {
-            let mut receiver = receiver.lock().await;
-            Ok(receiver.recv().await)
-        } else {
-            Ok(None)
+            let session = session_arc.lock().await;
+            let receiver = session
+                .output_receiver
+                .clone()
+                .ok_or_else(|| SshError::TerminalClosed(terminal_id.to_string()))?;
+            let closed = session.closed.clone();
+            drop(session);
+            (session_arc, receiver, closed)
+        };
+
+        // 受信待ちの間はセッションのロックを保持しない（入力送信を妨げないため）
+        // 閉じられた場合は、I/Oタスクが終了して送信側が破棄されるのを待たずに終える
+        let data = tokio::select! {
+            data = async { receiver.lock().await.recv().await } => data,
+            _ = closed.cancelled() => return Ok(None),
+        };
+        match data {
+            Some(data) => Ok(Some(data)),
+            None => {
+                let mut session = session_arc.lock().await;
+                session.output_receiver = None;
+                session.info.is_active = false;
+                Err(SshError::TerminalClosed(terminal_id.to_string()))
+            }
         }
     }
 
@@ -109,12 +403,107 @@ impl TerminalManager {
         if let Some(session_arc) = sessions.remove(terminal_id) {
             let mut session = session_arc.lock().await;
             session.info.is_active = false;
-            session.connection = None;
+            session.closed.cancel();
+            if let Some(sender) = session.input_sender.take() {
+                let _ = sender.send(TerminalCommand::Close);
+            }
+
+            let mut by_ssh_session = self.by_ssh_session.write().await;
+            if let Some(ids) = by_ssh_session.get_mut(&session.info.ssh_session_id) {
+                ids.retain(|id| id != terminal_id);
+                if ids.is_empty() {
+                    by_ssh_session.remove(&session.info.ssh_session_id);
+                }
+            }
         }
 
         Ok(())
     }
 
+    /// シェルに `exit` を送ってログアウトさせ、チャネルが閉じてからセッションを削除する
+    ///
+    /// `~/.bash_logout` などのログアウト処理を実行させるためのもの。`timeout` 以内に
+    /// 閉じなければ `close_terminal_session` と同様に強制的に閉じる。正常にログアウトできた場合は true。
+    pub async fn logout_terminal(&self, terminal_id: &str, timeout: Duration) -> Result<bool, SshError> {
+        let session_arc = self
+            .sessions
+            .read()
+            .await
+            .get(terminal_id)
+            .cloned()
+            .ok_or_else(|| SshError::SessionNotFound(terminal_id.to_string()))?;
+        let sender = session_arc.lock().await.input_sender.clone();
+
+        let graceful = match sender {
+            Some(sender) => {
+                let (done, closed) = oneshot::channel();
+                match sender.send(TerminalCommand::Logout { done }) {
+                    Ok(()) => tokio::time::timeout(timeout, closed).await.is_ok(),
+                    // I/Oタスクが既に終了している
+                    Err(_) => true,
+                }
+            }
+            None => true,
+        };
+
+        self.close_terminal_session(terminal_id).await?;
+        Ok(graceful)
+    }
+
+    /// SSHセッションに紐づく全ターミナルを終了し、終了したターミナルIDを返す
+    pub async fn close_all_for_session(&self, ssh_session_id: &str) -> Result<Vec<String>, SshError> {
+        let terminal_ids = self
+            .by_ssh_session
+            .write()
+            .await
+            .remove(ssh_session_id)
+            .unwrap_or_default();
+
+        for terminal_id in &terminal_ids {
+            self.close_terminal_session(terminal_id).await?;
+        }
+
+        Ok(terminal_ids)
+    }
+
+    /// SSH接続が切れたことで終了したターミナルのIDを返し、開き直す対象から外す
+    pub async fn take_lost_terminals(&self, ssh_session_id: &str) -> Vec<String> {
+        let terminal_ids = self
+            .by_ssh_session
+            .read()
+            .await
+            .get(ssh_session_id)
+            .cloned()
+            .unwrap_or_default();
+        let sessions = self.sessions.read().await;
+
+        let mut lost = Vec::new();
+        for terminal_id in terminal_ids {
+            let Some(session_arc) = sessions.get(&terminal_id) else {
+                continue;
+            };
+            let mut session = session_arc.lock().await;
+            if std::mem::take(&mut session.connection_lost) {
+                lost.push(terminal_id);
+            }
+        }
+        lost
+    }
+
+    /// ターミナル作成時に指定したターミナルモードと転送を取得
+    pub async fn creation_options(
+        &self,
+        terminal_id: &str,
+    ) -> Result<(Option<Vec<(u8, u32)>>, TerminalForwarding), SshError> {
+        let sessions = self.sessions.read().await;
+        let session_arc = sessions
+            .get(terminal_id)
+            .ok_or_else(|| SshError::SessionNotFound(terminal_id.to_string()))?;
+
+        let session = session_arc.lock().await;
+        Ok((session.terminal_modes.clone(), session.forwarding.clone()))
+    }
+
     /// ターミナルセッション情報を取得
     pub async fn get_terminal_session(&self, terminal_id: &str) -> Result<TerminalSession, SshError> {
         let sessions = self.sessions.read().await;
@@ -126,6 +515,83 @@ impl TerminalManager {
         Ok(session.info.clone())
     }
 
+    /// スクロールバックの末尾 `lines` 行を取得（省略時は保持している全体）
+    pub async fn get_scrollback(&self, terminal_id: &str, lines: Option<usize>) -> Result<String, SshError> {
+        let session_arc = self
+            .sessions
+            .read()
+            .await
+            .get(terminal_id)
+            .cloned()
+            .ok_or_else(|| SshError::SessionNotFound(terminal_id.to_string()))?;
+        let scrollback = session_arc.lock().await.scrollback.clone();
+        let scrollback = scrollback
+            .lock()
+            .map_err(|_| SshError::TerminalClosed(terminal_id.to_string()))?;
+        Ok(match lines {
+            Some(lines) => scrollback.last_lines(lines),
+            None => scrollback.contents(),
+        })
+    }
+
+    /// ターミナルの出力をローカルファイルに書き出し始める（既存のファイルには追記する）
+    ///
+    /// 既にログを書き出している場合は、そのログを閉じて新しいファイルに切り替える。
+    pub async fn start_log(&self, terminal_id: &str, path: &Path, timestamps: bool) -> Result<(), SshError> {
+        let session_arc = self
+            .sessions
+            .read()
+            .await
+            .get(terminal_id)
+            .cloned()
+            .ok_or_else(|| SshError::SessionNotFound(terminal_id.to_string()))?;
+        let mut session = session_arc.lock().await;
+        if !session.info.is_active {
+            return Err(SshError::TerminalClosed(terminal_id.to_string()));
+        }
+
+        let log = TerminalLog::create(path, timestamps)?;
+        let previous = session
+            .log
+            .lock()
+            .map_err(|_| SshError::TerminalClosed(terminal_id.to_string()))?
+            .replace(log);
+        session.info.log_path = Some(path.display().to_string());
+        drop(session);
+
+        if let Some(previous) = previous {
+            previous.finish()?;
+        }
+        Ok(())
+    }
+
+    /// ログの書き出しを止めてファイルを閉じる（書き出していなければ false）
+    pub async fn stop_log(&self, terminal_id: &str) -> Result<bool, SshError> {
+        let session_arc = self
+            .sessions
+            .read()
+            .await
+            .get(terminal_id)
+            .cloned()
+            .ok_or_else(|| SshError::SessionNotFound(terminal_id.to_string()))?;
+        let mut session = session_arc.lock().await;
+        let log = session
+            .log
+            .lock()
+            .map_err(|_| SshError::TerminalClosed(terminal_id.to_string()))?
+            .take();
+        session.info.log_path = None;
+        drop(session);
+
+        match log {
+            Some(log) => {
+                log.finish()?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
     /// 全ターミナルセッション一覧を取得
     pub async fn list_terminal_sessions(&self) -> Vec<TerminalSession> {
         let sessions = self.sessions.read().await;
@@ -143,21 +609,398 @@ impl TerminalManager {
     pub async fn resize_terminal(
         &self,
         terminal_id: &str,
-        _width: u32,
-        _height: u32,
+        width: u32,
+        height: u32,
     ) -> Result<(), SshError> {
-        let sessions = self.sessions.read().await;
-        let _session_arc = sessions
+        self.send_command(terminal_id, TerminalCommand::Resize { width, height })
+            .await
+    }
+
+    /// 起動済みのターミナルに環境変数を設定する（サーバーが受け付けたら `true`）
+    ///
+    /// 多くのサーバーはシェル起動後の env 要求を拒否するため、値は同じSSHセッションで
+    /// 次に作成するターミナルにも適用する。
+    pub async fn set_env(&self, terminal_id: &str, name: String, value: String) -> Result<bool, SshError> {
+        if name.is_empty() || name.contains('=') || name.contains('\0') {
+            return Err(SshError::ConfigError(format!("invalid environment variable name: {:?}", name)));
+        }
+
+        let session_arc = self
+            .sessions
+            .read()
+            .await
             .get(terminal_id)
+            .cloned()
             .ok_or_else(|| SshError::SessionNotFound(terminal_id.to_string()))?;
+        let ssh_session_id = {
+            let mut session = session_arc.lock().await;
+            session.info.env.insert(name.clone(), value.clone());
+            session.info.ssh_session_id.clone()
+        };
+        self.env_overrides
+            .write()
+            .await
+            .entry(ssh_session_id)
+            .or_default()
+            .insert(name.clone(), value.clone());
 
-        // 今回は簡易的に成功を返す
-        Ok(())
+        let (reply, accepted) = oneshot::channel();
+        self.send_command(terminal_id, TerminalCommand::SetEnv { name, value, reply })
+            .await?;
+        Ok(accepted.await.unwrap_or(false))
+    }
+
+    /// SSHセッションに設定された環境変数を破棄する
+    pub async fn clear_env_for_session(&self, ssh_session_id: &str) {
+        self.env_overrides.write().await.remove(ssh_session_id);
     }
 }
 
 impl Default for TerminalManager {
     fn default() -> Self {
-        Self::new()
+        Self::new(EventBus::new())
+    }
+}
+
+/// 送信を待っているリサイズ指示（最後のサイズのみ送る）
+#[derive(Clone, Copy)]
+struct PendingResize {
+    width: u32,
+    height: u32,
+    first_at: Instant,
+    deadline: Instant,
+}
+
+/// ターミナルのチャネルを駆動するタスク
+///
+/// 入力・リサイズ指示をチャネルへ書き込み、チャネルからの出力を受信キューへ流す。
+/// ウィンドウのドラッグなどで続けて届くリサイズ指示はまとめ、最後のサイズだけを送る。
+/// 出力フィルターが指定されている場合は、該当する制御シーケンスを取り除いてから流す。
+/// クリップボードへの書き込みが許可されている場合は、OSC 52 を取り出してイベントで通知する。
+/// シェルが OSC 7 で通知する作業ディレクトリはセッション情報に記録する。
+/// ログを書き出している場合は、受信したバイト列をフィルター前のまま追記し、終了時に閉じる。
+/// アイドル時間が設定されている場合、入出力が途絶えたらEOFを送って終了する。
+async fn run_terminal_io(
+    terminal_id: String,
+    mut channel: Channel<Msg>,
+    mut commands: mpsc::UnboundedReceiver<TerminalCommand>,
+    output: mpsc::UnboundedSender<TerminalData>,
+    session: Arc<Mutex<TerminalSessionData>>,
+    settings: TerminalSettings,
+    events: EventBus,
+) {
+    let TerminalSettings {
+        idle_close,
+        output_filter,
+        allow_clipboard_write,
+        ..
+    } = settings;
+    let mut last_activity = Instant::now();
+    let mut exit_status = None;
+    let mut decoder = Utf8Decoder::new();
+    let mut cwd_tracker = CwdTracker::new();
+    let mut filter = if allow_clipboard_write {
+        Some(EscapeFilter::new(output_filter.unwrap_or_default()).with_clipboard_capture())
+    } else {
+        output_filter.map(EscapeFilter::new)
+    };
+    let (ssh_session_id, scrollback, log) = {
+        let session = session.lock().await;
+        (session.info.ssh_session_id.clone(), session.scrollback.clone(), session.log.clone())
+    };
+    // 応答待ちの env 要求（応答は要求順に届き、先にPTY要求とシェル起動の応答が届く）
+    let mut startup_replies = 2;
+    let mut env_replies: VecDeque<oneshot::Sender<bool>> = VecDeque::new();
+    let mut pending_resize: Option<PendingResize> = None;
+    let mut logout_waiters: Vec<oneshot::Sender<()>> = Vec::new();
+
+    let reason = loop {
+        let idle_deadline = idle_close.map(|d| last_activity + d);
+        let log_deadline = log
+            .lock()
+            .ok()
+            .and_then(|log| log.as_ref()?.flush_deadline())
+            .map(Instant::from_std);
+
+        tokio::select! {
+            _ = idle_timer(idle_deadline) => {
+                let _ = channel.eof().await;
+                let _ = channel.close().await;
+                break TerminalExitReason::IdleTimeout;
+            }
+            _ = idle_timer(log_deadline) => {
+                write_log(&terminal_id, &ssh_session_id, &session, &log, &events, TerminalLog::flush).await;
+            }
+            _ = idle_timer(pending_resize.map(|pending| pending.deadline)) => {
+                if let Some(resize) = pending_resize.take() {
+                    let _ = channel.window_change(resize.width, resize.height, 0, 0).await;
+                }
+            }
+            command = commands.recv() => match command {
+                Some(TerminalCommand::Input(bytes)) => {
+                    last_activity = Instant::now();
+                    // 入力より前のリサイズは先に反映する
+                    if let Some(resize) = pending_resize.take() {
+                        let _ = channel.window_change(resize.width, resize.height, 0, 0).await;
+                    }
+                    if let Err(e) = channel.data(&bytes[..]).await {
+                        break TerminalExitReason::ChannelError(e.to_string());
+                    }
+                }
+                Some(TerminalCommand::Resize { width, height }) => {
+                    let now = Instant::now();
+                    let first_at = pending_resize.map_or(now, |pending| pending.first_at);
+                    pending_resize = Some(PendingResize {
+                        width,
+                        height,
+                        first_at,
+                        deadline: (now + RESIZE_DEBOUNCE).min(first_at + RESIZE_MAX_DELAY),
+                    });
+                }
+                Some(TerminalCommand::SetEnv { name, value, reply }) => {
+                    match channel.set_env(true, name, value).await {
+                        Ok(()) => env_replies.push_back(reply),
+                        Err(_) => {
+                            let _ = reply.send(false);
+                        }
+                    }
+                }
+                Some(TerminalCommand::Logout { done }) => {
+                    last_activity = Instant::now();
+                    let line = format!("exit{}", DEFAULT_LINE_TERMINATOR);
+                    if let Err(e) = channel.data(line.as_bytes()).await {
+                        break TerminalExitReason::ChannelError(e.to_string());
+                    }
+                    logout_waiters.push(done);
+                }
+                Some(TerminalCommand::Close) | None => {
+                    let _ = channel.close().await;
+                    break TerminalExitReason::Closed;
+                }
+            },
+            msg = channel.wait() => match msg {
+                Some(ChannelMsg::Data { data }) | Some(ChannelMsg::ExtendedData { data, .. }) => {
+                    last_activity = Instant::now();
+                    // フィルターで OSC が取り除かれる前に作業ディレクトリを追跡する
+                    if let Some(cwd) = cwd_tracker.feed(&data) {
+                        session.lock().await.info.cwd = Some(cwd);
+                    }
+                    write_log(&terminal_id, &ssh_session_id, &session, &log, &events, |log| log.write(&data)).await;
+                    let text = match filter.as_mut() {
+                        Some(filter) => decoder.decode(&filter.filter(&data)),
+                        None => decoder.decode(&data),
+                    };
+                    for write in filter.as_mut().map(EscapeFilter::take_clipboard).unwrap_or_default() {
+                        events.emit(SshEvent::TerminalClipboard {
+                            terminal_id: terminal_id.clone(),
+                            ssh_session_id: ssh_session_id.clone(),
+                            selection: write.selection,
+                            text: write.text,
+                        });
+                    }
+                    if !text.is_empty() {
+                        if let Ok(mut scrollback) = scrollback.lock() {
+                            scrollback.push(&text);
+                        }
+                        let _ = output.send(TerminalData {
+                            session_id: terminal_id.clone(),
+                            data: text,
+                            timestamp: chrono::Utc::now(),
+                        });
+                    }
+                }
+                Some(ChannelMsg::ExitStatus { exit_status: status }) => {
+                    exit_status = Some(status);
+                }
+                msg @ (Some(ChannelMsg::Close) | None) => {
+                    let mut rest = filter
+                        .as_mut()
+                        .map(|filter| decoder.decode(&filter.finish()))
+                        .unwrap_or_default();
+                    rest.push_str(&decoder.finish());
+                    if !rest.is_empty() {
+                        if let Ok(mut scrollback) = scrollback.lock() {
+                            scrollback.push(&rest);
+                        }
+                        let _ = output.send(TerminalData {
+                            session_id: terminal_id.clone(),
+                            data: rest,
+                            timestamp: chrono::Utc::now(),
+                        });
+                    }
+                    // 接続が切れるとチャネルは閉じる通知なしに終わる
+                    break match msg {
+                        Some(_) => TerminalExitReason::ShellExited(exit_status),
+                        None => TerminalExitReason::ConnectionLost,
+                    };
+                }
+                Some(ChannelMsg::Success) if startup_replies > 0 => startup_replies -= 1,
+                Some(ChannelMsg::Failure) if startup_replies > 0 => {
+                    // no-pty 指定などでPTY要求が拒否されても、強制コマンドの出力は中継し続ける
+                    startup_replies -= 1;
+                }
+                Some(ChannelMsg::Success) => {
+                    if let Some(reply) = env_replies.pop_front() {
+                        let _ = reply.send(true);
+                    }
+                }
+                Some(ChannelMsg::Failure) => {
+                    if let Some(reply) = env_replies.pop_front() {
+                        let _ = reply.send(false);
+                    }
+                }
+                Some(_) => {}
+            },
+        }
+    };
+
+    let log = {
+        let mut session = session.lock().await;
+        session.info.is_active = false;
+        session.input_sender = None;
+        session.info.log_path = None;
+        session.connection_lost = matches!(reason, TerminalExitReason::ConnectionLost);
+        log.lock().ok().and_then(|mut log| log.take())
+    };
+    drop(logout_waiters);
+    if let Some(log) = log {
+        let path = log.path().display().to_string();
+        if let Err(e) = log.finish() {
+            events.emit(SshEvent::TerminalLogFailed {
+                terminal_id: terminal_id.clone(),
+                ssh_session_id: ssh_session_id.clone(),
+                path,
+                error: e.to_string(),
+            });
+        }
+    }
+
+    events.emit(SshEvent::TerminalExit {
+        terminal_id,
+        ssh_session_id,
+        reason,
+    });
+}
+
+/// ログに書き込み、失敗した場合はログを止めてイベントで通知する
+async fn write_log(
+    terminal_id: &str,
+    ssh_session_id: &str,
+    session: &Mutex<TerminalSessionData>,
+    log: &std::sync::Mutex<Option<TerminalLog>>,
+    events: &EventBus,
+    write: impl FnOnce(&mut TerminalLog) -> std::io::Result<()>,
+) {
+    let (path, error) = {
+        let Ok(mut guard) = log.lock() else {
+            return;
+        };
+        let Some(current) = guard.as_mut() else {
+            return;
+        };
+        let Err(e) = write(current) else {
+            return;
+        };
+        let path = current.path().display().to_string();
+        guard.take();
+        (path, e.to_string())
+    };
+
+    session.lock().await.info.log_path = None;
+    events.emit(SshEvent::TerminalLogFailed {
+        terminal_id: terminal_id.to_string(),
+        ssh_session_id: ssh_session_id.to_string(),
+        path,
+        error,
+    });
+}
+
+/// `want_reply` 付きのチャネル要求への応答を待つ（許可されたら `true`）
+async fn wait_for_request_reply(channel: &mut Channel<Msg>) -> Result<bool, SshError> {
+    loop {
+        match channel.wait().await {
+            Some(ChannelMsg::Success) => return Ok(true),
+            Some(ChannelMsg::Failure) => return Ok(false),
+            Some(_) => {}
+            None => return Err(SshError::CommandFailed("channel closed".to_string())),
+        }
+    }
+}
+
+/// アイドル期限まで待機する（期限がなければ完了しない）
+async fn idle_timer(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// 文字境界を保ったまま最大 `max_bytes` バイトずつに分割する
+fn split_utf8_chunks(text: &str, max_bytes: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = text;
+
+    while !rest.is_empty() {
+        let mut end = max_bytes.min(rest.len());
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        // 1文字が max_bytes を超える場合はその文字をまるごと送る
+        if end == 0 {
+            end = rest.chars().next().map(char::len_utf8).unwrap_or(rest.len());
+        }
+        let (chunk, tail) = rest.split_at(end);
+        chunks.push(chunk);
+        rest = tail;
+    }
+
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh::test_server::{TestServer, TEST_PASSWORD};
+    use crate::ssh::{AuthMethod, SshSessionManager};
+
+    #[tokio::test]
+    async fn closing_terminal_ends_pending_receive() {
+        let server = TestServer::start().await;
+        let sessions = SshSessionManager::new(EventBus::new());
+        let session_id = sessions
+            .create_session(server.config(AuthMethod::Password(TEST_PASSWORD.to_string())))
+            .await
+            .unwrap();
+        sessions.connect(&session_id).await.unwrap();
+        let connection = sessions.get_connection(&session_id).await.unwrap();
+
+        let terminals = Arc::new(TerminalManager::new(EventBus::new()));
+        let terminal_id = terminals
+            .create_terminal_session(
+                session_id,
+                &connection,
+                TerminalSettings::default(),
+                None,
+                TerminalForwarding::default(),
+                &X11Slot::default(),
+            )
+            .await
+            .unwrap();
+
+        // 出力のないシェルで受信待ちにしておく
+        let receive = tokio::spawn({
+            let terminals = terminals.clone();
+            let terminal_id = terminal_id.clone();
+            async move { terminals.receive_output(&terminal_id).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!receive.is_finished());
+
+        terminals.close_terminal_session(&terminal_id).await.unwrap();
+        let received = tokio::time::timeout(Duration::from_secs(1), receive)
+            .await
+            .expect("receive did not return after close")
+            .unwrap();
+        assert!(matches!(received, Ok(None)));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,80 @@
+use crate::ssh::SshError;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// ログファイルへ書き出すまでの最大の遅れ（この間はバッファに溜める）
+pub const LOG_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// ターミナルの出力をそのままローカルファイルに書き出すログ
+///
+/// 既存のファイルには追記する。`timestamps` 指定時は各行の先頭に受信時刻（UTC）を付ける。
+pub struct TerminalLog {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    timestamps: bool,
+    /// 次に書く位置が行の先頭か
+    at_line_start: bool,
+    /// ファイルに書き出していない出力を最初に受け取った時刻
+    dirty_since: Option<Instant>,
+}
+
+impl TerminalLog {
+    pub fn create(path: &Path, timestamps: bool) -> Result<Self, SshError> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+            path: path.to_path_buf(),
+            timestamps,
+            at_line_start: true,
+            dirty_since: None,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 受信した出力を追記する（溜めた出力が一定時間を超えていればファイルに書き出す）
+    pub fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        if self.timestamps {
+            for line in data.split_inclusive(|&b| b == b'\n') {
+                if self.at_line_start {
+                    let now = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+                    write!(self.writer, "[{}] ", now)?;
+                }
+                self.writer.write_all(line)?;
+                self.at_line_start = line.ends_with(b"\n");
+            }
+        } else {
+            self.writer.write_all(data)?;
+        }
+
+        let dirty_since = *self.dirty_since.get_or_insert_with(Instant::now);
+        if dirty_since.elapsed() >= LOG_FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// 溜めた出力を書き出すべき時刻（書き出していない出力がなければ `None`）
+    pub fn flush_deadline(&self) -> Option<Instant> {
+        self.dirty_since.map(|since| since + LOG_FLUSH_INTERVAL)
+    }
+
+    /// 溜めた出力をファイルに書き出す
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.dirty_since = None;
+        self.writer.flush()
+    }
+
+    /// 残りを書き出して閉じる
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()
+    }
+}
@@ -0,0 +1,285 @@
+use crate::ssh::{EventBus, SshEvent, TransferAggregate, TransferInfo, TransferKind, TransferState};
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// 完了した転送を一覧に残しておく既定の時間
+const DEFAULT_COMPLETED_RETENTION: Duration = Duration::from_secs(60);
+
+/// 実行中・完了直後のSFTP転送を追跡し、全体の進捗を集計する
+///
+/// 転送処理の内側から同期的に更新できるよう、ロックは短時間しか保持しない。
+/// 終了した転送は保持期間が過ぎると一覧から取り除き、`TransferRemoved` を通知する。
+#[derive(Clone, Default)]
+pub struct TransferManager {
+    transfers: Arc<Mutex<HashMap<String, TransferEntry>>>,
+    /// SSHセッションごとの保持期間（未設定のセッションは既定値）
+    retention: Arc<Mutex<HashMap<String, Duration>>>,
+    events: EventBus,
+}
+
+/// 個別の転送の記録
+struct TransferEntry {
+    info: TransferInfo,
+    started: Instant,
+    finished: Option<Instant>,
+    retention: Duration,
+    limiter: Arc<RateLimiter>,
+}
+
+/// 転送速度の上限（トークンバケット）
+///
+/// 1秒分までの転送はまとめて通し、それを超える分は上限に収まるまで待たせる。
+/// 上限は転送中でも変更できる。
+#[derive(Default)]
+pub struct RateLimiter {
+    /// バイト/秒（0は無制限）
+    limit: AtomicU64,
+    /// 残りのトークンと最後に補充した時刻
+    bucket: Mutex<Option<(f64, Instant)>>,
+}
+
+impl RateLimiter {
+    /// 上限を設定する（`None` で制限しない）
+    pub fn set_limit(&self, max_bytes_per_sec: Option<u64>) {
+        self.limit.store(max_bytes_per_sec.unwrap_or(0), Ordering::Relaxed);
+        if let Ok(mut bucket) = self.bucket.lock() {
+            *bucket = None;
+        }
+    }
+
+    pub fn limit(&self) -> Option<u64> {
+        Some(self.limit.load(Ordering::Relaxed)).filter(|&limit| limit > 0)
+    }
+
+    /// `bytes` 分のトークンを消費し、上限を超えた分だけ待つ
+    pub async fn acquire(&self, bytes: u64) {
+        let Some(limit) = self.limit() else {
+            return;
+        };
+        let wait = {
+            let Ok(mut bucket) = self.bucket.lock() else {
+                return;
+            };
+            let rate = limit as f64;
+            let now = Instant::now();
+            let (tokens, last) = bucket.unwrap_or((rate, now));
+            let tokens = (tokens + now.duration_since(last).as_secs_f64() * rate).min(rate) - bytes as f64;
+            *bucket = Some((tokens, now));
+            if tokens < 0.0 {
+                Duration::from_secs_f64(-tokens / rate)
+            } else {
+                Duration::ZERO
+            }
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+impl TransferEntry {
+    /// 開始からの平均転送速度（バイト/秒）
+    fn bytes_per_sec(&self) -> f64 {
+        let elapsed = self
+            .finished
+            .unwrap_or_else(Instant::now)
+            .duration_since(self.started)
+            .as_secs_f64();
+        if elapsed > 0.0 {
+            self.info.bytes_transferred as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    fn snapshot(&self) -> TransferInfo {
+        TransferInfo {
+            bytes_per_sec: self.bytes_per_sec(),
+            max_bytes_per_sec: self.limiter.limit(),
+            ..self.info.clone()
+        }
+    }
+
+    /// 保持期間を過ぎた終了済みの転送か
+    fn expired(&self) -> bool {
+        self.finished
+            .is_some_and(|finished| finished.elapsed() >= self.retention)
+    }
+}
+
+impl TransferManager {
+    pub fn new(events: EventBus) -> Self {
+        Self {
+            events,
+            ..Self::default()
+        }
+    }
+
+    /// SSHセッションの終了した転送を一覧に残す時間を設定する（`None` で既定の60秒に戻す）
+    pub fn set_retention(&self, session_id: &str, retention: Option<Duration>) {
+        if let Ok(mut map) = self.retention.lock() {
+            match retention {
+                Some(retention) => map.insert(session_id.to_string(), retention),
+                None => map.remove(session_id),
+            };
+        }
+    }
+
+    /// 転送の開始を登録し、転送速度の上限を返す（初期状態は無制限）
+    pub fn start(
+        &self,
+        id: &str,
+        session_id: &str,
+        kind: TransferKind,
+        path: &str,
+        bytes_total: Option<u64>,
+    ) -> Arc<RateLimiter> {
+        let retention = self
+            .retention
+            .lock()
+            .ok()
+            .and_then(|map| map.get(session_id).copied())
+            .unwrap_or(DEFAULT_COMPLETED_RETENTION);
+        let entry = TransferEntry {
+            info: TransferInfo {
+                id: id.to_string(),
+                session_id: session_id.to_string(),
+                kind,
+                path: path.to_string(),
+                state: TransferState::Running,
+                bytes_transferred: 0,
+                bytes_total,
+                bytes_per_sec: 0.0,
+                max_bytes_per_sec: None,
+                started_at: Utc::now(),
+                finished_at: None,
+            },
+            started: Instant::now(),
+            finished: None,
+            retention,
+            limiter: Arc::new(RateLimiter::default()),
+        };
+        let limiter = entry.limiter.clone();
+        if let Ok(mut transfers) = self.transfers.lock() {
+            transfers.insert(id.to_string(), entry);
+        }
+        limiter
+    }
+
+    /// 実行中の転送の速度の上限を変更する（転送が見つからなければ `false`）
+    pub fn set_rate_limit(&self, id: &str, max_bytes_per_sec: Option<u64>) -> bool {
+        let Ok(transfers) = self.transfers.lock() else {
+            return false;
+        };
+        match transfers.get(id).filter(|entry| entry.finished.is_none()) {
+            Some(entry) => {
+                entry.limiter.set_limit(max_bytes_per_sec);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 転送済みバイト数を更新する（合計が分かった場合はそれも更新する）
+    pub fn update(&self, id: &str, bytes_transferred: u64, bytes_total: Option<u64>) {
+        if let Ok(mut transfers) = self.transfers.lock() {
+            if let Some(entry) = transfers.get_mut(id) {
+                entry.info.bytes_transferred = bytes_transferred;
+                if bytes_total.is_some() {
+                    entry.info.bytes_total = bytes_total;
+                }
+            }
+        }
+    }
+
+    /// 転送の終了を記録する（保持期間が過ぎると一覧から消える）
+    pub fn finish(&self, id: &str, state: TransferState) {
+        let retention = {
+            let Ok(mut transfers) = self.transfers.lock() else {
+                return;
+            };
+            let Some(entry) = transfers.get_mut(id) else {
+                return;
+            };
+            entry.info.state = state;
+            entry.info.finished_at = Some(Utc::now());
+            entry.finished = Some(Instant::now());
+            entry.retention
+        };
+
+        let manager = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(retention).await;
+            manager.remove_expired();
+        });
+    }
+
+    /// 終了した転送をすべて一覧から取り除き、取り除いた数を返す
+    pub fn clear_completed(&self) -> usize {
+        self.remove_where(|entry| entry.finished.is_some())
+    }
+
+    /// 保持期間を過ぎた終了済みの転送を取り除く
+    fn remove_expired(&self) -> usize {
+        self.remove_where(TransferEntry::expired)
+    }
+
+    /// 条件に一致する転送を取り除き、取り除いたものを通知する
+    fn remove_where(&self, predicate: impl Fn(&TransferEntry) -> bool) -> usize {
+        let removed: Vec<(String, String)> = {
+            let Ok(mut transfers) = self.transfers.lock() else {
+                return 0;
+            };
+            let ids: Vec<String> = transfers
+                .iter()
+                .filter(|(_, entry)| predicate(entry))
+                .map(|(id, _)| id.clone())
+                .collect();
+            ids.into_iter()
+                .filter_map(|id| transfers.remove(&id))
+                .map(|entry| (entry.info.id, entry.info.session_id))
+                .collect()
+        };
+
+        for (transfer_id, session_id) in &removed {
+            self.events.emit(SshEvent::TransferRemoved {
+                transfer_id: transfer_id.clone(),
+                session_id: session_id.clone(),
+            });
+        }
+        removed.len()
+    }
+
+    /// 実行中と完了直後の転送一覧（開始順）
+    pub fn list(&self) -> Vec<TransferInfo> {
+        self.remove_expired();
+        let Ok(transfers) = self.transfers.lock() else {
+            return Vec::new();
+        };
+
+        let mut list: Vec<TransferInfo> = transfers.values().map(TransferEntry::snapshot).collect();
+        list.sort_by_key(|info| info.started_at);
+        list
+    }
+
+    /// 実行中の転送全体の速度と残りバイト数を集計する
+    pub fn aggregate(&self) -> TransferAggregate {
+        let Ok(transfers) = self.transfers.lock() else {
+            return TransferAggregate::default();
+        };
+
+        let mut aggregate = TransferAggregate::default();
+        for entry in transfers.values().filter(|entry| entry.finished.is_none()) {
+            aggregate.active_transfers += 1;
+            aggregate.bytes_transferred += entry.info.bytes_transferred;
+            aggregate.bytes_per_sec += entry.bytes_per_sec();
+            if let Some(total) = entry.info.bytes_total {
+                aggregate.bytes_remaining += total.saturating_sub(entry.info.bytes_transferred);
+            }
+        }
+        aggregate
+    }
+}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 
 /// SSH接続設定
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -8,6 +9,210 @@ pub struct SshConfig {
     pub username: String,
     pub auth_method: AuthMethod,
     pub timeout: Option<u64>,
+    /// 認証中にユーザー入力を待つ時間（秒、未指定時は120秒）
+    pub auth_timeout_secs: Option<u64>,
+    /// 初回TCP接続に使用するプロキシ
+    pub proxy: Option<ProxyConfig>,
+    /// 入出力がこの秒数途絶えたターミナルを自動的に閉じる
+    pub terminal_idle_close_secs: Option<u64>,
+    /// ターミナルごとに保持するスクロールバックの上限（バイト、未指定時は1MB）
+    pub max_scrollback_bytes: Option<usize>,
+    /// ターミナルごとに保持するスクロールバックの上限（行、未指定時は10000行）
+    ///
+    /// バイト数と行数のどちらかの上限に達した時点で古い行から捨てる。
+    pub max_scrollback_lines: Option<usize>,
+    /// 受信ストリームとファイル転送の読み込みバッファサイズ（バイト、未指定時は32KB）
+    pub read_buffer_size: Option<usize>,
+    /// 切断時の自動再接続（未指定時は再接続しない）
+    pub reconnect: Option<ReconnectPolicy>,
+    /// ネゴシエーション結果として許可するアルゴリズム（未指定時は検査しない）
+    pub allowed_algorithms: Option<AlgorithmAllowlist>,
+    /// グループ・その他から読める秘密鍵の使用を許可する
+    #[serde(default)]
+    pub allow_insecure_key_permissions: bool,
+    /// SHA-1署名の `ssh-rsa` ホスト鍵を受け入れる（古い機器向け）
+    #[serde(default)]
+    pub allow_legacy_host_keys: bool,
+    /// 接続時にリモートのシェルの種類を調べる（失敗しても接続は続ける）
+    #[serde(default)]
+    pub detect_shell: bool,
+    /// known_hosts にも固定済みの鍵にもないホストに初めて接続した際、鍵を固定して接続を続ける
+    ///
+    /// 固定済みの鍵や known_hosts の鍵と一致しない場合は接続を拒否する。
+    #[serde(default)]
+    pub trust_on_first_use: bool,
+    /// サーバーに求めるホスト鍵の種類（例: `ssh-ed25519`、未指定時は検査しない）
+    ///
+    /// 異なる種類の鍵が提示された場合は、ダウングレードとみなして接続を拒否する。
+    pub expected_host_key_type: Option<String>,
+    /// ターミナルへ1行送信する際に付加する改行（未指定時は `\r`）
+    pub line_terminator: Option<String>,
+    /// 公開鍵がサーバーに受け入れられなかった場合に続けて試すパスワード
+    pub fallback_password: Option<String>,
+    /// 破壊的に見えるコマンドを確認なしでは実行しない（未指定時は無効）
+    pub safe_mode: Option<SafeModeConfig>,
+    /// 終了したSFTP転送を一覧に残す秒数（未指定時は60秒）
+    pub transfer_retention_secs: Option<u64>,
+    /// ホスト名の解決に使うDNSサーバー（未指定時はシステムのリゾルバー）
+    pub resolver: Option<ResolverConfig>,
+    /// 接続前に送るポートノッキングの手順（プロキシ経由の場合は送らない）
+    pub port_knock: Option<PortKnockConfig>,
+    /// ターミナル出力から取り除く制御シーケンス（未指定時はすべて通す）
+    pub terminal_filter: Option<TerminalOutputFilter>,
+    /// リモートのプログラムによるクリップボードへの書き込み（OSC 52）を通知する
+    #[serde(default)]
+    pub allow_clipboard_write: bool,
+    /// 同時に開くコマンド実行チャネルの上限（超えた分は空くまで待つ、未指定時は8）
+    pub max_concurrent_commands: Option<usize>,
+    /// このホストで使う名前付きコマンド（エクスポートにも含まれる）
+    #[serde(default)]
+    pub macros: Vec<CommandMacro>,
+}
+
+/// 名前付きのコマンド
+///
+/// `command` 中の `{name}` は実行時に渡した引数で置き換える（`${name}` はシェルの変数として残す）。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandMacro {
+    pub id: String,
+    pub name: String,
+    pub command: String,
+}
+
+/// ターミナル出力から取り除く制御シーケンスの種類
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TerminalOutputFilter {
+    /// ベル（BEL）
+    pub drop_bell: bool,
+    /// カーソル位置の保存・復元（`ESC 7` / `ESC 8` / `CSI s` / `CSI u`）
+    pub drop_cursor_save_restore: bool,
+    /// OSCシーケンス（ウィンドウタイトルの設定など）
+    pub drop_osc: bool,
+}
+
+/// 名前解決の設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResolverConfig {
+    /// 問い合わせ先のDNSサーバー（`10.0.0.53` や `[fd00::53]:5353`、ポート省略時は53）
+    ///
+    /// UDPで直接問い合わせるため、TCPへの切り替えが必要な大きな応答やDoHには対応しない。
+    pub nameserver: String,
+}
+
+/// ポートノッキングの設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortKnockConfig {
+    /// 順にノックするポートとプロトコル
+    pub sequence: Vec<(u16, KnockProtocol)>,
+    /// ノックの間隔（ミリ秒、未指定時は200ms）
+    pub delay_ms: Option<u64>,
+}
+
+/// ノックに使うパケットの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KnockProtocol {
+    /// TCPの接続要求（SYN）を送り、応答は待たない
+    Tcp,
+    /// 空のUDPデータグラムを送る
+    Udp,
+}
+
+/// セーフモードの設定
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SafeModeConfig {
+    /// 拒否するコマンドのパターン（部分一致、連続する空白は1つとみなす）
+    ///
+    /// 未指定時は `rm -rf /`・`mkfs`・`dd of=/dev/` などの既定のパターンを使う。
+    pub patterns: Option<Vec<String>>,
+}
+
+/// ネゴシエーションされたアルゴリズムの許可リスト
+///
+/// 項目ごとに指定した場合のみ検査する。AEAD暗号（GCM / ChaCha20-Poly1305）では
+/// MACは使われないため検査しない。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AlgorithmAllowlist {
+    pub kex: Option<Vec<String>>,
+    pub cipher: Option<Vec<String>>,
+    pub mac: Option<Vec<String>>,
+}
+
+/// 接続診断の段階
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum DiagnosticStage {
+    Dns,
+    TcpConnect,
+    Handshake,
+    Authentication,
+}
+
+/// 接続診断の各段階の結果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageResult {
+    pub stage: DiagnosticStage,
+    pub success: bool,
+    pub duration_ms: u64,
+    /// 解決したアドレスや接続先など、成功時の補足情報
+    pub detail: Option<String>,
+    pub error: Option<String>,
+}
+
+/// 接続診断の結果
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConnectionDiagnostics {
+    /// 実行した段階（失敗した段階で打ち切る）
+    pub stages: Vec<StageResult>,
+    pub failed_stage: Option<DiagnosticStage>,
+    /// サーバーの識別文字列（受信できた場合）
+    pub server_version: Option<String>,
+}
+
+/// 自動再接続の設定
+///
+/// 待機時間は `initial_delay_ms` から試行ごとに倍になり、`max_delay_ms` で頭打ちになる。
+/// 多数のセッションが同時に切断された場合に再接続が集中しないよう、待機時間は
+/// `jitter_ratio` の割合だけ前後にランダムにずらす。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReconnectPolicy {
+    pub max_attempts: u32,
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+    /// 待機時間をずらす割合（0.0〜1.0、0で固定）
+    pub jitter_ratio: f64,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_delay_ms: 1000,
+            max_delay_ms: 30_000,
+            jitter_ratio: 0.2,
+        }
+    }
+}
+
+/// プロキシ設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProxyConfig {
+    pub kind: ProxyKind,
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// プロキシの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProxyKind {
+    /// SOCKS5 プロキシ
+    Socks5,
+    /// HTTP CONNECT プロキシ
+    Http,
 }
 
 /// 認証方法
@@ -15,6 +220,11 @@ pub struct SshConfig {
 pub enum AuthMethod {
     /// パスワード認証
     Password(String),
+    /// パスワードとワンタイムパスワード（keyboard-interactive の2段階認証）
+    ///
+    /// パスワードとOTPを順に尋ねるプロンプトに自動で応答する。想定と異なるプロンプトは
+    /// `AuthPromptRequired` イベントでフロントエンドに尋ねる。
+    PasswordWithOtp { password: String, otp: String },
     /// 公開鍵認証
     PublicKey {
         private_key_path: String,
@@ -33,6 +243,13 @@ pub enum ConnectionStatus {
     Failed(String),
 }
 
+impl ConnectionStatus {
+    /// 同じ種類の状態かどうか（`Failed` のメッセージは比較しない）
+    pub fn same_kind(&self, other: &ConnectionStatus) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
 /// SSH セッション情報
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SshSessionInfo {
@@ -40,14 +257,335 @@ pub struct SshSessionInfo {
     pub config: SshConfig,
     pub status: ConnectionStatus,
     pub connected_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// 最後に接続が使用された時刻
+    pub last_activity: Option<chrono::DateTime<chrono::Utc>>,
+    /// 強制コマンドの検出結果（未確認の場合は `None`）
+    pub forced_command: Option<bool>,
+    /// 直近の接続失敗またはサーバーからの切断理由
+    pub last_error: Option<String>,
+    /// サーバーの識別文字列（例: `SSH-2.0-OpenSSH_9.6`、未接続の場合は `None`）
+    pub server_version: Option<String>,
+    /// リモートの既定のシェルの種類（未確認の場合は `None`）
+    pub shell_kind: Option<ShellKind>,
+    /// 直近の接続の各段階の所要時間（一度も接続していない場合は `None`）
+    #[serde(default)]
+    pub connect_timings: Option<ConnectTimings>,
+}
+
+/// 接続の各段階の所要時間（ミリ秒）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectTimings {
+    /// 名前解決・プロキシ・ポートノックを含むTCP接続の確立まで
+    pub tcp_connect_ms: u64,
+    /// 鍵交換とホスト鍵の確認
+    pub handshake_ms: u64,
+    /// 認証（エージェントへの署名要求やプロンプトへの応答待ちを含む）
+    pub auth_ms: u64,
+}
+
+/// リモートの既定のシェルの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShellKind {
+    Bash,
+    Zsh,
+    Sh,
+    Fish,
+    Cmd,
+    PowerShell,
+    /// 判定できなかった
+    Unknown,
+}
+
+impl ShellKind {
+    /// POSIX sh と同じクォート・`export` が使えるか（fish も単一引用符の扱いは互換）
+    pub fn is_posix_compatible(&self) -> bool {
+        !matches!(self, ShellKind::Cmd | ShellKind::PowerShell)
+    }
+}
+
+/// セッションの状態をまとめて取得した結果（詳細表示向け）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionSnapshot {
+    pub info: SshSessionInfo,
+    /// 接続時にネゴシエーションされた内容（未接続の場合は `None`）
+    pub details: Option<ConnectionDetails>,
+    pub telemetry: SessionTelemetry,
+    pub forwards: Vec<ForwardInfo>,
+    pub terminals: Vec<TerminalSession>,
+    pub running_commands: Vec<RunningExecInfo>,
+    /// 終了していないストリーミング実行
+    pub exec_streams: Vec<ExecStreamInfo>,
+    /// 開いているチャネル数（ターミナル・実行中のコマンド・ストリーミング実行の合計。転送の接続は含まない）
+    pub open_channels: usize,
+}
+
+/// 接続確立時にネゴシエーションされた内容
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ConnectionDetails {
+    /// 接続先として解決されたアドレス（プロキシ経由の場合はNone）
+    pub resolved_address: Option<String>,
+    /// サーバーの識別文字列
+    pub server_version: Option<String>,
+    pub kex_algorithm: Option<String>,
+    pub host_key_algorithm: Option<String>,
+    pub cipher: Option<String>,
+    pub mac: Option<String>,
+    pub compression: Option<String>,
+    /// ホスト鍵のSHA256フィンガープリント
+    pub host_key_fingerprint: Option<String>,
+    /// known_hosts との照合結果（記録のみで、接続の可否には影響しない）
+    #[serde(default)]
+    pub host_key_check: Option<HostKeyCheck>,
+    /// 安全でない可能性のあるネゴシエーション結果（旧式のアルゴリズムの使用など）
+    #[serde(default)]
+    pub warnings: Vec<String>,
+}
+
+/// サーバーのホスト鍵と known_hosts の照合結果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostKeyCheck {
+    pub status: HostKeyStatus,
+    /// ホスト名に一致したすべてのエントリ（複数のファイルにまたがる場合も含む）
+    pub matches: Vec<KnownHostMatch>,
+}
+
+/// ホスト鍵の照合状態
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HostKeyStatus {
+    /// 一致する鍵が登録されている
+    Trusted,
+    /// ホストは登録されているが、鍵が一致しない
+    Mismatch,
+    /// ホストが登録されていない
+    Unknown,
+}
+
+/// ホスト名に一致した known_hosts のエントリ
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnownHostMatch {
+    /// エントリが見つかったファイル
+    pub source: String,
+    /// 行番号（1始まり）
+    pub line: usize,
+    pub key_type: String,
+    pub fingerprint: String,
+    /// サーバーが提示した鍵と一致する
+    pub key_matches: bool,
+}
+
+/// サーバーのホスト鍵
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostKeyInfo {
+    pub key_type: String,
+    /// `ssh-ed25519 AAAA...` 形式の公開鍵
+    pub public_key: String,
+    pub fingerprint: String,
+    /// known_hosts にそのまま追記できる行（ポートが22以外は `[host]:port` 形式）
+    pub known_hosts_line: String,
+}
+
+/// アプリが固定したホスト鍵
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PinnedHostKey {
+    pub host: String,
+    pub port: u16,
+    pub key_type: String,
+    /// `ssh-ed25519 AAAA...` 形式の公開鍵
+    pub public_key: String,
+    pub fingerprint: String,
+    pub pinned_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// サーバーの ext-info から分かること
+///
+/// russh は ext-info の内容を公開しておらず、server-sig-algs から判断した RSA 署名アルゴリズムしか問い合わせられない。
+/// server-sig-algs の一覧そのものや、その他の拡張名は取得できない。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServerExtensions {
+    /// server-sig-algs から russh が判断した最良の RSA 署名アルゴリズム（一覧そのものではない）
+    ///
+    /// `rsa-sha2-*` が含まれない場合は `ssh-rsa`、サーバーが server-sig-algs を送らなかった場合は `None`。
+    pub best_rsa_signature_algorithm: Option<String>,
+}
+
+/// 長時間セッションの通信テレメトリ
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionTelemetry {
+    pub session_id: String,
+    /// トランスポート上の送信バイト数
+    pub bytes_sent: u64,
+    /// トランスポート上の受信バイト数
+    pub bytes_received: u64,
+    /// チャネルで受信したペイロードのバイト数
+    pub payload_bytes_received: u64,
+    /// 受信方向の圧縮率（ペイロード / トランスポート）
+    pub compression_ratio: Option<f64>,
+    /// 鍵再交換の推定回数（russhは再交換を通知しないため、設定された上限から算出）
+    pub estimated_rekeys: u64,
+    pub connected_secs: u64,
+    /// 最後に鍵再交換を要求した時刻（`ssh_rekey` によるもの）
+    pub last_rekey_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// コマンド実行のオプション
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CommandOptions {
+    /// 標準出力・標準エラーそれぞれの末尾N行のみを保持する
+    pub tail_lines: Option<usize>,
+    /// PTYを割り当てて実行する（TTYが必要なコマンド向け。標準エラーは標準出力に混ざる）
+    pub pty: bool,
+    /// PTY実行時に、エコーされたコマンド行と末尾のプロンプトを出力から取り除く
+    ///
+    /// 送信したコマンド文字列との一致で判定するヒューリスティックのため、
+    /// コマンドの出力自体がプロンプトに似た行で終わる場合は誤って削除されることがある。
+    /// また `tail_lines` によってエコー行が切り捨てられた場合は取り除かれない。
+    pub strip_echo: bool,
+    /// ログインシェル経由で実行する（`<shell> -lc '<command>'`）
+    ///
+    /// `.profile` 等で設定される PATH を有効にするためのもの。リモートにそのシェルが
+    /// 必要で、`-l` と `-c` を解釈できる bash 互換のシェルを前提とする。
+    pub login_shell: bool,
+    /// `login_shell` で使用するシェル（未指定時は `bash`）
+    pub shell: Option<String>,
+    /// 標準出力・標準エラーに最初の出力が届いた時点（出力がなければ短い猶予の後）で戻る
+    ///
+    /// デーモン化して標準出力を閉じないコマンドの起動確認向け。チャネルは裏で閉じられるまで
+    /// 読み捨てるため、終了前に戻った場合の `exit_code` は `None` になる。
+    pub return_on_first_output: bool,
+    /// 標準出力・標準エラーそれぞれで受け取る最大バイト数（超えた分は破棄する）
+    pub max_output_bytes: Option<usize>,
+    /// セーフモードで拒否されるコマンドを確認済みとして実行する
+    pub confirmed: bool,
+    /// 成功とみなす終了コード（未指定時は 0 のみ。`grep` の 1 など）
+    pub success_codes: Option<Vec<u32>>,
+    /// コマンドの前に export する環境変数
+    pub env: BTreeMap<String, String>,
+    /// 適用する環境変数プロファイルの名前（`env` と同じ変数は `env` が優先）
+    pub env_profile: Option<String>,
+    /// この時刻までに終わらなければタイムアウトとする（セッションのタイムアウトより短い場合）
+    pub deadline: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl CommandOptions {
+    /// 終了コードが成功とみなせるか（終了前に戻った場合・終了コードがない場合は false）
+    pub fn is_success(&self, exit_code: Option<u32>) -> bool {
+        match (exit_code, &self.success_codes) {
+            (Some(code), Some(codes)) => codes.contains(&code),
+            (Some(code), None) => code == 0,
+            (None, _) => false,
+        }
+    }
+}
+
+/// 名前付きの環境変数の組（コマンド・ターミナルに名前で適用する）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnvProfile {
+    pub name: String,
+    pub vars: BTreeMap<String, String>,
+}
+
+/// コマンド出力をローカルファイルへ書き出す際のオプション
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FileOutputOptions {
+    /// 標準エラーも同じファイルに書き出す
+    pub include_stderr: bool,
+    /// 既存のファイルに追記する（false の場合は切り詰める）
+    pub append: bool,
+    /// セーフモードで危険と判定されるコマンドも実行する
+    pub confirmed: bool,
+}
+
+/// コマンド出力をファイルへ書き出した結果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileOutputResult {
+    /// 終了コード（サーバーが終了ステータスを送らなかった場合は `None`）
+    pub exit_code: Option<u32>,
+    pub bytes_written: u64,
 }
 
 /// コマンド実行結果
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CommandResult {
-    pub exit_code: u32,
+    /// 終了コード（`return_on_first_output` で終了前に戻った場合・サーバーが終了ステータスを送らなかった場合は `None`）
+    pub exit_code: Option<u32>,
     pub stdout: String,
     pub stderr: String,
+    /// exec 要求を送った時刻
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    /// チャネルが閉じた時刻（`return_on_first_output` で終了前に戻った場合は `None`）
+    pub ended_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// 終了コードが `success_codes` に含まれる（終了コードがない場合は false）
+    pub is_success: bool,
+}
+
+/// 出力をバイト列のまま返すコマンド実行結果（出力はbase64）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BytesCommandResult {
+    /// 終了コード（`return_on_first_output` で終了前に戻った場合・サーバーが終了ステータスを送らなかった場合は `None`）
+    pub exit_code: Option<u32>,
+    pub stdout_b64: String,
+    pub stderr_b64: String,
+    /// `max_output_bytes` を超えて出力を破棄した
+    pub truncated: bool,
+}
+
+/// 所要時間付きのコマンド実行結果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimedCommandResult {
+    #[serde(flatten)]
+    pub result: CommandResult,
+    /// exec 要求からチャネルが閉じるまでの経過時間
+    pub duration_ms: u64,
+}
+
+/// 既定値と上書きを反映した、実際に使われる設定値
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EffectiveConfig {
+    /// 無通信で切断するまでの時間
+    pub inactivity_timeout_ms: Option<u64>,
+    /// キープアライブの送信間隔
+    pub keepalive_interval_ms: Option<u64>,
+    /// 応答のないキープアライブをいくつ送ったら切断するか
+    pub keepalive_max: usize,
+    /// 認証中にユーザー入力を待つ時間
+    pub auth_timeout_ms: u64,
+    /// コマンド実行のタイムアウト（`ssh_set_session_timeout` で指定した場合のみ）
+    pub command_timeout_ms: Option<u64>,
+    pub read_buffer_size: usize,
+    pub maximum_packet_size: u32,
+    pub window_size: u32,
+    pub channel_buffer_size: usize,
+    pub max_concurrent_commands: usize,
+    /// 鍵再交換を行う間隔と通信量
+    pub rekey_time_limit_ms: u64,
+    pub rekey_read_limit: u64,
+    pub rekey_write_limit: u64,
+    pub reconnect: Option<ReconnectPolicy>,
+    pub terminal_idle_close_ms: Option<u64>,
+    /// `SshClientBuilder::with_russh_config` などでrusshの設定が直接指定されている
+    pub custom_russh_config: bool,
+}
+
+/// 前回の出力と比較したコマンド実行結果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandDiffResult {
+    /// 標準出力または終了コードが前回から変わった
+    pub changed: bool,
+    /// 前回の標準出力からの unified diff（変わっていなければ空）
+    pub diff: String,
+    pub exit_code: Option<u32>,
+    /// 今回の標準出力（変わった場合のみ。次回の比較に使う）
+    pub output: Option<String>,
+}
+
+/// リモートでのコマンドの有無
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteCommandInfo {
+    pub exists: bool,
+    /// `command -v` が返したパス（シェル組み込みの場合はコマンド名）
+    pub path: Option<String>,
 }
 
 /// ターミナルセッション情報
@@ -57,6 +595,49 @@ pub struct TerminalSession {
     pub ssh_session_id: String,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub is_active: bool,
+    /// エージェント転送がサーバーに許可された
+    #[serde(default)]
+    pub agent_forwarded: bool,
+    /// X11転送がサーバーに許可された
+    #[serde(default)]
+    pub x11_forwarded: bool,
+    /// SIGHUPを無視した状態でシェルを起動した（切断後もジョブが残る）
+    #[serde(default)]
+    pub nohup_on_detach: bool,
+    /// 作成時の環境変数プロファイルと `terminal_set_env` で設定された環境変数
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// シェルが OSC 7 で通知した直近の作業ディレクトリ
+    #[serde(default)]
+    pub cwd: Option<String>,
+    /// 出力を書き出しているローカルのログファイル
+    #[serde(default)]
+    pub log_path: Option<String>,
+}
+
+/// ターミナル作成時に要求する転送
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TerminalForwarding {
+    /// SSHエージェント転送（ローカルの `SSH_AUTH_SOCK` に中継する）
+    pub agent: bool,
+    /// X11転送（ローカルの `$DISPLAY` に中継する）
+    pub x11: bool,
+}
+
+/// ターミナルが終了した理由
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TerminalExitReason {
+    /// ユーザーが閉じた
+    Closed,
+    /// アイドルタイムアウトにより閉じた
+    IdleTimeout,
+    /// シェルが終了した（終了コードが分かれば含む）
+    ShellExited(Option<u32>),
+    /// チャネルへの書き込みに失敗した
+    ChannelError(String),
+    /// SSH接続が切れた（自動再接続に成功すると開き直す）
+    ConnectionLost,
 }
 
 /// ターミナルデータ
@@ -67,6 +648,424 @@ pub struct TerminalData {
     pub timestamp: chrono::DateTime<chrono::Utc>,
 }
 
+/// ストリーミング実行の情報
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecStreamInfo {
+    pub id: String,
+    pub session_id: String,
+    pub command: String,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub finished: bool,
+    pub exit_code: Option<u32>,
+    /// これまでに受信した出力のバイト数
+    #[serde(default)]
+    pub bytes_produced: u64,
+    /// コマンドを終了させたシグナル
+    #[serde(default)]
+    pub exit_signal: Option<String>,
+}
+
+/// 実行中のコマンドの情報
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunningExecInfo {
+    /// `exec_stream_cancel` に渡すID
+    pub exec_id: String,
+    pub session_id: String,
+    pub command: String,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    /// これまでに受信した標準出力・標準エラーのバイト数
+    pub bytes_produced: u64,
+    /// ストリーミング実行か（`false` なら完了を待つ通常の実行）
+    pub streaming: bool,
+}
+
+/// 出力の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StdStream {
+    Stdout,
+    Stderr,
+}
+
+/// ストリーミング実行から受信する項目
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ExecStreamData {
+    /// 出力チャンク
+    Output {
+        stream_id: String,
+        /// 標準出力か標準エラーか
+        stream: StdStream,
+        data: String,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    },
+    /// コマンドの終了（最後の項目。中断・切断された場合は送られない）
+    Completed {
+        stream_id: String,
+        completion: ExecCompletion,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    },
+}
+
+/// ストリーミング実行したコマンドの終了のしかた
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ExecCompletion {
+    /// 終了コードを返して終了した
+    Exited { exit_code: u32 },
+    /// シグナルで終了した（OOM killer による SIGKILL など）
+    Signaled {
+        signal: String,
+        core_dumped: bool,
+        error_message: String,
+    },
+}
+
+/// ペースト時の分割送信設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PasteOptions {
+    /// 1回の書き込みで送るバイト数の上限
+    pub chunk_size: usize,
+    /// チャンク間の待機時間（ミリ秒）
+    pub delay_ms: u64,
+    /// ブラケットペーストモードのエスケープシーケンスで囲む
+    pub bracketed: bool,
+}
+
+impl Default for PasteOptions {
+    fn default() -> Self {
+        Self {
+            chunk_size: 1024,
+            delay_ms: 10,
+            bracketed: false,
+        }
+    }
+}
+
+/// `sftp_wait_for` で待つ条件
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WaitCondition {
+    /// パスが存在する
+    Exists,
+    /// パスが存在しない
+    NotExists,
+    /// ファイルが存在し、続けて調べたサイズが変わらない（書き込みが終わった）
+    SizeStable,
+}
+
+/// systemd journal のログエントリ
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub timestamp: Option<chrono::DateTime<chrono::Utc>>,
+    /// syslogの優先度（0: emerg 〜 7: debug）
+    pub priority: Option<u8>,
+    pub message: String,
+    pub unit: Option<String>,
+    /// `SYSLOG_IDENTIFIER`（プロセス名など）
+    pub identifier: Option<String>,
+    pub pid: Option<u32>,
+}
+
+/// リモートのプロセス（`ps aux` の1行）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub user: String,
+    /// CPU使用率（%）
+    pub cpu: f64,
+    /// メモリ使用率（%）
+    pub mem: f64,
+    /// 引数を含むコマンドライン
+    pub command: String,
+}
+
+/// ディレクトリ同期の方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SyncDirection {
+    /// ローカルからリモートへ
+    Upload,
+    /// リモートからローカルへ
+    Download,
+}
+
+/// ディレクトリ同期の設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncOptions {
+    pub direction: SyncDirection,
+    /// 転送元に存在しないファイルを転送先から削除する
+    #[serde(default)]
+    pub delete: bool,
+    /// 1回に読み書きするバイト数（未指定時はセッションの読み込みバッファサイズ、1KB〜255KBに丸める）
+    #[serde(default)]
+    pub chunk_size: Option<usize>,
+    /// 転送速度の上限（バイト/秒、未指定時は制限しない。転送中に `transfer_set_rate_limit` で変更できる）
+    #[serde(default)]
+    pub max_bytes_per_sec: Option<u64>,
+}
+
+/// 転送速度の測定結果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BandwidthTestResult {
+    /// 転送したバイト数（アップロード・ダウンロードそれぞれ）
+    pub bytes: u64,
+    pub upload_ms: u64,
+    pub download_ms: u64,
+    pub upload_bytes_per_sec: f64,
+    pub download_bytes_per_sec: f64,
+}
+
+/// ディレクトリ同期の結果
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SyncSummary {
+    pub sync_id: String,
+    pub added: usize,
+    pub updated: usize,
+    pub deleted: usize,
+    pub skipped: usize,
+    pub bytes_transferred: u64,
+    pub bytes_total: u64,
+    pub cancelled: bool,
+}
+
+/// ポート転送の定義
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ForwardSpec {
+    /// ローカルで待ち受け、リモート側から `remote_host:remote_port` へ接続する（`ssh -L`）
+    Local {
+        bind_address: String,
+        bind_port: u16,
+        remote_host: String,
+        remote_port: u16,
+    },
+    /// リモートで待ち受け、ローカル側から `local_host:local_port` へ接続する（`ssh -R`）
+    Remote {
+        bind_address: String,
+        bind_port: u16,
+        local_host: String,
+        local_port: u16,
+    },
+    /// ローカルで SOCKS5 プロキシとして待ち受ける（`ssh -D`）
+    Dynamic {
+        bind_address: String,
+        bind_port: u16,
+    },
+}
+
+/// ポート転送の状態
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForwardInfo {
+    pub id: String,
+    pub session_id: String,
+    pub spec: ForwardSpec,
+    /// 実際に待ち受けているポート（`bind_port` に0を指定した場合に割り当てられたもの）
+    pub bound_port: u16,
+    /// 現在の接続上で転送が有効か
+    pub active: bool,
+    /// 再確立に失敗した場合のエラー
+    pub error: Option<String>,
+}
+
+/// 転送の種類
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TransferKind {
+    /// ディレクトリ同期
+    Sync,
+    /// リモートファイルのストリーム読み込み
+    StreamRead,
+    /// 転送速度の測定
+    BandwidthTest,
+    /// サーバー上でのファイルのコピー
+    RemoteCopy,
+    /// 複数ファイルのアーカイブとしてのダウンロード
+    TarDownload,
+}
+
+/// アーカイブとしてのダウンロードに使った方法
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum TarDownloadMethod {
+    /// リモートの `tar` の出力をそのまま受信した
+    Tar,
+    /// SFTPで1ファイルずつ取得してローカルでアーカイブにした
+    Sftp,
+}
+
+/// アーカイブとしてのダウンロードの結果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TarDownloadResult {
+    /// 転送一覧・進捗イベントのID
+    pub transfer_id: String,
+    pub method: TarDownloadMethod,
+    /// 書き出したアーカイブのバイト数
+    pub bytes: u64,
+    /// アーカイブは作成できたが、一部のファイルが欠けている可能性がある場合の理由
+    /// （`tar` の0以外の終了、途中で消えたファイルなど）
+    pub warning: Option<String>,
+}
+
+/// リモートでのファイルのコピーに使った方法
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum RemoteCopyMethod {
+    /// SFTPの `copy-data@openssh.com` 拡張
+    CopyData,
+    /// `cp` コマンドの実行
+    Exec,
+}
+
+/// リモートでのファイルのコピー結果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteCopyResult {
+    pub method: RemoteCopyMethod,
+    /// コピーしたバイト数（`cp` の場合は `None`）
+    pub bytes: Option<u64>,
+}
+
+/// 転送の状態
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TransferState {
+    Running,
+    Completed,
+    Cancelled,
+    Failed(String),
+}
+
+/// 個別の転送の情報
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferInfo {
+    /// 同期IDまたはストリームID
+    pub id: String,
+    pub session_id: String,
+    pub kind: TransferKind,
+    /// 対象のリモートパス
+    pub path: String,
+    pub state: TransferState,
+    pub bytes_transferred: u64,
+    /// 合計バイト数（分からない場合は `None`）
+    pub bytes_total: Option<u64>,
+    /// 開始からの平均転送速度（バイト/秒、速度制限中は制限後の実効速度）
+    pub bytes_per_sec: f64,
+    /// 転送速度の上限（バイト/秒、制限しない場合は `None`）
+    #[serde(default)]
+    pub max_bytes_per_sec: Option<u64>,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// 実行中の転送全体の集計
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TransferAggregate {
+    pub active_transfers: usize,
+    pub bytes_transferred: u64,
+    /// 合計バイト数が分かっている転送の残りバイト数の合計
+    pub bytes_remaining: u64,
+    /// 各転送の転送速度の合計（バイト/秒）
+    pub bytes_per_sec: f64,
+}
+
+/// リモートパスの情報
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RemotePathInfo {
+    pub exists: bool,
+    pub is_dir: bool,
+    pub is_file: bool,
+    pub is_symlink: bool,
+    pub size: Option<u64>,
+    pub mode: Option<u32>,
+    /// 存在するが権限不足で情報を取得できない
+    pub permission_denied: bool,
+}
+
+/// 転送が必要かの判定結果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferCheck {
+    pub needs_transfer: bool,
+    pub reason: TransferCheckReason,
+}
+
+/// 転送の要否を決めた理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransferCheckReason {
+    /// リモートにファイルがない
+    RemoteMissing,
+    /// サイズが異なる
+    SizeDiffers,
+    /// SHA-256 が異なる
+    HashDiffers,
+    /// SHA-256 が一致する
+    HashMatches,
+    /// `sha256sum` が使えないため更新時刻で比較し、異なっていた
+    MtimeDiffers,
+    /// `sha256sum` が使えないため更新時刻で比較し、一致した
+    MtimeMatches,
+}
+
+/// リモートファイルから読み込んだ範囲
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteByteRange {
+    /// 読み込んだデータ（base64）
+    pub data_b64: String,
+    pub start: u64,
+    /// 実際に読み込んだバイト数（要求より短い場合がある）
+    pub length: u64,
+    /// ファイル全体のサイズ
+    pub file_size: u64,
+}
+
+/// グロブに一致したリモートのファイル
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteFileEntry {
+    pub path: String,
+    pub name: String,
+    pub is_dir: bool,
+    pub is_file: bool,
+    pub is_symlink: bool,
+    pub size: Option<u64>,
+    pub mode: Option<u32>,
+    /// 更新日時（UNIX時刻の秒）
+    pub mtime: Option<u64>,
+}
+
+/// ローカルの秘密鍵ファイル
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocalKeyInfo {
+    pub path: String,
+    /// 鍵の種類（例: `ssh-ed25519`）。判定できない場合は `None`
+    pub key_type: Option<String>,
+    /// パスフレーズで暗号化されているか
+    pub encrypted: bool,
+    /// SHA256 指紋（OpenSSH形式の鍵のみ）
+    pub fingerprint: Option<String>,
+}
+
+/// 復号を確認した秘密鍵の情報
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyInfo {
+    /// 鍵の種類（例: `ssh-ed25519`）
+    pub key_type: String,
+    /// 鍵長（ビット、判定できない場合は `None`）
+    pub bits: Option<u32>,
+    /// SHA256 指紋
+    pub fingerprint: String,
+    /// 鍵に埋め込まれたコメント（OpenSSH形式以外は空）
+    pub comment: String,
+    /// パスフレーズで暗号化されていたか
+    pub encrypted: bool,
+}
+
+/// 生成する鍵の種類
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum KeyType {
+    Ed25519,
+    /// ビット数を指定したRSA鍵
+    Rsa { bits: usize },
+}
+
+/// SSHエージェントに登録されている鍵
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentIdentity {
+    pub comment: String,
+    pub key_type: String,
+    pub fingerprint: String,
+}
+
 /// ファイル転送の進捗情報
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransferProgress {
@@ -86,8 +1085,20 @@ pub enum SshError {
     CommandFailed(String),
     #[error("File transfer failed: {0}")]
     TransferFailed(String),
+    #[error("SFTP channel failed: {0}")]
+    SftpChannelFailed(String),
+    #[error("Invalid configuration: {0}")]
+    ConfigError(String),
+    #[error("Host not found: {0}")]
+    DnsResolutionFailed(String),
+    #[error("Proxy error: {0}")]
+    ProxyFailed(String),
     #[error("Session not found: {0}")]
     SessionNotFound(String),
+    #[error("Timed out: {0}")]
+    Timeout(String),
+    #[error("Terminal output channel closed: {0}")]
+    TerminalClosed(String),
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
     #[error("SSH error: {0}")]
@@ -0,0 +1,195 @@
+use crate::ssh::SshError;
+use russh::client::Msg;
+use russh::Channel;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use uuid::Uuid;
+
+/// X11の認証プロトコル名
+const X11_AUTH_PROTOCOL: &str = "MIT-MAGIC-COOKIE-1";
+
+/// TCPで待ち受けるXサーバーの基準ポート（ディスプレイ番号を加える）
+const X11_BASE_PORT: u16 = 6000;
+
+/// X11転送の中継先（再接続をまたいで同じ設定を使うため、セッションが保持する）
+pub type X11Slot = Arc<std::sync::Mutex<Option<X11Display>>>;
+
+/// 中継先のローカルディスプレイと認証情報
+///
+/// サーバーには本物の認証クッキーを渡さず、偽のクッキーを渡して接続時に差し替える。
+#[derive(Clone)]
+pub struct X11Display {
+    display: String,
+    fake_cookie: Vec<u8>,
+    real_cookie: Option<Vec<u8>>,
+}
+
+impl X11Display {
+    /// `$DISPLAY` から中継先を決める
+    pub async fn from_env() -> Result<Self, SshError> {
+        let display = std::env::var("DISPLAY")
+            .ok()
+            .filter(|display| !display.is_empty())
+            .ok_or_else(|| {
+                SshError::ConfigError("X11 forwarding requires DISPLAY to be set".to_string())
+            })?;
+        let real_cookie = read_xauth_cookie(&display).await;
+
+        Ok(Self {
+            display,
+            fake_cookie: Uuid::new_v4().into_bytes().to_vec(),
+            real_cookie,
+        })
+    }
+
+    /// `x11-req` で送る認証クッキー（16進文字列）
+    pub fn fake_cookie_hex(&self) -> String {
+        self.fake_cookie.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+/// サーバーから開かれたX11チャネルをローカルのXサーバーに中継する
+pub async fn relay_x11(channel: Channel<Msg>, slot: X11Slot) {
+    let display = slot.lock().ok().and_then(|slot| slot.clone());
+    let Some(display) = display else {
+        let _ = channel.close().await;
+        return;
+    };
+
+    let mut stream = channel.into_stream();
+    let Some(setup) = rewrite_setup(&mut stream, &display).await else {
+        return;
+    };
+
+    match parse_display(&display.display) {
+        #[cfg(unix)]
+        Some(LocalDisplay::Unix(path)) => {
+            if let Ok(local) = tokio::net::UnixStream::connect(path).await {
+                relay(stream, local, &setup).await;
+            }
+        }
+        Some(LocalDisplay::Tcp(host, port)) => {
+            if let Ok(local) = TcpStream::connect((host.as_str(), port)).await {
+                relay(stream, local, &setup).await;
+            }
+        }
+        _ => {}
+    }
+}
+
+/// 書き換えた接続開始要求を送ってから双方向に中継する
+async fn relay<S, L>(mut stream: S, mut local: L, setup: &[u8])
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    L: AsyncRead + AsyncWrite + Unpin,
+{
+    if local.write_all(setup).await.is_err() {
+        return;
+    }
+    let _ = tokio::io::copy_bidirectional(&mut stream, &mut local).await;
+}
+
+/// X11の接続開始要求を読み、偽のクッキーを本物に差し替える
+///
+/// クッキーが一致しない接続は拒否する（`None`）。
+async fn rewrite_setup<S>(stream: &mut S, display: &X11Display) -> Option<Vec<u8>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut header = [0u8; 12];
+    stream.read_exact(&mut header).await.ok()?;
+    let big_endian = header[0] == b'B';
+    let read_u16 = |bytes: [u8; 2]| {
+        if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        }
+    };
+    let name_len = read_u16([header[6], header[7]]) as usize;
+    let data_len = read_u16([header[8], header[9]]) as usize;
+
+    let mut name = vec![0u8; name_len + padding(name_len)];
+    stream.read_exact(&mut name).await.ok()?;
+    let mut data = vec![0u8; data_len + padding(data_len)];
+    stream.read_exact(&mut data).await.ok()?;
+    if &name[..name_len] != X11_AUTH_PROTOCOL.as_bytes() || data[..data_len] != display.fake_cookie[..] {
+        return None;
+    }
+
+    let (name, data): (&[u8], &[u8]) = match &display.real_cookie {
+        Some(cookie) => (X11_AUTH_PROTOCOL.as_bytes(), cookie),
+        None => (&[], &[]),
+    };
+    let write_u16 = |value: u16| {
+        if big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        }
+    };
+    let mut setup = header[..6].to_vec();
+    setup.extend_from_slice(&write_u16(name.len() as u16));
+    setup.extend_from_slice(&write_u16(data.len() as u16));
+    setup.extend_from_slice(&header[10..]);
+    setup.extend_from_slice(name);
+    setup.resize(setup.len() + padding(name.len()), 0);
+    setup.extend_from_slice(data);
+    setup.resize(setup.len() + padding(data.len()), 0);
+    Some(setup)
+}
+
+/// 4バイト境界までの埋め草の長さ
+fn padding(len: usize) -> usize {
+    (4 - len % 4) % 4
+}
+
+/// ローカルのXサーバーの接続先
+enum LocalDisplay {
+    #[cfg(unix)]
+    Unix(String),
+    Tcp(String, u16),
+}
+
+/// `$DISPLAY`（`[host]:display[.screen]`）を接続先に変換する
+fn parse_display(display: &str) -> Option<LocalDisplay> {
+    let (host, rest) = display.rsplit_once(':')?;
+    let number: u16 = rest.split('.').next()?.parse().ok()?;
+
+    if host.is_empty() || host == "unix" {
+        #[cfg(unix)]
+        return Some(LocalDisplay::Unix(format!("/tmp/.X11-unix/X{}", number)));
+        #[cfg(not(unix))]
+        return Some(LocalDisplay::Tcp("127.0.0.1".to_string(), X11_BASE_PORT + number));
+    }
+    Some(LocalDisplay::Tcp(host.to_string(), X11_BASE_PORT.checked_add(number)?))
+}
+
+/// `xauth list` からディスプレイの認証クッキーを取得する（見つからなければ `None`）
+async fn read_xauth_cookie(display: &str) -> Option<Vec<u8>> {
+    let output = tokio::process::Command::new("xauth")
+        .args(["list", display])
+        .output()
+        .await
+        .ok()?;
+
+    String::from_utf8_lossy(&output.stdout).lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let _ = fields.next()?;
+        if fields.next()? != X11_AUTH_PROTOCOL {
+            return None;
+        }
+        decode_hex(fields.next()?)
+    })
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
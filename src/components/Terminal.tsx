@@ -102,6 +102,7 @@ export function Terminal({ sessionId, sshSessionId, onClose }: TerminalProps) {
 		if (!terminalSession) return;
 
 		// Poll for output data
+		let interval: ReturnType<typeof setInterval> | undefined;
 		const pollOutput = async () => {
 			try {
 				const output: TerminalData | null = await invoke(
@@ -115,11 +116,16 @@ export function Terminal({ sessionId, sshSessionId, onClose }: TerminalProps) {
 					xtermRef.current.write(output.data);
 				}
 			} catch (error) {
+				// Stop polling once the terminal has closed
+				if (String(error).includes("Terminal output channel closed")) {
+					clearInterval(interval);
+					return;
+				}
 				console.error("Failed to receive terminal output:", error);
 			}
 		};
 
-		const interval = setInterval(pollOutput, 100);
+		interval = setInterval(pollOutput, 100);
 		return () => clearInterval(interval);
 	}, [sessionId, terminalSession]);
 
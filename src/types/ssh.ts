@@ -10,6 +10,7 @@ export interface SshConfig {
 
 export type AuthMethod =
 	| { Password: string }
+	| { PasswordWithOtp: { password: string; otp: string } }
 	| { PublicKey: { private_key_path: string; passphrase?: string } }
 	| "Agent";
 
@@ -24,12 +25,26 @@ export interface SshSessionInfo {
 	config: SshConfig;
 	status: ConnectionStatus;
 	connected_at?: string; // ISO 8601 datetime string
+	server_version?: string | null;
+	shell_kind?: ShellKind | null;
+	connect_timings?: ConnectTimings | null;
 }
 
+export interface ConnectTimings {
+	tcp_connect_ms: number;
+	handshake_ms: number;
+	auth_ms: number;
+}
+
+export type ShellKind = "Bash" | "Zsh" | "Sh" | "Fish" | "Cmd" | "PowerShell" | "Unknown";
+
 export interface CommandResult {
-	exit_code: number;
+	exit_code: number | null;
 	stdout: string;
 	stderr: string;
+	started_at: string; // ISO 8601 datetime string
+	ended_at: string | null;
+	is_success: boolean;
 }
 
 export interface TerminalSession {
@@ -37,6 +52,12 @@ export interface TerminalSession {
 	ssh_session_id: string;
 	created_at: string;
 	is_active: boolean;
+	agent_forwarded: boolean;
+	x11_forwarded: boolean;
+	nohup_on_detach: boolean;
+	env: Record<string, string>;
+	cwd: string | null;
+	log_path: string | null;
 }
 
 export interface TerminalData {